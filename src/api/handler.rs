@@ -1,5 +1,7 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
 
+use chrono::Utc;
 use ipnetwork::IpNetwork;
 use jsonrpsee::{
     core::{Error, RpcResult},
@@ -7,12 +9,24 @@ use jsonrpsee::{
 };
 use log::info;
 
-use super::peers::{peer_to_detail, peer_to_summary};
-use super::routes::entry_to_route;
-use super::rpc::{ApiServer, FlowSpec, LearnedRoute, PeerDetail, PeerSummary, RouteSpec};
+use super::peers::{capabilities_info, peer_to_detail, peer_to_summary};
+use super::routes::{
+    entries_to_paths, entry_to_route, has_next_hop, originates_from, within_prefix,
+};
+use super::rpc::{
+    ApiServer, CapabilityInfo, DampenedRoute, FamilySummary, FilterStats, FlowSpec, LearnedRoute,
+    PeerDetail, PeerSummary, ReloadSummary, RibPath, RouteSpec, ServerSummary,
+};
 use crate::handler::Server;
+use crate::mrt::{MrtPeer, TableDump};
 use crate::rib::EntrySource;
-use crate::utils::{get_host_address, parse_flow_spec, parse_route_spec};
+use crate::session::SessionState;
+use crate::utils::{
+    asn_from_dotted, get_host_address, longest_match, parse_flow_spec, parse_route_spec, ParseError,
+};
+
+/// Time to wait (after notifying peers) before the daemon exits from a `shutdown` request
+const SHUTDOWN_DELAY: Duration = Duration::from_millis(500);
 
 #[async_trait::async_trait]
 impl ApiServer for Server {
@@ -27,7 +41,7 @@ impl ApiServer for Server {
             .iter()
             .map(|(addr, session)| {
                 let pfx_rcvd = rib.get_routes_from_peer(*addr).len() as u64;
-                peer_to_summary(session.config.clone(), Some(session), Some(pfx_rcvd))
+                peer_to_summary(session.config.clone(), Some(session), None, Some(pfx_rcvd))
             })
             .collect();
         output.extend(session_summaries);
@@ -41,7 +55,8 @@ impl ApiServer for Server {
                         return None;
                     }
                 }
-                Some(peer_to_summary(config, None, None))
+                let state = sessions.idle_peers.get_peer_state(&config.remote_ip);
+                Some(peer_to_summary(config, None, state, None))
             })
             .collect::<Vec<PeerSummary>>();
         output.extend(idle_summaries);
@@ -53,13 +68,22 @@ impl ApiServer for Server {
         let sessions = self.inner.sessions.read().await;
         let configs = sessions.get_peer_configs();
         let active_sessions = sessions.sessions.read().await;
+        let history = sessions.history.read().await;
         let rib = self.inner.rib.read().await;
         // Detail for any non-idle sessions
         let session_details: Vec<PeerDetail> = active_sessions
             .iter()
             .map(|(addr, session)| {
                 let pfx_rcvd = rib.get_routes_from_peer(*addr).len() as u64;
-                peer_to_detail(session.config.clone(), Some(session), Some(pfx_rcvd))
+                let loop_dropped = rib.filter_stats(*addr).as_path_loop;
+                peer_to_detail(
+                    session.config.clone(),
+                    Some(session),
+                    None,
+                    Some(pfx_rcvd),
+                    Some(loop_dropped),
+                    history.get(addr).cloned(),
+                )
             })
             .collect();
         output.extend(session_details);
@@ -73,7 +97,10 @@ impl ApiServer for Server {
                         return None;
                     }
                 }
-                Some(peer_to_detail(config, None, None))
+                let state = sessions.idle_peers.get_peer_state(&config.remote_ip);
+                let history = get_host_address(&config.remote_ip)
+                    .and_then(|remote_ip| history.get(&remote_ip).cloned());
+                Some(peer_to_detail(config, None, state, None, None, history))
             })
             .collect();
         output.extend(idle_details);
@@ -83,8 +110,14 @@ impl ApiServer for Server {
     async fn show_routes_learned(
         &self,
         from_peer: Option<IpNetwork>,
+        origin_as: Option<String>,
+        next_hop: Option<IpAddr>,
     ) -> RpcResult<Vec<LearnedRoute>> {
         let mut output: Vec<LearnedRoute> = vec![];
+        let origin_as = origin_as
+            .map(|asn| asn_from_dotted(&asn))
+            .transpose()
+            .map_err(|e| Error::Custom(e.to_string()))?;
         let entries = {
             let rib = self.inner.rib.read().await;
             if let Some(peer) = from_peer {
@@ -101,14 +134,42 @@ impl ApiServer for Server {
                 rib.get_routes()
             }
         };
-        let routes: Vec<_> = entries.into_iter().map(entry_to_route).collect();
+        let routes: Vec<_> = entries
+            .into_iter()
+            .filter(|entry| match origin_as {
+                Some(asn) => originates_from(entry, asn),
+                None => true,
+            })
+            .filter(|entry| match next_hop {
+                Some(next_hop) => has_next_hop(entry, next_hop),
+                None => true,
+            })
+            .map(entry_to_route)
+            .collect();
         output.extend(routes);
         Ok(output)
     }
 
+    async fn show_rib(&self, prefix: Option<IpNetwork>) -> RpcResult<Vec<RibPath>> {
+        let entries: Vec<_> = self
+            .inner
+            .rib
+            .read()
+            .await
+            .get_routes()
+            .into_iter()
+            .filter(|entry| match prefix {
+                Some(prefix) => within_prefix(entry, prefix),
+                None => true,
+            })
+            .collect();
+        Ok(entries_to_paths(entries))
+    }
+
     async fn show_routes_advertised(
         &self,
         to_peer: Option<IpNetwork>,
+        next_hop: Option<IpAddr>,
     ) -> RpcResult<Vec<LearnedRoute>> {
         let mut output: Vec<LearnedRoute> = vec![];
         let sessions = self.inner.sessions.read().await;
@@ -123,6 +184,10 @@ impl ApiServer for Server {
                 s.routes
                     .advertised()
                     .into_iter()
+                    .filter(|entry| match next_hop {
+                        Some(next_hop) => has_next_hop(entry, next_hop),
+                        None => true,
+                    })
                     .map(|entry| {
                         let mut entry = entry_to_route(entry);
                         entry.source = EntrySource::Peer(s.addr).to_string();
@@ -136,19 +201,165 @@ impl ApiServer for Server {
     }
 
     async fn advertise_route(&self, route: RouteSpec) -> RpcResult<LearnedRoute> {
-        let update = parse_route_spec(&route).map_err(|e| Error::Custom(e.to_string()))?;
-        let (family, attributes, nlri) = update;
-        let mut rib = self.inner.rib.write().await;
-        let entry = rib.insert_from_api(family, attributes, nlri);
-        Ok(entry_to_route(entry))
+        Server::advertise_route(self, route)
+            .await
+            .map_err(|e| Error::Custom(e.to_string()))
     }
 
     async fn advertise_flow(&self, flow: FlowSpec) -> RpcResult<LearnedRoute> {
-        let update = parse_flow_spec(&flow).map_err(|e| Error::Custom(e.to_string()))?;
-        let (family, attributes, nlri) = update;
+        Server::advertise_flow(self, flow)
+            .await
+            .map_err(|e| Error::Custom(e.to_string()))
+    }
+
+    async fn withdraw_route(&self, prefix: IpNetwork) -> RpcResult<Vec<LearnedRoute>> {
+        let removed = Server::withdraw_route(self, prefix).await;
+        if removed.is_empty() {
+            return Err(Error::Custom(format!(
+                "No API advertised route found for {}",
+                prefix
+            )));
+        }
+        Ok(removed)
+    }
+
+    async fn clear_session(&self, peer: IpAddr, reason: Option<String>) -> RpcResult<()> {
+        self.inner
+            .sessions
+            .read()
+            .await
+            .clear_session(peer, reason.as_deref())
+            .await
+            .map_err(|e| Error::Custom(e.to_string()))?;
         let mut rib = self.inner.rib.write().await;
-        let entry = rib.insert_from_api(family, attributes, nlri);
-        Ok(entry_to_route(entry))
+        rib.peer_down(peer);
+        rib.remove_from_peer(peer);
+        Ok(())
+    }
+
+    async fn set_peer_enabled(&self, peer: IpAddr, enabled: bool) -> RpcResult<()> {
+        self.inner
+            .sessions
+            .read()
+            .await
+            .set_peer_enabled(peer, enabled)
+            .await
+            .map_err(|e| Error::Custom(e.to_string()))
+    }
+
+    async fn show_peer_filter_stats(&self, peer: IpAddr) -> RpcResult<FilterStats> {
+        let sessions = self.inner.sessions.read().await;
+        if !sessions
+            .get_peer_configs()
+            .iter()
+            .any(|c| c.remote_ip.contains(peer))
+        {
+            return Err(Error::Custom(format!("No peer configured for {}", peer)));
+        }
+        Ok(self.inner.rib.read().await.filter_stats(peer))
+    }
+
+    async fn show_peer_capabilities(&self, peer: IpAddr) -> RpcResult<Vec<CapabilityInfo>> {
+        let sessions = self.inner.sessions.read().await;
+        let active_sessions = sessions.sessions.read().await;
+        let session = active_sessions
+            .get(&peer)
+            .ok_or_else(|| Error::Custom(format!("No active session for {}", peer)))?;
+        Ok(capabilities_info(session))
+    }
+
+    async fn show_summary(&self) -> RpcResult<ServerSummary> {
+        let sessions = self.inner.sessions.read().await;
+        let peers_configured = sessions.get_peer_configs().len();
+        let peers_established = sessions
+            .sessions
+            .read()
+            .await
+            .values()
+            .filter(|session| session.state == SessionState::Established)
+            .count();
+        let rib = self.inner.rib.read().await;
+        let families = rib
+            .family_counts()
+            .into_iter()
+            .map(|(family, entries)| FamilySummary {
+                afi: family.afi.to_string(),
+                safi: family.safi.to_string(),
+                entries,
+            })
+            .collect();
+        Ok(ServerSummary {
+            router_id: sessions.config.router_id,
+            local_asn: sessions.config.default_as,
+            peers_configured,
+            peers_established,
+            rib_entries: rib.len(),
+            families,
+        })
+    }
+
+    async fn show_dampened(&self) -> RpcResult<Vec<DampenedRoute>> {
+        Ok(self.inner.rib.read().await.dampened_routes())
+    }
+
+    async fn dump_rib_mrt(&self, path: String) -> RpcResult<()> {
+        let sessions = self.inner.sessions.read().await;
+        let configs = sessions.get_peer_configs();
+        let collector_id = match sessions.config.router_id {
+            IpAddr::V4(router_id) => router_id,
+            IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+        };
+        let mut dump = TableDump::new(collector_id);
+        for entry in self.inner.rib.read().await.get_routes() {
+            let peer = match entry.source {
+                EntrySource::Peer(addr) => MrtPeer {
+                    router_id: match entry.router_id {
+                        Some(IpAddr::V4(router_id)) => router_id,
+                        _ => Ipv4Addr::UNSPECIFIED,
+                    },
+                    addr,
+                    asn: longest_match(configs.iter().map(|c| &c.remote_ip), addr)
+                        .and_then(|network| configs.iter().find(|c| c.remote_ip == *network))
+                        .map_or(0, |config| config.remote_as),
+                },
+                // Locally originated routes
+                EntrySource::Api | EntrySource::Config => MrtPeer {
+                    router_id: collector_id,
+                    addr: Ipv4Addr::UNSPECIFIED.into(),
+                    asn: sessions.config.default_as,
+                },
+            };
+            let index = dump.peer_index(peer);
+            dump.add_route(index, entry);
+        }
+        let mut buf: Vec<u8> = Vec::with_capacity(4096);
+        dump.encode(Utc::now(), &mut buf)
+            .and_then(|_| std::fs::write(&path, buf))
+            .map_err(|e| Error::Custom(format!("Error writing {}: {}", path, e)))?;
+        info!("Wrote {} prefixes to {}", dump.prefix_count(), path);
+        Ok(())
+    }
+
+    async fn shutdown(&self, message: Option<String>) -> RpcResult<()> {
+        let notified = self
+            .inner
+            .sessions
+            .read()
+            .await
+            .shutdown_sessions(message.as_deref())
+            .await;
+        info!("Shutdown requested, notified {} peers", notified.len());
+        // Stop the daemon after a short delay to flush NOTIFICATIONs & return this response
+        let server = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(SHUTDOWN_DELAY).await;
+            server.stop();
+        });
+        Ok(())
+    }
+
+    async fn reload(&self) -> RpcResult<ReloadSummary> {
+        self.reload_config().await.map_err(Error::Custom)
     }
 }
 
@@ -162,4 +373,424 @@ impl Server {
             .start(server.into_rpc())?;
         Ok(handle)
     }
+
+    /// Advertise a route to peers (like the `advertise_route` API, without JSON-RPC)
+    pub async fn advertise_route(&self, route: RouteSpec) -> Result<LearnedRoute, ParseError> {
+        let (family, attributes, nlri) = parse_route_spec(&route)?;
+        let mut rib = self.inner.rib.write().await;
+        let (group_key, entry) = rib.insert_from_api(family, attributes, nlri);
+        let mut route = entry_to_route(entry);
+        route.attr_group = Some(group_key);
+        Ok(route)
+    }
+
+    /// Advertise a flow to peers (like the `advertise_flow` API, without JSON-RPC)
+    pub async fn advertise_flow(&self, flow: FlowSpec) -> Result<LearnedRoute, ParseError> {
+        let (family, attributes, nlri) = parse_flow_spec(&flow)?;
+        let mut rib = self.inner.rib.write().await;
+        let (group_key, entry) = rib.insert_from_api(family, attributes, nlri);
+        let mut route = entry_to_route(entry);
+        route.attr_group = Some(group_key);
+        Ok(route)
+    }
+
+    /// Withdraw routes advertised with `advertise_route`/`advertise_flow` (or the API)
+    /// for a prefix, returning the routes removed (if any)
+    pub async fn withdraw_route(&self, prefix: IpNetwork) -> Vec<LearnedRoute> {
+        let removed = self.inner.rib.write().await.remove_from_api(prefix);
+        removed.into_iter().map(entry_to_route).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+    use tokio::sync::watch;
+
+    use bgp_rs::{NLRIEncoding, Origin, PathAttribute, Update, AFI, SAFI};
+
+    use crate::api::rpc::SpecAttributes;
+    use crate::rib::session::SessionRoutes;
+    use crate::rib::{Families, Family};
+
+    async fn server(peers: &str) -> Server {
+        let config = format!(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            {}
+            "#,
+            peers
+        );
+        let config = Arc::new(crate::config::from_str(&config).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (_tx, rx) = watch::channel(config.clone());
+        Server::new(config, vec![listener], rx).unwrap()
+    }
+
+    fn route(prefix: &str, local_pref: u32) -> RouteSpec {
+        RouteSpec {
+            prefix: prefix.parse().unwrap(),
+            next_hop: "127.0.0.1".parse().unwrap(),
+            attributes: SpecAttributes {
+                local_pref: Some(local_pref),
+                ..Default::default()
+            },
+            track_peer: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let mut server = server("").await;
+        server
+            .shutdown(Some("Maintenance".to_string()))
+            .await
+            .unwrap();
+        // The daemon loop stops once the shutdown delay has passed
+        tokio::time::timeout(SHUTDOWN_DELAY * 4, server.run())
+            .await
+            .expect("Server stopped")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let peers = |peers: &[(&str, u32)]| -> String {
+            let mut config = "router_id = \"1.1.1.1\"\ndefault_as = 65000\n".to_string();
+            for (remote_ip, remote_as) in peers {
+                config.push_str(&format!(
+                    "[[peers]]\nremote_ip = \"{}\"\nremote_as = {}\n",
+                    remote_ip, remote_as
+                ));
+            }
+            config
+        };
+        let path = std::env::temp_dir().join(format!("bgpd-reload-{}.toml", std::process::id()));
+        std::fs::write(&path, peers(&[("127.0.0.2", 65001), ("127.0.0.3", 65002)])).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let config = Arc::new(crate::config::from_file(&path).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (tx, rx) = watch::channel(config.clone());
+        let server = Server::new(config, vec![listener], rx.clone()).unwrap();
+        // No config file to reload yet
+        assert!(server.reload().await.is_err());
+        server.set_config_source(path.clone(), tx);
+
+        std::fs::write(&path, peers(&[("127.0.0.2", 65005), ("127.0.0.4", 65004)])).unwrap();
+        let summary = server.reload().await.unwrap();
+        assert_eq!(summary.peers_added, vec!["127.0.0.4/32"]);
+        assert_eq!(summary.peers_removed, vec!["127.0.0.3/32"]);
+        assert_eq!(summary.peers_changed, vec!["127.0.0.2/32"]);
+        let remote_ips: Vec<_> = rx.borrow().peers.iter().map(|p| p.remote_ip).collect();
+        assert_eq!(remote_ips.len(), 2);
+
+        // Unchanged config
+        let summary = server.reload().await.unwrap();
+        assert!(summary.peers_added.is_empty());
+        assert!(summary.peers_removed.is_empty());
+        assert!(summary.peers_changed.is_empty());
+
+        // Invalid config is not applied
+        std::fs::write(&path, "router_id = ").unwrap();
+        assert!(server.reload().await.is_err());
+        assert_eq!(rx.borrow().peers.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_static_routes() {
+        let config = |prefixes: &[&str]| -> String {
+            let mut config = "router_id = \"1.1.1.1\"\ndefault_as = 65000\n\
+                [[peers]]\nremote_ip = \"127.0.0.2\"\nremote_as = 65001\n"
+                .to_string();
+            for prefix in prefixes {
+                config.push_str(&format!(
+                    "[[peers.static_routes]]\nprefix = \"{}\"\nnext_hop = \"127.0.0.1\"\n",
+                    prefix
+                ));
+            }
+            config
+        };
+        let path = std::env::temp_dir().join(format!("bgpd-static-{}.toml", std::process::id()));
+        std::fs::write(&path, config(&["10.0.0.0/24", "10.0.1.0/24"])).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let current = Arc::new(crate::config::from_file(&path).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (tx, rx) = watch::channel(current.clone());
+        let server = Server::new(current, vec![listener], rx).unwrap();
+        server.set_config_source(path.clone(), tx);
+
+        // Both routes were advertised to a peer
+        let peer: IpAddr = "127.0.0.3".parse().unwrap();
+        let family = Family::new(AFI::IPV4, SAFI::Unicast);
+        let mut routes = SessionRoutes::new(Families::new(vec![family]));
+        routes.insert_routes(server.inner.rib.read().await.get_routes_for_peer(peer));
+        for entry in routes.pending() {
+            routes.mark_advertised(&entry);
+        }
+        assert_eq!(routes.advertised().len(), 2);
+
+        std::fs::write(&path, config(&["10.0.0.0/24"])).unwrap();
+        let summary = server.reload().await.unwrap();
+        assert_eq!(summary.peers_changed, vec!["127.0.0.2/32"]);
+        assert_eq!(summary.static_routes_removed, 1);
+        assert_eq!(summary.static_routes_added, 0);
+        routes.insert_routes(server.inner.rib.read().await.get_routes_for_peer(peer));
+        let withdrawn = routes.take_withdrawn(&[]);
+        assert_eq!(withdrawn.len(), 1);
+        assert_eq!(format!("{:?}", withdrawn[0].update.nlri), "IP(10.0.1.0/24)");
+        assert!(routes.pending().is_empty());
+
+        // Re-added routes are advertised again
+        std::fs::write(&path, config(&["10.0.0.0/24", "10.0.1.0/24"])).unwrap();
+        let summary = server.reload().await.unwrap();
+        assert_eq!(summary.static_routes_added, 1);
+        assert_eq!(summary.static_routes_removed, 0);
+        assert_eq!(server.inner.rib.read().await.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_show_summary() {
+        let server = server(
+            r#"
+            [[peers]]
+            remote_ip = "127.0.0.2"
+            remote_as = 65001
+            [[peers]]
+            remote_ip = "127.0.0.3"
+            remote_as = 65002
+            "#,
+        )
+        .await;
+        server
+            .advertise_route(route("10.0.0.0/24", 100))
+            .await
+            .unwrap();
+        server
+            .advertise_route(route("10.0.1.0/24", 200))
+            .await
+            .unwrap();
+        let mut v6 = route("2001:db8::/32", 100);
+        v6.next_hop = "::1".parse().unwrap();
+        server.advertise_route(v6).await.unwrap();
+
+        let summary = server.show_summary().await.unwrap();
+        assert_eq!(summary.router_id.to_string(), "1.1.1.1");
+        assert_eq!(summary.local_asn, 65000);
+        assert_eq!(summary.peers_configured, 2);
+        assert_eq!(summary.peers_established, 0);
+        assert_eq!(summary.rib_entries, 3);
+        let families: Vec<_> = summary
+            .families
+            .iter()
+            .map(|f| format!("{} {} {}", f.afi, f.safi, f.entries))
+            .collect();
+        assert_eq!(families, vec!["IPv4 Unicast 2", "IPv6 Unicast 1"]);
+    }
+
+    #[tokio::test]
+    async fn test_advertise_route_attr_group() {
+        let server = server("").await;
+        let first = server
+            .advertise_route(route("10.0.0.0/24", 100))
+            .await
+            .unwrap();
+        let second = server
+            .advertise_route(route("10.0.1.0/24", 100))
+            .await
+            .unwrap();
+        let other = server
+            .advertise_route(route("10.0.2.0/24", 200))
+            .await
+            .unwrap();
+        assert!(first.attr_group.is_some());
+        assert_eq!(first.attr_group, second.attr_group);
+        assert_ne!(first.attr_group, other.attr_group);
+    }
+
+    #[tokio::test]
+    async fn test_tag_community() {
+        let server = server(
+            r#"
+            [[peers]]
+            remote_ip = "127.0.0.2"
+            remote_as = 65001
+            tag_community = "65000:5"
+            "#,
+        )
+        .await;
+        let peer = "127.0.0.2".parse().unwrap();
+        let config = server.inner.sessions.read().await.get_peer_configs()[0].clone();
+        let update = Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+                PathAttribute::COMMUNITY(vec![(65001 << 16) + 100]),
+                PathAttribute::LARGE_COMMUNITY(vec![(65001, 1, 2)]),
+            ],
+            announced_routes: vec![NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into())],
+        };
+        server
+            .inner
+            .rib
+            .write()
+            .await
+            .update_from_peer(peer, &config, update)
+            .unwrap();
+
+        let stored = server.inner.rib.read().await.get_routes_from_peer(peer);
+        assert_eq!(
+            stored[0].update.attributes.communities.standard(),
+            vec![(65001 << 16) + 100, (65000 << 16) + 5]
+        );
+        let learned = server.show_routes_learned(None, None, None).await.unwrap();
+        assert_eq!(
+            learned[0].communities,
+            vec!["65001:100", "65000:5", "65001:1:2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_show_rib() {
+        let server = server(
+            r#"
+            [[peers]]
+            remote_ip = "127.0.0.0/24"
+            remote_as = 65001
+            "#,
+        )
+        .await;
+        let config = server.inner.sessions.read().await.get_peer_configs()[0].clone();
+        let update = |peer: IpAddr, prefix: &str, local_pref: u32| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath {
+                    segments: vec![bgp_rs::Segment::AS_SEQUENCE(vec![65001])],
+                }),
+                PathAttribute::NEXT_HOP(peer),
+                PathAttribute::LOCAL_PREF(local_pref),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        };
+        let (low, high): (IpAddr, IpAddr) =
+            ("127.0.0.2".parse().unwrap(), "127.0.0.3".parse().unwrap());
+        {
+            let mut rib = server.inner.rib.write().await;
+            rib.update_from_peer(low, &config, update(low, "10.0.0.0", 100))
+                .unwrap();
+            rib.update_from_peer(high, &config, update(high, "10.0.0.0", 200))
+                .unwrap();
+            rib.update_from_peer(low, &config, update(low, "10.1.0.0", 100))
+                .unwrap();
+        }
+        server
+            .advertise_route(route("9.9.9.0/24", 100))
+            .await
+            .unwrap();
+
+        let paths = server.show_rib(None).await.unwrap();
+        let summary: Vec<_> = paths
+            .iter()
+            .map(|p| (p.route.prefix.as_str(), p.route.source.as_str(), p.best))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("9.9.9.0/24", "API", true),
+                ("10.0.0.0/24", "127.0.0.3", true),
+                ("10.0.0.0/24", "127.0.0.2", false),
+                ("10.1.0.0/24", "127.0.0.2", true),
+            ]
+        );
+
+        let paths = server.show_rib("10.0.0.0/16".parse().ok()).await.unwrap();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.iter().all(|p| p.route.prefix == "10.0.0.0/24"));
+        assert!(server
+            .show_rib("10.0.0.0/25".parse().ok())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dump_rib_mrt() {
+        let server = server("").await;
+        server
+            .advertise_route(route("10.0.0.0/24", 100))
+            .await
+            .unwrap();
+        let mut v6 = route("3001:100::/64", 100);
+        v6.next_hop = "3001:1::1".parse().unwrap();
+        server.advertise_route(v6).await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("bgpd-rib-{}.mrt", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        server.dump_rib_mrt(path.clone()).await.unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // (Type, Subtype) from each MRT header
+        let mut headers = vec![];
+        let mut offset = 0;
+        while offset < data.len() {
+            let header = &data[offset..offset + 12];
+            let length = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+            headers.push((
+                u16::from_be_bytes([header[4], header[5]]),
+                u16::from_be_bytes([header[6], header[7]]),
+            ));
+            offset += 12 + length as usize;
+        }
+        assert_eq!(headers, vec![(13, 1), (13, 2), (13, 4)]);
+
+        assert!(server
+            .dump_rib_mrt("/nonexistent/rib.mrt".to_string())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_show_routes_by_next_hop() {
+        let server = server("").await;
+        let mut gateway = route("10.0.0.0/24", 100);
+        gateway.next_hop = "172.16.20.2".parse().unwrap();
+        server.advertise_route(gateway).await.unwrap();
+        server
+            .advertise_route(route("10.0.1.0/24", 100))
+            .await
+            .unwrap();
+        let flow = FlowSpec::new(
+            1,
+            "redirect 6:302".to_string(),
+            vec!["destination 10.0.0.0/24".to_string()],
+        );
+        server.advertise_flow(flow).await.unwrap();
+        assert_eq!(
+            server
+                .show_routes_learned(None, None, None)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+
+        let next_hop = "172.16.20.2".parse().ok();
+        let learned = server
+            .show_routes_learned(None, None, next_hop)
+            .await
+            .unwrap();
+        assert_eq!(learned.len(), 1);
+        assert_eq!(learned[0].prefix, "10.0.0.0/24");
+        assert_eq!(learned[0].next_hop, next_hop);
+    }
 }
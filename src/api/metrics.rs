@@ -0,0 +1,437 @@
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use super::rpc::{ApiServer, PeerSummary};
+use crate::handler::Server;
+use crate::rib::CacheStats;
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+/// Largest request head we'll read before giving up
+const MAX_REQUEST_SIZE: usize = 8192;
+/// Seconds a client has to send the request head before the connection is closed
+const REQUEST_TIMEOUT: u64 = 5;
+
+/// Exposition format for the metrics endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricsFormat {
+    /// Prometheus text format (0.0.4)
+    Prometheus,
+    /// OpenMetrics text format (1.0.0)
+    /// Only counters & gauges are exported, there are no histograms (or exemplars)
+    OpenMetrics,
+}
+
+impl MetricsFormat {
+    /// Pick a format based on the request `Accept` header
+    /// OpenMetrics is only used when explicitly requested
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        match accept {
+            Some(accept)
+                if accept
+                    .split(',')
+                    .any(|t| t.trim().starts_with("application/openmetrics-text")) =>
+            {
+                MetricsFormat::OpenMetrics
+            }
+            _ => MetricsFormat::Prometheus,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            MetricsFormat::Prometheus => PROMETHEUS_CONTENT_TYPE,
+            MetricsFormat::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+/// A metric family and its samples (label values, value)
+#[derive(Debug)]
+struct Metric {
+    name: &'static str,
+    help: &'static str,
+    unit: Option<&'static str>,
+    kind: MetricType,
+    samples: Vec<(Vec<(&'static str, String)>, f64)>,
+}
+
+impl Metric {
+    fn new(name: &'static str, help: &'static str, kind: MetricType) -> Self {
+        Self {
+            name,
+            help,
+            unit: None,
+            kind,
+            samples: vec![],
+        }
+    }
+
+    fn with_unit(mut self, unit: &'static str) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+
+    fn render(&self, format: MetricsFormat, out: &mut String) {
+        let type_name = match self.kind {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+        };
+        // Counters samples are always suffixed with `_total`, but the OpenMetrics
+        // family name (used in metadata) must not be
+        let sample_name = match self.kind {
+            MetricType::Counter => format!("{}_total", self.name),
+            MetricType::Gauge => self.name.to_string(),
+        };
+        let family_name = match format {
+            MetricsFormat::OpenMetrics => self.name.to_string(),
+            MetricsFormat::Prometheus => sample_name.clone(),
+        };
+        let _ = writeln!(out, "# HELP {} {}", family_name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", family_name, type_name);
+        if let (MetricsFormat::OpenMetrics, Some(unit)) = (format, self.unit) {
+            let _ = writeln!(out, "# UNIT {} {}", family_name, unit);
+        }
+        for (labels, value) in &self.samples {
+            let labels = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+                .collect::<Vec<_>>()
+                .join(",");
+            if labels.is_empty() {
+                let _ = writeln!(out, "{} {}", sample_name, value);
+            } else {
+                let _ = writeln!(out, "{}{{{}}} {}", sample_name, labels, value);
+            }
+        }
+    }
+}
+
+/// Build metric families from peer summaries, the total RIB size, and PathAttributeCache stats
+fn collect_metrics(peers: &[PeerSummary], rib_routes: usize, cache: CacheStats) -> Vec<Metric> {
+    let mut received = Metric::new(
+        "bgpd_peer_messages_received",
+        "BGP messages received from peer",
+        MetricType::Counter,
+    );
+    let mut sent = Metric::new(
+        "bgpd_peer_messages_sent",
+        "BGP messages sent to peer",
+        MetricType::Counter,
+    );
+    let mut prefixes = Metric::new(
+        "bgpd_peer_prefixes_received",
+        "Prefixes currently received from peer",
+        MetricType::Gauge,
+    );
+    let mut established = Metric::new(
+        "bgpd_peer_established",
+        "Whether the peer session is Established",
+        MetricType::Gauge,
+    );
+    let mut uptime = Metric::new(
+        "bgpd_peer_uptime_seconds",
+        "Time since the peer session was started",
+        MetricType::Gauge,
+    )
+    .with_unit("seconds");
+    let now = Utc::now().timestamp();
+    for peer in peers {
+        let labels = || vec![("peer", peer.peer.clone())];
+        established.samples.push((
+            labels(),
+            if peer.state == "Established" {
+                1.0
+            } else {
+                0.0
+            },
+        ));
+        if let Some(count) = peer.msg_received {
+            received.samples.push((labels(), count as f64));
+        }
+        if let Some(count) = peer.msg_sent {
+            sent.samples.push((labels(), count as f64));
+        }
+        if let Some(count) = peer.prefixes_received {
+            prefixes.samples.push((labels(), count as f64));
+        }
+        if let Some(connect_time) = peer.connect_time {
+            uptime
+                .samples
+                .push((labels(), (now - connect_time).max(0) as f64));
+        }
+    }
+    let mut routes = Metric::new(
+        "bgpd_rib_routes",
+        "Routes stored in the RIB",
+        MetricType::Gauge,
+    );
+    routes.samples.push((vec![], rib_routes as f64));
+    let mut groups = Metric::new(
+        "bgpd_rib_attribute_groups",
+        "Distinct path attribute groups in the RIB cache",
+        MetricType::Gauge,
+    );
+    groups.samples.push((vec![], cache.groups as f64));
+    let mut hits = Metric::new(
+        "bgpd_rib_attribute_cache_hits",
+        "Path attributes matching an existing cached group",
+        MetricType::Counter,
+    );
+    hits.samples.push((vec![], cache.hits as f64));
+    let mut misses = Metric::new(
+        "bgpd_rib_attribute_cache_misses",
+        "Path attributes creating a new cached group",
+        MetricType::Counter,
+    );
+    misses.samples.push((vec![], cache.misses as f64));
+    let mut hit_rate = Metric::new(
+        "bgpd_rib_attribute_cache_hit_ratio",
+        "Ratio of path attribute cache hits",
+        MetricType::Gauge,
+    )
+    .with_unit("ratio");
+    hit_rate.samples.push((vec![], cache.hit_rate()));
+    vec![
+        received,
+        sent,
+        prefixes,
+        established,
+        uptime,
+        routes,
+        groups,
+        hits,
+        misses,
+        hit_rate,
+    ]
+}
+
+/// Render metrics in the given format
+fn render_metrics(metrics: &[Metric], format: MetricsFormat) -> String {
+    let mut out = String::with_capacity(1024);
+    for metric in metrics {
+        metric.render(format, &mut out);
+    }
+    if format == MetricsFormat::OpenMetrics {
+        out.push_str("# EOF\n");
+    }
+    out
+}
+
+/// Escape a label value for the text formats (backslash, double-quote & line feed)
+fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Find the value of a header (case-insensitive) in a raw HTTP request head
+fn find_header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+impl Server {
+    /// Serve metrics over HTTP on `GET /metrics`
+    /// Content is negotiated between Prometheus & OpenMetrics formats using the `Accept` header
+    pub async fn serve_metrics(&self, socket: SocketAddr) -> std::io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(socket).await?;
+        info!("Starting metrics server on {}...", socket);
+        let server = self.clone();
+        Ok(tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, remote)) => {
+                        // Each connection in its own task, so a slow client doesn't block others
+                        let server = server.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = server.handle_metrics_request(stream).await {
+                                debug!("Error serving metrics to {}: {}", remote, err);
+                            }
+                        });
+                    }
+                    Err(err) => warn!("Error accepting metrics connection: {}", err),
+                }
+            }
+        }))
+    }
+
+    async fn handle_metrics_request(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut buf = Vec::with_capacity(1024);
+        let read_head = async {
+            let mut chunk = [0u8; 1024];
+            while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < MAX_REQUEST_SIZE {
+                let read = stream.read(&mut chunk).await?;
+                if read == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..read]);
+            }
+            Ok::<_, std::io::Error>(())
+        };
+        tokio::time::timeout(Duration::from_secs(REQUEST_TIMEOUT), read_head)
+            .await
+            .map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "Request timed out")
+            })??;
+        let request = String::from_utf8_lossy(&buf);
+        let path = request
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .unwrap_or("/");
+        let response = if path == "/metrics" {
+            let format = MetricsFormat::from_accept(find_header(&request, "Accept"));
+            let body = self.render_metrics(format).await;
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                format.content_type(),
+                body.len(),
+                body
+            )
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        };
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    async fn render_metrics(&self, format: MetricsFormat) -> String {
+        let peers = ApiServer::show_peers(self).await.unwrap_or_default();
+        let (rib_routes, cache) = {
+            let rib = self.inner.rib.read().await;
+            (rib.len(), rib.cache_stats())
+        };
+        render_metrics(&collect_metrics(&peers, rib_routes, cache), format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> PeerSummary {
+        PeerSummary {
+            peer: "127.0.0.2".to_string(),
+            enabled: true,
+            router_id: Some("2.2.2.2".parse().unwrap()),
+            remote_asn: 65000,
+            local_asn: 65000,
+            msg_received: Some(10),
+            msg_sent: Some(12),
+            connect_time: Some(Utc::now().timestamp()),
+            uptime: Some("00:00:00".to_string()),
+            state: "Established".to_string(),
+            prefixes_received: Some(4),
+        }
+    }
+
+    fn cache() -> CacheStats {
+        CacheStats {
+            groups: 2,
+            hits: 3,
+            misses: 1,
+        }
+    }
+
+    #[test]
+    fn test_content_negotiation() {
+        assert_eq!(MetricsFormat::from_accept(None), MetricsFormat::Prometheus);
+        assert_eq!(
+            MetricsFormat::from_accept(Some("text/plain;version=0.0.4")),
+            MetricsFormat::Prometheus
+        );
+        let format = MetricsFormat::from_accept(Some(
+            "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5",
+        ));
+        assert_eq!(format, MetricsFormat::OpenMetrics);
+        assert!(format
+            .content_type()
+            .starts_with("application/openmetrics-text"));
+    }
+
+    #[test]
+    fn test_render_openmetrics() {
+        let metrics = collect_metrics(&[peer()], 6, cache());
+        let output = render_metrics(&metrics, MetricsFormat::OpenMetrics);
+        assert!(output.contains("# TYPE bgpd_peer_messages_received counter\n"));
+        assert!(output.contains("bgpd_peer_messages_received_total{peer=\"127.0.0.2\"} 10\n"));
+        assert!(output.contains("# UNIT bgpd_peer_uptime_seconds seconds\n"));
+        assert!(output.contains("bgpd_peer_prefixes_received{peer=\"127.0.0.2\"} 4\n"));
+        assert!(output.contains("bgpd_rib_routes 6\n"));
+        assert!(output.contains("bgpd_rib_attribute_groups 2\n"));
+        assert!(output.contains("bgpd_rib_attribute_cache_hits_total 3\n"));
+        assert!(output.contains("bgpd_rib_attribute_cache_hit_ratio 0.75\n"));
+        assert!(output.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = collect_metrics(&[peer()], 6, cache());
+        let output = render_metrics(&metrics, MetricsFormat::Prometheus);
+        assert!(output.contains("# TYPE bgpd_peer_messages_sent_total counter\n"));
+        assert!(output.contains("bgpd_peer_messages_sent_total{peer=\"127.0.0.2\"} 12\n"));
+        assert!(!output.contains("# UNIT"));
+        assert!(!output.contains("# EOF"));
+    }
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape_label_value("127.0.0.2"), "127.0.0.2");
+        assert_eq!(escape_label_value("a\\b \"c\"\nd"), "a\\\\b \\\"c\\\"\\nd");
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        use std::sync::Arc;
+        use tokio::sync::watch;
+
+        let config = crate::config::from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            "#,
+        )
+        .unwrap();
+        let config = Arc::new(config);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (_tx, rx) = watch::channel(config.clone());
+        let server = Server::new(config, vec![listener], rx).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // Client connects, but never sends a request
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio::time::pause();
+        let err = server.handle_metrics_request(stream).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
@@ -1,4 +1,5 @@
 mod handler;
+mod metrics;
 mod peers;
 mod routes;
 /// RPC Definition for the API
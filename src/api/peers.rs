@@ -1,15 +1,18 @@
 use std::sync::Arc;
 
 use bgp_rs::Capabilities;
+use chrono::Utc;
 
-use super::rpc::{PeerDetail, PeerSummary};
+use super::rpc::{CapabilityInfo, MessageStat, PeerDetail, PeerSummary};
 use crate::config::PeerConfig;
-use crate::session::{Session, SessionState};
+use crate::session::{keepalive_interval, PeerHistory, Session, SessionState, MESSAGE_TYPES};
 use crate::utils::{format_time_as_elapsed, get_host_address};
 
+/// `idle_state` is the Poller's FSM state for peers without an active session
 pub fn peer_to_summary(
     config: Arc<PeerConfig>,
     session: Option<&Session>,
+    idle_state: Option<SessionState>,
     prefixes_received: Option<u64>,
 ) -> PeerSummary {
     PeerSummary {
@@ -31,6 +34,8 @@ pub fn peer_to_summary(
         state: session.map(|s| s.state.to_string()).unwrap_or_else(|| {
             if !config.enabled {
                 "Disabled".to_string()
+            } else if let Some(state) = idle_state {
+                state.to_string()
             } else if !config.passive {
                 SessionState::Active.to_string()
             } else {
@@ -44,21 +49,38 @@ pub fn peer_to_summary(
 pub fn peer_to_detail(
     config: Arc<PeerConfig>,
     session: Option<&Session>,
+    idle_state: Option<SessionState>,
     prefixes_received: Option<u64>,
+    prefixes_dropped_loop: Option<u64>,
+    history: Option<PeerHistory>,
 ) -> PeerDetail {
+    let history = history.unwrap_or_default();
+    let last_notification = session
+        .and_then(|s| s.last_notification.as_ref())
+        .or(history.last_notification.as_ref())
+        .map(|n| n.to_string());
     let capabilities = session
         .map(|s| capabilities_export(&s.capabilities))
         .unwrap_or_else(|| config.families.iter().map(|f| f.to_string()).collect());
     PeerDetail {
-        summary: peer_to_summary(config.clone(), session, prefixes_received),
+        summary: peer_to_summary(config.clone(), session, idle_state, prefixes_received),
         capabilities,
+        passive: config.passive,
+        prefixes_dropped_loop,
+        prefix_limit_warning: session.map_or(false, |s| s.prefix_limit.warning()),
+        last_error: history.last_error,
+        last_notification,
         hold_timer: session
             .map(|s| s.hold_timer.hold_timer)
             .unwrap_or(config.hold_timer),
         hold_timer_interval: session
             .map(|s| s.hold_timer.interval)
-            .unwrap_or(config.hold_timer / 3),
+            .unwrap_or_else(|| keepalive_interval(config.hold_timer, config.min_keepalive)),
         hold_time: session.map(|s| s.hold_timer.to_string()),
+        configured_hold_time: config.hold_timer,
+        negotiated_hold_time: session
+            .filter(|s| s.state == SessionState::Established)
+            .map(|s| s.hold_timer.hold_timer),
         last_received: session.map(|s| format_time_as_elapsed(s.hold_timer.last_received)),
         last_sent: session.map(|s| format_time_as_elapsed(s.hold_timer.last_sent)),
         tcp_connection: session.map(|s| {
@@ -74,9 +96,124 @@ pub fn peer_to_detail(
                     .unwrap_or_else(|_| "---".to_string()),
             )
         }),
+        message_stats: session.map(message_stats).unwrap_or_default(),
+        prefix_stats: session.map(prefix_stats).unwrap_or_default(),
+        bytes_sent: session.map(|s| s.protocol.codec().bytes_sent()),
+        bytes_received: session.map(|s| s.protocol.codec().bytes_received()),
+        updates_per_second: session.map(updates_per_second),
     }
 }
 
+/// UPDATEs sent & received per second since the session connected
+fn updates_per_second(session: &Session) -> f64 {
+    let updates = session.counts.by_type("UPDATE");
+    let uptime = Utc::now().signed_duration_since(session.connect_time);
+    // At least a second, to avoid inflated rates for new sessions
+    let seconds = (uptime.num_milliseconds() as f64 / 1000.0).max(1.0);
+    (updates.sent + updates.received) as f64 / seconds
+}
+
+/// Messages sent & received by type, E.g. "OPEN"
+fn message_stats(session: &Session) -> Vec<MessageStat> {
+    MESSAGE_TYPES
+        .iter()
+        .map(|message_type| {
+            let count = session.counts.by_type(message_type);
+            MessageStat {
+                name: message_type.to_string(),
+                sent: count.sent,
+                received: count.received,
+            }
+        })
+        .collect()
+}
+
+/// NLRI announced & withdrawn in UPDATEs
+fn prefix_stats(session: &Session) -> Vec<MessageStat> {
+    let announced = session.counts.announced();
+    let withdrawn = session.counts.withdrawn();
+    vec![
+        MessageStat {
+            name: "Announced".to_string(),
+            sent: announced.sent,
+            received: announced.received,
+        },
+        MessageStat {
+            name: "Withdrawn".to_string(),
+            sent: withdrawn.sent,
+            received: withdrawn.received,
+        },
+    ]
+}
+
+/// Compare capabilities advertised locally & by the peer (once its OPEN is received)
+/// with those negotiated for the session
+pub fn capabilities_info(session: &Session) -> Vec<CapabilityInfo> {
+    let mut capabilities = compare_capabilities(
+        &session.local_capabilities,
+        session.received_capabilities.as_ref(),
+        &session.capabilities,
+    );
+    // Not tracked in bgp-rs Capabilities
+    capabilities.push(CapabilityInfo {
+        name: "Extended Message".to_string(),
+        local: session.config.extended_messages,
+        remote: session.peer_extended_messages,
+        negotiated: session.extended_messages,
+    });
+    capabilities.push(CapabilityInfo {
+        name: "BGP Role".to_string(),
+        local: session.config.role.is_some(),
+        remote: session.peer_role.is_some(),
+        negotiated: session.config.role.is_some() && session.peer_role.is_some(),
+    });
+    capabilities
+}
+
+fn compare_capabilities(
+    local: &Capabilities,
+    remote: Option<&Capabilities>,
+    negotiated: &Capabilities,
+) -> Vec<CapabilityInfo> {
+    // Session capabilities are only negotiated after receiving the peer's OPEN
+    let negotiated = remote.map(|_| negotiated);
+    let info = |name: String, check: &dyn Fn(&Capabilities) -> bool| CapabilityInfo {
+        name,
+        local: check(local),
+        remote: remote.map_or(false, check),
+        negotiated: negotiated.map_or(false, check),
+    };
+
+    let mut families: Vec<_> = local
+        .MP_BGP_SUPPORT
+        .iter()
+        .chain(remote.iter().flat_map(|c| c.MP_BGP_SUPPORT.iter()))
+        .copied()
+        .collect();
+    families.sort_by_key(|(afi, safi)| (*afi as u16, *safi as u8));
+    families.dedup();
+    let mut capabilities: Vec<_> = families
+        .into_iter()
+        .map(|family| {
+            info(format!("Address family {} {}", family.0, family.1), &|c| {
+                c.MP_BGP_SUPPORT.contains(&family)
+            })
+        })
+        .collect();
+    capabilities.extend(vec![
+        info("Route Refresh".to_string(), &|c| c.ROUTE_REFRESH_SUPPORT),
+        info("Enhanced Route Refresh".to_string(), &|c| {
+            c.ENHANCED_ROUTE_REFRESH_SUPPORT
+        }),
+        info("4-octet ASN".to_string(), &|c| c.FOUR_OCTET_ASN_SUPPORT),
+        info("Graceful Restart".to_string(), &|c| {
+            !c.GRACEFUL_RESTART_SUPPORT.is_empty()
+        }),
+        info("ADD-PATH".to_string(), &|c| !c.ADD_PATH_SUPPORT.is_empty()),
+    ]);
+    capabilities
+}
+
 fn capabilities_export(capabilities: &Capabilities) -> Vec<String> {
     let mut caps: Vec<String> = vec![];
     for fam in &capabilities.MP_BGP_SUPPORT {
@@ -85,5 +222,123 @@ fn capabilities_export(capabilities: &Capabilities) -> Vec<String> {
     if capabilities.ROUTE_REFRESH_SUPPORT {
         caps.push("Route Refresh".to_string());
     }
+    if !capabilities.GRACEFUL_RESTART_SUPPORT.is_empty() {
+        caps.push("Graceful Restart".to_string());
+    }
     caps
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::{AFI, SAFI};
+
+    #[test]
+    fn test_compare_capabilities() {
+        let families = |families: &[(AFI, SAFI)]| families.iter().copied().collect();
+        let local = Capabilities {
+            MP_BGP_SUPPORT: families(&[(AFI::IPV4, SAFI::Unicast), (AFI::IPV6, SAFI::Unicast)]),
+            FOUR_OCTET_ASN_SUPPORT: true,
+            ..Default::default()
+        };
+        let remote = Capabilities {
+            MP_BGP_SUPPORT: families(&[(AFI::IPV4, SAFI::Unicast), (AFI::IPV4, SAFI::Flowspec)]),
+            ROUTE_REFRESH_SUPPORT: true,
+            FOUR_OCTET_ASN_SUPPORT: true,
+            ..Default::default()
+        };
+        let negotiated = Capabilities {
+            MP_BGP_SUPPORT: families(&[(AFI::IPV4, SAFI::Unicast)]),
+            FOUR_OCTET_ASN_SUPPORT: true,
+            ..Default::default()
+        };
+
+        let find = |capabilities: &[CapabilityInfo], name: &str| {
+            let info = capabilities.iter().find(|c| c.name == name).unwrap();
+            (info.local, info.remote, info.negotiated)
+        };
+        let capabilities = compare_capabilities(&local, Some(&remote), &negotiated);
+        let v4 = find(&capabilities, "Address family IPv4 Unicast");
+        assert_eq!(v4, (true, true, true));
+        let v6 = find(&capabilities, "Address family IPv6 Unicast");
+        assert_eq!(v6, (true, false, false));
+        let flowspec = find(&capabilities, "Address family IPv4 Flowspec");
+        assert_eq!(flowspec, (false, true, false));
+        assert_eq!(find(&capabilities, "Route Refresh"), (false, true, false));
+        assert_eq!(find(&capabilities, "4-octet ASN"), (true, true, true));
+
+        // Before the peer's OPEN is received, nothing is negotiated
+        let capabilities = compare_capabilities(&local, None, &local);
+        let v4 = find(&capabilities, "Address family IPv4 Unicast");
+        assert_eq!(v4, (true, false, false));
+    }
+
+    #[test]
+    fn test_idle_peer_hold_time() {
+        let config = crate::config::from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "127.0.0.2"
+            remote_as = 65001
+            hold_timer = 90
+            "#,
+        )
+        .unwrap();
+        let peer = config.peers[0].clone();
+        let detail = peer_to_detail(peer, None, Some(SessionState::Idle), None, None, None);
+        assert_eq!(detail.configured_hold_time, 90);
+        // Only negotiated once a session is Established
+        assert_eq!(detail.negotiated_hold_time, None);
+        assert_eq!(detail.hold_time, None);
+    }
+
+    #[tokio::test]
+    async fn test_prefix_limit_warning() {
+        use crate::session::MessageCodec;
+        use tokio::net::{TcpListener, TcpStream};
+        use tokio_util::codec::Framed;
+
+        let config = crate::config::from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "127.0.0.1"
+            remote_as = 65001
+            max_prefix_in = 100
+            max_prefix_in_warn_pct = 75
+            "#,
+        )
+        .unwrap();
+        let peer = config.peers[0].clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (_remote, local) = tokio::join!(
+            TcpStream::connect(listener.local_addr().unwrap()),
+            listener.accept()
+        );
+        let mut session = Session::new(
+            peer.clone(),
+            Framed::new(local.unwrap().0, MessageCodec::new()),
+        );
+        let detail = |session: &Session, received: u64| {
+            peer_to_detail(
+                peer.clone(),
+                Some(session),
+                None,
+                Some(received),
+                None,
+                None,
+            )
+        };
+
+        session.check_prefix_limit(50).unwrap();
+        assert!(!detail(&session, 50).prefix_limit_warning);
+        // Over the warning threshold, but under the limit
+        session.check_prefix_limit(80).unwrap();
+        assert!(detail(&session, 80).prefix_limit_warning);
+        assert!(session.check_prefix_limit(100).is_ok());
+        assert!(detail(&session, 100).prefix_limit_warning);
+    }
+}
@@ -1,19 +1,94 @@
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use bgp_rs::{NLRIEncoding, Segment};
+use ipnetwork::IpNetwork;
+use itertools::Itertools;
 
-use super::rpc::LearnedRoute;
-use crate::rib::ExportEntry;
-use crate::utils::{format_time_as_elapsed, u32_to_dotted};
+use super::rpc::{LearnedRoute, RibPath};
+use crate::rib::{path_rank, Community, ExportEntry};
+use crate::utils::{flowspec_match_to_string, format_time_as_elapsed, u32_to_dotted};
+
+/// Is this entry originated (last ASN in the AS_PATH) by the given ASN
+pub fn originates_from(entry: &Arc<ExportEntry>, asn: u32) -> bool {
+    entry.update.attributes.as_path.origin() == Some(asn)
+}
+
+/// Does this entry have the given next hop (IPv4-mapped IPv6 next hops match the IPv4 address)
+/// Entries without a next hop (E.g. Flowspec) never match
+pub fn has_next_hop(entry: &Arc<ExportEntry>, next_hop: IpAddr) -> bool {
+    let unmapped = |addr: IpAddr| match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+        addr => addr,
+    };
+    entry
+        .update
+        .attributes
+        .next_hop
+        .map_or(false, |nh| unmapped(nh) == unmapped(next_hop))
+}
+
+/// Is this entry a prefix within (or equal to) the given network
+/// Entries without a prefix (E.g. Flowspec) never match
+pub fn within_prefix(entry: &Arc<ExportEntry>, network: IpNetwork) -> bool {
+    match &entry.update.nlri {
+        NLRIEncoding::IP(prefix) | NLRIEncoding::IP_WITH_PATH_ID((prefix, _)) => {
+            let (addr, length): (IpAddr, u8) = prefix.into();
+            addr.is_ipv4() == network.is_ipv4()
+                && length >= network.prefix()
+                && network.contains(addr)
+        }
+        _ => false,
+    }
+}
+
+/// All paths for each prefix (grouped by family & prefix), with the best path first
+pub fn entries_to_paths(entries: Vec<Arc<ExportEntry>>) -> Vec<RibPath> {
+    let mut paths: Vec<_> = entries
+        .into_iter()
+        .map(|entry| {
+            let network: Option<(IpAddr, u8)> = match &entry.update.nlri {
+                NLRIEncoding::IP(prefix) | NLRIEncoding::IP_WITH_PATH_ID((prefix, _)) => {
+                    Some(prefix.into())
+                }
+                _ => None,
+            };
+            let rank = path_rank(&entry);
+            (network, rank, entry_to_route(entry))
+        })
+        .collect();
+    paths.sort_by(|(a_net, a_rank, a), (b_net, b_rank, b)| {
+        (&a.afi, &a.safi, a_net, &a.prefix, a_rank)
+            .cmp(&(&b.afi, &b.safi, b_net, &b.prefix, b_rank))
+    });
+    let mut output = Vec::with_capacity(paths.len());
+    let grouped = paths.into_iter().group_by(|(network, _, route)| {
+        (
+            route.afi.clone(),
+            route.safi.clone(),
+            *network,
+            route.prefix.clone(),
+        )
+    });
+    for (_, group) in &grouped {
+        for (i, (_, _, route)) in group.enumerate() {
+            output.push(RibPath {
+                route,
+                best: i == 0,
+            });
+        }
+    }
+    output
+}
 
 pub fn entry_to_route(entry: Arc<ExportEntry>) -> LearnedRoute {
     let prefix = {
         use NLRIEncoding::*;
         match &entry.update.nlri {
-            IP(prefix) => prefix.to_string(),
+            IP(prefix) | IP_WITH_PATH_ID((prefix, _)) => prefix.to_string(),
             FLOWSPEC(filters) => filters
                 .iter()
-                .map(|f| f.to_string())
+                .map(flowspec_match_to_string)
                 .collect::<Vec<_>>()
                 .join("; "),
             nlri => format!("{:?}", nlri),
@@ -27,6 +102,7 @@ pub fn entry_to_route(entry: Arc<ExportEntry>) -> LearnedRoute {
         age: format_time_as_elapsed(entry.timestamp),
         prefix,
         next_hop: entry.update.attributes.next_hop,
+        link_local_next_hop: entry.update.attributes.link_local_next_hop,
         origin: entry.update.attributes.origin.to_string(),
         as_path: entry
             .update
@@ -55,5 +131,135 @@ pub fn entry_to_route(entry: Arc<ExportEntry>) -> LearnedRoute {
             .iter()
             .map(std::string::ToString::to_string)
             .collect(),
+        communities_detail: entry
+            .update
+            .attributes
+            .communities
+            .iter()
+            .map(Community::info)
+            .collect(),
+        aggregator: entry
+            .update
+            .attributes
+            .aggregator
+            .map(|(asn, router_id)| format!("{} {}", u32_to_dotted(asn, '.'), router_id)),
+        atomic_aggregate: entry.update.attributes.atomic_aggregate,
+        attr_group: None,
+        router_id: entry.router_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::{ASPath, Origin, AFI, SAFI};
+
+    use crate::rib::{CommunityList, EntrySource, ExportedUpdate, Family, PathAttributes};
+    use crate::utils::asn_from_dotted;
+
+    fn entry_with_path(asns: Vec<u32>) -> Arc<ExportEntry> {
+        entry_with(asns, None)
+    }
+
+    fn entry_with(asns: Vec<u32>, next_hop: Option<&str>) -> Arc<ExportEntry> {
+        let attributes = PathAttributes {
+            next_hop: next_hop.map(|nh| nh.parse().unwrap()),
+            link_local_next_hop: None,
+            origin: Origin::IGP,
+            as_path: ASPath {
+                segments: vec![Segment::AS_SEQUENCE(asns)],
+            },
+            local_pref: None,
+            multi_exit_disc: None,
+            originator_id: None,
+            cluster_list: vec![],
+            atomic_aggregate: false,
+            aggregator: None,
+            communities: CommunityList(vec![]),
+        };
+        let update = ExportedUpdate {
+            family: Family::new(AFI::IPV4, SAFI::Unicast),
+            attributes: Arc::new(attributes),
+            nlri: NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into()),
+        };
+        Arc::new(ExportEntry::new(update, EntrySource::Api))
+    }
+
+    #[test]
+    fn test_originates_from() {
+        let entries = vec![
+            entry_with_path(vec![100, 200, 65000]),
+            entry_with_path(vec![65000, 300]),
+            entry_with_path(vec![100, 4259840100]),
+            entry_with_path(vec![]),
+        ];
+        let matched: Vec<_> = entries
+            .iter()
+            .filter(|e| originates_from(e, 65000))
+            .collect();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].update.attributes.as_path.origin(), Some(65000));
+
+        let dotted = asn_from_dotted("65000.100").unwrap();
+        let matched: Vec<_> = entries
+            .iter()
+            .filter(|e| originates_from(e, dotted))
+            .collect();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(
+            matched[0].update.attributes.as_path.origin(),
+            Some(4259840100)
+        );
+    }
+
+    #[test]
+    fn test_aggregate_attributes() {
+        let route = entry_to_route(entry_with_path(vec![100]));
+        assert_eq!(route.aggregator, None);
+        assert!(!route.atomic_aggregate);
+
+        let update = ExportedUpdate {
+            family: Family::new(AFI::IPV4, SAFI::Unicast),
+            attributes: Arc::new(PathAttributes {
+                next_hop: None,
+                link_local_next_hop: None,
+                origin: Origin::IGP,
+                as_path: ASPath { segments: vec![] },
+                local_pref: None,
+                multi_exit_disc: None,
+                originator_id: None,
+                cluster_list: vec![],
+                atomic_aggregate: true,
+                aggregator: Some((4259840100, "10.0.0.1".parse().unwrap())),
+                communities: CommunityList(vec![]),
+            }),
+            nlri: NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 22).into()),
+        };
+        let route = entry_to_route(Arc::new(ExportEntry::new(update, EntrySource::Api)));
+        assert_eq!(route.aggregator.as_deref(), Some("65000.100 10.0.0.1"));
+        assert!(route.atomic_aggregate);
+    }
+
+    #[test]
+    fn test_has_next_hop() {
+        let entries = vec![
+            entry_with(vec![100], Some("172.16.20.2")),
+            entry_with(vec![200], Some("::ffff:172.16.20.2")),
+            entry_with(vec![300], Some("172.16.20.3")),
+            entry_with(vec![400], Some("3001:1::1")),
+            // Flowspec routes have no next hop
+            entry_with(vec![500], None),
+        ];
+        let next_hop: IpAddr = "172.16.20.2".parse().unwrap();
+        let matched: Vec<_> = entries
+            .iter()
+            .filter(|e| has_next_hop(e, next_hop))
+            .map(|e| e.update.attributes.as_path.origin())
+            .collect();
+        assert_eq!(matched, vec![Some(100), Some(200)]);
+
+        let next_hop: IpAddr = "3001:1::1".parse().unwrap();
+        let matched = entries.iter().filter(|e| has_next_hop(e, next_hop)).count();
+        assert_eq!(matched, 1);
     }
 }
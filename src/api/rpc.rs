@@ -1,6 +1,6 @@
 #![allow(unused_variables)]
 use ipnetwork::IpNetwork;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 
 use jsonrpsee::{core::RpcResult, proc_macros::rpc};
 use serde::{self, Deserialize, Serialize};
@@ -15,16 +15,84 @@ pub trait Api {
     async fn show_routes_learned(
         &self,
         from_peer: Option<IpNetwork>,
+        origin_as: Option<String>,
+        next_hop: Option<IpAddr>,
     ) -> RpcResult<Vec<LearnedRoute>>;
+    #[method(name = "show_rib")]
+    async fn show_rib(&self, prefix: Option<IpNetwork>) -> RpcResult<Vec<RibPath>>;
     #[method(name = "show_routes_advertised")]
     async fn show_routes_advertised(
         &self,
         to_peer: Option<IpNetwork>,
+        next_hop: Option<IpAddr>,
     ) -> RpcResult<Vec<LearnedRoute>>;
     #[method(name = "advertise_route")]
     async fn advertise_route(&self, route: RouteSpec) -> RpcResult<LearnedRoute>;
     #[method(name = "advertise_flow")]
     async fn advertise_flow(&self, flow: FlowSpec) -> RpcResult<LearnedRoute>;
+    #[method(name = "withdraw_route")]
+    async fn withdraw_route(&self, prefix: IpNetwork) -> RpcResult<Vec<LearnedRoute>>;
+    #[method(name = "clear_session")]
+    async fn clear_session(&self, peer: IpAddr, reason: Option<String>) -> RpcResult<()>;
+    #[method(name = "set_peer_enabled")]
+    async fn set_peer_enabled(&self, peer: IpAddr, enabled: bool) -> RpcResult<()>;
+    #[method(name = "show_peer_filter_stats")]
+    async fn show_peer_filter_stats(&self, peer: IpAddr) -> RpcResult<FilterStats>;
+    #[method(name = "show_peer_capabilities")]
+    async fn show_peer_capabilities(&self, peer: IpAddr) -> RpcResult<Vec<CapabilityInfo>>;
+    #[method(name = "show_summary")]
+    async fn show_summary(&self) -> RpcResult<ServerSummary>;
+    #[method(name = "show_dampened")]
+    async fn show_dampened(&self) -> RpcResult<Vec<DampenedRoute>>;
+    #[method(name = "dump_rib_mrt")]
+    async fn dump_rib_mrt(&self, path: String) -> RpcResult<()>;
+    #[method(name = "shutdown")]
+    async fn shutdown(&self, message: Option<String>) -> RpcResult<()>;
+    #[method(name = "reload")]
+    async fn reload(&self) -> RpcResult<ReloadSummary>;
+}
+
+/// Peer configs (by `remote_ip`) added, removed, or changed by a config reload
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ReloadSummary {
+    pub peers_added: Vec<String>,
+    pub peers_removed: Vec<String>,
+    pub peers_changed: Vec<String>,
+    // Static routes & flows added to (or removed from) the RIB
+    #[serde(default)]
+    pub static_routes_added: usize,
+    #[serde(default)]
+    pub static_routes_removed: usize,
+}
+
+/// A learned route suppressed by Route Flap Dampening
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DampenedRoute {
+    pub peer: IpAddr,
+    pub prefix: IpNetwork,
+    // Figure of merit (the penalty, decayed to now)
+    pub penalty: u32,
+    // Time until the route is reused (unless it flaps again), E.g. "00:12:30"
+    pub reuse_in: String,
+}
+
+/// Overview of the daemon: local identity, peer sessions & RIB size
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ServerSummary {
+    pub router_id: IpAddr,
+    pub local_asn: u32,
+    pub peers_configured: usize,
+    pub peers_established: usize,
+    pub rib_entries: usize,
+    pub families: Vec<FamilySummary>,
+}
+
+/// RIB entries for a single family
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FamilySummary {
+    pub afi: String,
+    pub safi: String,
+    pub entries: usize,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -47,13 +115,89 @@ pub struct PeerDetail {
     pub summary: PeerSummary,
     pub hold_timer: u16,
     pub hold_timer_interval: u16,
-    // Either the negotiated (if active) or configured hold_time
+    // Remaining hold time (if active), E.g. "00:01:18"
     pub hold_time: Option<String>,
+    // Hold time from the peer config
+    #[serde(default)]
+    pub configured_hold_time: u16,
+    // Lower of the configured & peer's OPEN hold time (once Established)
+    #[serde(default)]
+    pub negotiated_hold_time: Option<u16>,
     pub last_received: Option<String>,
     pub last_sent: Option<String>,
     // TCP Stream info Local, Remote
     pub tcp_connection: Option<(String, String)>,
     pub capabilities: Vec<String>,
+    // Only accepting incoming connections (no outbound connection attempts)
+    #[serde(default)]
+    pub passive: bool,
+    // Prefixes dropped (this session) for an AS_PATH loop
+    #[serde(default)]
+    pub prefixes_dropped_loop: Option<u64>,
+    // Received prefixes have crossed the `max_prefix_warning` percentage of `max_prefixes`
+    #[serde(default)]
+    pub prefix_limit_warning: bool,
+    // Error that ended the last session with this peer
+    #[serde(default)]
+    pub last_error: Option<String>,
+    // Last NOTIFICATION sent or received, E.g. "Cease / Administrative Shutdown (sent)"
+    #[serde(default)]
+    pub last_notification: Option<String>,
+    // Messages sent & received (this session) by type, E.g. "UPDATE"
+    #[serde(default)]
+    pub message_stats: Vec<MessageStat>,
+    // NLRI announced & withdrawn (this session) in UPDATEs
+    #[serde(default)]
+    pub prefix_stats: Vec<MessageStat>,
+    // Bytes sent & received (this session), including message headers
+    #[serde(default)]
+    pub bytes_sent: Option<u64>,
+    #[serde(default)]
+    pub bytes_received: Option<u64>,
+    // UPDATEs sent & received per second, averaged over the session uptime
+    #[serde(default)]
+    pub updates_per_second: Option<f64>,
+}
+
+/// A sent & received count for a peer session, E.g. KEEPALIVE messages
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MessageStat {
+    pub name: String,
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// A capability as advertised by each side of a session, and if it was negotiated
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CapabilityInfo {
+    pub name: String,
+    pub local: bool,
+    pub remote: bool,
+    pub negotiated: bool,
+}
+
+/// Prefixes dropped from a peer (this session) by each import filter
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FilterStats {
+    // Not permitted by prefix_list_in
+    pub prefix_list: u64,
+    // Within a reserved range (reject_bogons)
+    pub bogon: u64,
+    // Local AS found in the AS_PATH (eBGP)
+    pub as_path_loop: u64,
+    // Leftmost AS isn't the peer's AS (eBGP)
+    pub first_as: u64,
+    // Rejected by community_filter_in
+    #[serde(default)]
+    pub community: u64,
+    // Flowspec rules that failed validation (flowspec_validation)
+    pub flowspec_validation: u64,
+    // Route Reflection loop, our ORIGINATOR_ID or Cluster ID found (iBGP)
+    #[serde(default)]
+    pub reflection_loop: u64,
+    // Without a route target in import_rts
+    #[serde(default)]
+    pub route_target: u64,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -65,14 +209,60 @@ pub struct LearnedRoute {
     pub age: String,
     pub prefix: String,
     pub next_hop: Option<IpAddr>,
+    // Link-local next hop, received after the global IPv6 next hop (RFC 2545)
+    #[serde(default)]
+    pub link_local_next_hop: Option<Ipv6Addr>,
     pub origin: String,
     pub as_path: String,
     pub local_pref: Option<u32>,
     pub multi_exit_disc: Option<u32>,
     pub communities: Vec<String>,
+    /// Decoded `communities` (E.g. to tell route targets from Flowspec actions)
+    #[serde(default)]
+    pub communities_detail: Vec<CommunityInfo>,
+    /// AGGREGATOR (ASN & Router ID) of the speaker that formed an aggregate route
+    #[serde(default)]
+    pub aggregator: Option<String>,
+    /// ATOMIC_AGGREGATE, the route was aggregated (and may have lost AS_PATH information)
+    #[serde(default)]
+    pub atomic_aggregate: bool,
+    /// Path attribute group (cache key) shared by routes with identical attributes
+    #[serde(default)]
+    pub attr_group: Option<u64>,
+    /// BGP Identifier of the peer the route was learned from
+    #[serde(default)]
+    pub router_id: Option<IpAddr>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// A community attached to a learned route
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CommunityInfo {
+    /// "standard", "extended", "ipv6-extended", or "large"
+    pub kind: String,
+    /// Extended community type (E.g. "target", "redirect", "traffic-rate"),
+    /// or the name of a well-known standard community (E.g. "no-export")
+    #[serde(default)]
+    pub subtype: Option<String>,
+    /// Global administrator, an ASN or IP address
+    #[serde(default)]
+    pub global_admin: Option<String>,
+    /// Local administrator (E.g. "100"), or the decoded value for Flowspec actions
+    #[serde(default)]
+    pub local_admin: Option<String>,
+    /// As displayed in `communities`
+    pub value: String,
+}
+
+/// A path for a prefix in the RIB, including paths that weren't selected as best
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RibPath {
+    #[serde(flatten)]
+    pub route: LearnedRoute,
+    /// Selected as the best path for this prefix
+    pub best: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct SpecAttributes {
     pub origin: Option<String>,
     #[serde(default = "Vec::new")]
@@ -81,12 +271,14 @@ pub struct SpecAttributes {
     pub multi_exit_disc: Option<u32>,
     #[serde(default = "Vec::new")]
     pub communities: Vec<String>,
+    /// ORIGINATOR_ID (IPv4 Router ID) to simulate reflected routes
+    pub originator_id: Option<IpAddr>,
     // TODO: Accept some sort of Policy Object
     //       So that this can be targeted at peer(s)
 }
 
 /// API Input for Route to advertise to peers
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct RouteSpec {
     /// Prefix to advertise (E.g. "100.1.0.0/16" or "2620:100:ab::/64")
     pub prefix: IpNetwork,
@@ -94,6 +286,9 @@ pub struct RouteSpec {
     pub next_hop: IpAddr,
     #[serde(flatten, default = "SpecAttributes::default")]
     pub attributes: SpecAttributes,
+    /// Only advertise while this peer is Established (static routes only)
+    #[serde(default)]
+    pub track_peer: Option<IpAddr>,
 }
 
 impl RouteSpec {
@@ -102,12 +297,13 @@ impl RouteSpec {
             prefix,
             next_hop,
             attributes: SpecAttributes::default(),
+            track_peer: None,
         }
     }
 }
 
 /// API Input for Route to advertise to peers
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct FlowSpec {
     /// Primary address family (ipv4=1, ipv6=2)
     pub afi: u16,
@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use bgp_rs::{Message, Notification, Open, Update};
+use byteorder::{NetworkEndian, WriteBytesExt};
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_util::codec::Encoder;
+
+use crate::session::{MessageCodec, NotificationRecord, Session};
+
+/// BMP version (RFC 7854 [4.1])
+const VERSION: u8 = 3;
+/// BMP common header size (version, length, type)
+const COMMON_HEADER_SIZE: usize = 6;
+/// Seconds between connection attempts to the collector
+const RECONNECT_INTERVAL: u64 = 30;
+/// Messages queued for the collector, once full messages are dropped
+const QUEUE_SIZE: usize = 1024;
+
+/// BMP message types (RFC 7854 [4.1])
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageType {
+    RouteMonitoring = 0,
+    PeerDown = 2,
+    PeerUp = 3,
+    Initiation = 4,
+}
+
+/// Peer Down reasons (RFC 7854 [4.9])
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PeerDownReason {
+    /// Local system closed the session, NOTIFICATION follows
+    LocalNotification = 1,
+    /// Remote system closed the session with a NOTIFICATION (which follows)
+    RemoteNotification = 3,
+    /// Remote system closed the session without a NOTIFICATION
+    RemoteNoData = 4,
+}
+
+/// Identifies the monitored peer in each message (RFC 7854 [4.2])
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerHeader {
+    pub addr: IpAddr,
+    pub asn: u32,
+    pub router_id: IpAddr,
+}
+
+impl PeerHeader {
+    fn encode(&self, timestamp: DateTime<Utc>, buf: &mut impl Write) -> io::Result<()> {
+        buf.write_u8(0 /* Global Instance Peer */)?;
+        // Pre-policy Adj-RIB-In, 4-byte AS_PATH
+        let flags = if self.addr.is_ipv6() { 0x80 } else { 0 };
+        buf.write_u8(flags)?;
+        buf.write_u64::<NetworkEndian>(0 /* Peer Distinguisher */)?;
+        buf.write_all(&address_bytes(self.addr))?;
+        buf.write_u32::<NetworkEndian>(self.asn)?;
+        match self.router_id {
+            IpAddr::V4(router_id) => buf.write_all(&router_id.octets())?,
+            IpAddr::V6(_) => buf.write_u32::<NetworkEndian>(0)?,
+        }
+        buf.write_u32::<NetworkEndian>(timestamp.timestamp() as u32)?;
+        buf.write_u32::<NetworkEndian>(timestamp.timestamp_subsec_micros())
+    }
+}
+
+/// Session details for a Peer Up notification (RFC 7854 [4.10])
+#[derive(Clone, Debug)]
+pub struct PeerUp {
+    pub peer: PeerHeader,
+    pub local_addr: SocketAddr,
+    pub remote_port: u16,
+    pub sent_open: Open,
+    pub received_open: Open,
+}
+
+impl PeerUp {
+    /// Peer Up details for an Established session (None if the session has no OPEN from the peer)
+    pub fn from_session(session: &Session) -> Option<Self> {
+        let stream = session.protocol.get_ref();
+        Some(Self {
+            peer: PeerHeader {
+                addr: session.addr,
+                asn: session.config.remote_as,
+                router_id: session.router_id,
+            },
+            local_addr: stream.local_addr().ok()?,
+            remote_port: stream.peer_addr().ok()?.port(),
+            sent_open: session.create_open(),
+            received_open: session.received_open.clone()?,
+        })
+    }
+}
+
+/// A monitored peer, with the last NOTIFICATION recorded when it came up
+/// (to tell if the session ended with a new NOTIFICATION)
+struct MonitoredPeer {
+    header: PeerHeader,
+    notification: Option<NotificationRecord>,
+}
+
+/// An encoded message queued for the collector, with the peer it's for
+#[derive(Debug)]
+enum Queued {
+    PeerUp(IpAddr, Vec<u8>),
+    RouteMonitoring(IpAddr, Vec<u8>),
+    PeerDown(IpAddr, Vec<u8>),
+}
+
+/// BGP Monitoring Protocol (RFC 7854) feed of peer state & learned routes to a collector
+///
+/// Messages are queued for a background task, so a slow or unreachable collector
+/// doesn't hold up session processing (messages are dropped if the queue fills)
+pub struct BmpClient {
+    tx: mpsc::Sender<Queued>,
+    peers: HashMap<IpAddr, MonitoredPeer>,
+    dropped: AtomicU64,
+}
+
+impl BmpClient {
+    /// Connect to the collector in a background task (reconnecting if the connection fails)
+    pub fn start(collector: SocketAddr, router_id: IpAddr) -> io::Result<Self> {
+        let initiation = encode_initiation(
+            &router_id.to_string(),
+            &format!("bgpd-rs {}", env!("CARGO_PKG_VERSION")),
+        )?;
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(send_to_collector(collector, initiation, rx));
+        Ok(Self {
+            tx,
+            peers: HashMap::new(),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Peer Up for a session that reached Established
+    /// `notification` is the last NOTIFICATION recorded for the peer (if any)
+    pub fn peer_up(&mut self, up: &PeerUp, notification: Option<NotificationRecord>) {
+        match encode_peer_up(up, Utc::now()) {
+            Ok(message) => self.send(Queued::PeerUp(up.peer.addr, message)),
+            Err(err) => warn!("Error encoding BMP Peer Up for {}: {}", up.peer.addr, err),
+        }
+        self.peers.insert(
+            up.peer.addr,
+            MonitoredPeer {
+                header: up.peer.clone(),
+                notification,
+            },
+        );
+    }
+
+    /// Route Monitoring for an UPDATE received from an Established peer
+    pub fn route_monitoring(&self, peer: IpAddr, update: &Update) {
+        let monitored = match self.peers.get(&peer) {
+            Some(monitored) => monitored,
+            None => return,
+        };
+        match encode_route_monitoring(&monitored.header, update, Utc::now()) {
+            Ok(message) => self.send(Queued::RouteMonitoring(peer, message)),
+            Err(err) => warn!("Error encoding BMP Route Monitoring for {}: {}", peer, err),
+        }
+    }
+
+    /// Peer Down for an ended session (if the peer was reported up)
+    /// `notification` is the last NOTIFICATION recorded for the peer (if any)
+    pub fn peer_down(&mut self, peer: IpAddr, notification: Option<NotificationRecord>) {
+        let monitored = match self.peers.remove(&peer) {
+            Some(monitored) => monitored,
+            None => return,
+        };
+        // Only a NOTIFICATION recorded since the Peer Up ended this session
+        let notification = notification.filter(|n| Some(n) != monitored.notification.as_ref());
+        match encode_peer_down(&monitored.header, notification.as_ref(), Utc::now()) {
+            Ok(message) => self.send(Queued::PeerDown(peer, message)),
+            Err(err) => warn!("Error encoding BMP Peer Down for {}: {}", peer, err),
+        }
+    }
+
+    /// Number of messages dropped because the collector fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn send(&self, message: Queued) {
+        match self.tx.try_send(message) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!("BMP collector is falling behind, dropping messages");
+                }
+            }
+            Err(TrySendError::Closed(_)) => {
+                debug!("BMP collector task has ended, dropping message")
+            }
+        }
+    }
+}
+
+/// Message to send the collector for a queued message, tracking the peers that are up
+/// Messages for peers that aren't up (E.g. their Peer Up was dropped) aren't sent
+fn collector_message(queued: Queued, peers_up: &mut HashMap<IpAddr, Vec<u8>>) -> Option<Vec<u8>> {
+    match queued {
+        Queued::PeerUp(peer, message) => {
+            peers_up.insert(peer, message.clone());
+            Some(message)
+        }
+        Queued::RouteMonitoring(peer, message) => peers_up.contains_key(&peer).then_some(message),
+        Queued::PeerDown(peer, message) => peers_up.remove(&peer).map(|_| message),
+    }
+}
+
+/// Send the Initiation message, followed by queued messages, to the collector
+///
+/// A new connection starts with the Initiation & a Peer Up for each peer that's up
+/// (RFC 7854 [3.3]), followed by the message that failed to send on the last connection
+async fn send_to_collector(
+    collector: SocketAddr,
+    initiation: Vec<u8>,
+    mut rx: mpsc::Receiver<Queued>,
+) {
+    use tokio::io::AsyncWriteExt;
+    let mut peers_up: HashMap<IpAddr, Vec<u8>> = HashMap::new();
+    let mut pending: Option<Vec<u8>> = None;
+    'connect: loop {
+        let mut stream = match TcpStream::connect(collector).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Error connecting to BMP collector {}: {}", collector, err);
+                tokio::time::sleep(Duration::from_secs(RECONNECT_INTERVAL)).await;
+                continue;
+            }
+        };
+        info!("Connected to BMP collector {}", collector);
+        let resync = std::iter::once(&initiation)
+            .chain(peers_up.values())
+            .chain(pending.iter());
+        for message in resync {
+            if let Err(err) = stream.write_all(message).await {
+                warn!("Error sending to BMP collector {}: {}", collector, err);
+                tokio::time::sleep(Duration::from_secs(RECONNECT_INTERVAL)).await;
+                continue 'connect;
+            }
+        }
+        pending = None;
+        loop {
+            let message = match rx.recv().await {
+                Some(queued) => match collector_message(queued, &mut peers_up) {
+                    Some(message) => message,
+                    None => continue,
+                },
+                // BMP client was dropped
+                None => return,
+            };
+            if let Err(err) = stream.write_all(&message).await {
+                warn!("Error sending to BMP collector {}: {}", collector, err);
+                // Peer Ups are sent again with the rest on the next connection
+                if !peers_up.values().any(|up| up == &message) {
+                    pending = Some(message);
+                }
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(RECONNECT_INTERVAL)).await;
+    }
+}
+
+/// Common header (RFC 7854 [4.1]) followed by the message body
+fn encode_message(message_type: MessageType, body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(COMMON_HEADER_SIZE + body.len());
+    buf.write_u8(VERSION)?;
+    buf.write_u32::<NetworkEndian>((COMMON_HEADER_SIZE + body.len()) as u32)?;
+    buf.write_u8(message_type as u8)?;
+    buf.write_all(body)?;
+    Ok(buf)
+}
+
+/// Initiation with the sysName & sysDescr information TLVs (RFC 7854 [4.3])
+fn encode_initiation(sys_name: &str, sys_descr: &str) -> io::Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(8 + sys_name.len() + sys_descr.len());
+    for (tlv_type, value) in [(2 /* sysName */, sys_name), (1 /* sysDescr */, sys_descr)] {
+        body.write_u16::<NetworkEndian>(tlv_type)?;
+        body.write_u16::<NetworkEndian>(value.len() as u16)?;
+        body.write_all(value.as_bytes())?;
+    }
+    encode_message(MessageType::Initiation, &body)
+}
+
+fn encode_peer_up(up: &PeerUp, timestamp: DateTime<Utc>) -> io::Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(256);
+    up.peer.encode(timestamp, &mut body)?;
+    body.write_all(&address_bytes(up.local_addr.ip()))?;
+    body.write_u16::<NetworkEndian>(up.local_addr.port())?;
+    body.write_u16::<NetworkEndian>(up.remote_port)?;
+    body.write_all(&encode_bgp_message(Message::Open(up.sent_open.clone()))?)?;
+    body.write_all(&encode_bgp_message(Message::Open(
+        up.received_open.clone(),
+    ))?)?;
+    encode_message(MessageType::PeerUp, &body)
+}
+
+fn encode_route_monitoring(
+    peer: &PeerHeader,
+    update: &Update,
+    timestamp: DateTime<Utc>,
+) -> io::Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(128);
+    peer.encode(timestamp, &mut body)?;
+    body.write_all(&encode_bgp_message(Message::Update(update.clone()))?)?;
+    encode_message(MessageType::RouteMonitoring, &body)
+}
+
+fn encode_peer_down(
+    peer: &PeerHeader,
+    notification: Option<&NotificationRecord>,
+    timestamp: DateTime<Utc>,
+) -> io::Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(64);
+    peer.encode(timestamp, &mut body)?;
+    match notification {
+        Some(notification) => {
+            let reason = if notification.sent {
+                PeerDownReason::LocalNotification
+            } else {
+                PeerDownReason::RemoteNotification
+            };
+            body.write_u8(reason as u8)?;
+            body.write_all(&encode_bgp_message(Message::Notification(Notification {
+                major_err_code: notification.major,
+                minor_err_code: notification.minor,
+                data: notification.data.clone(),
+            }))?)?;
+        }
+        None => body.write_u8(PeerDownReason::RemoteNoData as u8)?,
+    }
+    encode_message(MessageType::PeerDown, &body)
+}
+
+/// BGP message (with header), as sent on the wire
+fn encode_bgp_message(message: Message) -> io::Result<Vec<u8>> {
+    let mut buf = BytesMut::with_capacity(64);
+    let mut codec = MessageCodec::new();
+    // Updates from peers with Extended Messages negotiated may exceed 4096 bytes
+    codec.set_extended_messages(true);
+    codec.encode(message, &mut buf)?;
+    Ok(buf.to_vec())
+}
+
+/// 16-byte address field, IPv4 addresses are in the low-order 4 bytes
+fn address_bytes(addr: IpAddr) -> [u8; 16] {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mut bytes = [0u8; 16];
+            bytes[12..].copy_from_slice(&addr.octets());
+            bytes
+        }
+        IpAddr::V6(addr) => addr.octets(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::{ASPath, NLRIEncoding, Origin, PathAttribute};
+    use chrono::TimeZone;
+
+    fn peer_header() -> PeerHeader {
+        PeerHeader {
+            addr: "10.0.0.2".parse().unwrap(),
+            asn: 65001,
+            router_id: "2.2.2.2".parse().unwrap(),
+        }
+    }
+
+    fn timestamp() -> DateTime<Utc> {
+        Utc.timestamp(1_600_000_000, 250_000_000)
+    }
+
+    #[test]
+    fn test_common_header() {
+        let message = encode_message(MessageType::PeerUp, &[1, 2, 3]).unwrap();
+        assert_eq!(message, vec![3, 0, 0, 0, 9, 3, 1, 2, 3]);
+        let message = encode_message(MessageType::RouteMonitoring, &[]).unwrap();
+        assert_eq!(message, vec![3, 0, 0, 0, 6, 0]);
+    }
+
+    #[test]
+    fn test_per_peer_header() {
+        let mut buf = Vec::new();
+        peer_header().encode(timestamp(), &mut buf).unwrap();
+        assert_eq!(buf.len(), 42);
+        assert_eq!(&buf[..2], &[0, 0]);
+        assert_eq!(&buf[2..10], &[0; 8]);
+        assert_eq!(&buf[10..26], &address_bytes("10.0.0.2".parse().unwrap()));
+        assert_eq!(&buf[22..26], &[10, 0, 0, 2]);
+        assert_eq!(&buf[26..30], &65001u32.to_be_bytes());
+        assert_eq!(&buf[30..34], &[2, 2, 2, 2]);
+        assert_eq!(&buf[34..38], &1_600_000_000u32.to_be_bytes());
+        assert_eq!(&buf[38..42], &250_000u32.to_be_bytes());
+
+        // IPv6 peers set the V flag
+        let mut header = peer_header();
+        header.addr = "2001:db8::2".parse().unwrap();
+        let mut buf = Vec::new();
+        header.encode(timestamp(), &mut buf).unwrap();
+        assert_eq!(buf[1], 0x80);
+        assert_eq!(&buf[10..12], &[0x20, 0x01]);
+    }
+
+    #[test]
+    fn test_initiation() {
+        let message = encode_initiation("1.1.1.1", "bgpd").unwrap();
+        assert_eq!(&message[..6], &[3, 0, 0, 0, 25, 4]);
+        assert_eq!(&message[6..10], &[0, 2, 0, 7]);
+        assert_eq!(&message[10..17], b"1.1.1.1");
+        assert_eq!(&message[17..21], &[0, 1, 0, 4]);
+        assert_eq!(&message[21..], b"bgpd");
+    }
+
+    #[test]
+    fn test_route_monitoring() {
+        let update = Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP("10.0.0.2".parse().unwrap()),
+            ],
+            announced_routes: vec![NLRIEncoding::IP(("10.1.0.0".parse().unwrap(), 24).into())],
+        };
+        let message = encode_route_monitoring(&peer_header(), &update, timestamp()).unwrap();
+        let bgp_message = encode_bgp_message(Message::Update(update)).unwrap();
+        assert_eq!(message.len(), COMMON_HEADER_SIZE + 42 + bgp_message.len());
+        assert_eq!(&message[1..5], &(message.len() as u32).to_be_bytes());
+        assert_eq!(message[5], MessageType::RouteMonitoring as u8);
+        assert_eq!(&message[48..], &bgp_message[..]);
+        // BGP message marker
+        assert_eq!(&message[48..64], &[0xff; 16]);
+    }
+
+    #[test]
+    fn test_peer_down() {
+        let message = encode_peer_down(&peer_header(), None, timestamp()).unwrap();
+        assert_eq!(message.len(), COMMON_HEADER_SIZE + 42 + 1);
+        assert_eq!(message[5], MessageType::PeerDown as u8);
+        assert_eq!(message[48], PeerDownReason::RemoteNoData as u8);
+
+        let notification = NotificationRecord {
+            sent: true,
+            major: 6,
+            minor: 2,
+            data: vec![],
+        };
+        let message = encode_peer_down(&peer_header(), Some(&notification), timestamp()).unwrap();
+        assert_eq!(message[48], PeerDownReason::LocalNotification as u8);
+        // NOTIFICATION message: header & error codes
+        assert_eq!(&message[49..65], &[0xff; 16]);
+        assert_eq!(&message[65..], &[0, 21, 3, 6, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_peer_down_reason() {
+        let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+        let mut client = BmpClient {
+            tx,
+            peers: HashMap::new(),
+            dropped: AtomicU64::new(0),
+        };
+        let peer = peer_header();
+        let stale = NotificationRecord {
+            sent: false,
+            major: 6,
+            minor: 4,
+            data: vec![],
+        };
+        client.peers.insert(
+            peer.addr,
+            MonitoredPeer {
+                header: peer.clone(),
+                notification: Some(stale.clone()),
+            },
+        );
+        // Routes from peers that aren't up aren't monitored
+        client.route_monitoring(
+            "10.0.0.3".parse().unwrap(),
+            &Update {
+                withdrawn_routes: vec![],
+                attributes: vec![],
+                announced_routes: vec![],
+            },
+        );
+        assert!(rx.try_recv().is_err());
+        // NOTIFICATION from the previous session isn't reported
+        client.peer_down(peer.addr, Some(stale));
+        let message = match rx.try_recv().unwrap() {
+            Queued::PeerDown(_, message) => message,
+            queued => panic!("Expected Peer Down, got {:?}", queued),
+        };
+        assert_eq!(message[48], PeerDownReason::RemoteNoData as u8);
+        // Peer is no longer monitored
+        client.peer_down(peer.addr, None);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_queue_full() {
+        let (tx, _rx) = mpsc::channel(1);
+        let client = BmpClient {
+            tx,
+            peers: HashMap::new(),
+            dropped: AtomicU64::new(0),
+        };
+        for _ in 0..3 {
+            client.send(Queued::RouteMonitoring(peer_header().addr, vec![]));
+        }
+        assert_eq!(client.dropped(), 2);
+    }
+
+    #[test]
+    fn test_collector_message() {
+        let peer = peer_header().addr;
+        let mut peers_up = HashMap::new();
+        // Peer Up was dropped, the collector doesn't know this peer
+        assert_eq!(
+            collector_message(Queued::RouteMonitoring(peer, vec![0]), &mut peers_up),
+            None
+        );
+        assert_eq!(
+            collector_message(Queued::PeerDown(peer, vec![2]), &mut peers_up),
+            None
+        );
+        assert_eq!(
+            collector_message(Queued::PeerUp(peer, vec![3]), &mut peers_up),
+            Some(vec![3])
+        );
+        assert_eq!(peers_up.get(&peer), Some(&vec![3]));
+        assert_eq!(
+            collector_message(Queued::RouteMonitoring(peer, vec![0]), &mut peers_up),
+            Some(vec![0])
+        );
+        assert_eq!(
+            collector_message(Queued::PeerDown(peer, vec![2]), &mut peers_up),
+            Some(vec![2])
+        );
+        assert!(peers_up.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        /// Read a BMP message from the collector's side of the connection
+        async fn read_message(stream: &mut TcpStream) -> Vec<u8> {
+            let mut message = vec![0u8; COMMON_HEADER_SIZE];
+            stream.read_exact(&mut message).await.unwrap();
+            let length = u32::from_be_bytes([message[1], message[2], message[3], message[4]]);
+            message.resize(length as usize, 0);
+            stream
+                .read_exact(&mut message[COMMON_HEADER_SIZE..])
+                .await
+                .unwrap();
+            message
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let collector = listener.local_addr().unwrap();
+        let initiation = encode_initiation("1.1.1.1", "bgpd").unwrap();
+        let peer = peer_header().addr;
+        let peer_up = encode_message(MessageType::PeerUp, &[1]).unwrap();
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(send_to_collector(collector, initiation.clone(), rx));
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        tx.send(Queued::PeerUp(peer, peer_up.clone()))
+            .await
+            .unwrap();
+        assert_eq!(read_message(&mut stream).await, initiation);
+        assert_eq!(read_message(&mut stream).await, peer_up);
+        drop(stream);
+
+        // Keep sending until the closed connection is noticed
+        tokio::time::pause();
+        let mut sent = vec![];
+        let mut accept = Box::pin(listener.accept());
+        let mut stream = loop {
+            let message =
+                encode_message(MessageType::RouteMonitoring, &[sent.len() as u8]).unwrap();
+            tx.send(Queued::RouteMonitoring(peer, message.clone()))
+                .await
+                .unwrap();
+            sent.push(message);
+            tokio::select! {
+                accepted = &mut accept => break accepted.unwrap().0,
+                _ = tokio::time::sleep(Duration::from_millis(100)) => (),
+            }
+        };
+
+        // New connection repeats the Initiation & Peer Up before queued messages
+        assert_eq!(read_message(&mut stream).await, initiation);
+        assert_eq!(read_message(&mut stream).await, peer_up);
+        let resent = read_message(&mut stream).await;
+        let index = sent.iter().position(|m| m == &resent).unwrap();
+        for message in &sent[index + 1..] {
+            assert_eq!(&read_message(&mut stream).await, message);
+        }
+    }
+}
@@ -5,7 +5,7 @@ use std::fmt::Display;
 use prettytable::{cell, row, Row};
 
 use super::table::ToRow;
-use crate::api::rpc::{LearnedRoute, PeerSummary};
+use crate::api::rpc::{CapabilityInfo, DampenedRoute, LearnedRoute, PeerSummary, RibPath};
 
 pub const EMPTY_VALUE: &str = "";
 
@@ -56,6 +56,23 @@ impl ToRow for PeerSummaryRow {
     }
 }
 
+/// Peer summary for a specific BGPd node (when querying multiple daemons)
+pub struct NodePeerSummaryRow(pub String, pub PeerSummaryRow);
+
+impl ToRow for NodePeerSummaryRow {
+    fn columns() -> Row {
+        let mut columns = PeerSummaryRow::columns();
+        columns.insert_cell(0, cell!("Node"));
+        columns
+    }
+
+    fn to_row(&self) -> Result<Row, Box<dyn Error>> {
+        let mut row = self.1.to_row()?;
+        row.insert_cell(0, cell!(self.0));
+        Ok(row)
+    }
+}
+
 pub struct LearnedRouteRow(pub LearnedRoute);
 
 impl ToRow for LearnedRouteRow {
@@ -92,6 +109,49 @@ impl ToRow for LearnedRouteRow {
     }
 }
 
+/// A RIB path, with the prefix only displayed for the first path of each prefix
+pub struct RibPathRow(pub RibPath, pub bool);
+
+impl ToRow for RibPathRow {
+    fn columns() -> Row {
+        row![
+            "",
+            "Prefix",
+            "Next Hop",
+            "Metric",
+            "Local Pref",
+            "AS Path",
+            "Origin",
+            "Source",
+            "Router ID",
+            "Age",
+        ]
+    }
+
+    fn to_row(&self) -> Result<Row, Box<dyn Error>> {
+        let RibPathRow(path, first) = self;
+        let route = &path.route;
+        let prefix = if *first {
+            route.prefix.replace("; ", "\n")
+        } else {
+            String::from(EMPTY_VALUE)
+        };
+        let row = row![
+            if path.best { ">" } else { EMPTY_VALUE },
+            prefix,
+            display_cell(route.next_hop.as_ref()),
+            display_cell(route.multi_exit_disc.as_ref()),
+            display_cell(route.local_pref.as_ref()),
+            route.as_path,
+            route.origin,
+            route.source,
+            display_cell(route.router_id.as_ref()),
+            route.age,
+        ];
+        Ok(row)
+    }
+}
+
 pub struct AdvertisedRouteRow(pub LearnedRoute);
 
 impl ToRow for AdvertisedRouteRow {
@@ -127,3 +187,37 @@ impl ToRow for AdvertisedRouteRow {
         Ok(row)
     }
 }
+
+pub struct CapabilityRow(pub CapabilityInfo);
+
+impl ToRow for CapabilityRow {
+    fn columns() -> Row {
+        row!["Capability", "Local", "Remote", "Negotiated"]
+    }
+
+    fn to_row(&self) -> Result<Row, Box<dyn Error>> {
+        let capability = &self.0;
+        let yes_no = |value: bool| if value { "Yes" } else { "No" };
+        let row = row![
+            capability.name,
+            yes_no(capability.local),
+            yes_no(capability.remote),
+            yes_no(capability.negotiated),
+        ];
+        Ok(row)
+    }
+}
+
+pub struct DampenedRouteRow(pub DampenedRoute);
+
+impl ToRow for DampenedRouteRow {
+    fn columns() -> Row {
+        row!["Peer", "Prefix", "Penalty", "Reuse In"]
+    }
+
+    fn to_row(&self) -> Result<Row, Box<dyn Error>> {
+        let route = &self.0;
+        let row = row![route.peer, route.prefix, route.penalty, route.reuse_in];
+        Ok(row)
+    }
+}
@@ -3,6 +3,15 @@
 //! The CLI provided for running the BGPd daemon can also be used to interact with a running instance of BGPd.
 //! It uses the default endpoint for the BGPd HTTP API (localhost:8080),
 //! but you can point to BGPd running remotely using the `--host` and `--port` options.
+//! `--host` can be repeated (or comma separated) to query multiple BGPd nodes at once.
+//!
+//! Output is rendered as tables by default, use `--output json` (`-o json`) to print
+//! the raw API responses as JSON for scripting:
+//! ```sh
+//! $ bgpd -o json show routes learned --next-hop 172.16.20.2 | jq '.[].prefix'
+//! "172.16.20.0/24"
+//! "3001:172:16:20::/64"
+//! ```
 //!
 //! ## Features
 //! - [x] CLI interface for viewing peer status and details
@@ -11,7 +20,7 @@
 //! - [x] Advertise IPv4/IPv6 Unicast routes (More attribute support coming soon)
 //! - [x] Advertise IPv4/IPv6 Flowspec flows
 //! - [ ] Filter learned/advertised routes (prefix, peer, attributes, ...)
-//! - [ ] Enable/disable Peers
+//! - [x] Enable/disable Peers
 //!
 //!
 //! # Show Commands
@@ -28,13 +37,51 @@
 //!  *127.0.0.3                65000                              Disabled
 //!  172.16.20.2  172.16.20.2  65000  29       28       00:11:33  Established  2
 //! ```
-//!  > Tip: Use the `watch` command for keeping this view up-to-date
+//!  > Tip: Use `--watch <seconds>` for keeping this view up-to-date (E.g. `bgpd show neighbors --watch 2`)
+//!
+//! Peer summary across multiple BGPd nodes:
+//! ```sh
+//! $ bgpd --host 172.16.20.90,172.16.20.91 show neighbors
+//!  Node          Neighbor     Router ID    AS     MsgRcvd  MsgSent  Uptime    State        PfxRcd
+//! -------------------------------------------------------------------------------------------------
+//!  172.16.20.90  127.0.0.2    2.2.2.2      100    76       70       00:11:27  Established  4
+//!  172.16.20.90  172.16.20.2  172.16.20.2  65000  29       28       00:11:33  Established  2
+//!  172.16.20.91  172.16.20.2  172.16.20.2  65000  31       30       00:12:02  Established  2
+//! ```
+//!
+//! Prefixes dropped by import filters for a peer (this session):
+//! ```sh
+//! $ bgpd show neighbors 172.16.20.2 filters
+//! Prefixes dropped by import filters for 172.16.20.2
+//!   prefix_list_in          3
+//!   Bogon                   1
+//!   AS_PATH loop            2
+//!   AS_PATH first AS        0
+//!   Community               4
+//!   Flowspec validation     0
+//!   Reflection loop         0
+//! ```
+//!
+//! Capabilities advertised by each side of a session, and negotiated:
+//! ```sh
+//! $ bgpd show neighbors 172.16.20.2 capabilities
+//!  Capability                   Local  Remote  Negotiated
+//! ---------------------------------------------------------
+//!  Address family IPv4 Unicast  Yes    Yes     Yes
+//!  Address family IPv6 Unicast  Yes    No      No
+//!  Route Refresh                No     Yes     No
+//!  Enhanced Route Refresh       No     No      No
+//!  4-octet ASN                  Yes    Yes     Yes
+//!  Graceful Restart             No     Yes     No
+//!  ADD-PATH                     No     No      No
+//! ```
 //!
 //! Peer Detail:
 //! ```sh
 //! $ bgpd show neighbors detail
 //! BGP neighbor is 127.0.0.3,  remote AS 65000, local AS 65000
 //!   *Peer is Disabled
+//!   Passive, only accepting incoming connections
 //!   Neighbor capabilities:
 //!     IPv4 Unicast
 //!     IPv4 Flowspec
@@ -48,14 +95,25 @@
 //!     Remote address: 172.16.20.2:179
 //!   BGP state = Established, up for 00:11:59
 //!   Hold time is 90 (00:01:18), keepalive interval is 30
+//!     Configured 180, negotiated 90
 //!     Last read 00:00:03, last write 00:00:11
 //!   Neighbor capabilities:
 //!     Address family IPv6 Unicast
 //!     Address family IPv4 Unicast
 //!
 //!   Message Statistics:
-//!                       Sent      Received
-//!     Total             30        29
+//!                         Sent      Received
+//!     Opens               1         1
+//!     Updates             2         3
+//!     Keepalives          27        25
+//!     Notifications       0         0
+//!     Route Refresh       0         0
+//!     Total               30        29
+//!     Prefixes announced  4         6
+//!     Prefixes withdrawn  0         1
+//!     Bytes               719       1011
+//!     Updates per second: 0.01
+//!   Prefixes dropped for AS_PATH loop: 0
 //! ```
 //!
 //! ## Routes
@@ -120,6 +178,30 @@
 //!  127.0.0.2      2621:a:1337::/64     3001:1::1           00:07:46  IGP                     404     100                   00:07:46
 //! ```
 //!
+//! Or by the originating AS (last ASN in the AS_PATH):
+//! ```sh
+//! $ bgpd show routes learned --origin-as 200
+//! IPv4 / Unicast
+//!  Received From  Prefix          Next Hop      Age       Origin      Local Pref  Metric  AS Path  Communities           Age
+//! --------------------------------------------------------------------------------------------------------------------------------
+//!  127.0.0.2      2.200.0.0/24    127.0.0.2     00:07:46  IGP                             100 200                        00:07:46
+//! ```
+//!
+//! Or by next hop (E.g. to find routes pointing at a gateway that's down), this is also
+//! available for advertised routes:
+//! ```sh
+//! $ bgpd show routes learned --next-hop 172.16.20.2
+//! IPv4 / Unicast
+//!  Received From  Prefix          Next Hop      Age       Origin      Local Pref  Metric  AS Path  Communities           Age
+//! --------------------------------------------------------------------------------------------------------------------------------
+//!  172.16.20.2    172.16.20.0/24  172.16.20.2   00:07:54  IGP         100                                                00:07:54
+//!
+//! IPv6 / Unicast
+//!  Received From  Prefix               Next Hop            Age       Origin      Local Pref  Metric  AS Path  Communities  Age
+//! ----------------------------------------------------------------------------------------------------------------------------------
+//!  172.16.20.2    3001:172:16:20::/64  ::ffff:172.16.20.2  00:07:54  IGP         100                                       00:07:54
+//! ```
+//!
 //! Advertised routes:
 //! ```sh
 //! $ bgpd show routes advertised
@@ -137,6 +219,35 @@
 //!  172.16.20.2    3001:404:b::/64      3001:1::1           00:08:06  Incomplete                                            00:08:06
 //! ```
 //!
+//! All paths for each prefix in the RIB (`>` marks the best path), optionally within a network:
+//! ```sh
+//! $ bgpd show rib 172.16.0.0/16
+//! IPv4 / Unicast
+//!     Prefix          Next Hop     Metric  Local Pref  AS Path  Origin  Source       Router ID  Age
+//! -------------------------------------------------------------------------------------------------------
+//!  >  172.16.20.0/24  172.16.20.2          200         65001    IGP     172.16.20.2  2.2.2.2    00:07:54
+//!                     127.0.0.2            100         65001    IGP     127.0.0.2    1.1.1.1    00:07:46
+//!  >  172.16.30.0/24  127.0.0.2    50      100         65001    IGP     127.0.0.2    1.1.1.1    00:07:46
+//! ```
+//!
+//! An overview of peer sessions & RIB size (in a single API call, handy for dashboards):
+//! ```sh
+//! $ bgpd show summary
+//! BGP router identifier 1.1.1.1, local AS number 65000
+//! Peers: 3 configured, 2 established
+//! RIB entries: 9
+//!   IPv4 Unicast            4
+//!   IPv6 Unicast            5
+//! ```
+//!
+//! Learned routes suppressed by Route Flap Dampening (with the time until they're reused):
+//! ```sh
+//! $ bgpd show dampened
+//!  Peer          Prefix          Penalty  Reuse In
+//! --------------------------------------------------
+//!  172.16.20.2   10.10.10.0/24   2850     00:28:53
+//! ```
+//!
 //! ## Advertise
 //!
 //! ### Unicast
@@ -173,18 +284,85 @@
 //!  172.16.20.2    3001:100:abcd::/64   3001:1::1           00:00:03  Incomplete                                            00:00:03
 //! ```
 //!
+//! ### Withdraw
+//! Routes advertised via the API can be withdrawn by prefix
+//! ```sh
+//! $ bgpd withdraw route 10.10.10.0/24
+//! Removed route from RIB for withdrawal:
+//!  Received From  Prefix         Next Hop      Age       Origin      Local Pref  Metric  AS Path  Communities  Age
+//! ----------------------------------------------------------------------------------------------------------------------
+//!  API            10.10.10.0/24  172.16.20.90  00:03:12  Incomplete                                            00:03:12
+//! ```
+//!
+//! ### Clear
+//! Reset an established session (the peer will reconnect once polled)
+//! ```sh
+//! $ bgpd clear 172.16.20.2
+//! Cleared session with 172.16.20.2
+//! ```
+//!
+//! A reason can be sent to the peer with the NOTIFICATION (RFC 9003 Shutdown Communication):
+//! ```sh
+//! $ bgpd clear 172.16.20.2 --reason "Applying new policy"
+//! Cleared session with 172.16.20.2
+//! ```
+//!
+//! ### Enable/Disable Peers
+//! Disable a peer (ending an active session with a Cease NOTIFICATION) or enable it again.
+//! The IP can be a single peer or any IP within a configured peer network (applies to the whole network).
+//! This takes precedence over `enabled` in the config file and is kept across config reloads,
+//! until BGPd is restarted (when the config file value applies again)
+//! ```sh
+//! $ bgpd peer 172.16.20.2 disable
+//! Disabled peer 172.16.20.2
+//! $ bgpd peer 172.16.20.2 enable
+//! Enabled peer 172.16.20.2
+//! ```
+//!
+//! ### Check
+//! Validate a config file before (re)starting BGPd, exits non-zero if the config is invalid
+//! ```sh
+//! $ bgpd check ./examples/config.toml
+//! ./examples/config.toml: 2 peers, 1 static routes, 2 static flows
+//! Config is valid
+//! ```
+//!
+//! ### Dump RIB
+//! Write a snapshot of the RIB (IPv4/IPv6 Unicast) to an MRT TABLE_DUMP_V2 file (RFC 6396)
+//! on the BGPd host, for offline analysis with tools like `bgpdump`
+//! ```sh
+//! $ bgpd dump-rib /tmp/rib.mrt
+//! Wrote RIB to /tmp/rib.mrt
+//! ```
+//!
+//! ### Shutdown
+//! Stop the daemon, sending established peers a Cease NOTIFICATION (Administrative Shutdown)
+//! ```sh
+//! $ bgpd shutdown --message "Upgrading BGPd"
+//! BGPd is shutting down
+//! ```
+//!
+//! ### Reload
+//! Re-read the config file (the same as sending the daemon SIGHUP), listing changed peer configs
+//! ```sh
+//! $ bgpd reload
+//! Reloaded config
+//!   Added peers: 172.16.20.3/32
+//!   Changed peers: 172.16.20.2/32
+//! ```
+//!
 //! ### Flowspec
 //! ```sh
 //! $ bgpd advertise flow ipv4 'traffic-rate 100' -m 'source 192.168.10.0/24'
 //! Added flow to RIB for announcement:
 //!  Received From  Prefix               Next Hop  Age       Origin      Local Pref  Metric  AS Path  Communities            Age
 //! ----------------------------------------------------------------------------------------------------------------------------------
-//!  Config         Src 192.168.10.0/24            00:00:00  Incomplete                               traffic-rate:0:100bps  00:00:00
+//!  Config         source 192.168.10.0/24         00:00:00  Incomplete                               traffic-rate:0:100bps  00:00:00
 //! $ bgpd advertise flow ipv6 'redirect 100:200' -m 'destination 3001:10:20::/64'
 //! Added flow to RIB for announcement:
 //!  Received From  Prefix               Next Hop  Age       Origin      Local Pref  Metric  AS Path  Communities       Age
 //! -----------------------------------------------------------------------------------------------------------------------------
-//!  Config         Dst 3001:10:20::/64            00:00:00  Incomplete                               redirect:100:200  00:00:00
+//!  Config         destination 3001:10:20::/64    00:00:00  Incomplete                               redirect:100:200  00:00:00
 //!  ```
 //!
 //!  ```sh
@@ -192,34 +370,42 @@
 //! IPv4 / Flowspec
 //!  Received From  Prefix              Next Hop  Age       Origin      Local Pref  Metric  AS Path  Communities     Age
 //! --------------------------------------------------------------------------------------------------------------------------
-//!  Config         Src 192.168.0.0/16            00:01:55  Incomplete                               redirect:6:302  00:01:55
+//!  Config         source 192.168.0.0/16         00:01:55  Incomplete                               redirect:6:302  00:01:55
 //!
 //! IPv6 / Flowspec
 //!  Received From  Prefix                    Next Hop  Age       Origin      Local Pref  Metric  AS Path  Communities              Age
 //! -----------------------------------------------------------------------------------------------------------------------------------------
-//!  127.0.0.2      Dst 3001:99:b::10/128               00:01:24  IGP                             200      traffic-rate:0:500bps    00:01:24
-//!                 Src 3001:99:a::10/128
-//!  Config         Src 3001:100::/56                   00:01:55  Incomplete                               traffic-rate:0:24000bps  00:01:55
-//!                 DstPort >8000, && <=8080
-//!                 Packet Length >100
+//!  127.0.0.2      destination 3001:99:b::10/128       00:01:24  IGP                             200      traffic-rate:0:500bps    00:01:24
+//!                 source 3001:99:a::10/128
+//!  Config         source 3001:100::/56                00:01:55  Incomplete                               traffic-rate:0:24000bps  00:01:55
+//!                 destination-port >8000 <=8080
+//!                 packet-length >100
 //!
 //! ```
 
 use std::error::Error;
+use std::future::Future;
 use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{ArgEnum, Parser};
 use colored::*;
+use futures::future::join_all;
 use ipnetwork::IpNetwork;
 use itertools::Itertools;
-use jsonrpsee::http_client::HttpClientBuilder;
+use jsonrpsee::http_client::{HttpClient, HttpClientBuilder};
+use serde::Serialize;
 
-use crate::api::rpc::{ApiClient, FlowSpec, RouteSpec};
+use crate::api::rpc::{ApiClient, FlowSpec, PeerSummary, RouteSpec};
+use crate::utils::get_host_address;
 
 mod display;
 mod table;
 
-use display::{AdvertisedRouteRow, LearnedRouteRow, PeerSummaryRow};
+use display::{
+    AdvertisedRouteRow, CapabilityRow, DampenedRouteRow, LearnedRouteRow, NodePeerSummaryRow,
+    PeerSummaryRow, RibPathRow,
+};
 
 #[derive(Parser, Debug)]
 #[clap(name = "bgpd-cli", rename_all = "kebab-case")]
@@ -227,16 +413,50 @@ use display::{AdvertisedRouteRow, LearnedRouteRow, PeerSummaryRow};
 pub struct Args {
     #[clap(subcommand)]
     pub cmd: Command,
-    #[clap(long, default_value = "127.0.0.1")]
-    pub host: String,
+    /// BGPd host(s) to query, can be repeated or comma separated (E.g. --host 10.0.0.1,10.0.0.2)
+    #[clap(
+        long,
+        default_value = "127.0.0.1",
+        multiple_occurrences = true,
+        use_value_delimiter = true
+    )]
+    pub host: Vec<String>,
     #[clap(short, long, default_value_t = 8080)]
     pub port: u16,
     /// API Listening address/port (E.g. 127.0.0.1:8080). If not provided, will fall back to config file value
     #[clap(long)]
     pub api: Option<SocketAddr>,
+    /// Output format for show/advertise/withdraw commands
+    #[clap(short, long, arg_enum, default_value = "table")]
+    pub output: OutputFormat,
     /// Show debug logs (additive for trace logs)
     #[clap(short, parse(from_occurrences), global = true)]
     pub verbose: u8,
+    /// Log line format
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "text",
+        env = "BGPD_LOG_FORMAT",
+        global = true
+    )]
+    pub log_format: LogFormat,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// env_logger text lines
+    Text,
+    /// A JSON object per line, with structured fields (E.g. peer, session state, message type)
+    Json,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Human-readable tables
+    Table,
+    /// Pretty-printed JSON (the raw API responses)
+    Json,
 }
 
 #[derive(Parser, Debug)]
@@ -246,6 +466,8 @@ pub enum Command {
     #[clap()]
     /// Run BGPd daemon
     Run(RunOptions),
+    /// Validate a config file (without starting BGPd)
+    Check(CheckOptions),
     #[clap(alias = "s")]
     /// View details about BGPd
     #[clap(subcommand)]
@@ -253,6 +475,19 @@ pub enum Command {
     /// Send routes to be advertised
     #[clap(subcommand)]
     Advertise(Advertise),
+    /// Withdraw routes advertised via the API
+    #[clap(subcommand)]
+    Withdraw(Withdraw),
+    /// Reset an established session with a peer
+    Clear(ClearOptions),
+    /// Enable or disable a peer (kept across config reloads)
+    Peer(PeerOptions),
+    /// Write a snapshot of the RIB to an MRT (TABLE_DUMP_V2) file
+    DumpRib(DumpRibOptions),
+    /// Stop the BGPd daemon, notifying established peers
+    Shutdown(ShutdownOptions),
+    /// Re-read the BGPd config file (like sending SIGHUP)
+    Reload,
 }
 
 #[derive(Parser, Debug)]
@@ -263,6 +498,13 @@ pub struct RunOptions {
     pub config_path: String,
 }
 
+#[derive(Parser, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub struct CheckOptions {
+    /// Path to BGP service config.toml
+    pub config_path: String,
+}
+
 #[derive(Parser, Debug)]
 #[clap(rename_all = "kebab-case")]
 pub enum Show {
@@ -272,6 +514,20 @@ pub enum Show {
     Neighbors(NeighborOptions),
     #[clap(alias = "r", subcommand)]
     Routes(Routes),
+    /// View all paths for each prefix in the RIB (> marks the best path)
+    Rib(RibOptions),
+    /// View an overview of peer sessions & RIB size
+    Summary,
+    /// View learned routes suppressed by Route Flap Dampening
+    Dampened,
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub struct RibOptions {
+    /// Only show prefixes within this network
+    #[clap()]
+    prefix: Option<IpNetwork>,
 }
 
 #[derive(Parser, Debug)]
@@ -280,6 +536,12 @@ pub enum ShowOptions {
     /// Show expanded details
     #[clap(alias = "d")]
     Detail,
+    /// Show prefixes dropped by import filters (requires a peer address)
+    #[clap(alias = "f")]
+    Filters,
+    /// Show capabilities advertised by each side of a session (requires a peer address)
+    #[clap(alias = "c")]
+    Capabilities,
 }
 
 #[derive(Parser, Debug)]
@@ -290,6 +552,9 @@ pub struct NeighborOptions {
     peer: Option<IpNetwork>,
     #[clap(subcommand)]
     options: Option<ShowOptions>,
+    /// Refresh the output every WATCH seconds (until Ctrl-C)
+    #[clap(short, long)]
+    watch: Option<u64>,
     // #[clap()]
     // family: Option<AFI>,
 }
@@ -297,16 +562,39 @@ pub struct NeighborOptions {
 #[derive(Parser, Debug)]
 #[clap(rename_all = "kebab-case")]
 pub enum Routes {
-    Learned(RouteOptions),
+    Learned(LearnedRouteOptions),
     Advertised(RouteOptions),
 }
 
+#[derive(Parser, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub struct LearnedRouteOptions {
+    /// IP Address or Network Prefix to match route source
+    #[clap()]
+    peer: Option<IpNetwork>,
+    /// Only show routes originated by this ASN (E.g. 65000 or 65000.100)
+    #[clap(long)]
+    origin_as: Option<String>,
+    /// Only show routes with this next hop
+    #[clap(long)]
+    next_hop: Option<IpAddr>,
+    /// Refresh the output every WATCH seconds (until Ctrl-C)
+    #[clap(short, long)]
+    watch: Option<u64>,
+}
+
 #[derive(Parser, Debug)]
 #[clap(rename_all = "kebab-case")]
 pub struct RouteOptions {
     /// IP Address or Network Prefix to match route source
     #[clap()]
     peer: Option<IpNetwork>,
+    /// Only show routes with this next hop
+    #[clap(long)]
+    next_hop: Option<IpAddr>,
+    /// Refresh the output every WATCH seconds (until Ctrl-C)
+    #[clap(short, long)]
+    watch: Option<u64>,
     // #[clap()]
     // family: Option<AFI>,
 }
@@ -320,6 +608,67 @@ pub enum Advertise {
     Flow(Flow),
 }
 
+#[derive(Parser, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub enum Withdraw {
+    #[clap()]
+    Route(WithdrawRoute),
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub struct WithdrawRoute {
+    /// Prefix to withdraw
+    #[clap()]
+    prefix: IpNetwork,
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub struct ClearOptions {
+    /// Peer IP of the session to reset
+    #[clap()]
+    peer: IpAddr,
+    /// Reason sent to the peer in the NOTIFICATION (RFC 9003 Shutdown Communication)
+    #[clap(long)]
+    reason: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub struct PeerOptions {
+    /// Peer IP (or an IP within a configured peer network)
+    #[clap()]
+    peer: IpAddr,
+    #[clap(subcommand)]
+    action: PeerAction,
+}
+
+#[derive(Parser, Debug, PartialEq)]
+#[clap(rename_all = "kebab-case")]
+pub enum PeerAction {
+    /// Resume connecting to (and accepting connections from) the peer
+    Enable,
+    /// End any active session and stop connecting to the peer
+    Disable,
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub struct DumpRibOptions {
+    /// File path to write (on the BGPd host)
+    #[clap()]
+    path: String,
+}
+
+#[derive(Parser, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub struct ShutdownOptions {
+    /// Message sent to peers in the NOTIFICATION (RFC 9003 Shutdown Communication)
+    #[clap(long)]
+    message: Option<String>,
+}
+
 #[derive(Parser, Debug)]
 #[clap(rename_all = "kebab-case")]
 pub struct Route {
@@ -332,7 +681,8 @@ pub struct Route {
     /// Origin (defaults to Incomplete)
     #[clap(short, long)]
     origin: Option<String>,
-    /// AS Path (e.g. --as-path 100 200 65000.100), defaults to an empty path
+    /// AS Path (e.g. --as-path "100 200 65000.100"), defaults to an empty path
+    /// ASNs in braces are an AS_SET (e.g. --as-path "100 200 {300 400}")
     #[clap(short, long, required = true)]
     as_path: Option<String>,
     /// Local Pref (defaults to 100)
@@ -341,9 +691,12 @@ pub struct Route {
     /// Multi-exit-discriminator
     #[clap(long)]
     med: Option<u32>,
-    /// Communities (e.g. --communities 100 200 redirect:65000:100)
+    /// Communities (e.g. --communities 100 65000:200 target:65000:1.1.1.1 65000:1:2 no-export)
     #[clap(short, long)]
     communities: Option<String>,
+    /// ORIGINATOR_ID (IPv4 Router ID) for simulating reflected routes
+    #[clap(long)]
+    originator_id: Option<IpAddr>,
 }
 
 #[derive(Parser, Debug)]
@@ -352,7 +705,7 @@ pub struct Flow {
     /// AFI [ipv6, ipv4]
     #[clap()]
     family: String,
-    /// Flowspec action E.g. "redirect 6:302" or "traffic-rate 302"
+    /// Flowspec action E.g. "redirect 6:302", "traffic-rate 302", or "discard"
     #[clap()]
     action: String,
     /// Origin (defaults to Incomplete)
@@ -361,7 +714,8 @@ pub struct Flow {
     /// Origin (defaults to Incomplete)
     #[clap(short, long)]
     origin: Option<String>,
-    /// AS Path (e.g. --as-path 100 200 65000.100), defaults to an empty path
+    /// AS Path (e.g. --as-path "100 200 65000.100"), defaults to an empty path
+    /// ASNs in braces are an AS_SET (e.g. --as-path "100 200 {300 400}")
     #[clap(short, long)]
     as_path: Option<String>,
     /// Local Pref (defaults to 100)
@@ -375,107 +729,464 @@ pub struct Flow {
     communities: Option<String>,
 }
 
+/// Print an API response as pretty-printed JSON
+fn print_json<T: Serialize>(value: &T) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn build_client(host: &str, port: u16) -> Result<HttpClient, Box<dyn Error>> {
+    let base = format!("http://{}:{}", host, port);
+    Ok(HttpClientBuilder::default().build(base)?)
+}
+
+/// Merge peer summaries from multiple nodes, labelling each peer with its node
+/// Returns the merged rows & any per-node errors
+fn merge_node_peers(
+    results: Vec<(String, Result<Vec<PeerSummary>, String>)>,
+) -> (Vec<NodePeerSummaryRow>, Vec<(String, String)>) {
+    let mut rows = vec![];
+    let mut errors = vec![];
+    for (node, result) in results {
+        match result {
+            Ok(peers) => rows.extend(
+                peers
+                    .into_iter()
+                    .map(|peer| NodePeerSummaryRow(node.clone(), PeerSummaryRow(peer))),
+            ),
+            Err(err) => errors.push((node, err)),
+        }
+    }
+    (rows, errors)
+}
+
+/// Query peer summaries from all hosts concurrently (a failed host doesn't affect the others)
+async fn query_node_peers(
+    clients: &[(String, HttpClient)],
+) -> Vec<(String, Result<Vec<PeerSummary>, String>)> {
+    join_all(clients.iter().map(|(host, client)| async move {
+        let peers = client.show_peers().await.map_err(|err| err.to_string());
+        (host.to_string(), peers)
+    }))
+    .await
+}
+
+/// Query all hosts concurrently and print a combined neighbor summary
+async fn show_neighbors_multi(
+    clients: &[(String, HttpClient)],
+    output: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let results = query_node_peers(clients).await;
+    if output == OutputFormat::Json {
+        // Peers keyed by node
+        let mut nodes = std::collections::BTreeMap::new();
+        for (node, peers) in results {
+            match peers {
+                Ok(peers) => {
+                    nodes.insert(node, peers);
+                }
+                Err(err) => eprintln!("{}", format!("Error querying {}: {}", node, err).red()),
+            }
+        }
+        return print_json(&nodes);
+    }
+    let (rows, errors) = merge_node_peers(results);
+    let mut table = table::OutputTable::new();
+    for row in rows {
+        table.add_row(&row)?;
+    }
+    table.print();
+    for (node, err) in errors {
+        eprintln!("{}", format!("Error querying {}: {}", node, err).red());
+    }
+    Ok(())
+}
+
+async fn show_neighbors(
+    client: &HttpClient,
+    options: &NeighborOptions,
+    output: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    if matches!(options.options, Some(ShowOptions::Capabilities)) {
+        let peer = options
+            .peer
+            .as_ref()
+            .and_then(get_host_address)
+            .ok_or("A peer address is required for capabilities")?;
+        let capabilities = client.show_peer_capabilities(peer).await?;
+        if output == OutputFormat::Json {
+            return print_json(&capabilities);
+        }
+        let mut table = table::OutputTable::new();
+        for capability in capabilities {
+            table.add_row(&CapabilityRow(capability))?;
+        }
+        table.print();
+    } else if matches!(options.options, Some(ShowOptions::Filters)) {
+        let peer = options
+            .peer
+            .as_ref()
+            .and_then(get_host_address)
+            .ok_or("A peer address is required for filter stats")?;
+        let stats = client.show_peer_filter_stats(peer).await?;
+        if output == OutputFormat::Json {
+            return print_json(&stats);
+        }
+        let lines = [
+            format!("Prefixes dropped by import filters for {}", peer),
+            format!("  prefix_list_in          {}", stats.prefix_list),
+            format!("  Bogon                   {}", stats.bogon),
+            format!("  AS_PATH loop            {}", stats.as_path_loop),
+            format!("  AS_PATH first AS        {}", stats.first_as),
+            format!("  Community               {}", stats.community),
+            format!("  Flowspec validation     {}", stats.flowspec_validation),
+            format!("  Reflection loop         {}", stats.reflection_loop),
+            format!("  Route target            {}", stats.route_target),
+        ];
+        println!("{}", lines.join("\n"));
+    } else if matches!(options.options, Some(ShowOptions::Detail)) {
+        let peers: Vec<_> = client.show_peer_detail().await?;
+        if output == OutputFormat::Json {
+            return print_json(&peers);
+        }
+        for peer in peers {
+            let summ = peer.summary;
+            let mut lines: Vec<String> = Vec::with_capacity(16);
+            lines.push(format!(
+                "BGP neighbor is {},  remote AS {}, local AS {}",
+                summ.peer, summ.remote_asn, summ.local_asn
+            ));
+            if !summ.enabled {
+                lines.push("*Peer is Disabled".to_string());
+            }
+            if peer.passive {
+                lines.push("Passive, only accepting incoming connections".to_string());
+            }
+            if let Some(router_id) = summ.router_id {
+                lines.push(format!("BGP version 4,  remote router-id {}", router_id));
+                if let Some(stream) = peer.tcp_connection {
+                    lines.push(format!("  Local address: {}", stream.0));
+                    lines.push(format!("  Remote address: {}", stream.1));
+                }
+                lines.push(format!(
+                    "BGP state = {}, up for {}",
+                    summ.state,
+                    summ.uptime.unwrap(),
+                ));
+                lines.push(format!(
+                    "Hold time is {} ({}), keepalive interval is {}",
+                    peer.hold_timer,
+                    peer.hold_time.unwrap(),
+                    peer.hold_timer_interval,
+                ));
+                lines.push(format!(
+                    "  Configured {}, negotiated {}",
+                    peer.configured_hold_time,
+                    peer.negotiated_hold_time
+                        .map(|h| h.to_string())
+                        .unwrap_or_else(|| "---".to_string()),
+                ));
+                lines.push(format!(
+                    "  Last read {}, last write {}",
+                    peer.last_received.unwrap(),
+                    peer.last_sent.unwrap(),
+                ));
+            }
+            lines.push("Neighbor capabilities:".to_string());
+            for capability in &peer.capabilities {
+                lines.push(format!("  {}", capability));
+            }
+            lines.push("".to_owned());
+            if let (Some(sent), Some(rcvd)) = (summ.msg_sent, summ.msg_received) {
+                lines.push("Message Statistics:".to_string());
+                lines.push("                      Sent      Received".to_string());
+                for stat in &peer.message_stats {
+                    let label = match stat.name.as_str() {
+                        "OPEN" => "Opens",
+                        "UPDATE" => "Updates",
+                        "KEEPALIVE" => "Keepalives",
+                        "NOTIFICATION" => "Notifications",
+                        "ROUTEREFRESH" => "Route Refresh",
+                        name => name,
+                    };
+                    lines.push(format!("  {:<20}{:<10}{}", label, stat.sent, stat.received));
+                }
+                lines.push(format!("  {:<20}{:<10}{}", "Total", sent, rcvd));
+                for stat in &peer.prefix_stats {
+                    let label = format!("Prefixes {}", stat.name.to_lowercase());
+                    lines.push(format!("  {:<20}{:<10}{}", label, stat.sent, stat.received));
+                }
+                if let (Some(sent), Some(rcvd)) = (peer.bytes_sent, peer.bytes_received) {
+                    lines.push(format!("  {:<20}{:<10}{}", "Bytes", sent, rcvd));
+                }
+                if let Some(rate) = peer.updates_per_second {
+                    lines.push(format!("  Updates per second: {:.2}", rate));
+                }
+            }
+            if let Some(dropped) = peer.prefixes_dropped_loop {
+                lines.push(format!("Prefixes dropped for AS_PATH loop: {}", dropped));
+            }
+            if peer.prefix_limit_warning {
+                lines
+                    .push("Prefix limit warning: max_prefix_warning threshold crossed".to_string());
+            }
+            if let Some(error) = &peer.last_error {
+                lines.push(format!("Last error: {}", error));
+            }
+            if let Some(notification) = &peer.last_notification {
+                lines.push(format!("Last notification: {}", notification));
+            }
+            println!("{}\n", lines.join("\n  "));
+        }
+    } else {
+        let peers = client.show_peers().await?;
+        if output == OutputFormat::Json {
+            return print_json(&peers);
+        }
+        let peers: Vec<_> = peers.into_iter().map(PeerSummaryRow).collect();
+        let mut table = table::OutputTable::new();
+        for peer in peers {
+            table.add_row(&peer)?;
+        }
+        table.print();
+    }
+    Ok(())
+}
+
+async fn show_routes_learned(
+    client: &HttpClient,
+    options: &LearnedRouteOptions,
+    output: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let mut routes: Vec<_> = client
+        .show_routes_learned(options.peer, options.origin_as.clone(), options.next_hop)
+        .await?;
+    if output == OutputFormat::Json {
+        return print_json(&routes);
+    }
+    routes.sort_by_key(|r| (r.afi.clone(), r.safi.clone()));
+    for (afi, routes) in &routes.into_iter().group_by(|r| r.afi.clone()) {
+        for (safi, routes) in &routes.group_by(|r| r.safi.clone()) {
+            println!("{} / {}", afi, safi);
+            let mut table = table::OutputTable::new();
+            for route in routes {
+                table.add_row(&LearnedRouteRow(route))?;
+            }
+            table.print();
+            println!();
+        }
+    }
+    Ok(())
+}
+
+async fn show_rib(
+    client: &HttpClient,
+    options: &RibOptions,
+    output: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    // Paths are grouped by family & prefix (best path first)
+    let paths: Vec<_> = client.show_rib(options.prefix).await?;
+    if output == OutputFormat::Json {
+        return print_json(&paths);
+    }
+    let by_family = paths
+        .into_iter()
+        .group_by(|p| (p.route.afi.clone(), p.route.safi.clone()));
+    for ((afi, safi), paths) in &by_family {
+        println!("{} / {}", afi, safi);
+        let mut table = table::OutputTable::new();
+        let mut last_prefix: Option<String> = None;
+        for path in paths {
+            let first = last_prefix.as_ref() != Some(&path.route.prefix);
+            last_prefix = Some(path.route.prefix.clone());
+            table.add_row(&RibPathRow(path, first))?;
+        }
+        table.print();
+        println!();
+    }
+    Ok(())
+}
+
+async fn show_dampened(client: &HttpClient, output: OutputFormat) -> Result<(), Box<dyn Error>> {
+    let routes = client.show_dampened().await?;
+    if output == OutputFormat::Json {
+        return print_json(&routes);
+    }
+    let mut table = table::OutputTable::new();
+    for route in routes {
+        table.add_row(&DampenedRouteRow(route))?;
+    }
+    table.print();
+    Ok(())
+}
+
+async fn show_summary(client: &HttpClient, output: OutputFormat) -> Result<(), Box<dyn Error>> {
+    let summary = client.show_summary().await?;
+    if output == OutputFormat::Json {
+        return print_json(&summary);
+    }
+    let mut lines = vec![
+        format!(
+            "BGP router identifier {}, local AS number {}",
+            summary.router_id, summary.local_asn
+        ),
+        format!(
+            "Peers: {} configured, {} established",
+            summary.peers_configured, summary.peers_established
+        ),
+        format!("RIB entries: {}", summary.rib_entries),
+    ];
+    for family in summary.families {
+        lines.push(format!(
+            "  {:<24}{}",
+            format!("{} {}", family.afi, family.safi),
+            family.entries
+        ));
+    }
+    println!("{}", lines.join("\n"));
+    Ok(())
+}
+
+async fn show_routes_advertised(
+    client: &HttpClient,
+    options: &RouteOptions,
+    output: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let mut routes: Vec<_> = client
+        .show_routes_advertised(options.peer, options.next_hop)
+        .await?;
+    if output == OutputFormat::Json {
+        return print_json(&routes);
+    }
+    routes.sort_by_key(|r| (r.afi.clone(), r.safi.clone()));
+    for (afi, routes) in &routes.into_iter().group_by(|r| r.afi.clone()) {
+        for (safi, routes) in &routes.group_by(|r| r.safi.clone()) {
+            println!("{} / {}", afi, safi);
+            let mut table = table::OutputTable::new();
+            for route in routes {
+                table.add_row(&AdvertisedRouteRow(route))?;
+            }
+            table.print();
+            println!();
+        }
+    }
+    Ok(())
+}
+
+/// Render once, or if an `interval` (seconds) is given clear the screen
+/// and re-render every interval until interrupted (Ctrl-C)
+async fn watch<F, Fut>(interval: Option<u64>, render: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), Box<dyn Error>>>,
+{
+    let mut render = render;
+    match interval {
+        None => render().await,
+        Some(interval) => {
+            let render = || {
+                // Clear screen & move cursor to top-left
+                print!("\x1B[2J\x1B[1;1H");
+                render()
+            };
+            let interrupted = async {
+                // Without a handler, Ctrl-C still exits (without waiting for the render)
+                if tokio::signal::ctrl_c().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            };
+            watch_loop(Duration::from_secs(interval), interrupted, render).await
+        }
+    }
+}
+
+/// Call `render` every `interval` until `cancel` completes
+/// A render in progress (E.g. waiting on a hung API request) is cancelled too
+async fn watch_loop<F, Fut, C>(
+    interval: Duration,
+    cancel: C,
+    mut render: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), Box<dyn Error>>>,
+    C: Future<Output = ()>,
+{
+    tokio::pin!(cancel);
+    loop {
+        let next = tokio::time::Instant::now() + interval;
+        tokio::select! {
+            result = render() => result?,
+            _ = &mut cancel => return Ok(()),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep_until(next) => (),
+            _ = &mut cancel => return Ok(()),
+        }
+    }
+}
+
 async fn run_cmd(args: &Args) -> Result<(), Box<dyn Error>> {
-    let client = {
-        let base = format!("http://{}:{}", args.host, args.port);
-        HttpClientBuilder::default().build(base)?
-    };
+    if let [host] = args.host.as_slice() {
+        return run_host_cmd(args, host).await;
+    }
+    if let Command::Show(Show::Neighbors(NeighborOptions {
+        options: None,
+        watch: interval,
+        ..
+    })) = &args.cmd
+    {
+        let clients = args
+            .host
+            .iter()
+            .map(|host| build_client(host, args.port).map(|client| (host.to_string(), client)))
+            .collect::<Result<Vec<_>, _>>()?;
+        return watch(*interval, || show_neighbors_multi(&clients, args.output)).await;
+    }
+    // Other commands are run against each node in turn,
+    // errors are reported per-node without aborting the rest
+    for host in &args.host {
+        // Keep JSON output parseable (as a stream of JSON values)
+        if args.output == OutputFormat::Table {
+            println!("{}", host.bold());
+        }
+        if let Err(err) = run_host_cmd(args, host).await {
+            eprintln!("{}", format!("Error querying {}: {}", host, err).red());
+        }
+        if args.output == OutputFormat::Table {
+            println!();
+        }
+    }
+    Ok(())
+}
+
+async fn run_host_cmd(args: &Args, host: &str) -> Result<(), Box<dyn Error>> {
+    let client = build_client(host, args.port)?;
     match &args.cmd {
         Command::Show(show) => match show {
             Show::Neighbors(options) => {
-                if matches!(options.options, Some(ShowOptions::Detail)) {
-                    let peers: Vec<_> = client.show_peer_detail().await?;
-                    for peer in peers {
-                        let summ = peer.summary;
-                        let mut lines: Vec<String> = Vec::with_capacity(16);
-                        lines.push(format!(
-                            "BGP neighbor is {},  remote AS {}, local AS {}",
-                            summ.peer, summ.remote_asn, summ.local_asn
-                        ));
-                        if !summ.enabled {
-                            lines.push("*Peer is Disabled".to_string());
-                        }
-                        if let Some(router_id) = summ.router_id {
-                            lines.push(format!("BGP version 4,  remote router-id {}", router_id));
-                            if let Some(stream) = peer.tcp_connection {
-                                lines.push(format!("  Local address: {}", stream.0));
-                                lines.push(format!("  Remote address: {}", stream.1));
-                            }
-                            lines.push(format!(
-                                "BGP state = {}, up for {}",
-                                summ.state,
-                                summ.uptime.unwrap(),
-                            ));
-                            lines.push(format!(
-                                "Hold time is {} ({}), keepalive interval is {}",
-                                peer.hold_timer,
-                                peer.hold_time.unwrap(),
-                                peer.hold_timer_interval,
-                            ));
-                            lines.push(format!(
-                                "  Last read {}, last write {}",
-                                peer.last_received.unwrap(),
-                                peer.last_sent.unwrap(),
-                            ));
-                        }
-                        lines.push("Neighbor capabilities:".to_string());
-                        for capability in &peer.capabilities {
-                            lines.push(format!("  {}", capability));
-                        }
-                        lines.push("".to_owned());
-                        if let (Some(sent), Some(rcvd)) = (summ.msg_received, summ.msg_sent) {
-                            lines.push("Message Statistics:".to_string());
-                            lines.push("                    Sent      Received".to_string());
-                            lines.push(format!("  Total             {}        {}", sent, rcvd));
-                        }
-                        println!("{}\n", lines.join("\n  "));
-                    }
-                } else {
-                    let peers: Vec<_> = client
-                        .show_peers()
-                        .await?
-                        .into_iter()
-                        .map(PeerSummaryRow)
-                        .collect();
-                    let mut table = table::OutputTable::new();
-                    for peer in peers {
-                        table.add_row(&peer)?;
-                    }
-                    table.print();
-                }
+                watch(options.watch, || {
+                    show_neighbors(&client, options, args.output)
+                })
+                .await?
             }
             Show::Routes(routes) => match routes {
                 Routes::Learned(options) => {
-                    let mut routes: Vec<_> = client.show_routes_learned(options.peer).await?;
-                    routes.sort_by_key(|r| (r.afi.clone(), r.safi.clone()));
-                    for (afi, routes) in &routes.into_iter().group_by(|r| r.afi.clone()) {
-                        for (safi, routes) in &routes.group_by(|r| r.safi.clone()) {
-                            println!("{} / {}", afi, safi);
-                            let mut table = table::OutputTable::new();
-                            for route in routes {
-                                table.add_row(&LearnedRouteRow(route))?;
-                            }
-                            table.print();
-                            println!();
-                        }
-                    }
+                    watch(options.watch, || {
+                        show_routes_learned(&client, options, args.output)
+                    })
+                    .await?
                 }
                 Routes::Advertised(options) => {
-                    let mut routes: Vec<_> = client.show_routes_advertised(options.peer).await?;
-                    routes.sort_by_key(|r| (r.afi.clone(), r.safi.clone()));
-                    for (afi, routes) in &routes.into_iter().group_by(|r| r.afi.clone()) {
-                        for (safi, routes) in &routes.group_by(|r| r.safi.clone()) {
-                            println!("{} / {}", afi, safi);
-                            let mut table = table::OutputTable::new();
-                            for route in routes {
-                                table.add_row(&AdvertisedRouteRow(route))?;
-                            }
-                            table.print();
-                            println!();
-                        }
-                    }
+                    watch(options.watch, || {
+                        show_routes_advertised(&client, options, args.output)
+                    })
+                    .await?
                 }
             },
+            Show::Rib(options) => show_rib(&client, options, args.output).await?,
+            Show::Summary => show_summary(&client, args.output).await?,
+            Show::Dampened => show_dampened(&client, args.output).await?,
         },
         Command::Advertise(advertise) => match advertise {
             Advertise::Route(route) => {
@@ -489,6 +1200,7 @@ async fn run_cmd(args: &Args) -> Result<(), Box<dyn Error>> {
                 if let Some(med) = &route.med {
                     spec.attributes.multi_exit_disc = Some(*med);
                 }
+                spec.attributes.originator_id = route.originator_id;
                 if let Some(as_path) = &route.as_path {
                     spec.attributes.as_path =
                         as_path.split(' ').map(|asn| asn.to_string()).collect();
@@ -500,6 +1212,7 @@ async fn run_cmd(args: &Args) -> Result<(), Box<dyn Error>> {
                         .collect();
                 }
                 match client.advertise_route(spec).await {
+                    Ok(advertised) if args.output == OutputFormat::Json => print_json(&advertised)?,
                     Ok(advertised) => {
                         println!("Added route to RIB for announcement:");
                         let mut table = table::OutputTable::new();
@@ -539,6 +1252,7 @@ async fn run_cmd(args: &Args) -> Result<(), Box<dyn Error>> {
                         .collect();
                 }
                 match client.advertise_flow(spec).await {
+                    Ok(advertised) if args.output == OutputFormat::Json => print_json(&advertised)?,
                     Ok(advertised) => {
                         println!("Added flow to RIB for announcement:");
                         let mut table = table::OutputTable::new();
@@ -549,7 +1263,67 @@ async fn run_cmd(args: &Args) -> Result<(), Box<dyn Error>> {
                 }
             }
         },
-        _ => unimplemented!(), // ::Run should never get called since it's handled in main
+        Command::Withdraw(withdraw) => match withdraw {
+            Withdraw::Route(route) => match client.withdraw_route(route.prefix).await {
+                Ok(withdrawn) if args.output == OutputFormat::Json => print_json(&withdrawn)?,
+                Ok(withdrawn) => {
+                    println!("Removed route from RIB for withdrawal:");
+                    let mut table = table::OutputTable::new();
+                    for route in withdrawn {
+                        table.add_row(&LearnedRouteRow(route))?;
+                    }
+                    table.print();
+                }
+                Err(err) => eprintln!("Error withdrawing route: {}", err),
+            },
+        },
+        Command::Clear(options) => match client
+            .clear_session(options.peer, options.reason.clone())
+            .await
+        {
+            Ok(()) => println!("Cleared session with {}", options.peer),
+            Err(err) => eprintln!("Error clearing session: {}", err),
+        },
+        Command::Peer(options) => {
+            let enabled = options.action == PeerAction::Enable;
+            match client.set_peer_enabled(options.peer, enabled).await {
+                Ok(()) if enabled => println!("Enabled peer {}", options.peer),
+                Ok(()) => println!("Disabled peer {}", options.peer),
+                Err(err) => eprintln!("Error updating peer: {}", err),
+            }
+        }
+        Command::DumpRib(options) => match client.dump_rib_mrt(options.path.clone()).await {
+            Ok(()) => println!("Wrote RIB to {}", options.path),
+            Err(err) => eprintln!("Error dumping RIB: {}", err),
+        },
+        Command::Shutdown(options) => match client.shutdown(options.message.clone()).await {
+            Ok(()) => println!("BGPd is shutting down"),
+            Err(err) => eprintln!("Error shutting down: {}", err),
+        },
+        Command::Reload => match client.reload().await {
+            Ok(summary) if args.output == OutputFormat::Json => print_json(&summary)?,
+            Ok(summary) => {
+                println!("Reloaded config");
+                for (action, peers) in [
+                    ("Added", &summary.peers_added),
+                    ("Removed", &summary.peers_removed),
+                    ("Changed", &summary.peers_changed),
+                ] {
+                    if !peers.is_empty() {
+                        println!("  {} peers: {}", action, peers.join(", "));
+                    }
+                }
+                if summary.static_routes_added > 0 || summary.static_routes_removed > 0 {
+                    println!(
+                        "  Static routes: {} added, {} removed",
+                        summary.static_routes_added, summary.static_routes_removed
+                    );
+                }
+            }
+            Err(err) => eprintln!("Error reloading config: {}", err),
+        },
+        // ::Run and ::Check should never get called since they're handled in main
+        _ => unimplemented!(),
     }
     Ok(())
 }
@@ -560,3 +1334,367 @@ pub async fn query_bgpd(args: &Args) {
         eprintln!("{}", err.to_string().red());
     }
 }
+
+/// Validate a config file, printing a summary and any errors
+/// Returns false if the config is invalid
+pub fn check_config(options: &CheckOptions) -> bool {
+    let config = match crate::config::from_file(&options.config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!(
+                "{}",
+                format!("Error parsing {}: {}", options.config_path, err).red()
+            );
+            return false;
+        }
+    };
+    println!(
+        "{}: {} peers, {} static routes, {} static flows",
+        options.config_path,
+        config.peers.len(),
+        config
+            .peers
+            .iter()
+            .map(|p| p.static_routes.len())
+            .sum::<usize>(),
+        config
+            .peers
+            .iter()
+            .map(|p| p.static_flows.len())
+            .sum::<usize>(),
+    );
+    for (a, b) in config.overlapping_peers() {
+        println!(
+            "{}",
+            format!("Warning: Peer configs {} and {} overlap", a, b).yellow()
+        );
+    }
+    match config.validate() {
+        Ok(()) => {
+            println!("{}", "Config is valid".green());
+            true
+        }
+        Err(errors) => {
+            for error in errors.iter() {
+                eprintln!("{}", error.red());
+            }
+            eprintln!("Found {} errors", errors.len());
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::table::ToRow;
+    use jsonrpsee::http_server::{HttpServerBuilder, HttpServerHandle};
+
+    fn peer(addr: &str) -> PeerSummary {
+        PeerSummary {
+            peer: addr.to_string(),
+            enabled: true,
+            router_id: None,
+            remote_asn: 65000,
+            local_asn: 65000,
+            msg_received: None,
+            msg_sent: None,
+            connect_time: None,
+            uptime: None,
+            state: "Idle".to_string(),
+            prefixes_received: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_node_peers() {
+        let results = vec![
+            (
+                "10.0.0.1".to_string(),
+                Ok(vec![peer("127.0.0.2"), peer("127.0.0.3")]),
+            ),
+            (
+                "10.0.0.2".to_string(),
+                Err("Connection refused".to_string()),
+            ),
+            ("10.0.0.3".to_string(), Ok(vec![peer("127.0.0.2")])),
+        ];
+        let (rows, errors) = merge_node_peers(results);
+        let nodes: Vec<_> = rows
+            .iter()
+            .map(|r| (r.0.as_str(), r.1 .0.peer.as_str()))
+            .collect();
+        assert_eq!(
+            nodes,
+            vec![
+                ("10.0.0.1", "127.0.0.2"),
+                ("10.0.0.1", "127.0.0.3"),
+                ("10.0.0.3", "127.0.0.2")
+            ]
+        );
+        assert_eq!(
+            errors,
+            vec![("10.0.0.2".to_string(), "Connection refused".to_string())]
+        );
+        let row = rows[0].to_row().unwrap();
+        assert_eq!(row.get_cell(0).unwrap().get_content(), "10.0.0.1");
+        assert_eq!(NodePeerSummaryRow::columns().len(), 9);
+    }
+
+    /// Serve `show_peers` with these peers, returns the server's port
+    async fn stub_api_server(peers: Vec<PeerSummary>) -> (u16, HttpServerHandle) {
+        let peers = serde_json::to_value(peers).unwrap();
+        let mut module = jsonrpsee::RpcModule::new(());
+        module
+            .register_method("show_peers", move |_, _| Ok(peers.clone()))
+            .unwrap();
+        let server = HttpServerBuilder::default()
+            .build("127.0.0.1:0")
+            .await
+            .unwrap();
+        let port = server.local_addr().unwrap().port();
+        (port, server.start(module).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_query_multiple_nodes() {
+        let (port_a, _a) = stub_api_server(vec![peer("127.0.0.2"), peer("127.0.0.3")]).await;
+        let (port_b, _b) = stub_api_server(vec![peer("127.0.0.4")]).await;
+        // Nothing is listening on port 1
+        let nodes = [("node-a", port_a), ("unreachable", 1), ("node-b", port_b)];
+        let clients: Vec<_> = nodes
+            .iter()
+            .map(|(node, port)| (node.to_string(), build_client("127.0.0.1", *port).unwrap()))
+            .collect();
+
+        let (rows, errors) = merge_node_peers(query_node_peers(&clients).await);
+        let peers: Vec<_> = rows
+            .iter()
+            .map(|r| (r.0.as_str(), r.1 .0.peer.as_str()))
+            .collect();
+        assert_eq!(
+            peers,
+            vec![
+                ("node-a", "127.0.0.2"),
+                ("node-a", "127.0.0.3"),
+                ("node-b", "127.0.0.4")
+            ]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "unreachable");
+        assert!(!errors[0].1.is_empty());
+    }
+
+    #[test]
+    fn test_multiple_hosts() {
+        let args = Args::parse_from([
+            "bgpd",
+            "--host",
+            "10.0.0.1,10.0.0.2",
+            "--host",
+            "10.0.0.3",
+            "show",
+            "neighbors",
+        ]);
+        assert_eq!(args.host, vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+        let args = Args::parse_from(["bgpd", "show", "neighbors"]);
+        assert_eq!(args.host, vec!["127.0.0.1"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch_loop() {
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let mut stop_tx = Some(stop_tx);
+        let mut calls: Vec<tokio::time::Instant> = vec![];
+        let interval = Duration::from_secs(2);
+        let cancel = async {
+            stop_rx.await.ok();
+        };
+        watch_loop(interval, cancel, || {
+            calls.push(tokio::time::Instant::now());
+            // Simulate a signal after the 3rd render
+            if calls.len() == 3 {
+                stop_tx.take().unwrap().send(()).unwrap();
+            }
+            async { Ok(()) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls.len(), 3);
+        for pair in calls.windows(2) {
+            assert!(pair[1] - pair[0] >= interval);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch_loop_cancels_render() {
+        let start = tokio::time::Instant::now();
+        // Render never completes (E.g. a hung API request)
+        let cancel = tokio::time::sleep(Duration::from_secs(1));
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            watch_loop(Duration::from_secs(2), cancel, || {
+                std::future::pending::<Result<(), Box<dyn Error>>>()
+            }),
+        )
+        .await
+        .expect("Blocked render is cancelled");
+        assert!(result.is_ok());
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_parse_withdraw_route() {
+        let args = Args::parse_from(["bgpd", "withdraw", "route", "10.0.0.0/24"]);
+        match args.cmd {
+            Command::Withdraw(Withdraw::Route(route)) => {
+                assert_eq!(route.prefix, "10.0.0.0/24".parse::<IpNetwork>().unwrap())
+            }
+            other => panic!("Expected withdraw route, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_output_format() {
+        let args = Args::parse_from(["bgpd", "show", "neighbors"]);
+        assert_eq!(args.output, OutputFormat::Table);
+        let args = Args::parse_from(["bgpd", "-o", "json", "show", "routes", "learned"]);
+        assert_eq!(args.output, OutputFormat::Json);
+        let args = Args::parse_from([
+            "bgpd",
+            "--output",
+            "json",
+            "advertise",
+            "route",
+            "10.0.0.0/24",
+            "127.0.0.1",
+            "-o",
+            "igp",
+            "-a",
+            "100",
+        ]);
+        assert_eq!(args.output, OutputFormat::Json);
+        assert!(Args::try_parse_from(["bgpd", "-o", "yaml", "show", "neighbors"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_neighbor_filters() {
+        let args = Args::parse_from(["bgpd", "show", "neighbors", "172.16.20.2", "filters"]);
+        match args.cmd {
+            Command::Show(Show::Neighbors(options)) => {
+                assert!(matches!(options.options, Some(ShowOptions::Filters)));
+                assert_eq!(options.peer, Some("172.16.20.2".parse().unwrap()));
+            }
+            other => panic!("Expected show neighbors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_clear() {
+        let args = Args::parse_from(["bgpd", "clear", "172.16.20.2"]);
+        match args.cmd {
+            Command::Clear(options) => {
+                assert_eq!(options.peer, "172.16.20.2".parse::<IpAddr>().unwrap());
+                assert!(options.reason.is_none());
+            }
+            other => panic!("Expected clear, got {:?}", other),
+        }
+        let args = Args::parse_from(["bgpd", "clear", "172.16.20.2", "--reason", "Maintenance"]);
+        match args.cmd {
+            Command::Clear(options) => assert_eq!(options.reason.as_deref(), Some("Maintenance")),
+            other => panic!("Expected clear, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_peer() {
+        let args = Args::parse_from(["bgpd", "peer", "172.16.20.2", "disable"]);
+        match args.cmd {
+            Command::Peer(options) => {
+                assert_eq!(options.peer, "172.16.20.2".parse::<IpAddr>().unwrap());
+                assert_eq!(options.action, PeerAction::Disable);
+            }
+            other => panic!("Expected peer, got {:?}", other),
+        }
+        let args = Args::parse_from(["bgpd", "peer", "172.16.20.2", "enable"]);
+        match args.cmd {
+            Command::Peer(options) => assert_eq!(options.action, PeerAction::Enable),
+            other => panic!("Expected peer, got {:?}", other),
+        }
+        assert!(Args::try_parse_from(["bgpd", "peer", "172.16.20.2", "reset"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_dump_rib() {
+        let args = Args::parse_from(["bgpd", "dump-rib", "/tmp/rib.mrt"]);
+        match args.cmd {
+            Command::DumpRib(options) => assert_eq!(options.path, "/tmp/rib.mrt"),
+            other => panic!("Expected dump-rib, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_show_summary() {
+        let args = Args::parse_from(["bgpd", "show", "summary"]);
+        assert!(matches!(args.cmd, Command::Show(Show::Summary)));
+    }
+
+    #[test]
+    fn test_parse_show_dampened() {
+        let args = Args::parse_from(["bgpd", "show", "dampened"]);
+        assert!(matches!(args.cmd, Command::Show(Show::Dampened)));
+    }
+
+    #[test]
+    fn test_parse_log_format() {
+        let args = Args::parse_from(["bgpd", "run", "config.toml"]);
+        assert_eq!(args.log_format, LogFormat::Text);
+        let args = Args::parse_from(["bgpd", "run", "config.toml", "--log-format", "json"]);
+        assert_eq!(args.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn test_parse_reload() {
+        let args = Args::parse_from(["bgpd", "reload"]);
+        assert!(matches!(args.cmd, Command::Reload));
+    }
+
+    #[test]
+    fn test_parse_shutdown() {
+        let args = Args::parse_from(["bgpd", "shutdown"]);
+        match args.cmd {
+            Command::Shutdown(options) => assert!(options.message.is_none()),
+            other => panic!("Expected shutdown, got {:?}", other),
+        }
+        let args = Args::parse_from(["bgpd", "shutdown", "--message", "Upgrading"]);
+        match args.cmd {
+            Command::Shutdown(options) => assert_eq!(options.message.as_deref(), Some("Upgrading")),
+            other => panic!("Expected shutdown, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_show_rib() {
+        let args = Args::parse_from(["bgpd", "show", "rib"]);
+        match args.cmd {
+            Command::Show(Show::Rib(options)) => assert!(options.prefix.is_none()),
+            other => panic!("Expected show rib, got {:?}", other),
+        }
+        let args = Args::parse_from(["bgpd", "show", "rib", "10.0.0.0/16"]);
+        match args.cmd {
+            Command::Show(Show::Rib(options)) => {
+                assert_eq!(options.prefix, "10.0.0.0/16".parse().ok())
+            }
+            other => panic!("Expected show rib, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_check() {
+        let args = Args::parse_from(["bgpd", "check", "config.toml"]);
+        match args.cmd {
+            Command::Check(options) => assert_eq!(options.config_path, "config.toml"),
+            other => panic!("Expected check, got {:?}", other),
+        }
+    }
+}
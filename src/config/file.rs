@@ -1,15 +1,19 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 use std::fs::File;
 use std::io::{self, Read};
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 
 use bgp_rs::{AFI, SAFI};
 use ipnetwork::IpNetwork;
 use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
-use toml;
+use toml::{self, value::Table, Value};
 
 use crate::api::rpc::{FlowSpec, RouteSpec};
-use crate::rib::Family;
+use crate::rib::{Community, CommunityMatch, Family, PrefixListEntry};
+use crate::session::{capability_code, Role};
 
 struct Defaults {}
 
@@ -18,8 +22,12 @@ impl Defaults {
         "[::]:8080".parse().expect("Can parse default socket")
     }
 
-    fn bgp_socket() -> SocketAddr {
-        "[::]:179".parse().expect("Can parse default socket")
+    fn bgp_sockets() -> Vec<SocketAddr> {
+        vec!["[::]:179".parse().expect("Can parse default socket")]
+    }
+
+    fn bgp_backlog() -> u32 {
+        1024
     }
 
     fn enabled() -> bool {
@@ -34,14 +42,62 @@ impl Defaults {
         30
     }
 
+    fn fib_install() -> bool {
+        false
+    }
+
+    fn flowspec_validation() -> bool {
+        false
+    }
+
     fn hold_timer() -> u16 {
         180
     }
 
+    fn connect_retry() -> u16 {
+        120
+    }
+
     fn dest_port() -> u16 {
         179
     }
 
+    fn separate_capabilities() -> bool {
+        false
+    }
+
+    fn log_capabilities() -> bool {
+        true
+    }
+
+    fn extended_messages() -> bool {
+        true
+    }
+
+    fn initial_batch() -> bool {
+        false
+    }
+
+    fn strict_fsm() -> bool {
+        false
+    }
+
+    fn strict_role() -> bool {
+        false
+    }
+
+    fn reject_bogons() -> bool {
+        false
+    }
+
+    fn add_path() -> bool {
+        false
+    }
+
+    fn normalize_mapped_nexthop() -> bool {
+        false
+    }
+
     fn families() -> Vec<Family> {
         vec![
             Family::new(AFI::IPV4, SAFI::Unicast),
@@ -54,6 +110,22 @@ impl Defaults {
     fn advertise_sources() -> Vec<AdvertiseSource> {
         vec![AdvertiseSource::Api, AdvertiseSource::Config]
     }
+
+    fn half_life() -> u32 {
+        900
+    }
+
+    fn suppress_limit() -> u32 {
+        2000
+    }
+
+    fn reuse_limit() -> u32 {
+        750
+    }
+
+    fn max_penalty() -> u32 {
+        12000
+    }
 }
 
 /// Config (toml) representation of a Peer Config
@@ -69,6 +141,8 @@ pub(super) struct PeerConfigSpec {
     // Peer is configured and allowed to connect
     #[serde(default = "Defaults::enabled")]
     pub(super) enabled: bool,
+    // Reason sent (RFC 9003 Shutdown Communication) when the session is ended by disabling the peer
+    pub(super) shutdown_message: Option<String>,
 
     // Only listen to incoming TCP sessions for passive peers
     // And don't attempt outbound TCP connections
@@ -80,14 +154,149 @@ pub(super) struct PeerConfigSpec {
     #[serde(default = "Defaults::hold_timer")]
     pub(super) hold_timer: u16,
 
+    // Cap the keepalive interval (E.g. to keep NAT/firewall state alive with large hold times)
+    pub(super) min_keepalive: Option<u16>,
+
+    // Seconds to wait before retrying a failed outbound connection
+    // Doubles with each consecutive failure (up to 8x)
+    #[serde(default = "Defaults::connect_retry")]
+    pub(super) connect_retry: u16,
+
+    // Seconds to wait for the peer's OPEN before sending ours (DelayOpen, RFC 4271)
+    // Avoids racing OPENs (& a collision) when both sides connect at the same time
+    pub(super) delay_open: Option<u16>,
+
+    // Tear down the session if the peer sends more than this many prefixes
+    #[serde(alias = "max_prefix_in")]
+    pub(super) max_prefixes: Option<u32>,
+    // Log a warning (once) when received prefixes cross this percentage of `max_prefixes`
+    // (the session isn't torn down until `max_prefixes` is exceeded)
+    #[serde(alias = "max_prefix_in_warn_pct")]
+    pub(super) max_prefix_warning: Option<u8>,
+    // Stop advertising additional prefixes to the peer beyond this many
+    pub(super) max_prefix_out: Option<u32>,
+
+    // TCP MD5 Signature (RFC 2385) password (Linux only)
+    pub(super) md5_password: Option<String>,
+
+    // TTL Security (GTSM, RFC 5082): send with TTL 255, drop packets below this TTL (Linux only)
+    pub(super) min_ttl: Option<u8>,
+
+    // Source address for outbound connections to this peer
+    // (defaults to the address of a BGP listener of the same family)
+    pub(super) local_address: Option<IpAddr>,
+
     // Destination port for BGP session
     // Used when initiating connection to peer
     #[serde(default = "Defaults::dest_port")]
     pub(super) dest_port: u16,
 
+    // Send each capability in its own OPEN Optional Parameter
+    // (instead of a single combined Capabilities parameter)
+    #[serde(default = "Defaults::separate_capabilities")]
+    pub(super) separate_capabilities: bool,
+
+    // Capabilities to send first in the OPEN, in this order (for order-sensitive peers)
+    // The remaining capabilities follow in the default order
+    #[serde(default, deserialize_with = "deserialize_capability_order")]
+    pub(super) capability_order: Vec<u8>,
+
+    // Log a summary of the negotiated capabilities when the session is Established
+    #[serde(default = "Defaults::log_capabilities")]
+    pub(super) log_capabilities: bool,
+
+    // Advertise the Extended Message capability (RFC 8654), allowing messages up to 65535 bytes
+    #[serde(default = "Defaults::extended_messages")]
+    pub(super) extended_messages: bool,
+
+    // Store IPv4-mapped IPv6 next hops (E.g. "::ffff:10.0.0.1") in learned routes as IPv4
+    #[serde(default = "Defaults::normalize_mapped_nexthop")]
+    pub(super) normalize_mapped_nexthop: bool,
+
+    // Treat a KEEPALIVE received before OpenConfirm as an FSM error (instead of ignoring it)
+    #[serde(default = "Defaults::strict_fsm")]
+    pub(super) strict_fsm: bool,
+
+    // BGP Role (RFC 9234) of the local router for this eBGP peer, advertised in the OPEN
+    // E.g. "provider" (the peer is a customer), "customer", "peer", "rs", or "rs-client"
+    // Routes are marked with the Only to Customer (OTC) attribute to prevent route leaks
+    #[serde(default, deserialize_with = "deserialize_role")]
+    pub(super) role: Option<Role>,
+    // Reject peers that don't advertise a BGP Role with a Role Mismatch NOTIFICATION
+    #[serde(default = "Defaults::strict_role")]
+    pub(super) strict_role: bool,
+
+    // Batch the initial route dump at Established into as few UPDATEs as possible
+    // (grouped by path attributes) followed by End-of-RIB markers
+    #[serde(default = "Defaults::initial_batch")]
+    pub(super) initial_batch: bool,
+
     // AFI/SAFI Families to Rx/TX for this peer
     #[serde(default = "Defaults::families")]
     pub(super) families: Vec<Family>,
+    // Only accept learned unicast prefixes matching one of these entries (all if empty)
+    // E.g. "10.0.0.0/8 le 24", "2001:db8::/32 ge 48 le 64"
+    #[serde(default = "Vec::new")]
+    pub(super) prefix_list_in: Vec<PrefixListEntry>,
+    // Accept or reject learned routes by community (first matching rule, accepted if none match)
+    // E.g. "match 65000:666 reject", "match target:65000:1.1.1.1 accept"
+    #[serde(default = "Vec::new")]
+    pub(super) community_filter_in: Vec<CommunityMatch>,
+    // Drop learned unicast prefixes within reserved/unallocated (bogon) ranges
+    #[serde(default = "Defaults::reject_bogons")]
+    pub(super) reject_bogons: bool,
+    // Number of times to prepend the local AS on eBGP advertisements (default 1, max 16)
+    pub(super) as_path_prepend: Option<u8>,
+    // Maximum UPDATEs per second sent to the peer for each family (unlimited if not set)
+    // Large advertisements (including the `initial_batch` dump) are spread over time to protect slow peers
+    pub(super) max_update_rate: Option<u32>,
+    // Minimum Route Advertisement Interval (RFC 4271 [9.2.1.1]) in seconds, 0 to disable
+    // Changes to a prefix are held until this long after it was last advertised
+    // (defaults to 30 for eBGP, 5 for iBGP)
+    pub(super) mrai: Option<u16>,
+    // Advertise Graceful Restart (RFC 4724) with this restart time (seconds, max 4095)
+    // Routes from a peer that also advertised it are retained as stale while it restarts
+    pub(super) graceful_restart: Option<u16>,
+    // Families for which our local session address is advertised as the next hop
+    // (or `true` for all of the peer's families)
+    #[serde(default)]
+    pub(super) next_hop_self: NextHopSelf,
+    // Link-local address (E.g. "fe80::1") advertised after our global IPv6 next hop (RFC 2545)
+    // for directly connected peers, in a 32-byte MP_REACH_NLRI next hop
+    pub(super) link_local_next_hop: Option<Ipv6Addr>,
+    // Advertise ADD-PATH (RFC 7911) send & receive for the peer's unicast & multicast families
+    // Multiple paths for a prefix are then advertised to the peer, each with a path ID
+    #[serde(default = "Defaults::add_path")]
+    pub(super) add_path: bool,
+    // Route Flap Dampening of routes learned from the peer: `true` (with the [dampening] parameters),
+    // `false` (even if [dampening] is configured), or a table of parameters for this peer
+    // Peers without `dampening` use [dampening] (if configured)
+    pub(super) dampening: Option<PeerDampening>,
+    // Advertise these networks (E.g. "10.0.0.0/22") in place of the more-specific routes they contain
+    #[serde(default = "Vec::new")]
+    pub(super) aggregate: Vec<IpNetwork>,
+    // Originate a default route (0.0.0.0/0, ::/0) to the peer
+    pub(super) default_originate: Option<DefaultOriginateSpec>,
+    // Add this community to routes learned from the peer (E.g. "65000:5") to identify the ingress peer
+    #[serde(default, deserialize_with = "deserialize_community")]
+    pub(super) tag_community: Option<Community>,
+    // Only accept learned routes (E.g. Flowspec) carrying one of these route targets (all if empty)
+    // E.g. "target:65000:100", "target:2001:db8::1:100"
+    #[serde(default, deserialize_with = "deserialize_route_targets")]
+    pub(super) import_rts: Vec<Community>,
+    // Add these route targets to routes advertised to the peer
+    #[serde(default, deserialize_with = "deserialize_route_targets")]
+    pub(super) export_rts: Vec<Community>,
+    // Adjust the MED of routes learned from the peer (multiply, then add the offset)
+    // So that MEDs from different peers are comparable, results saturate at 0 and u32::MAX
+    pub(super) med_multiplier: Option<u32>,
+    pub(super) med_offset: Option<i64>,
+    // Replace the MED of routes learned from the peer (instead of adjusting it)
+    pub(super) med_in_override: Option<u32>,
+    // Set the LOCAL_PREF of routes learned from the peer (E.g. to prefer one transit over another)
+    pub(super) local_pref_in: Option<u32>,
+    // Advertise routes to the peer with this MED (regardless of the route's MED)
+    pub(super) med_out: Option<u32>,
     // Routes from which source(s) should we advertise to this peer?
     #[serde(default = "Defaults::advertise_sources")]
     pub(super) advertise_sources: Vec<AdvertiseSource>,
@@ -99,33 +308,314 @@ pub(super) struct PeerConfigSpec {
     pub(super) static_flows: Vec<FlowSpec>,
 }
 
+/// `next_hop_self` for all families (`true`) or a list of families
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub(super) enum NextHopSelf {
+    All(bool),
+    Families(Vec<Family>),
+}
+
+impl Default for NextHopSelf {
+    fn default() -> Self {
+        NextHopSelf::All(false)
+    }
+}
+
+impl NextHopSelf {
+    pub(super) fn families(&self, families: &[Family]) -> Vec<Family> {
+        match self {
+            NextHopSelf::All(true) => families.to_vec(),
+            NextHopSelf::All(false) => vec![],
+            NextHopSelf::Families(families) => families.clone(),
+        }
+    }
+}
+
+/// Peer `dampening`, enabled/disabled (`true`/`false`) or with parameters
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub(super) enum PeerDampening {
+    Enabled(bool),
+    Parameters(DampeningConfigSpec),
+}
+
+impl PeerDampening {
+    /// Dampening parameters for the peer, given the global [dampening] config (if any)
+    pub(super) fn parameters(
+        &self,
+        global: Option<&DampeningConfigSpec>,
+    ) -> Option<DampeningConfigSpec> {
+        match self {
+            PeerDampening::Enabled(true) => Some(global.cloned().unwrap_or_default()),
+            PeerDampening::Enabled(false) => None,
+            PeerDampening::Parameters(parameters) => Some(parameters.clone()),
+        }
+    }
+}
+
+impl Default for DampeningConfigSpec {
+    fn default() -> Self {
+        Self {
+            half_life: Defaults::half_life(),
+            suppress_limit: Defaults::suppress_limit(),
+            reuse_limit: Defaults::reuse_limit(),
+            max_penalty: Defaults::max_penalty(),
+        }
+    }
+}
+
+/// Config (toml) representation of Route Flap Dampening parameters
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct DampeningConfigSpec {
+    // Time (seconds) for an accumulated penalty to be reduced by half
+    #[serde(default = "Defaults::half_life")]
+    pub(super) half_life: u32,
+    // Routes are suppressed once their penalty exceeds this value
+    #[serde(default = "Defaults::suppress_limit")]
+    pub(super) suppress_limit: u32,
+    // Suppressed routes are reused once their penalty decays below this value
+    #[serde(default = "Defaults::reuse_limit")]
+    pub(super) reuse_limit: u32,
+    // Upper bound for an accumulated penalty
+    #[serde(default = "Defaults::max_penalty")]
+    pub(super) max_penalty: u32,
+}
+
+/// Config (toml) representation of default route origination
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct DefaultOriginateSpec {
+    // Only originate while this prefix is in the RIB (always, if not present)
+    pub(super) conditional_on: Option<IpNetwork>,
+}
+
 #[derive(Debug, Deserialize)]
 pub(super) struct ServerConfigSpec {
     /// Global Router-ID (can be overriden per-peer in peer config)
-    pub(super) router_id: IpAddr,
+    /// Auto-selected if not provided (or not IPv4)
+    pub(super) router_id: Option<IpAddr>,
     /// Global ASN (can be overriden per-peer in peer config)
     pub(super) default_as: u32,
-    /// BGP TCP ;istening socket
-    #[serde(default = "Defaults::bgp_socket")]
-    pub(super) bgp_socket: SocketAddr,
+    /// BGP TCP listening socket(s), a single address or a list (E.g. IPv4 & IPv6)
+    #[serde(
+        alias = "bgp_socket",
+        default = "Defaults::bgp_sockets",
+        deserialize_with = "deserialize_sockets"
+    )]
+    pub(super) bgp_sockets: Vec<SocketAddr>,
+    /// Pending connection queue size for the BGP listening socket(s)
+    #[serde(default = "Defaults::bgp_backlog")]
+    pub(super) bgp_backlog: u32,
     /// API HTTP listening socket
     #[serde(default = "Defaults::api_socket")]
     pub(super) api_socket: SocketAddr,
+    /// Metrics HTTP listening socket (disabled if not present)
+    pub(super) metrics_socket: Option<SocketAddr>,
+    /// BMP (RFC 7854) collector to send peer state & learned routes to (disabled if not present)
+    pub(super) bmp_collector: Option<SocketAddr>,
+    /// File to log every UPDATE received from peers to, for debugging (disabled if not present)
+    pub(super) update_log: Option<String>,
     /// Intverval to poll idle peers (outbound connection)
     #[serde(default = "Defaults::poll_interval")]
     pub(super) poll_interval: u16,
+    /// Install best-path learned routes into the kernel FIB (Linux, `fib` feature)
+    #[serde(default = "Defaults::fib_install")]
+    pub(super) fib_install: bool,
+    /// Drop received Flowspec rules not backed by a unicast route from the same peer (RFC 8955)
+    #[serde(default = "Defaults::flowspec_validation")]
+    pub(super) flowspec_validation: bool,
+    /// Route Flap Dampening of learned routes (disabled if not present)
+    pub(super) dampening: Option<DampeningConfigSpec>,
+    /// Warn when the RIB holds more than this many distinct path attribute groups
+    pub(super) max_attribute_groups: Option<usize>,
+    /// Route Reflection (RFC 4456) Cluster ID, routes with it in their CLUSTER_LIST are dropped
+    pub(super) cluster_id: Option<IpAddr>,
+    /// Additional files with `[[peers]]` definitions, relative to this file (E.g. "peers/*.toml")
+    #[serde(default = "Vec::new")]
+    pub(super) include: Vec<String>,
+    /// Peers, with values inherited from their `[[peer_groups]]` already resolved (see `PeerGroups`)
     #[serde(default = "Vec::new")]
     pub(super) peers: Vec<PeerConfigSpec>,
 }
 
+/// Config (toml) representation of an included file (only peer definitions)
+#[derive(Debug, Deserialize)]
+struct IncludedConfigSpec {
+    #[serde(default = "Vec::new")]
+    peers: Vec<PeerConfigSpec>,
+}
+
+/// Named `[[peer_groups]]` of peer defaults (any peer key, E.g. `remote_as`, `families`, `hold_timer`)
+/// Peers with `group = "<name>"` inherit the group's values for keys they don't set themselves
+/// Groups are defined in the main config file, and also apply to peers in included files
+#[derive(Debug, Default)]
+struct PeerGroups(HashMap<String, Table>);
+
+impl PeerGroups {
+    /// Remove `[[peer_groups]]` from a parsed config
+    fn take(config: &mut Value) -> io::Result<Self> {
+        let mut groups = HashMap::new();
+        let tables = match config.as_table_mut().and_then(|c| c.remove("peer_groups")) {
+            Some(Value::Array(tables)) => tables,
+            Some(_) => return Err(invalid_data("peer_groups must be an array of tables")),
+            None => return Ok(Self(groups)),
+        };
+        for table in tables {
+            let mut table = match table {
+                Value::Table(table) => table,
+                _ => return Err(invalid_data("peer_groups must be an array of tables")),
+            };
+            let name = match table.remove("name") {
+                Some(Value::String(name)) => name,
+                _ => return Err(invalid_data("Peer group is missing a name")),
+            };
+            if groups.contains_key(&name) {
+                return Err(invalid_data(format!("Duplicate peer group '{}'", name)));
+            }
+            groups.insert(name, table);
+        }
+        Ok(Self(groups))
+    }
+
+    /// Add group values to `[[peers]]` with a `group` (unless set on the peer)
+    fn apply(&self, config: &mut Value) -> io::Result<()> {
+        let peers = match config.get_mut("peers").and_then(|p| p.as_array_mut()) {
+            Some(peers) => peers,
+            None => return Ok(()),
+        };
+        for peer in peers.iter_mut().filter_map(|p| p.as_table_mut()) {
+            let name = match peer.remove("group") {
+                Some(Value::String(name)) => name,
+                Some(_) => return Err(invalid_data("Peer group must be a name")),
+                None => continue,
+            };
+            let group = self.0.get(&name).ok_or_else(|| {
+                let remote_ip = peer.get("remote_ip").and_then(|ip| ip.as_str());
+                invalid_data(format!(
+                    "Peer {}: Undefined peer group '{}'",
+                    remote_ip.unwrap_or("<unknown>"),
+                    name
+                ))
+            })?;
+            for (key, value) in group {
+                peer.entry(key.as_str()).or_insert_with(|| value.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
 impl ServerConfigSpec {
     pub(super) fn from_file(path: &str) -> io::Result<Self> {
-        let mut file = File::open(path)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        let config: ServerConfigSpec = toml::from_str(&contents).unwrap();
-        Ok(config)
+        let (mut spec, groups) = Self::parse_with_groups(&read_file(Path::new(path))?)?;
+        let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        for pattern in &spec.include {
+            for included in expand_include(base, pattern)? {
+                let contents = read_file(&included)?;
+                let included_spec: IncludedConfigSpec = toml::from_str(&contents)
+                    .map_err(io::Error::from)
+                    .and_then(|mut value| {
+                        groups.apply(&mut value)?;
+                        value.try_into().map_err(io::Error::from)
+                    })
+                    .map_err(|err| invalid_data(format!("{}: {}", included.display(), err)))?;
+                for peer in included_spec.peers {
+                    if spec.peers.iter().any(|p| p.remote_ip == peer.remote_ip) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "{}: Duplicate remote_ip {}",
+                                included.display(),
+                                peer.remote_ip
+                            ),
+                        ));
+                    }
+                    spec.peers.push(peer);
+                }
+            }
+        }
+        Ok(spec)
+    }
+
+    pub(super) fn parse(contents: &str) -> io::Result<Self> {
+        Self::parse_with_groups(contents).map(|(spec, _)| spec)
+    }
+
+    /// Parse a config, with peers inheriting values from their peer group
+    fn parse_with_groups(contents: &str) -> io::Result<(Self, PeerGroups)> {
+        let mut config: Value = toml::from_str(contents).map_err(invalid_data)?;
+        let groups = PeerGroups::take(&mut config)?;
+        groups.apply(&mut config)?;
+        let spec = config.try_into().map_err(invalid_data)?;
+        Ok((spec, groups))
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Files matching an include pattern, in sorted order
+/// Wildcards (`*` and `?`) are supported in the file name only (E.g. "peers/*.toml")
+fn expand_include(base: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let pattern = base.join(pattern);
+    let file_pattern = match pattern.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.contains(|c| c == '*' || c == '?') => name.to_string(),
+        _ => return Ok(vec![pattern]),
+    };
+    let dir = pattern.parent().unwrap_or_else(|| Path::new(""));
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let mut paths = vec![];
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let matches = entry
+            .file_name()
+            .to_str()
+            .map(|name| wildcard_match(&file_pattern, name))
+            .unwrap_or(false);
+        if matches && entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
     }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Match a name against a pattern, where `*` matches any run of characters and `?` any one
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` seen (and the name position it was tried at)
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last `*` consume one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
 /// Specify static route/flow for a PeerConfig
@@ -177,6 +667,75 @@ impl<'de> Deserialize<'de> for AdvertiseSource {
     }
 }
 
+fn deserialize_community<'de, D>(deserializer: D) -> Result<Option<Community>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Community::try_from(s.as_str())
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_role<'de, D>(deserializer: D) -> Result<Option<Role>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Role::try_from(s.as_str())
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_capability_order<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| capability_code(name).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn deserialize_route_targets<'de, D>(deserializer: D) -> Result<Vec<Community>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| match Community::try_from(s.as_str()) {
+            Ok(community) if community.is_route_target() => Ok(community),
+            Ok(_) => Err(serde::de::Error::custom(format!(
+                "Not a route target '{}' (E.g. \"target:65000:100\")",
+                s
+            ))),
+            Err(err) => Err(serde::de::Error::custom(err)),
+        })
+        .collect()
+}
+
+fn deserialize_sockets<'de, D>(deserializer: D) -> Result<Vec<SocketAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Sockets {
+        Single(SocketAddr),
+        Multiple(Vec<SocketAddr>),
+    }
+    let sockets = match Sockets::deserialize(deserializer)? {
+        Sockets::Single(socket) => vec![socket],
+        Sockets::Multiple(sockets) => sockets,
+    };
+    if sockets.is_empty() {
+        return Err(serde::de::Error::custom(
+            "At least one BGP socket is required",
+        ));
+    }
+    Ok(sockets)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -185,7 +744,10 @@ mod tests {
     #[test]
     fn test_parse_config() {
         let config = ServerConfigSpec::from_file("./examples/config.toml").unwrap();
-        assert_eq!(config.router_id, IpAddr::from(Ipv4Addr::new(1, 1, 1, 1)));
+        assert_eq!(
+            config.router_id,
+            Some(IpAddr::from(Ipv4Addr::new(1, 1, 1, 1)))
+        );
         assert_eq!(config.default_as, 65000);
         assert_eq!(config.peers.len(), 2);
         let v4_peer = config
@@ -214,4 +776,148 @@ mod tests {
         assert_eq!(v6_peer.hold_timer, 180);
         assert!(v6_peer.passive);
     }
+
+    #[test]
+    fn test_parse_bgp_sockets() {
+        let parse = |sockets: &str| {
+            ServerConfigSpec::parse(&format!(
+                "router_id = \"1.1.1.1\"\ndefault_as = 65000\n{}",
+                sockets
+            ))
+        };
+        let socket = |s: &str| s.parse::<SocketAddr>().unwrap();
+
+        let config = parse("").unwrap();
+        assert_eq!(config.bgp_sockets, vec![socket("[::]:179")]);
+        assert_eq!(config.bgp_backlog, 1024);
+
+        // Single socket (backwards compatible)
+        let config = parse("bgp_socket = \"127.0.0.1:1179\"").unwrap();
+        assert_eq!(config.bgp_sockets, vec![socket("127.0.0.1:1179")]);
+
+        let config =
+            parse("bgp_sockets = [\"0.0.0.0:179\", \"[::]:179\"]\nbgp_backlog = 16").unwrap();
+        assert_eq!(
+            config.bgp_sockets,
+            vec![socket("0.0.0.0:179"), socket("[::]:179")]
+        );
+        assert_eq!(config.bgp_backlog, 16);
+        let config = parse("bgp_socket = [\"10.0.0.1:179\"]").unwrap();
+        assert_eq!(config.bgp_sockets, vec![socket("10.0.0.1:179")]);
+
+        assert!(parse("bgp_sockets = []").is_err());
+        assert!(parse("bgp_socket = \"10.0.0.1\"").is_err());
+    }
+
+    #[test]
+    fn test_route_targets() {
+        let parse = |route_targets: &str| {
+            ServerConfigSpec::parse(&format!(
+                "router_id = \"1.1.1.1\"\ndefault_as = 65000\n[[peers]]\nremote_ip = \"10.0.0.1\"\nremote_as = 65001\nimport_rts = {}",
+                route_targets
+            ))
+        };
+        let config = parse(r#"["target:65000:100", "target:2001:db8::1:100"]"#).unwrap();
+        assert_eq!(
+            config.peers[0].import_rts,
+            vec![
+                Community::try_from("target:65000:100").unwrap(),
+                Community::try_from("target:2001:db8::1:100").unwrap(),
+            ]
+        );
+        assert!(config.peers[0].export_rts.is_empty());
+        // Not a route target
+        let err = parse(r#"["65000:100"]"#).unwrap_err();
+        assert!(err.to_string().contains("Not a route target '65000:100'"));
+        assert!(parse(r#"["redirect:65000:100"]"#).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.toml", "peers.toml"));
+        assert!(wildcard_match("*.toml", ".toml"));
+        assert!(wildcard_match("peer-?.toml", "peer-1.toml"));
+        assert!(wildcard_match("*-*.toml", "edge-a-b.toml"));
+        assert!(!wildcard_match("*.toml", "peers.toml.bak"));
+        assert!(!wildcard_match("peer-?.toml", "peer-10.toml"));
+        assert!(!wildcard_match("peers.toml", "peers.yaml"));
+    }
+
+    #[test]
+    fn test_include_peers() {
+        let dir = std::env::temp_dir().join(format!("bgpd-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("peers")).unwrap();
+        let main = dir.join("bgpd.toml");
+        std::fs::write(
+            &main,
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            include = ["peers/*.toml"]
+            [[peer_groups]]
+            name = "transit"
+            remote_as = 65003
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            remote_as = 65001
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("peers/a.toml"),
+            r#"
+            [[peers]]
+            remote_ip = "10.0.0.2"
+            remote_as = 65002
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("peers/b.toml"),
+            r#"
+            [[peers]]
+            remote_ip = "10.0.0.3"
+            group = "transit"
+            [[peers]]
+            remote_ip = "10.0.0.4"
+            remote_as = 65004
+            "#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("peers/notes.txt"), "not a config").unwrap();
+
+        let config = ServerConfigSpec::from_file(main.to_str().unwrap()).unwrap();
+        let peers: Vec<_> = config
+            .peers
+            .iter()
+            .map(|p| (p.remote_ip.to_string(), p.remote_as))
+            .collect();
+        assert_eq!(
+            peers,
+            vec![
+                ("10.0.0.1/32".to_string(), 65001),
+                ("10.0.0.2/32".to_string(), 65002),
+                ("10.0.0.3/32".to_string(), 65003),
+                ("10.0.0.4/32".to_string(), 65004),
+            ]
+        );
+
+        // Included peers can't re-define an existing peer
+        std::fs::write(
+            dir.join("peers/c.toml"),
+            r#"
+            [[peers]]
+            remote_ip = "10.0.0.2"
+            remote_as = 65005
+            "#,
+        )
+        .unwrap();
+        let err = ServerConfigSpec::from_file(main.to_str().unwrap()).unwrap_err();
+        assert!(
+            err.to_string().contains("Duplicate remote_ip 10.0.0.2/32"),
+            "{}",
+            err
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
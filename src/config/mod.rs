@@ -1,16 +1,20 @@
 mod file;
+mod router_id;
 
 pub use file::AdvertiseSource;
 
 use std::collections::HashSet;
 use std::io::Result;
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 
 use ipnetwork::IpNetwork;
+use log::{info, warn};
 
-use crate::api::rpc::{FlowSpec, RouteSpec};
-use crate::rib::Family;
+use crate::api::rpc::{FlowSpec, ReloadSummary, RouteSpec};
+use crate::rib::{Community, CommunityMatch, Family, PrefixListEntry};
+use crate::session::{validate_md5_password, validate_min_ttl, Role};
+use crate::utils::{parse_flow_spec, parse_route_spec};
 
 /// Parse a TOML config file and return a ServerConfig
 pub fn from_file(path: &str) -> Result<ServerConfig> {
@@ -18,31 +22,114 @@ pub fn from_file(path: &str) -> Result<ServerConfig> {
     Ok(ServerConfig::from_spec(spec))
 }
 
+/// Parse TOML config contents and return a ServerConfig
+pub fn from_str(contents: &str) -> Result<ServerConfig> {
+    let spec = file::ServerConfigSpec::parse(contents)?;
+    Ok(ServerConfig::from_spec(spec))
+}
+
 /// Global BGP daemon options.
 /// `router_id`, `default_as`, and `poll_interval` can be overridden at the peer level
 #[derive(Debug)]
 pub struct ServerConfig {
     pub router_id: IpAddr,
     pub default_as: u32,
-    pub bgp_socket: SocketAddr,
+    pub bgp_sockets: Vec<SocketAddr>,
+    pub bgp_backlog: u32,
     pub api_socket: SocketAddr,
+    pub metrics_socket: Option<SocketAddr>,
+    pub bmp_collector: Option<SocketAddr>,
+    pub update_log: Option<String>,
     pub poll_interval: u16,
+    pub fib_install: bool,
+    pub flowspec_validation: bool,
+    pub dampening: Option<DampeningConfig>,
+    pub max_attribute_groups: Option<usize>,
+    pub cluster_id: Option<IpAddr>,
     pub peers: Vec<Arc<PeerConfig>>,
 }
 
+/// Route Flap Dampening parameters (RFC 2439)
+/// - `half_life` is in seconds, limits are penalty values
+#[derive(Clone, Debug, PartialEq)]
+pub struct DampeningConfig {
+    pub half_life: u32,
+    pub suppress_limit: u32,
+    pub reuse_limit: u32,
+    pub max_penalty: u32,
+}
+
+impl From<&file::DampeningConfigSpec> for DampeningConfig {
+    fn from(spec: &file::DampeningConfigSpec) -> Self {
+        Self {
+            half_life: spec.half_life,
+            suppress_limit: spec.suppress_limit,
+            reuse_limit: spec.reuse_limit,
+            max_penalty: spec.max_penalty,
+        }
+    }
+}
+
+/// Default route origination to a peer
+/// - Only originated while `conditional_on` is in the RIB (if present)
+#[derive(Clone, Debug, PartialEq)]
+pub struct DefaultOriginate {
+    pub conditional_on: Option<IpNetwork>,
+}
+
 /// Peer (or peers) config and static advertisements
 /// - `peers` can specify a single peer IP address or a subnet+mask
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct PeerConfig {
     pub remote_ip: IpNetwork,
     pub remote_as: u32,
     pub local_as: u32,
     pub local_router_id: IpAddr,
     pub enabled: bool,
+    pub shutdown_message: Option<String>,
     pub passive: bool,
     pub hold_timer: u16,
+    pub min_keepalive: Option<u16>,
+    pub connect_retry: u16,
+    pub delay_open: Option<u16>,
+    pub max_prefixes: Option<u32>,
+    pub max_prefix_warning: Option<u8>,
+    pub max_prefix_out: Option<u32>,
     pub dest_port: u16,
+    pub md5_password: Option<String>,
+    pub min_ttl: Option<u8>,
+    pub local_address: Option<IpAddr>,
+    pub separate_capabilities: bool,
+    pub capability_order: Vec<u8>,
+    pub log_capabilities: bool,
+    pub extended_messages: bool,
+    pub normalize_mapped_nexthop: bool,
+    pub initial_batch: bool,
+    pub strict_fsm: bool,
+    pub role: Option<Role>,
+    pub strict_role: bool,
     pub families: Vec<Family>,
+    pub prefix_list_in: Vec<PrefixListEntry>,
+    pub community_filter_in: Vec<CommunityMatch>,
+    pub reject_bogons: bool,
+    pub as_path_prepend: Option<u8>,
+    pub max_update_rate: Option<u32>,
+    pub mrai: u16,
+    pub graceful_restart: Option<u16>,
+    pub next_hop_self: Vec<Family>,
+    pub link_local_next_hop: Option<Ipv6Addr>,
+    pub add_path: bool,
+    pub aggregate: Vec<IpNetwork>,
+    pub dampening: Option<DampeningConfig>,
+    pub default_originate: Option<DefaultOriginate>,
+    pub tag_community: Option<Community>,
+    pub import_rts: Vec<Community>,
+    pub export_rts: Vec<Community>,
+    pub med_multiplier: Option<u32>,
+    pub med_offset: Option<i64>,
+    pub med_in_override: Option<u32>,
+    pub local_pref_in: Option<u32>,
+    pub med_out: Option<u32>,
     pub advertise_sources: HashSet<AdvertiseSource>,
     pub static_routes: Vec<RouteSpec>,
     pub static_flows: Vec<FlowSpec>,
@@ -56,21 +143,237 @@ impl PeerConfig {
 }
 
 impl ServerConfig {
+    /// Pairs of peer configs with overlapping `remote_ip` networks
+    /// Incoming connections matching more than one use the most-specific config
+    pub fn overlapping_peers(&self) -> Vec<(IpNetwork, IpNetwork)> {
+        let mut overlapping = vec![];
+        for (i, a) in self.peers.iter().enumerate() {
+            for b in &self.peers[i + 1..] {
+                if a.remote_ip.is_ipv4() != b.remote_ip.is_ipv4() {
+                    continue;
+                }
+                if a.remote_ip.contains(b.remote_ip.ip()) || b.remote_ip.contains(a.remote_ip.ip())
+                {
+                    overlapping.push((a.remote_ip, b.remote_ip));
+                }
+            }
+        }
+        overlapping
+    }
+
+    /// Peer configs (by `remote_ip`) added, removed, or changed in a reloaded config
+    pub fn peer_changes(&self, reloaded: &ServerConfig) -> ReloadSummary {
+        let find = |peers: &[Arc<PeerConfig>], remote_ip| {
+            peers.iter().find(|p| p.remote_ip == remote_ip).cloned()
+        };
+        let mut summary = ReloadSummary::default();
+        for peer in &reloaded.peers {
+            match find(&self.peers, peer.remote_ip) {
+                None => summary.peers_added.push(peer.remote_ip.to_string()),
+                Some(current) if current != *peer => {
+                    summary.peers_changed.push(peer.remote_ip.to_string())
+                }
+                Some(_) => (),
+            }
+        }
+        for peer in &self.peers {
+            if find(&reloaded.peers, peer.remote_ip).is_none() {
+                summary.peers_removed.push(peer.remote_ip.to_string());
+            }
+        }
+        summary
+    }
+
+    /// Semantic validation of a parsed config (beyond what the TOML file can express)
+    /// Returns every error found, so a config can be fixed in one pass
+    pub fn validate(&self) -> std::result::Result<(), Vec<String>> {
+        let mut errors = vec![];
+        if let Err(err) = validate_router_id(self.router_id) {
+            errors.push(format!("router_id: {}", err));
+        }
+        if let Some(cluster_id) = self.cluster_id {
+            if let Err(err) = validate_router_id(cluster_id) {
+                errors.push(format!("cluster_id: {}", err));
+            }
+        }
+        for (i, peer) in self.peers.iter().enumerate() {
+            let name = peer.remote_ip;
+            if self.peers[..i]
+                .iter()
+                .any(|p| p.remote_ip == peer.remote_ip)
+            {
+                errors.push(format!("Peer {}: Duplicate remote_ip", name));
+            }
+            if peer.local_router_id != self.router_id {
+                if let Err(err) = validate_router_id(peer.local_router_id) {
+                    errors.push(format!("Peer {}: local_router_id: {}", name, err));
+                }
+            }
+            if peer.families.is_empty() {
+                errors.push(format!("Peer {}: No families configured", name));
+            }
+            for family in peer.next_hop_self.iter() {
+                if !peer.families.contains(family) {
+                    errors.push(format!(
+                        "Peer {}: next_hop_self family {} is not configured",
+                        name, family
+                    ));
+                }
+            }
+            if let Some(link_local) = peer.link_local_next_hop {
+                // fe80::/10
+                if link_local.segments()[0] & 0xffc0 != 0xfe80 {
+                    errors.push(format!(
+                        "Peer {}: link_local_next_hop {} is not a link-local address",
+                        name, link_local
+                    ));
+                }
+            }
+            if let Some(password) = &peer.md5_password {
+                if let Err(err) = validate_md5_password(password) {
+                    errors.push(format!("Peer {}: {}", name, err));
+                }
+            }
+            if let Some(min_ttl) = peer.min_ttl {
+                if let Err(err) = validate_min_ttl(min_ttl) {
+                    errors.push(format!("Peer {}: {}", name, err));
+                }
+            }
+            if let Some(delay_open) = peer.delay_open {
+                // Must expire before the hold timer (started on connect) does
+                if delay_open == 0 || (peer.hold_timer != 0 && delay_open >= peer.hold_timer) {
+                    errors.push(format!(
+                        "Peer {}: delay_open must be 1 second or more, less than the hold_timer",
+                        name
+                    ));
+                }
+            }
+            if let Some(dampening) = &peer.dampening {
+                // Suppressed routes would never be reused
+                if dampening.reuse_limit >= dampening.suppress_limit {
+                    errors.push(format!(
+                        "Peer {}: dampening reuse_limit must be less than suppress_limit",
+                        name
+                    ));
+                }
+            }
+            if peer.role.is_some() && !peer.is_ebgp() {
+                errors.push(format!("Peer {}: role only applies to eBGP peers", name));
+            }
+            if peer.strict_role && peer.role.is_none() {
+                errors.push(format!("Peer {}: strict_role requires a role", name));
+            }
+            if let Some(local_address) = peer.local_address {
+                if local_address.is_ipv4() != peer.remote_ip.is_ipv4() {
+                    errors.push(format!(
+                        "Peer {}: local_address {} is a different address family than remote_ip",
+                        name, local_address
+                    ));
+                }
+            }
+            for route in peer.static_routes.iter() {
+                if let Err(err) = parse_route_spec(route) {
+                    errors.push(format!(
+                        "Peer {}: Static route {}: {}",
+                        name, route.prefix, err.reason
+                    ));
+                }
+            }
+            for (j, flow) in peer.static_flows.iter().enumerate() {
+                if let Err(err) = parse_flow_spec(flow) {
+                    errors.push(format!(
+                        "Peer {}: Static flow #{}: {}",
+                        name,
+                        j + 1,
+                        err.reason
+                    ));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     fn from_spec(spec: file::ServerConfigSpec) -> Self {
+        let router_id = resolve_router_id(&spec);
         let peers: Vec<_> = spec
             .peers
             .iter()
             .map(|p| {
+                let local_as = p.local_as.unwrap_or(spec.default_as);
                 Arc::new(PeerConfig {
                     remote_ip: p.remote_ip,
                     remote_as: p.remote_as,
-                    local_as: p.local_as.unwrap_or(spec.default_as),
-                    local_router_id: p.local_router_id.unwrap_or(spec.router_id),
+                    local_as,
+                    local_router_id: match p.local_router_id {
+                        Some(id @ IpAddr::V4(_)) => id,
+                        Some(id) => {
+                            warn!(
+                                "Peer {}: local_router_id {} is not IPv4, using {}",
+                                p.remote_ip, id, router_id
+                            );
+                            router_id
+                        }
+                        None => router_id,
+                    },
                     enabled: p.enabled,
+                    shutdown_message: p.shutdown_message.clone(),
                     passive: p.passive,
                     hold_timer: p.hold_timer,
+                    min_keepalive: p.min_keepalive,
+                    connect_retry: p.connect_retry,
+                    delay_open: p.delay_open,
+                    max_prefixes: p.max_prefixes,
+                    max_prefix_warning: p.max_prefix_warning,
+                    max_prefix_out: p.max_prefix_out,
                     dest_port: p.dest_port,
+                    md5_password: p.md5_password.clone(),
+                    min_ttl: p.min_ttl,
+                    local_address: p.local_address,
+                    separate_capabilities: p.separate_capabilities,
+                    capability_order: p.capability_order.clone(),
+                    log_capabilities: p.log_capabilities,
+                    extended_messages: p.extended_messages,
+                    normalize_mapped_nexthop: p.normalize_mapped_nexthop,
+                    initial_batch: p.initial_batch,
+                    strict_fsm: p.strict_fsm,
+                    role: p.role,
+                    strict_role: p.strict_role,
                     families: p.families.clone(),
+                    prefix_list_in: p.prefix_list_in.clone(),
+                    community_filter_in: p.community_filter_in.clone(),
+                    reject_bogons: p.reject_bogons,
+                    as_path_prepend: p.as_path_prepend,
+                    max_update_rate: p.max_update_rate,
+                    // Suggested intervals (RFC 4271 [10])
+                    mrai: p
+                        .mrai
+                        .unwrap_or(if p.remote_as == local_as { 5 } else { 30 }),
+                    graceful_restart: p.graceful_restart,
+                    next_hop_self: p.next_hop_self.families(&p.families),
+                    link_local_next_hop: p.link_local_next_hop,
+                    add_path: p.add_path,
+                    aggregate: p.aggregate.clone(),
+                    dampening: match &p.dampening {
+                        Some(dampening) => dampening.parameters(spec.dampening.as_ref()),
+                        None => spec.dampening.clone(),
+                    }
+                    .as_ref()
+                    .map(DampeningConfig::from),
+                    default_originate: p.default_originate.as_ref().map(|d| DefaultOriginate {
+                        conditional_on: d.conditional_on,
+                    }),
+                    tag_community: p.tag_community,
+                    import_rts: p.import_rts.clone(),
+                    export_rts: p.export_rts.clone(),
+                    med_multiplier: p.med_multiplier,
+                    med_offset: p.med_offset,
+                    med_in_override: p.med_in_override,
+                    local_pref_in: p.local_pref_in,
+                    med_out: p.med_out,
                     advertise_sources: p.advertise_sources.clone().into_iter().collect(),
                     static_routes: p.static_routes.clone().into_iter().collect(),
                     static_flows: p.static_flows.clone().into_iter().collect(),
@@ -78,13 +381,449 @@ impl ServerConfig {
             })
             .collect();
 
-        Self {
-            router_id: spec.router_id,
+        let config = Self {
+            router_id,
             default_as: spec.default_as,
-            bgp_socket: spec.bgp_socket,
+            bgp_sockets: spec.bgp_sockets,
+            bgp_backlog: spec.bgp_backlog,
             api_socket: spec.api_socket,
+            metrics_socket: spec.metrics_socket,
+            bmp_collector: spec.bmp_collector,
+            update_log: spec.update_log,
             poll_interval: spec.poll_interval,
+            fib_install: spec.fib_install,
+            flowspec_validation: spec.flowspec_validation,
+            dampening: spec.dampening.as_ref().map(DampeningConfig::from),
+            max_attribute_groups: spec.max_attribute_groups,
+            cluster_id: spec.cluster_id,
             peers,
+        };
+        for (a, b) in config.overlapping_peers() {
+            warn!(
+                "Peer configs {} and {} overlap, the most-specific is used for matching connections",
+                a, b
+            );
         }
+        config
+    }
+}
+
+/// Configured `router_id`, or an auto-selected BGP Identifier if not provided (or not IPv4)
+fn resolve_router_id(spec: &file::ServerConfigSpec) -> IpAddr {
+    if let Some(id @ IpAddr::V4(_)) = spec.router_id {
+        return id;
+    }
+    let addresses = spec
+        .bgp_sockets
+        .iter()
+        .map(|socket| socket.ip())
+        .chain(spec.peers.iter().filter_map(|p| p.local_address));
+    let auto = IpAddr::V4(router_id::auto_router_id(addresses));
+    match spec.router_id {
+        Some(id) => warn!("router_id {} is not IPv4, using {}", id, auto),
+        None => info!("No router_id configured, using {}", auto),
+    }
+    auto
+}
+
+/// The BGP Identifier is a (non-zero) 4-byte value, so must be an IPv4 address
+fn validate_router_id(router_id: IpAddr) -> std::result::Result<(), &'static str> {
+    match router_id {
+        IpAddr::V4(addr) if addr.is_unspecified() => Err("Must not be 0.0.0.0"),
+        IpAddr::V4(addr) if addr.is_multicast() || addr.is_broadcast() => {
+            Err("Must be a unicast IPv4 address")
+        }
+        IpAddr::V4(_) => Ok(()),
+        IpAddr::V6(_) => Err("Must be an IPv4 address"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlapping_peers() {
+        let config = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.0/8"
+            remote_as = 65001
+            [[peers]]
+            remote_ip = "10.1.0.0/16"
+            remote_as = 65002
+            [[peers]]
+            remote_ip = "10.1.2.3"
+            remote_as = 65003
+            [[peers]]
+            remote_ip = "192.168.0.0/24"
+            remote_as = 65004
+            [[peers]]
+            remote_ip = "::a01:0/104"
+            remote_as = 65005
+            "#,
+        )
+        .unwrap();
+        let overlapping: Vec<(String, String)> = config
+            .overlapping_peers()
+            .into_iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect();
+        let pair = |a: &str, b: &str| (a.to_string(), b.to_string());
+        assert_eq!(
+            overlapping,
+            vec![
+                pair("10.0.0.0/8", "10.1.0.0/16"),
+                pair("10.0.0.0/8", "10.1.2.3/32"),
+                pair("10.1.0.0/16", "10.1.2.3/32"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mrai() {
+        let config = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            remote_as = 65001
+            [[peers]]
+            remote_ip = "10.0.0.2"
+            remote_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.3"
+            remote_as = 65001
+            mrai = 0
+            "#,
+        )
+        .unwrap();
+        let mrai: Vec<_> = config.peers.iter().map(|p| p.mrai).collect();
+        assert_eq!(mrai, vec![30, 5, 0]);
+    }
+
+    #[test]
+    fn test_peer_dampening() {
+        let peers = |global: &str| {
+            let toml = format!(
+                r#"
+                router_id = "1.1.1.1"
+                default_as = 65000
+                {}
+                [[peers]]
+                remote_ip = "10.0.0.1"
+                remote_as = 65001
+                [[peers]]
+                remote_ip = "10.0.0.2"
+                remote_as = 65001
+                dampening = true
+                [[peers]]
+                remote_ip = "10.0.0.3"
+                remote_as = 65001
+                dampening = false
+                [[peers]]
+                remote_ip = "10.0.0.4"
+                remote_as = 65001
+                dampening = {{ half_life = 300 }}
+                "#,
+                global
+            );
+            from_str(&toml)
+                .unwrap()
+                .peers
+                .iter()
+                .map(|p| p.dampening.clone())
+                .collect::<Vec<_>>()
+        };
+        let defaults = DampeningConfig {
+            half_life: 900,
+            suppress_limit: 2000,
+            reuse_limit: 750,
+            max_penalty: 12000,
+        };
+        let short = DampeningConfig {
+            half_life: 300,
+            ..defaults.clone()
+        };
+        assert_eq!(
+            peers(""),
+            vec![None, Some(defaults.clone()), None, Some(short.clone())]
+        );
+
+        let global = DampeningConfig {
+            suppress_limit: 3000,
+            ..defaults
+        };
+        assert_eq!(
+            peers("[dampening]\nsuppress_limit = 3000"),
+            vec![Some(global.clone()), Some(global), None, Some(short)]
+        );
+
+        let config = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [dampening]
+            suppress_limit = 700
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            remote_as = 65001
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err(),
+            vec!["Peer 10.0.0.1/32: dampening reuse_limit must be less than suppress_limit"]
+        );
+    }
+
+    #[test]
+    fn test_auto_router_id() {
+        // IPv6-only host, without a router_id
+        let config = from_str(
+            r#"
+            default_as = 65000
+            bgp_sockets = ["[::]:179"]
+            [[peers]]
+            remote_ip = "2001:db8::2"
+            remote_as = 65001
+            local_router_id = "2001:db8::1"
+            "#,
+        )
+        .unwrap();
+        assert!(config.router_id.is_ipv4());
+        assert_eq!(config.peers[0].local_router_id, config.router_id);
+        assert!(config.validate().is_ok());
+
+        // Highest configured IPv4 address
+        let config = from_str(
+            r#"
+            router_id = "::1"
+            default_as = 65000
+            bgp_sockets = ["10.0.0.5:179", "[::]:179"]
+            [[peers]]
+            remote_ip = "10.0.0.2"
+            remote_as = 65001
+            local_address = "10.0.0.9"
+            [[peers]]
+            remote_ip = "10.0.1.2"
+            remote_as = 65001
+            local_router_id = "2.2.2.2"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.router_id, "10.0.0.9".parse::<IpAddr>().unwrap());
+        assert_eq!(config.peers[0].local_router_id, config.router_id);
+        assert_eq!(
+            config.peers[1].local_router_id,
+            "2.2.2.2".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_peer_groups() {
+        let config = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peer_groups]]
+            name = "edge"
+            remote_as = 65001
+            families = ["ipv4 unicast", "ipv6 unicast"]
+            hold_timer = 90
+            advertise_sources = ["peer"]
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            group = "edge"
+            [[peers]]
+            remote_ip = "10.0.0.2"
+            group = "edge"
+            hold_timer = 30
+            [[peers]]
+            remote_ip = "10.0.0.3"
+            remote_as = 65003
+            "#,
+        )
+        .unwrap();
+        let families: Vec<_> = config.peers[1]
+            .families
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(families, vec!["IPv4 Unicast", "IPv6 Unicast"]);
+        assert_eq!(config.peers[1].remote_as, 65001);
+        assert_eq!(
+            config.peers[1].advertise_sources,
+            vec![AdvertiseSource::Peer].into_iter().collect()
+        );
+        assert_eq!(config.peers[0].hold_timer, 90);
+        assert_eq!(config.peers[1].hold_timer, 30);
+        // Peers without a group are unchanged
+        assert_eq!(config.peers[2].hold_timer, 180);
+        assert_eq!(config.peers[2].families.len(), 4);
+
+        let err = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            group = "core"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Peer 10.0.0.1: Undefined peer group 'core'"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.0/8"
+            remote_as = 65001
+            [[peers.static_routes]]
+            prefix = "9.9.9.0/24"
+            next_hop = "10.0.0.1"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let config = from_str(
+            r#"
+            router_id = "::1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.1.2.3"
+            remote_as = 65001
+            local_router_id = "0.0.0.0"
+            families = []
+            [[peers]]
+            remote_ip = "10.1.2.3"
+            remote_as = 65002
+            link_local_next_hop = "2001:db8::1"
+            min_ttl = 0
+            local_address = "::1"
+            hold_timer = 30
+            delay_open = 30
+            [[peers.static_flows]]
+            afi = 1
+            action = "drop"
+            matches = ["source 192.168.0.0/16"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err(),
+            vec![
+                "Peer 10.1.2.3/32: local_router_id: Must not be 0.0.0.0",
+                "Peer 10.1.2.3/32: No families configured",
+                "Peer 10.1.2.3/32: Duplicate remote_ip",
+                "Peer 10.1.2.3/32: link_local_next_hop 2001:db8::1 is not a link-local address",
+                "Peer 10.1.2.3/32: min_ttl must be 1-255",
+                "Peer 10.1.2.3/32: delay_open must be 1 second or more, less than the hold_timer",
+                "Peer 10.1.2.3/32: local_address ::1 is a different address family than remote_ip",
+                "Peer 10.1.2.3/32: Static flow #1: Unsupported Flowspec Action 'drop' \
+                 (expected accept, discard, redirect, traffic-rate, traffic-action, or mark)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_role() {
+        let config = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            remote_as = 65001
+            role = "rs-client"
+            strict_role = true
+            [[peers]]
+            remote_ip = "10.0.0.2"
+            remote_as = 65000
+            role = "peer"
+            [[peers]]
+            remote_ip = "10.0.0.3"
+            remote_as = 65003
+            strict_role = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.peers[0].role, Some(Role::RouteServerClient));
+        assert!(config.peers[0].strict_role);
+        assert_eq!(config.peers[2].role, None);
+        assert_eq!(
+            config.validate().unwrap_err(),
+            vec![
+                "Peer 10.0.0.2/32: role only applies to eBGP peers",
+                "Peer 10.0.0.3/32: strict_role requires a role",
+            ]
+        );
+
+        let err = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            remote_as = 65001
+            role = "transit"
+            "#,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid role 'transit'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_capability_order() {
+        let config = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            remote_as = 65001
+            capability_order = ["four_octet_asn", "multiprotocol"]
+            [[peers]]
+            remote_ip = "10.0.0.2"
+            remote_as = 65002
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.peers[0].capability_order, vec![65, 1]);
+        assert!(config.peers[1].capability_order.is_empty());
+
+        let err = from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            remote_as = 65001
+            capability_order = ["route_server"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Unknown capability 'route_server'"),
+            "{}",
+            err
+        );
     }
 }
@@ -0,0 +1,61 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use super::validate_router_id;
+
+/// BGP Identifier for a config without an IPv4 `router_id` (E.g. an IPv6-only host)
+///
+/// The highest configured IPv4 address (BGP sockets & peer local addresses) is used,
+/// otherwise one is derived from a hash of the hostname
+pub(super) fn auto_router_id(addresses: impl Iterator<Item = IpAddr>) -> Ipv4Addr {
+    addresses
+        .filter_map(|addr| match addr {
+            // Loopback addresses aren't unique across routers
+            IpAddr::V4(addr) if !addr.is_loopback() => Some(addr),
+            _ => None,
+        })
+        .filter(|addr| validate_router_id(IpAddr::V4(*addr)).is_ok())
+        .max()
+        .unwrap_or_else(|| hashed_router_id(&hostname()))
+}
+
+/// Non-zero unicast IPv4 address derived from a (FNV-1a) hash of the name
+fn hashed_router_id(name: &str) -> Ipv4Addr {
+    let hash = name.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    let mut octets = hash.to_be_bytes();
+    // 1.x.x.x - 223.x.x.x (not 0.0.0.0, multicast, or broadcast)
+    octets[0] = 1 + octets[0] % 223;
+    Ipv4Addr::from(octets)
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if result != 0 {
+        return String::new();
+    }
+    // May not be terminated if truncated
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_router_id() {
+        let addresses = ["::1", "10.0.0.5", "127.0.0.1", "10.0.0.9", "0.0.0.0"];
+        let router_id = auto_router_id(addresses.iter().map(|a| a.parse().unwrap()));
+        assert_eq!(router_id, Ipv4Addr::new(10, 0, 0, 9));
+
+        // Derived from the hostname, without any IPv4 addresses
+        for name in &["", "router1", "bgpd.example.com"] {
+            let router_id = hashed_router_id(name);
+            assert!(validate_router_id(IpAddr::V4(router_id)).is_ok());
+            assert_eq!(router_id, hashed_router_id(name));
+        }
+        assert_ne!(hashed_router_id("router1"), hashed_router_id("router2"));
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::mem;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use bgp_rs::{NLRIEncoding, SAFI};
+use ipnetwork::IpNetwork;
+use log::{debug, warn};
+
+use crate::rib::{path_rank, EntrySource, ExportEntry};
+
+/// Route protocol used for installed routes (matches `bgp` in /etc/iproute2/rt_protos)
+const RTPROT_BGP: u8 = 186;
+
+/// Installs best-path learned unicast routes into the kernel FIB (main table)
+/// and removes them once they are withdrawn
+pub struct Fib {
+    socket: NetlinkSocket,
+    /// Currently installed routes (prefix -> next hop)
+    installed: HashMap<IpNetwork, IpAddr>,
+}
+
+impl Fib {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            socket: NetlinkSocket::new()?,
+            installed: HashMap::new(),
+        })
+    }
+
+    /// Sync the kernel FIB for changed prefixes, with the best-paths of their RIB routes
+    /// Netlink requests block on the kernel ACK, so this is run with `spawn_blocking`
+    pub fn sync(&mut self, changed: &HashSet<IpNetwork>, routes: &[Arc<ExportEntry>]) {
+        let selected = best_paths(routes);
+        let (add, remove) = fib_changes(&self.installed, &selected, changed);
+        for prefix in remove {
+            match self.socket.route(RouteAction::Delete, prefix, None) {
+                Ok(()) => debug!("Removed {} from FIB", prefix),
+                Err(err) => warn!("Error removing {} from FIB: {}", prefix, err),
+            }
+            self.installed.remove(&prefix);
+        }
+        for (prefix, next_hop) in add {
+            match self
+                .socket
+                .route(RouteAction::Replace, prefix, Some(next_hop))
+            {
+                Ok(()) => {
+                    debug!("Installed {} via {} in FIB", prefix, next_hop);
+                    self.installed.insert(prefix, next_hop);
+                }
+                Err(err) => warn!("Error installing {} in FIB: {}", prefix, err),
+            }
+        }
+    }
+}
+
+/// Select a best-path (prefix -> next hop) for each learned unicast prefix (see `path_rank`)
+fn best_paths(routes: &[Arc<ExportEntry>]) -> HashMap<IpNetwork, IpAddr> {
+    let mut best: HashMap<IpNetwork, &Arc<ExportEntry>> = HashMap::new();
+    for route in routes {
+        if !matches!(route.source, EntrySource::Peer(_))
+            || route.update.family.safi != SAFI::Unicast
+            || route.update.attributes.next_hop.is_none()
+        {
+            continue;
+        }
+        let prefix = match &route.update.nlri {
+            NLRIEncoding::IP(prefix) | NLRIEncoding::IP_WITH_PATH_ID((prefix, _)) => {
+                let (addr, length) = prefix.into();
+                match IpNetwork::new(addr, length) {
+                    Ok(prefix) => prefix,
+                    Err(_) => continue,
+                }
+            }
+            _ => continue,
+        };
+        match best.get(&prefix) {
+            Some(current) if path_rank(current) <= path_rank(route) => (),
+            _ => {
+                best.insert(prefix, route);
+            }
+        }
+    }
+    best.into_iter()
+        .filter_map(|(prefix, route)| {
+            route
+                .update
+                .attributes
+                .next_hop
+                .map(|next_hop| (prefix, next_hop))
+        })
+        .collect()
+}
+
+/// Work out routes to add/replace & remove for the `changed` prefixes,
+/// to get from `installed` to `selected` (best-paths for the changed prefixes)
+fn fib_changes(
+    installed: &HashMap<IpNetwork, IpAddr>,
+    selected: &HashMap<IpNetwork, IpAddr>,
+    changed: &HashSet<IpNetwork>,
+) -> (Vec<(IpNetwork, IpAddr)>, Vec<IpNetwork>) {
+    let add = selected
+        .iter()
+        .filter(|(prefix, next_hop)| installed.get(prefix) != Some(next_hop))
+        .map(|(prefix, next_hop)| (*prefix, *next_hop))
+        .collect();
+    let remove = changed
+        .iter()
+        .filter(|prefix| installed.contains_key(prefix) && !selected.contains_key(prefix))
+        .copied()
+        .collect();
+    (add, remove)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RouteAction {
+    Replace,
+    Delete,
+}
+
+/// Minimal rtnetlink (NETLINK_ROUTE) socket for adding/removing routes
+struct NetlinkSocket {
+    fd: libc::c_int,
+    seq: u32,
+}
+
+impl NetlinkSocket {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd, seq: 0 })
+    }
+
+    /// Send a route request and wait for the kernel ACK
+    fn route(
+        &mut self,
+        action: RouteAction,
+        prefix: IpNetwork,
+        next_hop: Option<IpAddr>,
+    ) -> io::Result<()> {
+        self.seq = self.seq.wrapping_add(1);
+        let message = route_message(action, prefix, next_hop, self.seq);
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let sent = unsafe {
+            libc::sendto(
+                self.fd,
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = [0u8; 1024];
+        loop {
+            let read =
+                unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if read < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(result) = parse_ack(&buf[..read as usize], self.seq) {
+                return result;
+            }
+        }
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Build a RTM_NEWROUTE/RTM_DELROUTE request
+fn route_message(
+    action: RouteAction,
+    prefix: IpNetwork,
+    next_hop: Option<IpAddr>,
+    seq: u32,
+) -> Vec<u8> {
+    let (msg_type, flags) = match action {
+        RouteAction::Replace => (
+            libc::RTM_NEWROUTE,
+            libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE | libc::NLM_F_REPLACE,
+        ),
+        RouteAction::Delete => (libc::RTM_DELROUTE, libc::NLM_F_REQUEST | libc::NLM_F_ACK),
+    };
+    let family = match prefix {
+        IpNetwork::V4(_) => libc::AF_INET,
+        IpNetwork::V6(_) => libc::AF_INET6,
+    };
+    let mut buf: Vec<u8> = Vec::with_capacity(64);
+    // nlmsghdr (length is filled in at the end)
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    buf.extend_from_slice(&msg_type.to_ne_bytes());
+    buf.extend_from_slice(&(flags as u16).to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    // rtmsg
+    buf.extend_from_slice(&[
+        family as u8,
+        prefix.prefix(),
+        0, // src_len
+        0, // tos
+        libc::RT_TABLE_MAIN,
+        RTPROT_BGP,
+        libc::RT_SCOPE_UNIVERSE,
+        libc::RTN_UNICAST,
+    ]);
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    // Attributes
+    push_attr(&mut buf, libc::RTA_DST, &ip_octets(prefix.network()));
+    if let Some(next_hop) = next_hop {
+        push_attr(&mut buf, libc::RTA_GATEWAY, &ip_octets(next_hop));
+    }
+    let len = buf.len() as u32;
+    buf[0..4].copy_from_slice(&len.to_ne_bytes());
+    buf
+}
+
+fn push_attr(buf: &mut Vec<u8>, attr_type: u16, data: &[u8]) {
+    let len = (4 + data.len()) as u16;
+    buf.extend_from_slice(&len.to_ne_bytes());
+    buf.extend_from_slice(&attr_type.to_ne_bytes());
+    buf.extend_from_slice(data);
+    // Attributes are aligned to 4 bytes
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+fn ip_octets(addr: IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    }
+}
+
+/// Find the NLMSG_ERROR (ACK) for this sequence number
+/// An error code of 0 is a successful ACK
+fn parse_ack(buf: &[u8], seq: u32) -> Option<io::Result<()>> {
+    let mut offset = 0usize;
+    while offset + 20 <= buf.len() {
+        let read_u32 = |at: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&buf[at..at + 4]);
+            u32::from_ne_bytes(bytes)
+        };
+        let len = read_u32(offset) as usize;
+        let msg_type = u16::from_ne_bytes([buf[offset + 4], buf[offset + 5]]);
+        let msg_seq = read_u32(offset + 8);
+        if msg_type == libc::NLMSG_ERROR as u16 && msg_seq == seq {
+            let code = read_u32(offset + 16) as i32;
+            return Some(if code == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::from_raw_os_error(-code))
+            });
+        }
+        if len == 0 {
+            break;
+        }
+        offset += (len + 3) & !3;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rib::{CommunityList, ExportedUpdate, Family, PathAttributes};
+    use bgp_rs::{ASPath, Origin, AFI};
+
+    fn learned(prefix: &str, next_hop: &str, local_pref: Option<u32>) -> Arc<ExportEntry> {
+        let prefix: IpNetwork = prefix.parse().unwrap();
+        let next_hop: IpAddr = next_hop.parse().unwrap();
+        let attributes = PathAttributes {
+            next_hop: Some(next_hop),
+            link_local_next_hop: None,
+            origin: Origin::IGP,
+            as_path: ASPath { segments: vec![] },
+            local_pref,
+            multi_exit_disc: None,
+            originator_id: None,
+            cluster_list: vec![],
+            atomic_aggregate: false,
+            aggregator: None,
+            communities: CommunityList(vec![]),
+        };
+        let update = ExportedUpdate {
+            family: Family::new(AFI::IPV4, SAFI::Unicast),
+            attributes: Arc::new(attributes),
+            nlri: NLRIEncoding::IP((prefix.ip(), prefix.prefix()).into()),
+        };
+        Arc::new(ExportEntry::new(update, EntrySource::Peer(next_hop)))
+    }
+
+    #[test]
+    fn test_best_path_changes() {
+        let routes = vec![
+            learned("10.0.0.0/24", "127.0.0.2", Some(100)),
+            learned("10.0.0.0/24", "127.0.0.3", Some(200)),
+            learned("10.1.0.0/24", "127.0.0.2", None),
+        ];
+        let selected = best_paths(&routes);
+        assert_eq!(selected.len(), 2);
+        let prefix: IpNetwork = "10.0.0.0/24".parse().unwrap();
+        assert_eq!(selected[&prefix], "127.0.0.3".parse::<IpAddr>().unwrap());
+
+        let changed: HashSet<IpNetwork> = selected.keys().copied().collect();
+        let (add, remove) = fib_changes(&HashMap::new(), &selected, &changed);
+        assert_eq!(add.len(), 2);
+        assert!(remove.is_empty());
+        let (add, remove) = fib_changes(&selected, &best_paths(&routes[2..]), &changed);
+        assert!(add.is_empty());
+        assert_eq!(remove, vec![prefix]);
+        // Installed routes for unchanged prefixes are left alone
+        let (add, remove) = fib_changes(&selected, &HashMap::new(), &HashSet::new());
+        assert!(add.is_empty());
+        assert!(remove.is_empty());
+    }
+
+    #[test]
+    fn test_router_id_tiebreak() {
+        let prefix: IpNetwork = "10.0.0.0/24".parse().unwrap();
+        let with_router_id = |next_hop: &str, router_id: &str| {
+            let mut route = Arc::try_unwrap(learned("10.0.0.0/24", next_hop, None)).unwrap();
+            route.router_id = Some(router_id.parse().unwrap());
+            Arc::new(route)
+        };
+        let low = with_router_id("127.0.0.3", "1.1.1.1");
+        let high = with_router_id("127.0.0.2", "2.2.2.2");
+        // Lower router-id wins in either order (even with a higher peer address)
+        for routes in [vec![low.clone(), high.clone()], vec![high, low]] {
+            let selected = best_paths(&routes);
+            assert_eq!(selected[&prefix], "127.0.0.3".parse::<IpAddr>().unwrap());
+        }
+
+        // Without router-ids, the lowest peer address wins (regardless of age)
+        let mut routes = vec![];
+        for (i, next_hop) in ["127.0.0.5", "127.0.0.4"].iter().enumerate() {
+            let mut route = Arc::try_unwrap(learned("10.0.0.0/24", next_hop, None)).unwrap();
+            route.timestamp = chrono::Utc::now() - chrono::Duration::minutes(i as i64);
+            routes.push(Arc::new(route));
+        }
+        let selected = best_paths(&routes);
+        assert_eq!(selected[&prefix], "127.0.0.4".parse::<IpAddr>().unwrap());
+    }
+
+    /// Requires CAP_NET_ADMIN, run in a network namespace: `unshare -rn cargo test --features fib -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_kernel_install_and_withdraw() {
+        let output = std::process::Command::new("ip")
+            .args(["link", "set", "lo", "up"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let route = learned("10.99.0.0/24", "127.0.0.2", None);
+        let changed = std::iter::once("10.99.0.0/24".parse().unwrap()).collect();
+        let mut fib = Fib::new().unwrap();
+        fib.sync(&changed, &[route]);
+        let show = || {
+            let output = std::process::Command::new("ip")
+                .args(["route", "show", "10.99.0.0/24"])
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+        assert!(show().contains("via 127.0.0.2"));
+        fib.sync(&changed, &[]);
+        assert!(show().is_empty());
+    }
+}
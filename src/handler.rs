@@ -1,15 +1,30 @@
 use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
-use log::trace;
+use jsonrpsee::http_server::HttpServerHandle;
+use log::{info, trace};
 use tokio::net::TcpListener;
-use tokio::sync::{watch, RwLock};
+use tokio::sync::{broadcast, watch, Notify, RwLock};
 
-use crate::config::ServerConfig;
+use crate::api::rpc::{FlowSpec, ReloadSummary, RouteSpec};
+use crate::bmp::{BmpClient, PeerUp};
+use crate::config::{self, ServerConfig};
+#[cfg(feature = "fib")]
+use crate::fib::Fib;
 use crate::rib::RIB;
-use crate::session::{SessionManager, SessionUpdate};
-use crate::utils::{parse_flow_spec, parse_route_spec};
+use crate::session::{
+    bind_listener, validate_md5_password, validate_min_ttl, SessionManager, SessionUpdate,
+};
+use crate::update_log::UpdateLog;
+use crate::utils::{parse_flow_spec, parse_route_spec, ParseError};
 
+/// Session updates queued for each `subscribe()` receiver, before the oldest are dropped
+const EVENT_QUEUE_SIZE: usize = 1024;
+
+/// BGPd server, processing session updates into the RIB (see `run()`)
+///
+/// Built with `ServerBuilder` to run BGPd embedded in another application
 #[derive(Clone)]
 pub struct Server {
     pub(crate) inner: Arc<State>,
@@ -18,63 +33,452 @@ pub struct Server {
 pub struct State {
     pub(crate) sessions: Arc<RwLock<SessionManager>>,
     pub(crate) rib: Arc<RwLock<RIB>>,
+    #[cfg(feature = "fib")]
+    pub(crate) fib: Option<std::sync::Mutex<Fib>>,
+    pub(crate) bmp: Option<std::sync::Mutex<BmpClient>>,
+    pub(crate) update_log: Option<UpdateLog>,
+    // Signals `run()` to stop (E.g. from the `shutdown` API)
+    pub(crate) shutdown: Notify,
+    // Session updates sent to `subscribe()` receivers
+    events: broadcast::Sender<SessionUpdate>,
+    // JSON-RPC server started by `ServerBuilder` (stopped when dropped)
+    api_handle: std::sync::Mutex<Option<HttpServerHandle>>,
+    // Config file re-read by `reload_config` (if set)
+    config_source: std::sync::Mutex<Option<ConfigSource>>,
+    // Local addresses of the BGP listeners
+    local_addrs: Vec<SocketAddr>,
+}
+
+/// Config file path & channel to send reloaded configs to the session manager
+struct ConfigSource {
+    path: String,
+    config_tx: watch::Sender<Arc<ServerConfig>>,
 }
 
 impl Server {
     pub fn new(
         config: Arc<ServerConfig>,
-        listener: TcpListener,
+        listeners: Vec<TcpListener>,
         config_rx: watch::Receiver<Arc<ServerConfig>>,
     ) -> Result<Self, Box<dyn Error>> {
         let mut rib = RIB::new();
+        if config.flowspec_validation {
+            rib.enable_flowspec_validation();
+        }
+        if let Some(max_groups) = config.max_attribute_groups {
+            rib.limit_attribute_cache(max_groups);
+        }
+        match config.cluster_id {
+            Some(IpAddr::V4(cluster_id)) => rib.set_cluster_id(cluster_id),
+            Some(IpAddr::V6(_)) => return Err("cluster_id must be an IPv4 address".into()),
+            None => (),
+        }
         for peer in config.peers.iter() {
+            if let Some(password) = &peer.md5_password {
+                validate_md5_password(password)
+                    .map_err(|err| format!("Invalid config for {}: {}", peer.remote_ip, err))?;
+            }
+            if let Some(min_ttl) = peer.min_ttl {
+                validate_min_ttl(min_ttl)
+                    .map_err(|err| format!("Invalid config for {}: {}", peer.remote_ip, err))?;
+            }
             for route in peer.static_routes.iter() {
                 let (family, attributes, nlri) = parse_route_spec(route)?;
-                rib.insert_from_config(family, attributes, nlri);
+                rib.insert_from_config(family, attributes, nlri, route.track_peer);
             }
             for route in peer.static_flows.iter() {
                 let (family, attributes, nlri) = parse_flow_spec(route)?;
-                rib.insert_from_config(family, attributes, nlri);
+                rib.insert_from_config(family, attributes, nlri, None);
             }
         }
-        let manager = SessionManager::new(config, listener, config_rx);
+        #[cfg(feature = "fib")]
+        let fib = if config.fib_install {
+            rib.track_fib_changes();
+            Some(std::sync::Mutex::new(Fib::new()?))
+        } else {
+            None
+        };
+        #[cfg(not(feature = "fib"))]
+        if config.fib_install {
+            log::warn!("`fib_install` is set, but BGPd was built without the `fib` feature");
+        }
+        let bmp = match config.bmp_collector {
+            Some(collector) => Some(std::sync::Mutex::new(BmpClient::start(
+                collector,
+                config.router_id,
+            )?)),
+            None => None,
+        };
+        let update_log = match &config.update_log {
+            Some(path) => Some(
+                UpdateLog::start(path)
+                    .map_err(|err| format!("Error opening update_log {}: {}", path, err))?,
+            ),
+            None => None,
+        };
+        let local_addrs = listeners
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect::<Result<Vec<_>, _>>()?;
+        let manager = SessionManager::new(config, listeners, config_rx);
 
         Ok(Self {
             inner: Arc::new(State {
                 sessions: Arc::new(RwLock::new(manager)),
                 rib: Arc::new(RwLock::new(rib)),
+                #[cfg(feature = "fib")]
+                fib,
+                bmp,
+                update_log,
+                shutdown: Notify::new(),
+                events: broadcast::channel(EVENT_QUEUE_SIZE).0,
+                api_handle: std::sync::Mutex::new(None),
+                config_source: std::sync::Mutex::new(None),
+                local_addrs,
             }),
         })
     }
 
+    /// Local addresses of the BGP listeners (with the bound port for a `bgp_sockets` port of 0)
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.inner.local_addrs
+    }
+
+    /// Receive session updates (routes learned, sessions established/ended, etc.)
+    /// as they're processed by `run()`
+    /// Updates are dropped for a receiver that falls behind (`RecvError::Lagged`)
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionUpdate> {
+        self.inner.events.subscribe()
+    }
+
+    /// Signal `run()` to return (without notifying peers, unlike the `shutdown` API)
+    pub fn stop(&self) {
+        self.inner.shutdown.notify_one();
+    }
+
+    /// Config file (and the channel the session manager receives configs on) to
+    /// re-read with `reload_config`
+    pub fn set_config_source(&self, path: String, config_tx: watch::Sender<Arc<ServerConfig>>) {
+        *self.inner.config_source.lock().expect("Config source lock") =
+            Some(ConfigSource { path, config_tx });
+    }
+
+    /// Re-read the config file (E.g. on SIGHUP or from the `reload` API)
+    /// and apply it, returning the peer configs added/removed/changed
+    pub async fn reload_config(&self) -> Result<ReloadSummary, String> {
+        let (mut summary, current, reloaded) = {
+            let source = self.inner.config_source.lock().expect("Config source lock");
+            let source = source
+                .as_ref()
+                .ok_or_else(|| "No config file to reload".to_string())?;
+            let reloaded = Arc::new(
+                config::from_file(&source.path)
+                    .map_err(|err| format!("Error reloading {}: {}", source.path, err))?,
+            );
+            let current = source.config_tx.borrow().clone();
+            let summary = current.peer_changes(&reloaded);
+            source
+                .config_tx
+                .send(reloaded.clone())
+                .map_err(|_| "Session manager is not running".to_string())?;
+            info!(
+                "Reloaded {} [{} peers added, {} removed, {} changed]",
+                source.path,
+                summary.peers_added.len(),
+                summary.peers_removed.len(),
+                summary.peers_changed.len(),
+            );
+            (summary, current, reloaded)
+        };
+        let (added, removed) = self.reload_static_routes(&current, &reloaded).await?;
+        summary.static_routes_added = added;
+        summary.static_routes_removed = removed;
+        Ok(summary)
+    }
+
+    /// Add static routes & flows new to the reloaded config, and remove those no longer
+    /// configured from the RIB (sessions withdraw them on their next sync)
+    /// Returns the number of routes added & removed
+    async fn reload_static_routes(
+        &self,
+        current: &ServerConfig,
+        reloaded: &ServerConfig,
+    ) -> Result<(usize, usize), String> {
+        let static_routes = |config: &ServerConfig| -> Vec<RouteSpec> {
+            config
+                .peers
+                .iter()
+                .flat_map(|peer| peer.static_routes.iter().cloned())
+                .collect()
+        };
+        let static_flows = |config: &ServerConfig| -> Vec<FlowSpec> {
+            config
+                .peers
+                .iter()
+                .flat_map(|peer| peer.static_flows.iter().cloned())
+                .collect()
+        };
+        let parse = |routes: Vec<RouteSpec>, flows: Vec<FlowSpec>| {
+            let mut parsed = Vec::with_capacity(routes.len() + flows.len());
+            for route in &routes {
+                let (family, attributes, nlri) = parse_route_spec(route)?;
+                parsed.push((family, attributes, nlri, route.track_peer));
+            }
+            for flow in &flows {
+                let (family, attributes, nlri) = parse_flow_spec(flow)?;
+                parsed.push((family, attributes, nlri, None));
+            }
+            Ok::<_, ParseError>(parsed)
+        };
+        let (removed_routes, added_routes) =
+            spec_changes(static_routes(current), static_routes(reloaded));
+        let (removed_flows, added_flows) =
+            spec_changes(static_flows(current), static_flows(reloaded));
+        let removed = parse(removed_routes, removed_flows).map_err(|err| err.to_string())?;
+        let added = parse(added_routes, added_flows).map_err(|err| err.to_string())?;
+        if added.is_empty() && removed.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let mut rib = self.inner.rib.write().await;
+        let removed = removed
+            .into_iter()
+            .filter_map(|(family, attributes, nlri, tracked_peer)| {
+                rib.remove_from_config(family, attributes, &nlri, tracked_peer)
+            })
+            .count();
+        let added_count = added.len();
+        for (family, attributes, nlri, tracked_peer) in added {
+            rib.insert_from_config(family, attributes, nlri, tracked_peer);
+        }
+        info!(
+            "Reloaded static routes [{} added, {} removed]",
+            added_count, removed
+        );
+        Ok((added_count, removed))
+    }
+
+    /// Process session updates until a shutdown is requested (or the session manager fails)
     pub async fn run(&mut self) -> Result<(), Box<dyn Error>> {
-        while let Ok(update) = self
-            .inner
-            .sessions
-            .write()
-            .await
-            .get_update(self.inner.rib.clone())
-            .await
-        {
+        loop {
+            let update = tokio::select! {
+                update = async {
+                    self.inner
+                        .sessions
+                        .write()
+                        .await
+                        .get_update(self.inner.rib.clone())
+                        .await
+                        // Not holding the (non-`Send`) error across awaits
+                        .ok()
+                } => update,
+                _ = self.inner.shutdown.notified() => {
+                    info!("Shutting down BGPd");
+                    break;
+                }
+            };
+            let update = match update {
+                Some(update) => update,
+                None => break,
+            };
             trace!("Rib has {} entries", self.inner.rib.read().await.len());
+            if let Some(update) = &update {
+                if self.inner.events.receiver_count() > 0 {
+                    // Only fails if all receivers were dropped
+                    let _ = self.inner.events.send(update.clone());
+                }
+            }
             match update {
-                Some(SessionUpdate::Learned((router_id, update))) => {
+                Some(SessionUpdate::Learned((router_id, config, update, otc))) => {
                     trace!("Incoming update from {}: {:?}", router_id, update);
+                    if let Some(bmp) = &self.inner.bmp {
+                        bmp.lock()
+                            .expect("BMP lock")
+                            .route_monitoring(router_id, &update);
+                    }
+                    if let Some(update_log) = &self.inner.update_log {
+                        update_log.log(router_id, &update);
+                    }
                     self.inner
                         .rib
                         .write()
                         .await
-                        .update_from_peer(router_id, update)?;
+                        .update_from_peer_with_otc(router_id, &config, update, otc)?;
+                }
+                Some(SessionUpdate::Established((peer, router_id))) => {
+                    if let Some(bmp) = &self.inner.bmp {
+                        let manager = self.inner.sessions.read().await;
+                        let up = manager
+                            .sessions
+                            .read()
+                            .await
+                            .get(&peer)
+                            .and_then(PeerUp::from_session);
+                        if let Some(up) = up {
+                            let notification = manager
+                                .get_history(peer)
+                                .await
+                                .and_then(|h| h.last_notification);
+                            bmp.lock().expect("BMP lock").peer_up(&up, notification);
+                        }
+                    }
+                    let mut rib = self.inner.rib.write().await;
+                    rib.peer_up(peer);
+                    rib.set_router_id(peer, router_id);
                 }
                 Some(SessionUpdate::Ended(peers)) => {
+                    if let Some(bmp) = &self.inner.bmp {
+                        let manager = self.inner.sessions.read().await;
+                        for (peer, _) in &peers {
+                            let notification = manager
+                                .get_history(*peer)
+                                .await
+                                .and_then(|h| h.last_notification);
+                            bmp.lock().expect("BMP lock").peer_down(*peer, notification);
+                        }
+                    }
                     let mut rib = self.inner.rib.write().await;
-                    for peer in peers {
-                        rib.remove_from_peer(peer);
+                    for (peer, restart) in peers {
+                        rib.peer_down(peer);
+                        match restart {
+                            Some(restart) => rib.retain_for_restart(peer, &restart),
+                            None => rib.remove_from_peer(peer),
+                        }
                     }
                 }
+                Some(SessionUpdate::BeginRefresh((peer, family))) => {
+                    self.inner.rib.write().await.mark_stale(peer, family);
+                }
+                Some(SessionUpdate::EndRefresh((peer, family))) => {
+                    self.inner.rib.write().await.remove_stale(peer, family);
+                }
+                Some(SessionUpdate::EndOfRib((peer, family))) => {
+                    self.inner.rib.write().await.end_of_rib(peer, family);
+                }
                 _ => (),
             }
+            let mut rib = self.inner.rib.write().await;
+            rib.purge_expired_restarts();
+            rib.purge_dampening_history();
+            #[cfg(feature = "fib")]
+            if self.inner.fib.is_some() {
+                let changed = rib.take_fib_changes();
+                if changed.is_empty() {
+                    continue;
+                }
+                let routes = rib.get_routes_for_prefixes(&changed);
+                drop(rib);
+                let inner = self.inner.clone();
+                tokio::task::spawn_blocking(move || {
+                    if let Some(fib) = &inner.fib {
+                        fib.lock().expect("FIB lock").sync(&changed, &routes);
+                    }
+                })
+                .await?;
+            }
         }
         Ok(())
     }
 }
+
+/// Builder for a `Server`, binding its BGP sockets (`bgp_sockets`) and starting
+/// the optional JSON-RPC API & metrics servers
+///
+/// ```no_run
+/// # async fn embedded() -> Result<(), Box<dyn std::error::Error>> {
+/// use bgpd_rs::api::rpc::RouteSpec;
+/// use bgpd_rs::handler::ServerBuilder;
+///
+/// let config = bgpd_rs::config::from_file("config.toml")?;
+/// let mut server = ServerBuilder::new(config)
+///     .with_api("127.0.0.1:8080".parse()?)
+///     .build()
+///     .await?;
+/// let mut events = server.subscribe();
+/// server
+///     .advertise_route(RouteSpec::new("100.1.0.0/16".parse()?, "10.0.0.1".parse()?))
+///     .await?;
+/// tokio::spawn(async move {
+///     while let Ok(update) = events.recv().await {
+///         println!("{:?}", update);
+///     }
+/// });
+/// server.run().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ServerBuilder {
+    config: Arc<ServerConfig>,
+    api_socket: Option<SocketAddr>,
+    metrics_socket: Option<SocketAddr>,
+    config_path: Option<String>,
+}
+
+impl ServerBuilder {
+    pub fn new(config: ServerConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            api_socket: None,
+            metrics_socket: None,
+            config_path: None,
+        }
+    }
+
+    /// Serve the JSON-RPC API (not started by default, `api_socket` isn't used)
+    pub fn with_api(mut self, socket: SocketAddr) -> Self {
+        self.api_socket = Some(socket);
+        self
+    }
+
+    /// Serve metrics (not started by default, `metrics_socket` isn't used)
+    pub fn with_metrics(mut self, socket: SocketAddr) -> Self {
+        self.metrics_socket = Some(socket);
+        self
+    }
+
+    /// Config file the config was read from, re-read by `Server::reload_config`
+    pub fn with_config_file(mut self, path: String) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+    pub async fn build(self) -> Result<Server, Box<dyn Error>> {
+        let config = self.config;
+        // Keep IPv6 listeners from also accepting (IPv4-mapped) IPv4 connections
+        // if there's a separate IPv4 listener
+        let only_v6 = config.bgp_sockets.iter().any(|socket| socket.is_ipv4());
+        let listeners = config
+            .bgp_sockets
+            .iter()
+            .map(|socket| bind_listener(*socket, config.bgp_backlog, only_v6))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (config_tx, config_rx) = watch::channel(config.clone());
+        let server = Server::new(config, listeners, config_rx)?;
+        if let Some(path) = self.config_path {
+            server.set_config_source(path, config_tx);
+        }
+        if let Some(socket) = self.api_socket {
+            let handle = server.serve_rpc_api(socket).await?;
+            *server.inner.api_handle.lock().expect("API handle lock") = Some(handle);
+        }
+        if let Some(socket) = self.metrics_socket {
+            server.serve_metrics(socket).await?;
+        }
+        Ok(server)
+    }
+}
+
+/// Specs only in `current` (removed) & only in `reloaded` (added)
+/// Duplicate specs are matched one-for-one
+fn spec_changes<T: PartialEq>(current: Vec<T>, mut reloaded: Vec<T>) -> (Vec<T>, Vec<T>) {
+    let mut removed = vec![];
+    for spec in current {
+        match reloaded.iter().position(|s| *s == spec) {
+            Some(i) => {
+                reloaded.remove(i);
+            }
+            None => removed.push(spec),
+        }
+    }
+    (removed, reloaded)
+}
@@ -23,20 +23,36 @@
 //! - `-vv` TRACE
 //! - `-vvv` TRACE (including tokio logs)
 //!
+//! For log aggregation, `--log-format json` (or `BGPD_LOG_FORMAT=json`) logs a JSON object per line,
+//! with structured fields where available (E.g. `peer`, `from`/`to` session states, `message_type`):
+//! ```sh
+//! $ bgpd run path/to/config.toml -v --log-format json
+//! {"event":"state_change","from":"OpenConfirm","level":"DEBUG","message":"127.0.0.2 went from OpenConfirm to Established","peer":"127.0.0.2","target":"bgpd_rs::session::lib","timestamp":"2022-06-01T12:00:00.000Z","to":"Established"}
+//! ```
+//!
 //! To update the daemon with an updated config while it's running, send a SIGHUP:
 //! ```sh
 //! pkill -1 bgpd$
 //! ```
+//! Or use the `reload` API (`bgpd reload`), which also lists the peer configs added, removed, or changed.
+//! Files matched by `include` are re-read on reload, so peers can be added or removed there too.
 //! The following peer items will be updated:
 //! - Peers added, removed, enabled, disabled
 //! - Active/passive polling for idle peers
 //! - *Hold Timer
 //! - *Supported Families
+//! - Static routes & flows (removed ones are withdrawn from peers)
 //!
 //!>  **When not in an active session only, since these are negotiated in the OPEN*
+//!
+//! BGPd can also run embedded in another tokio application with [`handler::ServerBuilder`],
+//! receiving session updates (`Server::subscribe`) and advertising routes & flows
+//! (`Server::advertise_route`, `Server::advertise_flow`) without the JSON-RPC API.
 
 /// JSON RPC API
 pub mod api;
+/// BGP Monitoring Protocol (RFC 7854) feed to a collector
+pub mod bmp;
 /// BGPd CLI for interacting with a running BGPd process
 pub mod cli;
 /// TOML Config Manager
@@ -45,8 +61,30 @@ pub mod cli;
 ///
 /// Details of config values:
 /// ```toml
-/// router_id = "1.1.1.1"        # Default Router ID for the service
+/// router_id = "1.1.1.1"        # Default Router ID for the service (auto-selected if not IPv4 or not set)
 /// default_as = 65000           # Used as the local-as if `local_as` is not defined for a peer
+/// bgp_sockets = ["0.0.0.0:179", "[::]:179"] # BGP address(es) & port (a single `bgp_socket` also works)
+/// bgp_backlog = 1024           # Pending connection queue size for the BGP socket(s) (default 1024)
+/// metrics_socket = "[::]:9179" # Optional Prometheus/OpenMetrics endpoint (GET /metrics)
+/// bmp_collector = "10.0.0.9:5000" # Optional BMP (RFC 7854) collector for peer state & learned routes
+/// update_log = "updates.log"   # Log every received UPDATE (decoded) to this rotating file, for debugging
+/// fib_install = true           # Install best-path learned routes in the kernel FIB (requires `fib` feature)
+/// flowspec_validation = true   # Drop learned Flowspec rules not backed by a unicast route from the same peer
+/// max_attribute_groups = 10000 # Warn when the RIB holds more distinct path attribute groups
+/// cluster_id = "1.1.1.1"       # Drop learned iBGP routes with this Cluster ID in their CLUSTER_LIST (RFC 4456)
+/// include = ["peers/*.toml"]   # Load additional `[[peers]]` from these files (relative to this file)
+///
+/// [dampening]                  # Optional Route Flap Dampening of learned routes (RFC 2439), for all peers
+/// half_life = 900              # Seconds for a penalty to decay by half
+/// suppress_limit = 2000        # Suppress a route once its penalty exceeds this
+/// reuse_limit = 750            # Re-use a suppressed route once its penalty decays below this
+/// max_penalty = 12000
+///
+/// [[peer_groups]]              # Shared peer settings, inherited by peers with `group = "<name>"`
+/// name = "ixp"                 # (any peer option, unless set on the peer itself)
+/// remote_as = 65100
+/// hold_timer = 30
+/// families = ["ipv4 unicast", "ipv6 unicast"]
 ///
 /// [[peers]]
 /// remote_ip = "127.0.0.2"      # This can also be an IPv6 address, see next peer
@@ -55,14 +93,58 @@ pub mod cli;
 /// passive = true               # If passive, bgpd won't attempt outbound connections
 /// router_id = "127.0.0.1"      # Can override local Router ID for this peer
 /// hold_timer = 90              # Set the hold timer for the peer, defaults to 180 seconds
+/// md5_password = "secret"      # TCP MD5 Signature (RFC 2385) password, Linux only
+/// min_ttl = 255                # TTL Security (RFC 5082), Linux only (255 = directly connected, lower for multihop)
+/// local_address = "127.0.0.1"  # Source address for outbound connections (default is the listener address)
+/// min_keepalive = 20           # Cap the keepalive interval (default is 1/3 of the hold timer)
+/// connect_retry = 60           # Seconds between outbound connection retries (default 120, backs off up to 8x)
+/// delay_open = 5               # Wait for the peer's OPEN before sending ours (DelayOpen), up to this many seconds
+/// max_prefixes = 1000          # Tear down the session if more prefixes are received (alias `max_prefix_in`)
+/// max_prefix_warning = 75      # Log a warning (once) at this percentage of max_prefixes (alias `max_prefix_in_warn_pct`)
+/// max_prefix_out = 5000        # Stop advertising more prefixes than this (protects against leaks)
+/// separate_capabilities = true # Send each capability in its own OPEN parameter (default false)
+/// capability_order = ["four_octet_asn", "multiprotocol"] # Send these capabilities first, in this order
+/// log_capabilities = false     # Log negotiated capabilities at Established (default true)
+/// extended_messages = false    # Advertise Extended Messages (RFC 8654), up to 65535 bytes (default true)
+/// normalize_mapped_nexthop = true # Store learned IPv4-mapped (::ffff:a.b.c.d) next hops as IPv4
+/// strict_fsm = true            # KEEPALIVE before OpenConfirm is an FSM error (default false, ignored)
+/// initial_batch = true         # Batch the initial route dump by attributes, followed by End-of-RIB
+/// tag_community = "65000:5"    # Add this community to routes learned from this peer
+/// import_rts = ["target:65000:100"] # Only accept learned routes (E.g. Flowspec) with one of these route targets
+/// export_rts = ["target:65000:100"] # Add these route targets to routes advertised to this peer
+/// med_multiplier = 10          # Scale the MED of learned routes (applied before med_offset)
+/// med_offset = 50              # Add to (or subtract from, if negative) the MED of learned routes
+/// med_in_override = 100        # Replace the MED of learned routes (instead of med_multiplier/offset)
+/// local_pref_in = 200          # Set the LOCAL_PREF of learned routes (added if missing)
+/// med_out = 200                # Advertise all routes to this peer with this MED
 /// families = [                 # Define the families this session should support
 ///   "ipv4 unicast",
 ///   "ipv6 unicast",
+///   "ipv4 multicast",          # Also: "ipv4 flow", "ipv4 labeled-unicast" (capability only)
+/// ]
+/// as_path_prepend = 3          # Prepend the local AS this many times to eBGP advertisements (default 1, max 16)
+/// max_update_rate = 100        # Maximum UPDATEs per second sent for each family (unlimited if not set)
+/// mrai = 10                    # Minimum interval (seconds) between advertisements of a prefix (default 30 eBGP, 5 iBGP, 0 disables)
+/// graceful_restart = 120       # Advertise Graceful Restart, retaining a restarting peer's routes (restart time)
+/// next_hop_self = ["ipv6 unicast"] # Advertise the local session address as next hop for these families (or true for all)
+/// link_local_next_hop = "fe80::1" # Also advertise this link-local next hop with our IPv6 next hop (RFC 2545)
+/// add_path = true              # Send & receive multiple paths per prefix (ADD-PATH, RFC 7911) for unicast/multicast families
+/// aggregate = ["10.0.0.0/22"]  # Advertise these networks in place of the more-specific routes they contain
+/// default_originate = { conditional_on = "10.0.0.0/8" }  # Advertise a default route (only while 10.0.0.0/8 is in the RIB, or always if `{}`)
+/// prefix_list_in = [            # Only accept learned unicast prefixes matching an entry
+///   "10.0.0.0/8 le 24",
+///   "2001:db8::/32 ge 48 le 64",
+/// ]
+/// community_filter_in = [       # Accept/reject learned routes by community (first match, accepted if none match)
+///   "match 65000:666 reject",
 /// ]
+/// reject_bogons = true         # Drop learned unicast prefixes in reserved (bogon) ranges (default false)
+/// dampening = { half_life = 300 } # Route Flap Dampening for this peer (`true` uses [dampening] or defaults, `false` disables)
 ///
 /// [[peers.static_routes]]      # Add static routes (advertised at session start)
 ///   prefix = "9.9.9.0/24"
 ///   next_hop = "127.0.0.1"
+///   track_peer = "127.0.0.2"   # Only advertise while this peer is Established (optional)
 /// [[peers.static_routes]]
 ///   prefix = "3001:100::/64"
 ///   next_hop = "3001:1::1"
@@ -71,28 +153,44 @@ pub mod cli;
 /// action = "traffic-rate 24000"
 /// matches= [
 ///     "source 3001:100::/56",
-///     "destination-port >8000 <=8080",
+///     "destination-port >8000 <=8080", # Bounds form an AND range
+///     "port 80 443",                   # Separate values are OR'd ('&' / '|' prefix to be explicit)
 ///     "packet-length >100",
 /// ]
-/// as_path = ["65000", "500"]
+/// as_path = ["65000", "500"]   # ASNs in braces are an AS_SET (E.g. ["100", "{200", "300}"])
 /// communities = ["101", "202", "65000:99"]
 ///
 ///
 /// [[peers]]
 /// remote_ip = "::2"
 /// enabled = false              # Peer is essentially de-configured
+/// shutdown_message = "Maintenance" # Reason sent (RFC 9003) when disabling a peer with an active session
 /// remote_as = 100
 /// local_as = 200
+/// role = "provider"            # BGP Role (RFC 9234) for this eBGP peer (customer, peer, rs, rs-client)
+/// strict_role = true           # Role Mismatch if the peer advertises no role (default false)
 /// families = [
 ///   "ipv6 unicast",
 /// ]
+///
+/// [[peers]]
+/// remote_ip = "10.10.0.5"
+/// group = "ixp"                # Inherits remote_as & families from the "ixp" peer group
+/// hold_timer = 90              # Overrides the group's hold_timer
 /// ```
 pub mod config;
-/// BGPd TCP listener
+/// Install learned routes into the kernel FIB (Linux)
+#[cfg(feature = "fib")]
+pub mod fib;
+/// BGPd server (& builder for running it embedded)
 pub mod handler;
+/// MRT (RFC 6396) export of the RIB
+pub mod mrt;
 /// BGP Route Store
 pub mod rib;
 /// BGP Session Manager & Utils
 pub mod session;
+/// Debug log of UPDATEs received from peers
+pub mod update_log;
 /// Misc BGP Message & Peer processing utilities
 pub mod utils;
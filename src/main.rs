@@ -1,17 +1,15 @@
 use std::error::Error;
 use std::process;
-use std::sync::Arc;
 
 use clap::Parser;
 use env_logger::Builder;
 use log::{debug, error, info, trace, LevelFilter};
 use signal_hook::{consts::signal::SIGHUP, iterator::Signals};
-use tokio::net::TcpListener;
-use tokio::sync::watch;
 
 use bgpd_rs::cli;
 use bgpd_rs::config;
-use bgpd_rs::handler::Server;
+use bgpd_rs::handler::ServerBuilder;
+use bgpd_rs::utils::{enable_log_fields, format_json};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -23,47 +21,62 @@ async fn main() -> Result<(), Box<dyn Error>> {
         2 => (LevelFilter::Trace, LevelFilter::Warn),
         _ => (LevelFilter::Trace, LevelFilter::Trace),
     };
-    Builder::new()
+    let mut logger = Builder::new();
+    logger
         .filter(Some("bgpd"), bgpd_level)
-        .filter(None, other_level)
-        .init();
+        .filter(None, other_level);
+    if args.log_format == cli::LogFormat::Json {
+        enable_log_fields();
+        logger.format(format_json);
+    }
+    logger.init();
     debug!("Args: {:?}", args);
     info!("Logging at levels {}/{}", bgpd_level, other_level);
 
     match args.cmd {
         cli::Command::Run(opts) => {
-            let config = Arc::new(config::from_file(&opts.config_path)?);
+            let config = config::from_file(&opts.config_path)?;
             debug!("Found {} peers in {}", config.peers.len(), opts.config_path);
             trace!("Using config: {:#?}", &config);
-            let (config_tx, config_rx) = watch::channel(config.clone());
-            config_tx.send(config.clone())?;
+            let sockets = config
+                .bgp_sockets
+                .iter()
+                .map(|socket| socket.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let api_socket = args.api.unwrap_or(config.api_socket);
+            let metrics_socket = config.metrics_socket;
 
-            let bgp_listener = TcpListener::bind(&config.bgp_socket).await?;
-            let mut bgp_server = Server::new(config.clone(), bgp_listener, config_rx)?;
-            // Setup JSON RPC Server
-            let _api_handle = bgp_server
-                .serve_rpc_api(args.api.unwrap_or(config.api_socket))
-                .await?;
+            let mut builder = ServerBuilder::new(config)
+                .with_api(api_socket)
+                .with_config_file(opts.config_path.clone());
+            if let Some(socket) = metrics_socket {
+                builder = builder.with_metrics(socket);
+            }
+            let mut bgp_server = builder.build().await?;
 
-            let mut signals = Signals::new(&[SIGHUP])?;
+            let mut signals = Signals::new([SIGHUP])?;
+            let server = bgp_server.clone();
+            let runtime = tokio::runtime::Handle::current();
             std::thread::spawn(move || {
                 for sig in signals.forever() {
                     info!("Received {}, reloading config", sig);
-                    config::from_file(&opts.config_path)
-                        .map(|new_config| config_tx.send(Arc::new(new_config)))
-                        .map_err(|err| error!("Error reloading config: {}", err))
-                        .ok();
+                    if let Err(err) = runtime.block_on(server.reload_config()) {
+                        error!("{}", err);
+                    }
                 }
             });
 
             // Start BGP Daemon
-            info!(
-                "Starting BGPd [pid {}] on {}...",
-                process::id(),
-                config.bgp_socket
-            );
+            info!("Starting BGPd [pid {}] on {}...", process::id(), sockets);
             bgp_server.run().await?;
         }
+        cli::Command::Check(opts) => {
+            if !cli::check_config(&opts) {
+                process::exit(1);
+            }
+        }
         _ => cli::query_bgpd(&args).await,
     }
     Ok(())
@@ -0,0 +1,383 @@
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+
+use bgp_rs::{Identifier, NLRIEncoding, PathAttribute, Segment, SAFI};
+use byteorder::{NetworkEndian, WriteBytesExt};
+use chrono::{DateTime, Utc};
+
+use crate::rib::{encode_attribute, ExportEntry, PathAttributes};
+
+/// MRT type for TABLE_DUMP_V2 records (RFC 6396 [4.3])
+const TABLE_DUMP_V2: u16 = 13;
+
+/// TABLE_DUMP_V2 subtypes (RFC 6396 [4.3])
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subtype {
+    PeerIndexTable = 1,
+    RibIpv4Unicast = 2,
+    RibIpv6Unicast = 4,
+}
+
+/// A peer in the PEER_INDEX_TABLE, referenced by index from RIB entries
+#[derive(Clone, Debug, PartialEq)]
+pub struct MrtPeer {
+    pub router_id: Ipv4Addr,
+    pub addr: IpAddr,
+    pub asn: u32,
+}
+
+/// TABLE_DUMP_V2 snapshot of IPv4/IPv6 Unicast routes (RFC 6396)
+pub struct TableDump {
+    collector_id: Ipv4Addr,
+    peers: Vec<MrtPeer>,
+    // (Peer index, prefix, route)
+    routes: Vec<(u16, (IpAddr, u8), Arc<ExportEntry>)>,
+}
+
+impl TableDump {
+    pub fn new(collector_id: Ipv4Addr) -> Self {
+        Self {
+            collector_id,
+            peers: vec![],
+            routes: vec![],
+        }
+    }
+
+    /// Index of the peer in the PEER_INDEX_TABLE (added if not already present)
+    pub fn peer_index(&mut self, peer: MrtPeer) -> u16 {
+        match self.peers.iter().position(|p| *p == peer) {
+            Some(index) => index as u16,
+            None => {
+                self.peers.push(peer);
+                (self.peers.len() - 1) as u16
+            }
+        }
+    }
+
+    /// Add a route from a peer (by index), routes for other families are skipped
+    pub fn add_route(&mut self, peer_index: u16, entry: Arc<ExportEntry>) {
+        if entry.update.family.safi != SAFI::Unicast {
+            return;
+        }
+        let prefix = match &entry.update.nlri {
+            NLRIEncoding::IP(prefix) => prefix.into(),
+            NLRIEncoding::IP_WITH_PATH_ID((prefix, _)) => prefix.into(),
+            _ => return,
+        };
+        self.routes.push((peer_index, prefix, entry));
+    }
+
+    /// Number of RIB records (one per prefix)
+    pub fn prefix_count(&self) -> usize {
+        let mut prefixes: Vec<_> = self.routes.iter().map(|(_, prefix, _)| *prefix).collect();
+        prefixes.sort();
+        prefixes.dedup();
+        prefixes.len()
+    }
+
+    /// PEER_INDEX_TABLE followed by a RIB record for each prefix (IPv4 first)
+    pub fn encode(&self, timestamp: DateTime<Utc>, buf: &mut impl Write) -> io::Result<()> {
+        encode_record(
+            timestamp,
+            Subtype::PeerIndexTable,
+            &self.encode_peer_index_table()?,
+            buf,
+        )?;
+        let mut routes: Vec<_> = self.routes.iter().collect();
+        routes.sort_by_key(|(peer_index, (addr, length), _)| {
+            (addr.is_ipv6(), *addr, *length, *peer_index)
+        });
+        let mut sequence: u32 = 0;
+        let mut start = 0;
+        while start < routes.len() {
+            let prefix = routes[start].1;
+            let end = routes[start..]
+                .iter()
+                .position(|(_, p, _)| *p != prefix)
+                .map_or(routes.len(), |offset| start + offset);
+            let subtype = if prefix.0.is_ipv4() {
+                Subtype::RibIpv4Unicast
+            } else {
+                Subtype::RibIpv6Unicast
+            };
+            let body = encode_rib_record(sequence, prefix, &routes[start..end])?;
+            encode_record(timestamp, subtype, &body, buf)?;
+            sequence = sequence.wrapping_add(1);
+            start = end;
+        }
+        Ok(())
+    }
+
+    fn encode_peer_index_table(&self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::with_capacity(8 + self.peers.len() * 25);
+        body.write_all(&self.collector_id.octets())?;
+        body.write_u16::<NetworkEndian>(0 /* View Name length */)?;
+        body.write_u16::<NetworkEndian>(self.peers.len() as u16)?;
+        for peer in &self.peers {
+            // Peer Type: 4-byte ASN (+ IPv6 address)
+            let peer_type = if peer.addr.is_ipv6() { 0x03 } else { 0x02 };
+            body.write_u8(peer_type)?;
+            body.write_all(&peer.router_id.octets())?;
+            match peer.addr {
+                IpAddr::V4(addr) => body.write_all(&addr.octets())?,
+                IpAddr::V6(addr) => body.write_all(&addr.octets())?,
+            }
+            body.write_u32::<NetworkEndian>(peer.asn)?;
+        }
+        Ok(body)
+    }
+}
+
+/// MRT common header (RFC 6396 [2]) followed by the record body
+fn encode_record(
+    timestamp: DateTime<Utc>,
+    subtype: Subtype,
+    body: &[u8],
+    buf: &mut impl Write,
+) -> io::Result<()> {
+    buf.write_u32::<NetworkEndian>(timestamp.timestamp() as u32)?;
+    buf.write_u16::<NetworkEndian>(TABLE_DUMP_V2)?;
+    buf.write_u16::<NetworkEndian>(subtype as u16)?;
+    buf.write_u32::<NetworkEndian>(body.len() as u32)?;
+    buf.write_all(body)
+}
+
+/// RIB_IPV4_UNICAST/RIB_IPV6_UNICAST record with an entry for each route (RFC 6396 [4.3.2])
+fn encode_rib_record(
+    sequence: u32,
+    (addr, length): (IpAddr, u8),
+    routes: &[&(u16, (IpAddr, u8), Arc<ExportEntry>)],
+) -> io::Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(64 * routes.len());
+    body.write_u32::<NetworkEndian>(sequence)?;
+    body.write_u8(length)?;
+    let octets = match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    };
+    body.write_all(&octets[..(usize::from(length) + 7) / 8])?;
+    body.write_u16::<NetworkEndian>(routes.len() as u16)?;
+    for (peer_index, _, entry) in routes {
+        let attributes = encode_attributes(&entry.update.attributes)?;
+        body.write_u16::<NetworkEndian>(*peer_index)?;
+        body.write_u32::<NetworkEndian>(entry.timestamp.timestamp() as u32)?;
+        body.write_u16::<NetworkEndian>(attributes.len() as u16)?;
+        body.write_all(&attributes)?;
+    }
+    Ok(body)
+}
+
+/// Path attributes for a RIB entry (RFC 6396 [4.3.4])
+///   - AS_PATH always uses 4-byte ASNs
+///   - IPv6 next hops are in an MP_REACH_NLRI with only the next hop fields
+fn encode_attributes(attributes: &PathAttributes) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(64);
+    encode_attribute(&PathAttribute::ORIGIN(attributes.origin.clone()), &mut buf)?;
+    encode_as_path(&attributes.as_path.segments, &mut buf)?;
+    match attributes.next_hop {
+        Some(IpAddr::V4(next_hop)) => {
+            encode_attribute(&PathAttribute::NEXT_HOP(IpAddr::V4(next_hop)), &mut buf)?
+        }
+        Some(IpAddr::V6(next_hop)) => {
+            // Optional, Non-transitive
+            buf.write_u8(0x80)?;
+            buf.write_u8(Identifier::MP_REACH_NLRI as u8)?;
+            buf.write_u8(17)?;
+            buf.write_u8(16)?;
+            buf.write_all(&next_hop.octets())?;
+        }
+        None => (),
+    }
+    let mut optional: Vec<PathAttribute> = Vec::with_capacity(4);
+    if let Some(med) = attributes.multi_exit_disc {
+        optional.push(PathAttribute::MULTI_EXIT_DISC(med));
+    }
+    if let Some(local_pref) = attributes.local_pref {
+        optional.push(PathAttribute::LOCAL_PREF(local_pref));
+    }
+    if attributes.atomic_aggregate {
+        optional.push(PathAttribute::ATOMIC_AGGREGATOR);
+    }
+    if let Some(aggregator) = attributes.aggregator {
+        optional.push(PathAttribute::AGGREGATOR(aggregator));
+    }
+    if let Some(IpAddr::V4(originator_id)) = attributes.originator_id {
+        optional.push(PathAttribute::ORIGINATOR_ID(originator_id.into()));
+    }
+    let communities = &attributes.communities;
+    if !communities.standard().is_empty() {
+        optional.push(PathAttribute::COMMUNITY(communities.standard()));
+    }
+    if !communities.extended().is_empty() {
+        optional.push(PathAttribute::EXTENDED_COMMUNITIES(communities.extended()));
+    }
+    if !communities.large().is_empty() {
+        optional.push(PathAttribute::LARGE_COMMUNITY(communities.large()));
+    }
+    if let Some(community) = communities.ipv6_extended().first() {
+        optional.push(PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(*community));
+    }
+    for attribute in &optional {
+        encode_attribute(attribute, &mut buf)?;
+    }
+    Ok(buf)
+}
+
+fn encode_as_path(segments: &[Segment], buf: &mut impl Write) -> io::Result<()> {
+    let mut value = Vec::with_capacity(16);
+    for segment in segments {
+        let (segment_type, asns) = match segment {
+            Segment::AS_SET(asns) => (1, asns),
+            Segment::AS_SEQUENCE(asns) => (2, asns),
+        };
+        value.write_u8(segment_type)?;
+        value.write_u8(asns.len() as u8)?;
+        for asn in asns {
+            value.write_u32::<NetworkEndian>(*asn)?;
+        }
+    }
+    // Well-known, Transitive (+ Extended Length if needed)
+    if value.len() > u8::MAX as usize {
+        buf.write_u8(0x50)?;
+        buf.write_u8(Identifier::AS_PATH as u8)?;
+        buf.write_u16::<NetworkEndian>(value.len() as u16)?;
+    } else {
+        buf.write_u8(0x40)?;
+        buf.write_u8(Identifier::AS_PATH as u8)?;
+        buf.write_u8(value.len() as u8)?;
+    }
+    buf.write_all(&value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::{ASPath, Origin, AFI};
+    use byteorder::ReadBytesExt;
+    use chrono::TimeZone;
+    use ipnetwork::IpNetwork;
+
+    use crate::rib::{CommunityList, EntrySource, ExportedUpdate, Family};
+
+    fn entry(prefix: &str, next_hop: &str, as_path: Vec<u32>) -> Arc<ExportEntry> {
+        let prefix: IpNetwork = prefix.parse().unwrap();
+        let attributes = PathAttributes {
+            next_hop: Some(next_hop.parse().unwrap()),
+            link_local_next_hop: None,
+            origin: Origin::IGP,
+            as_path: ASPath {
+                segments: vec![Segment::AS_SEQUENCE(as_path)],
+            },
+            local_pref: Some(100),
+            multi_exit_disc: None,
+            originator_id: None,
+            cluster_list: vec![],
+            atomic_aggregate: false,
+            aggregator: None,
+            communities: CommunityList(vec![]),
+        };
+        let afi = if prefix.is_ipv4() {
+            AFI::IPV4
+        } else {
+            AFI::IPV6
+        };
+        Arc::new(ExportEntry {
+            timestamp: Utc.timestamp(1_600_000_000, 0),
+            update: ExportedUpdate {
+                family: Family::new(afi, SAFI::Unicast),
+                attributes: Arc::new(attributes),
+                nlri: NLRIEncoding::IP((prefix.ip(), prefix.prefix()).into()),
+            },
+            source: EntrySource::Peer(next_hop.parse().unwrap()),
+            router_id: None,
+            only_to_customer: None,
+        })
+    }
+
+    /// (Type, Subtype, Body) of each record
+    fn records(mut data: &[u8]) -> Vec<(u16, u16, Vec<u8>)> {
+        let mut records = vec![];
+        while !data.is_empty() {
+            assert_eq!(data.read_u32::<NetworkEndian>().unwrap(), 1_700_000_000);
+            let record_type = data.read_u16::<NetworkEndian>().unwrap();
+            let subtype = data.read_u16::<NetworkEndian>().unwrap();
+            let length = data.read_u32::<NetworkEndian>().unwrap() as usize;
+            records.push((record_type, subtype, data[..length].to_vec()));
+            data = &data[length..];
+        }
+        records
+    }
+
+    #[test]
+    fn test_table_dump() {
+        let mut dump = TableDump::new(Ipv4Addr::new(1, 1, 1, 1));
+        let peer = MrtPeer {
+            router_id: Ipv4Addr::new(2, 2, 2, 2),
+            addr: "10.0.0.2".parse().unwrap(),
+            asn: 65001,
+        };
+        let v6_peer = MrtPeer {
+            router_id: Ipv4Addr::new(3, 3, 3, 3),
+            addr: "2001:db8::3".parse().unwrap(),
+            asn: 4_200_000_000,
+        };
+        let first = dump.peer_index(peer.clone());
+        let second = dump.peer_index(v6_peer);
+        assert_eq!((first, second), (0, 1));
+        assert_eq!(dump.peer_index(peer), 0);
+        dump.add_route(
+            1,
+            entry("2001:db8:1::/48", "2001:db8::3", vec![4_200_000_000]),
+        );
+        dump.add_route(0, entry("10.1.0.0/16", "10.0.0.2", vec![65001]));
+        dump.add_route(1, entry("10.1.0.0/16", "2001:db8::3", vec![4_200_000_000]));
+        assert_eq!(dump.prefix_count(), 2);
+
+        let mut buf = Vec::new();
+        dump.encode(Utc.timestamp(1_700_000_000, 0), &mut buf)
+            .unwrap();
+        let records = records(&buf);
+        assert_eq!(
+            records.iter().map(|(t, s, _)| (*t, *s)).collect::<Vec<_>>(),
+            vec![(13, 1), (13, 2), (13, 4)]
+        );
+
+        // PEER_INDEX_TABLE
+        let mut body = &records[0].2[..];
+        assert_eq!(body.read_u32::<NetworkEndian>().unwrap(), 0x0101_0101);
+        assert_eq!(body.read_u16::<NetworkEndian>().unwrap(), 0);
+        assert_eq!(body.read_u16::<NetworkEndian>().unwrap(), 2);
+        assert_eq!(body.read_u8().unwrap(), 0x02);
+        assert_eq!(body.read_u32::<NetworkEndian>().unwrap(), 0x0202_0202);
+        assert_eq!(body.read_u32::<NetworkEndian>().unwrap(), 0x0a00_0002);
+        assert_eq!(body.read_u32::<NetworkEndian>().unwrap(), 65001);
+        assert_eq!(body.read_u8().unwrap(), 0x03);
+        assert_eq!(body.len(), 4 + 16 + 4);
+
+        // RIB_IPV4_UNICAST: 10.1.0.0/16 from both peers
+        let mut body = &records[1].2[..];
+        assert_eq!(body.read_u32::<NetworkEndian>().unwrap(), 0);
+        assert_eq!(body.read_u8().unwrap(), 16);
+        assert_eq!(body.read_u16::<NetworkEndian>().unwrap(), 0x0a01);
+        assert_eq!(body.read_u16::<NetworkEndian>().unwrap(), 2);
+        assert_eq!(body.read_u16::<NetworkEndian>().unwrap(), 0);
+        assert_eq!(body.read_u32::<NetworkEndian>().unwrap(), 1_600_000_000);
+        let attributes_len = body.read_u16::<NetworkEndian>().unwrap() as usize;
+        // ORIGIN, AS_PATH (4-byte), NEXT_HOP, LOCAL_PREF
+        assert_eq!(attributes_len, 4 + 9 + 7 + 7);
+        assert_eq!(&body[4..9], &[0x40, 2, 6, 2, 1]);
+        assert_eq!(&body[9..13], &65001u32.to_be_bytes());
+        body = &body[attributes_len..];
+        assert_eq!(body.read_u16::<NetworkEndian>().unwrap(), 1);
+
+        // RIB_IPV6_UNICAST: sequence continues, IPv6 next hop in MP_REACH_NLRI
+        let mut body = &records[2].2[..];
+        assert_eq!(body.read_u32::<NetworkEndian>().unwrap(), 1);
+        assert_eq!(body.read_u8().unwrap(), 48);
+        assert_eq!(&body[..6], &[0x20, 0x01, 0x0d, 0xb8, 0, 1]);
+        assert!(body
+            .windows(4)
+            .any(|w| w == [0x80, Identifier::MP_REACH_NLRI as u8, 17, 16]));
+    }
+}
@@ -0,0 +1,228 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+
+use bgp_rs::{ASPath, NLRIEncoding, Origin, SAFI};
+use ipnetwork::IpNetwork;
+
+use super::{CommunityList, EntrySource, ExportEntry, ExportedUpdate, Family, PathAttributes};
+use crate::config::PeerConfig;
+
+/// Replace exported unicast routes within the peer's `aggregate` networks with
+/// a single route for each aggregate (with ATOMIC_AGGREGATE & AGGREGATOR)
+/// An aggregate is only exported while it contains at least one route
+pub fn aggregate_routes(
+    routes: Vec<Arc<ExportEntry>>,
+    config: &PeerConfig,
+) -> Vec<Arc<ExportEntry>> {
+    if config.aggregate.is_empty() {
+        return routes;
+    }
+    let mut contributors: Vec<Vec<Arc<ExportEntry>>> = vec![Vec::new(); config.aggregate.len()];
+    let mut exported: Vec<Arc<ExportEntry>> = Vec::with_capacity(routes.len());
+    for entry in routes {
+        match find_aggregate(&entry, &config.aggregate) {
+            Some(index) => contributors[index].push(entry),
+            None => exported.push(entry),
+        }
+    }
+    let router_id = match config.local_router_id {
+        IpAddr::V4(router_id) => router_id,
+        IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+    };
+    for (network, entries) in config.aggregate.iter().zip(contributors) {
+        if entries.is_empty() {
+            continue;
+        }
+        let entry = aggregate_entry(*network, &entries, (config.local_as, router_id));
+        exported.push(Arc::new(entry));
+    }
+    exported
+}
+
+/// Index of the most-specific aggregate containing this (unicast) entry
+fn find_aggregate(entry: &ExportEntry, aggregates: &[IpNetwork]) -> Option<usize> {
+    if entry.update.family.safi != SAFI::Unicast {
+        return None;
+    }
+    let (addr, length): (IpAddr, u8) = match &entry.update.nlri {
+        NLRIEncoding::IP(prefix) | NLRIEncoding::IP_WITH_PATH_ID((prefix, _)) => prefix.into(),
+        _ => return None,
+    };
+    aggregates
+        .iter()
+        .enumerate()
+        .filter(|(_, network)| {
+            network.is_ipv4() == addr.is_ipv4()
+                && length >= network.prefix()
+                && network.contains(addr)
+        })
+        .max_by_key(|(_, network)| network.prefix())
+        .map(|(index, _)| index)
+}
+
+fn aggregate_entry(
+    network: IpNetwork,
+    contributors: &[Arc<ExportEntry>],
+    aggregator: (u32, Ipv4Addr),
+) -> ExportEntry {
+    // Aggregate ORIGIN per RFC 4271 [9.2.2.2]
+    let origins = contributors.iter().map(|e| &e.update.attributes.origin);
+    let origin = if origins.clone().any(|o| matches!(o, Origin::INCOMPLETE)) {
+        Origin::INCOMPLETE
+    } else if origins.clone().any(|o| matches!(o, Origin::EGP)) {
+        Origin::EGP
+    } else {
+        Origin::IGP
+    };
+    let attributes = PathAttributes {
+        // Advertised with the local session address
+        next_hop: None,
+        link_local_next_hop: None,
+        origin,
+        as_path: ASPath { segments: vec![] },
+        local_pref: None,
+        multi_exit_disc: None,
+        originator_id: None,
+        cluster_list: vec![],
+        atomic_aggregate: true,
+        aggregator: Some(aggregator),
+        communities: CommunityList(vec![]),
+    };
+    ExportEntry {
+        // Session routes are tracked by timestamp, so the aggregate is
+        // re-advertised when a newer route contributes to it
+        timestamp: contributors
+            .iter()
+            .map(|e| e.timestamp)
+            .max()
+            .expect("Aggregate has contributors"),
+        update: ExportedUpdate {
+            family: Family::new(contributors[0].update.family.afi, SAFI::Unicast),
+            attributes: Arc::new(attributes),
+            nlri: NLRIEncoding::IP((network.network(), network.prefix()).into()),
+        },
+        source: EntrySource::Config,
+        router_id: None,
+        only_to_customer: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::AFI;
+    use chrono::{Duration, Utc};
+
+    fn peer_config(aggregate: &str) -> Arc<PeerConfig> {
+        let config = crate::config::from_str(&format!(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "127.0.0.1"
+            remote_as = 65001
+            aggregate = [{}]
+            "#,
+            aggregate
+        ))
+        .unwrap();
+        config.peers[0].clone()
+    }
+
+    fn entry(prefix: &str, origin: Origin, age: i64) -> Arc<ExportEntry> {
+        let prefix: IpNetwork = prefix.parse().unwrap();
+        let attributes = PathAttributes {
+            next_hop: Some("10.255.0.1".parse().unwrap()),
+            link_local_next_hop: None,
+            origin,
+            as_path: ASPath { segments: vec![] },
+            local_pref: Some(200),
+            multi_exit_disc: None,
+            originator_id: None,
+            cluster_list: vec![],
+            atomic_aggregate: false,
+            aggregator: None,
+            communities: CommunityList(vec![]),
+        };
+        let afi = if prefix.is_ipv4() {
+            AFI::IPV4
+        } else {
+            AFI::IPV6
+        };
+        Arc::new(ExportEntry {
+            timestamp: Utc::now() - Duration::seconds(age),
+            update: ExportedUpdate {
+                family: Family::new(afi, SAFI::Unicast),
+                attributes: Arc::new(attributes),
+                nlri: NLRIEncoding::IP((prefix.ip(), prefix.prefix()).into()),
+            },
+            source: EntrySource::Peer("127.0.0.2".parse().unwrap()),
+            router_id: None,
+            only_to_customer: None,
+        })
+    }
+
+    fn prefixes(routes: &[Arc<ExportEntry>]) -> Vec<String> {
+        let mut prefixes: Vec<_> = routes
+            .iter()
+            .map(|e| format!("{:?}", e.update.nlri))
+            .collect();
+        prefixes.sort();
+        prefixes
+    }
+
+    #[test]
+    fn test_aggregate_routes() {
+        let config = peer_config(r#""10.0.0.0/22", "2001:db8::/32""#);
+        let newest = entry("10.0.2.0/24", Origin::IGP, 10);
+        let routes = vec![
+            entry("10.0.0.0/24", Origin::IGP, 30),
+            entry("10.0.1.0/24", Origin::EGP, 20),
+            newest.clone(),
+            entry("10.0.4.0/24", Origin::IGP, 0),
+        ];
+        let exported = aggregate_routes(routes, &config);
+        assert_eq!(
+            prefixes(&exported),
+            vec!["IP(10.0.0.0/22)", "IP(10.0.4.0/24)"]
+        );
+        let aggregate = exported
+            .iter()
+            .find(|e| format!("{:?}", e.update.nlri) == "IP(10.0.0.0/22)")
+            .unwrap();
+        let attributes = &aggregate.update.attributes;
+        assert!(attributes.atomic_aggregate);
+        assert_eq!(
+            attributes.aggregator,
+            Some((65000, "1.1.1.1".parse().unwrap()))
+        );
+        assert!(matches!(attributes.origin, Origin::EGP));
+        assert!(attributes.next_hop.is_none());
+        assert_eq!(aggregate.timestamp, newest.timestamp);
+        assert_eq!(
+            aggregate.update.family,
+            Family::new(AFI::IPV4, SAFI::Unicast)
+        );
+    }
+
+    #[test]
+    fn test_aggregate_without_contributors() {
+        let config = peer_config(r#""10.0.0.0/22""#);
+        let exported = aggregate_routes(vec![entry("10.1.0.0/24", Origin::IGP, 0)], &config);
+        assert_eq!(prefixes(&exported), vec!["IP(10.1.0.0/24)"]);
+
+        // Aggregates use the most-specific containing network
+        let config = peer_config(r#""10.0.0.0/16", "10.0.0.0/22""#);
+        let exported = aggregate_routes(
+            vec![
+                entry("10.0.1.0/24", Origin::IGP, 0),
+                entry("10.0.8.0/24", Origin::IGP, 0),
+            ],
+            &config,
+        );
+        assert_eq!(
+            prefixes(&exported),
+            vec!["IP(10.0.0.0/16)", "IP(10.0.0.0/22)"]
+        );
+    }
+}
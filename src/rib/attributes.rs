@@ -1,35 +1,106 @@
 use std::collections::{hash_map::DefaultHasher, HashMap};
 use std::hash::Hasher;
-use std::net::IpAddr;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use bgp_rs::{ASPath, Identifier, Origin, PathAttribute, AFI, SAFI};
+use byteorder::{BigEndian, WriteBytesExt};
 use itertools::Itertools;
+use log::warn;
 
 use crate::rib::{Community, CommunityList};
 use crate::utils::bytes_to_ipv6;
 
+/// Size & hit/miss counts of the PathAttributeCache
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub groups: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Ratio of inserts that matched an existing group
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
 #[derive(Debug)]
-pub struct PathAttributeCache(HashMap<u64, PathAttributeGroup>);
+pub struct PathAttributeCache {
+    groups: HashMap<u64, PathAttributeGroup>,
+    // Warn when more than this many distinct groups are cached
+    max_groups: Option<usize>,
+    over_limit: bool,
+    hits: u64,
+    misses: u64,
+}
 
 impl PathAttributeCache {
     pub fn with_capacity(size: usize) -> Self {
-        Self(HashMap::with_capacity(size))
+        Self {
+            groups: HashMap::with_capacity(size),
+            max_groups: None,
+            over_limit: false,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn set_max_groups(&mut self, max_groups: usize) {
+        self.max_groups = Some(max_groups);
     }
 
     pub fn insert(&mut self, attrs: Vec<PathAttribute>) -> u64 {
         let group = PathAttributeGroup::from_attributes(attrs);
         let hash = group.hash();
-        self.0.insert(hash, group);
+        if self.groups.insert(hash, group).is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
         hash
     }
 
     pub fn get(&self, key: u64) -> Option<&PathAttributeGroup> {
-        self.0.get(&key)
+        self.groups.get(&key)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            groups: self.groups.len(),
+            hits: self.hits,
+            misses: self.misses,
+        }
     }
 
     /// Cleanup a PathAttributeGroup with no more associated entries
     pub(super) fn remove(&mut self, key: u64) {
-        self.0.remove(&key);
+        self.groups.remove(&key);
+    }
+
+    /// Check the group count against `max_groups` (after empty groups are cleaned up)
+    /// Groups are still referenced by RIB entries, so only log (once) when over the limit
+    pub(super) fn check_limit(&mut self) {
+        let max_groups = match self.max_groups {
+            Some(max_groups) => max_groups,
+            None => return,
+        };
+        let over_limit = self.groups.len() > max_groups;
+        if over_limit && !self.over_limit {
+            let stats = self.stats();
+            warn!(
+                "PathAttributeCache has {} groups, over the limit of {} (hit rate {:.2})",
+                stats.groups,
+                max_groups,
+                stats.hit_rate()
+            );
+        }
+        self.over_limit = over_limit;
     }
 }
 
@@ -60,20 +131,78 @@ impl PathAttributeGroup {
             // Sort by identifier ID for consistent hashing
             .sorted_by(|a, b| Ord::cmp(&(a.id() as u8), &(b.id() as u8)))
         {
-            attr.encode(&mut bytes).expect("Can't hash PathAttribute");
+            encode_attribute(attr, &mut bytes).expect("Can't hash PathAttribute");
             hasher.write(&bytes);
         }
         hasher.finish()
     }
 }
 
+/// Encode a PathAttribute, including those that bgp-rs can parse but not encode
+pub fn encode_attribute(attribute: &PathAttribute, buf: &mut impl Write) -> io::Result<()> {
+    match attribute {
+        PathAttribute::LARGE_COMMUNITY(communities) => {
+            let length = communities.len() * 12;
+            // Optional, Transitive (+ Extended Length if needed)
+            if length > u8::MAX as usize {
+                buf.write_u8(0xd0)?;
+                buf.write_u8(Identifier::LARGE_COMMUNITY as u8)?;
+                buf.write_u16::<BigEndian>(length as u16)?;
+            } else {
+                buf.write_u8(0xc0)?;
+                buf.write_u8(Identifier::LARGE_COMMUNITY as u8)?;
+                buf.write_u8(length as u8)?;
+            }
+            for (global, local1, local2) in communities {
+                buf.write_u32::<BigEndian>(*global)?;
+                buf.write_u32::<BigEndian>(*local1)?;
+                buf.write_u32::<BigEndian>(*local2)?;
+            }
+            Ok(())
+        }
+        PathAttribute::ATOMIC_AGGREGATOR => {
+            // Well-known, Discretionary
+            buf.write_u8(0x40)?;
+            buf.write_u8(Identifier::ATOMIC_AGGREGATOR as u8)?;
+            buf.write_u8(0)
+        }
+        PathAttribute::AGGREGATOR((asn, addr)) => {
+            // Optional, Transitive (with a 4-octet ASN, RFC 6793)
+            buf.write_u8(0xc0)?;
+            buf.write_u8(Identifier::AGGREGATOR as u8)?;
+            buf.write_u8(8)?;
+            buf.write_u32::<BigEndian>(*asn)?;
+            buf.write_all(&addr.octets())
+        }
+        PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY((c_type, sub_type, addr, local)) => {
+            // Optional, Transitive
+            buf.write_u8(0xc0)?;
+            buf.write_u8(Identifier::IPV6_SPECIFIC_EXTENDED_COMMUNITY as u8)?;
+            buf.write_u8(20)?;
+            buf.write_u8(*c_type)?;
+            buf.write_u8(*sub_type)?;
+            buf.write_all(&addr.octets())?;
+            buf.write_u16::<BigEndian>(*local)
+        }
+        attribute => attribute.encode(buf),
+    }
+}
+
 #[derive(Debug)]
 pub struct PathAttributes {
     pub next_hop: Option<IpAddr>,
+    // Link-local next hop, sent after the global next hop in MP_REACH_NLRI (RFC 2545 [3])
+    pub link_local_next_hop: Option<Ipv6Addr>,
     pub origin: Origin,
     pub as_path: ASPath,
     pub local_pref: Option<u32>,
     pub multi_exit_disc: Option<u32>,
+    pub originator_id: Option<IpAddr>,
+    // Route Reflection CLUSTER_IDs the route has been reflected through (RFC 4456)
+    pub cluster_list: Vec<Ipv4Addr>,
+    pub atomic_aggregate: bool,
+    // (ASN, Router ID) of the speaker that formed an aggregate route
+    pub aggregator: Option<(u32, Ipv4Addr)>,
     pub communities: CommunityList,
 }
 
@@ -92,7 +221,16 @@ impl PathAttributes {
             .map(|attr| match attr {
                 PathAttribute::MP_REACH_NLRI(nlri) => {
                     if (nlri.afi, nlri.safi) == (AFI::IPV6, SAFI::Unicast) {
-                        Some(bytes_to_ipv6(&nlri.next_hop))
+                        match nlri.next_hop.len() {
+                            // Normalized IPv4(-mapped) next hop
+                            4 => Some(IpAddr::from([
+                                nlri.next_hop[0],
+                                nlri.next_hop[1],
+                                nlri.next_hop[2],
+                                nlri.next_hop[3],
+                            ])),
+                            _ => Some(bytes_to_ipv6(&nlri.next_hop)),
+                        }
                     } else {
                         None
                     }
@@ -106,6 +244,18 @@ impl PathAttributes {
                     _ => unreachable!(),
                 })
             });
+        let link_local_next_hop =
+            group
+                .get(Identifier::MP_REACH_NLRI)
+                .and_then(|attr| match attr {
+                    // 32-byte next hop: [global (16)][link-local (16)]
+                    PathAttribute::MP_REACH_NLRI(nlri) if nlri.next_hop.len() == 32 => {
+                        let mut octets = [0u8; 16];
+                        octets.copy_from_slice(&nlri.next_hop[16..]);
+                        Some(Ipv6Addr::from(octets))
+                    }
+                    _ => None,
+                });
         let as_path = group
             .get(Identifier::AS_PATH)
             .map(|attr| match attr {
@@ -127,46 +277,77 @@ impl PathAttributes {
                 _ => unreachable!(),
             })
             .unwrap_or(None);
-        let communities = group
-            .get(Identifier::COMMUNITY)
+        let originator_id = group.get(Identifier::ORIGINATOR_ID).map(|attr| match attr {
+            PathAttribute::ORIGINATOR_ID(id) => IpAddr::from(Ipv4Addr::from(*id)),
+            _ => unreachable!(),
+        });
+        let cluster_list = group
+            .get(Identifier::CLUSTER_LIST)
             .map(|attr| match attr {
-                PathAttribute::COMMUNITY(communities) => communities
-                    .iter()
-                    .map(|c| Community::STANDARD(*c))
-                    .collect::<Vec<Community>>(),
-                _ => unreachable!(),
-            })
-            .unwrap_or_else(Vec::new);
-
-        let ext_communities = group
-            .get(Identifier::EXTENDED_COMMUNITIES)
-            .map(|attr| match attr {
-                PathAttribute::EXTENDED_COMMUNITIES(communities) => communities
-                    .iter()
-                    .map(|c| Community::EXTENDED(*c))
-                    .collect::<Vec<Community>>(),
+                PathAttribute::CLUSTER_LIST(ids) => {
+                    ids.iter().map(|id| Ipv4Addr::from(*id)).collect()
+                }
                 _ => unreachable!(),
             })
-            .unwrap_or_else(Vec::new);
-
-        let community_list = CommunityList(
-            communities
-                .into_iter()
-                .chain(ext_communities.into_iter())
-                .collect(),
+            .unwrap_or_default();
+        let atomic_aggregate = group.get(Identifier::ATOMIC_AGGREGATOR).is_some();
+        let aggregator = group.get(Identifier::AGGREGATOR).map(|attr| match attr {
+            PathAttribute::AGGREGATOR(aggregator) => *aggregator,
+            _ => unreachable!(),
+        });
+        let community_list = community_list(
+            [
+                Identifier::COMMUNITY,
+                Identifier::EXTENDED_COMMUNITIES,
+                Identifier::LARGE_COMMUNITY,
+                Identifier::IPV6_SPECIFIC_EXTENDED_COMMUNITY,
+            ]
+            .iter()
+            .filter_map(|id| group.get(*id)),
         );
 
         PathAttributes {
             next_hop,
+            link_local_next_hop,
             origin,
             as_path,
             local_pref,
             multi_exit_disc,
+            originator_id,
+            cluster_list,
+            atomic_aggregate,
+            aggregator,
             communities: community_list,
         }
     }
 }
 
+/// All communities (standard, extended, large, & IPv6 specific) carried in these attributes
+pub(super) fn community_list<'a, I>(attributes: I) -> CommunityList
+where
+    I: IntoIterator<Item = &'a PathAttribute>,
+{
+    let mut communities = vec![];
+    for attribute in attributes {
+        match attribute {
+            PathAttribute::COMMUNITY(standard) => {
+                communities.extend(standard.iter().map(|c| Community::STANDARD(*c)))
+            }
+            PathAttribute::EXTENDED_COMMUNITIES(extended) => {
+                communities.extend(extended.iter().map(|c| Community::EXTENDED(*c)))
+            }
+            PathAttribute::LARGE_COMMUNITY(large) => {
+                communities.extend(large.iter().map(|c| Community::LARGE(*c)))
+            }
+            PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(community) => {
+                communities.push(Community::IPV6_EXTENDED(*community))
+            }
+            _ => (),
+        }
+    }
+    CommunityList(communities)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,6 +364,41 @@ mod tests {
         cache.insert(attrs);
         cache.insert(attrs_clone);
         dbg!(&cache);
-        assert_eq!(cache.0.len(), 1);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                groups: 1,
+                hits: 1,
+                misses: 1
+            }
+        );
+        assert_eq!(cache.stats().hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_link_local_next_hop() {
+        let attributes = |next_hop: Vec<u8>| {
+            let group = PathAttributeGroup::from_attributes(vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::MP_REACH_NLRI(bgp_rs::MPReachNLRI {
+                    afi: AFI::IPV6,
+                    safi: SAFI::Unicast,
+                    next_hop,
+                    announced_routes: vec![],
+                }),
+            ]);
+            PathAttributes::from_group(&group)
+        };
+        let global: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let link_local: Ipv6Addr = "fe80::1".parse().unwrap();
+
+        let attrs = attributes(global.octets().to_vec());
+        assert_eq!(attrs.next_hop, Some(IpAddr::V6(global)));
+        assert_eq!(attrs.link_local_next_hop, None);
+
+        let both = [global.octets(), link_local.octets()].concat();
+        let attrs = attributes(both);
+        assert_eq!(attrs.next_hop, Some(IpAddr::V6(global)));
+        assert_eq!(attrs.link_local_next_hop, Some(link_local));
     }
 }
@@ -0,0 +1,102 @@
+use std::net::IpAddr;
+
+use bgp_rs::{Origin, Segment};
+
+use super::{EntrySource, ExportEntry};
+
+/// Sortable path preference (lower is better)
+///   - Highest Local Pref
+///   - Shortest AS Path
+///   - Lowest Origin (IGP, EGP, INCOMPLETE)
+///   - Lowest MED
+///   - Lowest peer router-id, then lowest peer address
+///
+/// Route age isn't compared, so the same path is selected after a restart
+/// (when routes are re-learned in a different order)
+pub type PathRank = (i64, usize, u8, u32, Option<IpAddr>, Option<IpAddr>);
+
+pub fn path_rank(route: &ExportEntry) -> PathRank {
+    let attributes = &route.update.attributes;
+    let as_path_len = attributes
+        .as_path
+        .segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::AS_SEQUENCE(asns) => asns.len(),
+            Segment::AS_SET(_) => 1,
+        })
+        .sum();
+    let origin = match attributes.origin {
+        Origin::IGP => 0,
+        Origin::EGP => 1,
+        Origin::INCOMPLETE => 2,
+    };
+    (
+        -i64::from(attributes.local_pref.unwrap_or(100)),
+        as_path_len,
+        origin,
+        attributes.multi_exit_disc.unwrap_or(0),
+        route.router_id.or_else(|| peer_addr(route)),
+        peer_addr(route),
+    )
+}
+
+fn peer_addr(route: &ExportEntry) -> Option<IpAddr> {
+    match route.source {
+        EntrySource::Peer(addr) => Some(addr),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rib::{CommunityList, ExportedUpdate, Family, PathAttributes};
+    use bgp_rs::{ASPath, NLRIEncoding, AFI, SAFI};
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
+
+    fn learned(peer: &str, router_id: &str, origin: Origin, age: Duration) -> ExportEntry {
+        let attributes = PathAttributes {
+            next_hop: Some(peer.parse().unwrap()),
+            link_local_next_hop: None,
+            origin,
+            as_path: ASPath { segments: vec![] },
+            local_pref: None,
+            multi_exit_disc: None,
+            originator_id: None,
+            cluster_list: vec![],
+            atomic_aggregate: false,
+            aggregator: None,
+            communities: CommunityList(vec![]),
+        };
+        let update = ExportedUpdate {
+            family: Family::new(AFI::IPV4, SAFI::Unicast),
+            attributes: Arc::new(attributes),
+            nlri: NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into()),
+        };
+        let mut route = ExportEntry::new(update, EntrySource::Peer(peer.parse().unwrap()));
+        route.timestamp = Utc::now() - age;
+        route.router_id = Some(router_id.parse().unwrap());
+        route
+    }
+
+    #[test]
+    fn test_path_rank() {
+        // Lower router-id wins, even when learned after the other path
+        let older = learned("127.0.0.2", "2.2.2.2", Origin::IGP, Duration::hours(1));
+        let newer = learned("127.0.0.3", "1.1.1.1", Origin::IGP, Duration::zero());
+        assert!(path_rank(&newer) < path_rank(&older));
+
+        // Origin is compared before the router-id
+        let egp = learned("127.0.0.3", "1.1.1.1", Origin::EGP, Duration::zero());
+        assert!(path_rank(&older) < path_rank(&egp));
+        let incomplete = learned("127.0.0.3", "1.1.1.1", Origin::INCOMPLETE, Duration::zero());
+        assert!(path_rank(&egp) < path_rank(&incomplete));
+
+        // Lowest peer address breaks a router-id tie
+        let low = learned("127.0.0.4", "3.3.3.3", Origin::IGP, Duration::hours(1));
+        let high = learned("127.0.0.5", "3.3.3.3", Origin::IGP, Duration::zero());
+        assert!(path_rank(&low) < path_rank(&high));
+    }
+}
@@ -0,0 +1,64 @@
+use std::net::IpAddr;
+
+use bgp_rs::Prefix;
+use ipnetwork::IpNetwork;
+
+/// Reserved & special-purpose ranges that shouldn't be learned from peers (RFC 6890)
+const BOGONS: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/8",
+    "100::/64",
+    "2001:2::/48",
+    "2001:10::/28",
+    "2001:db8::/32",
+    "3ffe::/16",
+    "fc00::/7",
+    "fe80::/10",
+    "fec0::/10",
+    "ff00::/8",
+];
+
+/// Is this prefix within (or equal to) a bogon range
+pub fn is_bogon(prefix: &Prefix) -> bool {
+    let (addr, length): (IpAddr, u8) = prefix.into();
+    BOGONS.iter().any(|bogon| {
+        let bogon: IpNetwork = bogon.parse().expect("Valid bogon network");
+        addr.is_ipv4() == bogon.is_ipv4() && length >= bogon.prefix() && bogon.contains(addr)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(network: &str) -> Prefix {
+        let network: IpNetwork = network.parse().unwrap();
+        (network.ip(), network.prefix()).into()
+    }
+
+    #[test]
+    fn test_is_bogon() {
+        assert!(is_bogon(&prefix("10.1.0.0/16")));
+        assert!(is_bogon(&prefix("192.168.0.0/16")));
+        assert!(is_bogon(&prefix("2001:db8:1::/48")));
+        assert!(is_bogon(&prefix("fe80::/64")));
+        assert!(!is_bogon(&prefix("8.8.8.0/24")));
+        assert!(!is_bogon(&prefix("2600::/12")));
+        // Less-specific than the bogon range (default route)
+        assert!(!is_bogon(&prefix("0.0.0.0/0")));
+        assert!(!is_bogon(&prefix("::/0")));
+    }
+}
@@ -1,30 +1,116 @@
 use std::convert::TryFrom;
 use std::fmt;
 use std::io;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::slice::Iter;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use crate::api::rpc::CommunityInfo;
 use crate::utils::u32_to_dotted;
 
-#[derive(Serialize, Debug, Copy, Clone)]
+/// Well-known communities (RFC 1997) [name, value]
+const WELL_KNOWN: &[(&str, u32)] = &[
+    ("no-export", 0xFFFF_FF01),
+    ("no-advertise", 0xFFFF_FF02),
+    ("no-export-subconfed", 0xFFFF_FF03),
+];
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum Community {
     // TODO: Consider another datamodel for these
-    //       size of the max variant (EXTENDED) is much larger than
+    //       size of the max variant (LARGE) is much larger than
     //       the most typical use case (STANDARD)
     STANDARD(u32),
     EXTENDED(u64),
-    // TODO
-    // LARGE(Vec<(u32, u32, u32)>),
-    // IPV6_EXTENDED((u8, u8, Ipv6Addr, u16)),
+    /// Large Community (RFC 8092) [global admin, local data 1, local data 2]
+    LARGE((u32, u32, u32)),
+    /// IPv6 Address Specific Extended Community (RFC 5701) [type, sub-type, global admin, local admin]
+    IPV6_EXTENDED((u8, u8, Ipv6Addr, u16)),
+}
+
+impl Community {
+    /// Is this a Route Target extended community (RFC 4360, RFC 5701)
+    pub fn is_route_target(&self) -> bool {
+        match self {
+            // Transitive 2-octet AS, IPv4 Address, or 4-octet AS specific, Route Target sub-type
+            Community::EXTENDED(value) => {
+                let [c_type, sub_type, ..] = value.to_be_bytes();
+                c_type <= 0x02 && sub_type == 0x02
+            }
+            Community::IPV6_EXTENDED((c_type, sub_type, _, _)) => {
+                *c_type == 0x00 && *sub_type == 0x02
+            }
+            _ => false,
+        }
+    }
+
+    /// Decoded kind & fields of this community (for API clients)
+    pub fn info(&self) -> CommunityInfo {
+        let (kind, subtype, global_admin, local_admin) = match self {
+            Community::STANDARD(value) => (
+                "standard",
+                WELL_KNOWN
+                    .iter()
+                    .find(|(_, v)| v == value)
+                    .map(|(name, _)| name.to_string()),
+                Some((value >> 16).to_string()),
+                Some((value & 0xffff).to_string()),
+            ),
+            Community::EXTENDED(value) => {
+                let (subtype, global_admin, local_admin) = ext_community_fields(*value);
+                (
+                    "extended",
+                    Some(subtype.to_string()),
+                    global_admin,
+                    local_admin,
+                )
+            }
+            Community::IPV6_EXTENDED((c_type, sub_type, addr, local)) => {
+                let subtype = match (c_type, sub_type) {
+                    (0x00, 0x02) => "target",
+                    (0x00, 0x0d) => "redirect",
+                    _ => "unknown",
+                };
+                (
+                    "ipv6-extended",
+                    Some(subtype.to_string()),
+                    Some(addr.to_string()),
+                    Some(local.to_string()),
+                )
+            }
+            Community::LARGE((global, local1, local2)) => (
+                "large",
+                None,
+                Some(global.to_string()),
+                Some(format!("{}:{}", local1, local2)),
+            ),
+        };
+        CommunityInfo {
+            kind: kind.to_string(),
+            subtype,
+            global_admin,
+            local_admin,
+            value: self.to_string(),
+        }
+    }
 }
 
 impl fmt::Display for Community {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Community::STANDARD(value) => write!(f, "{}", u32_to_dotted(*value, ':')),
+            Community::STANDARD(value) => match WELL_KNOWN.iter().find(|(_, v)| v == value) {
+                Some((name, _)) => write!(f, "{}", name),
+                None => write!(f, "{}", u32_to_dotted(*value, ':')),
+            },
             Community::EXTENDED(value) => write!(f, "{}", ext_community_to_display(*value)),
+            Community::LARGE((global, local1, local2)) => {
+                write!(f, "{}:{}:{}", global, local1, local2)
+            }
+            Community::IPV6_EXTENDED(value) => {
+                write!(f, "{}", ipv6_ext_community_to_display(*value))
+            }
         }
     }
 }
@@ -33,6 +119,29 @@ impl TryFrom<&str> for Community {
     type Error = io::Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
+        // Well-known communities by name (E.g. "no-export")
+        if let Some((_, community)) = WELL_KNOWN.iter().find(|(name, _)| *name == value) {
+            return Ok(Community::STANDARD(*community));
+        }
+        // Extended communities (E.g. "target:65000:1.1.1.1", "redirect:65000:100", "0x0002fde801010101")
+        // or IPv6 Address Specific (E.g. "target:2001:db8::1:100", "redirect:2001:db8::1:100")
+        if let Some(target) = value.strip_prefix("target:") {
+            return parse_ext_community([0x00, 0x02], target)
+                .map(Community::EXTENDED)
+                .or_else(|_| parse_ipv6_ext_community((0x00, 0x02), target))
+                .map_err(|_| invalid_community(value));
+        }
+        if let Some(redirect) = value.strip_prefix("redirect:") {
+            return parse_ext_community([0x80, 0x08], redirect)
+                .map(Community::EXTENDED)
+                .or_else(|_| parse_ipv6_ext_community((0x00, 0x0d), redirect))
+                .map_err(|_| invalid_community(value));
+        }
+        if let Some(raw) = value.strip_prefix("0x") {
+            return u64::from_str_radix(raw, 16)
+                .map(Community::EXTENDED)
+                .map_err(|_| invalid_community(value));
+        }
         // Parse to list of u32, since we should support 4 byte aSN as a single int
         // (E.g. "42598400100")
         let chunks: Vec<_> = value.split(':').collect();
@@ -52,8 +161,18 @@ impl TryFrom<&str> for Community {
                 );
                 Ok(Community::STANDARD((a * 65536) + b))
             }
-            // TODO: support extended community parsing
-            _ => unimplemented!(),
+            // Large community (E.g. "65000:1:2")
+            3 => {
+                let mut parts = chunks
+                    .iter()
+                    .map(|c| c.parse::<u32>().map_err(|_| invalid_community(value)));
+                Ok(Community::LARGE((
+                    parts.next().expect("Has 3 chunks")?,
+                    parts.next().expect("Has 3 chunks")?,
+                    parts.next().expect("Has 3 chunks")?,
+                )))
+            }
+            _ => Err(invalid_community(value)),
         }
     }
 }
@@ -91,6 +210,32 @@ impl CommunityList {
             })
             .collect()
     }
+
+    pub fn ipv6_extended(&self) -> Vec<(u8, u8, Ipv6Addr, u16)> {
+        self.0
+            .iter()
+            .filter_map(|c| {
+                if let Community::IPV6_EXTENDED(comm) = c {
+                    Some(*comm)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn large(&self) -> Vec<(u32, u32, u32)> {
+        self.0
+            .iter()
+            .filter_map(|c| {
+                if let Community::LARGE(comm) = c {
+                    Some(*comm)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
 }
 
 impl fmt::Display for CommunityList {
@@ -105,6 +250,103 @@ impl fmt::Display for CommunityList {
     }
 }
 
+fn invalid_community(value: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid community '{}'", value),
+    )
+}
+
+/// Parse a 2-octet AS specific extended community value ("<asn>:<value>")
+///   where value can be an integer, dotted integer (E.g. "1.100"), or IPv4 address
+fn parse_ext_community(kind: [u8; 2], value: &str) -> Result<u64, io::Error> {
+    let (asn, local) = value
+        .split_once(':')
+        .ok_or_else(|| invalid_community(value))?;
+    let asn: u16 = asn.parse().map_err(|_| invalid_community(value))?;
+    let local: u32 = match local.split_once('.') {
+        None => local.parse().map_err(|_| invalid_community(value))?,
+        Some((high, low)) => match (high.parse::<u16>(), low.parse::<u16>()) {
+            (Ok(high), Ok(low)) => (u32::from(high) << 16) | u32::from(low),
+            _ => local
+                .parse::<Ipv4Addr>()
+                .map(u32::from)
+                .map_err(|_| invalid_community(value))?,
+        },
+    };
+    let mut bytes = [0u8; 8];
+    bytes[0..2].clone_from_slice(&kind);
+    bytes[2..4].clone_from_slice(&asn.to_be_bytes());
+    bytes[4..8].clone_from_slice(&local.to_be_bytes());
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Parse an IPv6 Address Specific extended community value ("<ipv6 address>:<value>")
+fn parse_ipv6_ext_community(kind: (u8, u8), value: &str) -> Result<Community, io::Error> {
+    let (addr, local) = value
+        .rsplit_once(':')
+        .ok_or_else(|| invalid_community(value))?;
+    let addr: Ipv6Addr = addr.parse().map_err(|_| invalid_community(value))?;
+    let local: u16 = local.parse().map_err(|_| invalid_community(value))?;
+    Ok(Community::IPV6_EXTENDED((kind.0, kind.1, addr, local)))
+}
+
+fn ipv6_ext_community_to_display(value: (u8, u8, Ipv6Addr, u16)) -> String {
+    match value {
+        // Route Target (RFC 5701)
+        (0x00, 0x02, addr, local) => format!("target:{}:{}", addr, local),
+        // Flow-Spec Redirect to IPv6 route target (RFC 8956)
+        (0x00, 0x0d, addr, local) => format!("redirect:{}:{}", addr, local),
+        (c_type, sub_type, addr, local) => {
+            format!("ipv6-ext:{}:{}:{}:{}", c_type, sub_type, addr, local)
+        }
+    }
+}
+
+/// Extended community (RFC 4360, RFC 8955) [sub-type name, global admin, local admin]
+fn ext_community_fields(value: u64) -> (&'static str, Option<String>, Option<String>) {
+    let bytes = value.to_be_bytes();
+    let (c_type, sub_type) = (bytes[0], bytes[1]);
+    let subtype = match (c_type, sub_type) {
+        (0x03, _) | (0x43, _) => return ("opaque", None, None),
+        (0x80, 0x07) => {
+            let action = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+            return ("traffic-action", None, Some(action.to_string()));
+        }
+        (0x80, 0x09) => return ("traffic-marking", None, Some(bytes[7].to_string())),
+        (0x80, 0x06) => "traffic-rate",
+        (0x80..=0x82, 0x08) => "redirect",
+        (_, 0x02) => "target",
+        (_, 0x03) => "origin",
+        _ => "unknown",
+    };
+    let asn = u16::from_be_bytes([bytes[2], bytes[3]]);
+    let local = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let short_local = u16::from_be_bytes([bytes[6], bytes[7]]);
+    let (global_admin, local_admin) = match c_type & 0x3f {
+        0x00 if subtype == "traffic-rate" => {
+            (asn.to_string(), format!("{}bps", f32::from_bits(local)))
+        }
+        // 2-octet AS Specific
+        0x00 => (asn.to_string(), local.to_string()),
+        // IPv4 Address Specific
+        0x01 => (
+            Ipv4Addr::new(bytes[2], bytes[3], bytes[4], bytes[5]).to_string(),
+            short_local.to_string(),
+        ),
+        // 4-octet AS Specific (RFC 5668)
+        0x02 => (
+            u32_to_dotted(
+                u32::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+                '.',
+            ),
+            short_local.to_string(),
+        ),
+        _ => return (subtype, None, None),
+    };
+    (subtype, Some(global_admin), Some(local_admin))
+}
+
 fn ext_community_to_display(value: u64) -> String {
     let c_type: u16 = ((value >> 48) & 0xff) as u16;
     match c_type {
@@ -181,6 +423,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_well_known_community() {
+        for (name, value) in &[
+            ("no-export", 0xFFFF_FF01),
+            ("no-advertise", 0xFFFF_FF02),
+            ("no-export-subconfed", 0xFFFF_FF03),
+        ] {
+            let community = Community::try_from(*name).unwrap();
+            assert_eq!(community, Community::STANDARD(*value));
+            assert_eq!(community.to_string(), *name);
+            // Numeric values are displayed by name too
+            assert_eq!(
+                Community::try_from(value.to_string().as_str())
+                    .unwrap()
+                    .to_string(),
+                *name
+            );
+        }
+        assert_eq!(
+            Community::try_from("65535:65281").unwrap(),
+            Community::STANDARD(0xFFFF_FF01)
+        );
+        assert!(Community::try_from("no-exports").is_err());
+    }
+
     #[test]
     fn test_ext_community_to_display() {
         let two_byte_asn: u64 =
@@ -214,6 +481,14 @@ mod tests {
             ext_community_to_display(traffic_rate),
             String::from("traffic-rate:65000:1.25bps")
         );
+        for (bps, display) in [
+            (24000.0f32, "traffic-rate:65000:24000bps"),
+            (1_000_000.0, "traffic-rate:65000:1000000bps"),
+            (0.0, "traffic-rate:65000:0bps"),
+        ] {
+            let traffic_rate = 0x8006_fde8_0000_0000 | u64::from(f32::to_bits(bps));
+            assert_eq!(ext_community_to_display(traffic_rate), display);
+        }
         let traffic_action: u64 =
             u64::from_be_bytes([0x80, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02]);
         assert_eq!(
@@ -221,4 +496,158 @@ mod tests {
             String::from("traffic-action:0:sample")
         );
     }
+
+    #[test]
+    fn test_parse_ext_community() {
+        let target = Community::try_from("target:65000:1.1.1.1").unwrap();
+        match target {
+            Community::EXTENDED(value) => assert_eq!(
+                value.to_be_bytes(),
+                [0x00, 0x02, 0xfd, 0xe8, 0x01, 0x01, 0x01, 0x01]
+            ),
+            _ => panic!("Expected EXTENDED community"),
+        }
+        assert_eq!(target.to_string(), "target:65000:1.1.1.1");
+
+        let redirect = Community::try_from("redirect:65000:100").unwrap();
+        match redirect {
+            Community::EXTENDED(value) => assert_eq!(
+                value.to_be_bytes(),
+                [0x80, 0x08, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x64]
+            ),
+            _ => panic!("Expected EXTENDED community"),
+        }
+        assert_eq!(redirect.to_string(), "redirect:65000:100");
+        let redirect = Community::try_from("redirect:65000:1.100").unwrap();
+        assert_eq!(redirect.to_string(), "redirect:65000:1.100");
+
+        let raw = Community::try_from("0x0002fde801010101").unwrap();
+        assert_eq!(raw.to_string(), "target:65000:1.1.1.1");
+
+        assert!(Community::try_from("target:4200000000:1").is_err());
+        assert!(Community::try_from("target:65000").is_err());
+        assert!(Community::try_from("redirect:65000:1.1.1").is_err());
+        assert!(Community::try_from("0xzz").is_err());
+    }
+
+    #[test]
+    fn test_ipv6_ext_community() {
+        let addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let redirect = Community::try_from("redirect:2001:db8::1:100").unwrap();
+        assert_eq!(redirect, Community::IPV6_EXTENDED((0x00, 0x0d, addr, 100)));
+        assert_eq!(redirect.to_string(), "redirect:2001:db8::1:100");
+        let target = Community::try_from("target:2001:db8::1:100").unwrap();
+        assert_eq!(target, Community::IPV6_EXTENDED((0x00, 0x02, addr, 100)));
+        assert_eq!(target.to_string(), "target:2001:db8::1:100");
+        assert_eq!(
+            Community::IPV6_EXTENDED((0x40, 0x04, addr, 7)).to_string(),
+            "ipv6-ext:64:4:2001:db8::1:7"
+        );
+
+        let list = CommunityList(vec![Community::STANDARD(100), redirect]);
+        assert_eq!(list.ipv6_extended(), vec![(0x00, 0x0d, addr, 100)]);
+        assert_eq!(list.standard(), vec![100]);
+
+        assert!(Community::try_from("redirect:2001:db8::1:65536").is_err());
+        assert!(Community::try_from("redirect:2001:db8::zz:100").is_err());
+    }
+
+    #[test]
+    fn test_large_community() {
+        let large = Community::try_from("65000:1:2").unwrap();
+        assert_eq!(large, Community::LARGE((65000, 1, 2)));
+        assert_eq!(large.to_string(), "65000:1:2");
+        let four_byte = Community::try_from("4200000000:4294967295:0").unwrap();
+        assert_eq!(four_byte.to_string(), "4200000000:4294967295:0");
+
+        let list = CommunityList(vec![Community::STANDARD(100), large, four_byte]);
+        assert_eq!(list.to_string(), "100 65000:1:2 4200000000:4294967295:0");
+        assert_eq!(
+            list.large(),
+            vec![(65000, 1, 2), (4200000000, 4294967295, 0)]
+        );
+        assert_eq!(list.standard(), vec![100]);
+
+        let json = serde_json::to_string(&large).unwrap();
+        assert_eq!(serde_json::from_str::<Community>(&json).unwrap(), large);
+
+        assert!(Community::try_from("65000:1:x").is_err());
+        assert!(Community::try_from("65000:1:4294967296").is_err());
+        assert!(Community::try_from("1:2:3:4").is_err());
+    }
+
+    #[test]
+    fn test_community_info() {
+        let communities: Vec<_> = [
+            "no-export",
+            "65000:100",
+            "65000:1:2",
+            "target:65000:1.1.1.1",
+            "redirect:2001:db8::1:100",
+            "0x0102c0a800010064",
+        ]
+        .iter()
+        .map(|c| Community::try_from(*c).unwrap())
+        .chain(vec![Community::EXTENDED(
+            0x8006_fde8_0000_0000 | u64::from(f32::to_bits(24000.0)),
+        )])
+        .collect();
+        let fields: Vec<_> = communities
+            .iter()
+            .map(|c| {
+                let info = c.info();
+                assert_eq!(info.value, c.to_string());
+                (
+                    info.kind,
+                    info.subtype,
+                    info.global_admin.unwrap_or_default(),
+                    info.local_admin.unwrap_or_default(),
+                )
+            })
+            .collect();
+        let expected = [
+            ("standard", Some("no-export"), "65535", "65281"),
+            ("standard", None, "65000", "100"),
+            ("large", None, "65000", "1:2"),
+            ("extended", Some("target"), "65000", "16843009"),
+            ("ipv6-extended", Some("redirect"), "2001:db8::1", "100"),
+            ("extended", Some("target"), "192.168.0.1", "100"),
+            ("extended", Some("traffic-rate"), "65000", "24000bps"),
+        ];
+        for ((kind, subtype, global, local), expected) in fields.iter().zip(expected.iter()) {
+            assert_eq!(
+                (
+                    kind.as_str(),
+                    subtype.as_deref(),
+                    global.as_str(),
+                    local.as_str()
+                ),
+                *expected
+            );
+        }
+        assert_eq!(fields.len(), expected.len());
+
+        let action = Community::EXTENDED(0x8007_0000_0000_0002).info();
+        assert_eq!(action.subtype.as_deref(), Some("traffic-action"));
+        assert_eq!(action.global_admin, None);
+        assert_eq!(action.local_admin.as_deref(), Some("2"));
+
+        let json = serde_json::to_value(communities[3].info()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "kind": "extended",
+                "subtype": "target",
+                "global_admin": "65000",
+                "local_admin": "16843009",
+                "value": "target:65000:1.1.1.1",
+            })
+        );
+        let info: CommunityInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(info, communities[3].info());
+        // Fields other than kind & value may be missing
+        let info: CommunityInfo =
+            serde_json::from_str(r#"{"kind": "large", "value": "65000:1:2"}"#).unwrap();
+        assert_eq!(info.subtype, None);
+    }
 }
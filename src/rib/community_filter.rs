@@ -0,0 +1,184 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{self, Deserialize, Deserializer};
+
+use super::{Community, CommunityList};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommunityAction {
+    Accept,
+    Reject,
+}
+
+impl fmt::Display for CommunityAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommunityAction::Accept => write!(f, "accept"),
+            CommunityAction::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+/// Community filter rule, taking an action for routes carrying a community
+/// E.g. "match 65000:666 reject", "match target:65000:1.1.1.1 accept"
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommunityMatch {
+    community: Community,
+    action: CommunityAction,
+}
+
+impl CommunityMatch {
+    /// Action to take if this rule matches the route's communities
+    pub fn evaluate(&self, communities: &CommunityList) -> Option<CommunityAction> {
+        if communities.iter().any(|c| *c == self.community) {
+            Some(self.action)
+        } else {
+            None
+        }
+    }
+}
+
+/// Action for the first matching rule, routes matching no rules are accepted
+pub fn evaluate_rules(rules: &[CommunityMatch], communities: &CommunityList) -> CommunityAction {
+    rules
+        .iter()
+        .find_map(|rule| rule.evaluate(communities))
+        .unwrap_or(CommunityAction::Accept)
+}
+
+impl FromStr for CommunityMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split_whitespace().collect();
+        match parts.as_slice() {
+            ["match", community, action] => {
+                let community = Community::try_from(*community)
+                    .map_err(|err| format!("Invalid community filter '{}': {}", s, err))?;
+                let action = match *action {
+                    "accept" => CommunityAction::Accept,
+                    "reject" => CommunityAction::Reject,
+                    _ => {
+                        return Err(format!(
+                            "Unsupported community filter action '{}' (expected accept or reject)",
+                            s
+                        ))
+                    }
+                };
+                Ok(Self { community, action })
+            }
+            _ => Err(format!(
+                "Invalid community filter '{}' (expected 'match <community> <action>')",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CommunityMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "match {} {}", self.community, self.action)
+    }
+}
+
+impl<'de> Deserialize<'de> for CommunityMatch {
+    fn deserialize<D>(deserializer: D) -> Result<CommunityMatch, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(s: &str) -> CommunityMatch {
+        s.parse().unwrap()
+    }
+
+    fn communities(values: &[&str]) -> CommunityList {
+        CommunityList(
+            values
+                .iter()
+                .map(|c| Community::try_from(*c).unwrap())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_standard_match() {
+        let reject = rule("match 65000:666 reject");
+        assert_eq!(reject.to_string(), "match 65000:666 reject");
+        assert_eq!(
+            reject.evaluate(&communities(&["65000:100", "65000:666"])),
+            Some(CommunityAction::Reject)
+        );
+        assert_eq!(reject.evaluate(&communities(&["65000:100"])), None);
+        // 4-byte value for the same community
+        assert_eq!(
+            reject.evaluate(&communities(&["4259840666"])),
+            Some(CommunityAction::Reject)
+        );
+    }
+
+    #[test]
+    fn test_extended_match() {
+        let accept = rule("match target:65000:1.1.1.1 accept");
+        assert_eq!(
+            accept.evaluate(&communities(&["target:65000:1.1.1.1"])),
+            Some(CommunityAction::Accept)
+        );
+        assert_eq!(accept.evaluate(&communities(&["redirect:65000:100"])), None);
+        let large = rule("match 65000:1:2 reject");
+        assert_eq!(
+            large.evaluate(&communities(&["65000:1:2"])),
+            Some(CommunityAction::Reject)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_rules() {
+        let rules = vec![
+            rule("match 65000:100 accept"),
+            rule("match 65000:666 reject"),
+        ];
+        // First matching rule wins
+        assert_eq!(
+            evaluate_rules(&rules, &communities(&["65000:666", "65000:100"])),
+            CommunityAction::Accept
+        );
+        assert_eq!(
+            evaluate_rules(&rules, &communities(&["65000:666"])),
+            CommunityAction::Reject
+        );
+        // Default permit
+        assert_eq!(
+            evaluate_rules(&rules, &communities(&["65000:200"])),
+            CommunityAction::Accept
+        );
+        assert_eq!(
+            evaluate_rules(&rules, &communities(&[])),
+            CommunityAction::Accept
+        );
+        assert_eq!(
+            evaluate_rules(&[], &communities(&["65000:666"])),
+            CommunityAction::Accept
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!("65000:666 reject".parse::<CommunityMatch>().is_err());
+        assert!("match 65000:666".parse::<CommunityMatch>().is_err());
+        assert!("match 65000:666 drop".parse::<CommunityMatch>().is_err());
+        assert!("match 65000:x reject".parse::<CommunityMatch>().is_err());
+        assert!("match 65000:666 reject now"
+            .parse::<CommunityMatch>()
+            .is_err());
+    }
+}
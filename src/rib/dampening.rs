@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use bgp_rs::NLRIEncoding;
+use chrono::{DateTime, Duration, Utc};
+use ipnetwork::IpNetwork;
+use log::info;
+
+use crate::api::rpc::DampenedRoute;
+use crate::config::DampeningConfig;
+use crate::utils::format_elapsed_time;
+
+/// Penalty added when a route is withdrawn
+const WITHDRAW_PENALTY: f64 = 1000.0;
+/// Penalty added when a route is re-announced with (possibly) changed attributes
+const REANNOUNCE_PENALTY: f64 = 500.0;
+/// Minimum time between scans for flap history that can be forgotten
+const PURGE_INTERVAL_SECS: i64 = 60;
+
+/// Flap history for a single (peer, prefix)
+#[derive(Debug)]
+struct FlapState {
+    penalty: f64,
+    last_update: DateTime<Utc>,
+    suppressed: bool,
+    /// Parameters of the peer the route was learned from
+    config: DampeningConfig,
+}
+
+impl FlapState {
+    /// Penalty (figure of merit) decayed to this time
+    fn penalty_at(&self, now: DateTime<Utc>) -> f64 {
+        decay(self.penalty, self.last_update, now, self.config.half_life)
+    }
+
+    fn is_suppressed_at(&self, now: DateTime<Utc>) -> bool {
+        self.suppressed && self.penalty_at(now) >= f64::from(self.config.reuse_limit)
+    }
+
+    /// History is forgotten once the penalty decays below half the reuse limit
+    fn is_forgotten_at(&self, now: DateTime<Utc>) -> bool {
+        !self.is_suppressed_at(now)
+            && self.penalty_at(now) < f64::from(self.config.reuse_limit) / 2.0
+    }
+}
+
+/// Route Flap Dampening (RFC 2439) for peers with `dampening` enabled
+/// Tracks a penalty per (peer, prefix) that decays exponentially over time,
+/// suppressing routes that exceed the suppress limit until they decay below the reuse limit
+#[derive(Debug, Default)]
+pub struct Dampening {
+    history: HashMap<(IpAddr, IpNetwork), FlapState>,
+    last_purge: Option<DateTime<Utc>>,
+}
+
+impl Dampening {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A route from this peer was withdrawn
+    pub fn withdrawn(&mut self, peer: IpAddr, nlri: &NLRIEncoding, config: &DampeningConfig) {
+        self.add_penalty(peer, nlri, WITHDRAW_PENALTY, config, Utc::now());
+    }
+
+    /// A route from this peer was announced
+    /// Only counts as a flap if the route already has flap history (or is currently present)
+    pub fn announced(
+        &mut self,
+        peer: IpAddr,
+        nlri: &NLRIEncoding,
+        existing: bool,
+        config: &DampeningConfig,
+    ) {
+        let now = Utc::now();
+        let has_history = dampening_key(nlri)
+            .map(|prefix| self.history.contains_key(&(peer, prefix)))
+            .unwrap_or(false);
+        if existing || has_history {
+            self.add_penalty(peer, nlri, REANNOUNCE_PENALTY, config, now);
+        }
+    }
+
+    /// Forget flap history that has decayed enough (so flapping unique prefixes
+    /// can't grow the history without bound), at most every `PURGE_INTERVAL_SECS`
+    /// Returns the prefixes with flap history when scanned (their suppression may have ended)
+    pub fn purge(&mut self) -> Vec<IpNetwork> {
+        let now = Utc::now();
+        let due = self.last_purge.map_or(true, |last| {
+            now - last >= Duration::seconds(PURGE_INTERVAL_SECS)
+        });
+        if due {
+            self.purge_at(now)
+        } else {
+            vec![]
+        }
+    }
+
+    fn purge_at(&mut self, now: DateTime<Utc>) -> Vec<IpNetwork> {
+        let flapped = self.history.keys().map(|(_, prefix)| *prefix).collect();
+        self.history.retain(|_, state| !state.is_forgotten_at(now));
+        self.last_purge = Some(now);
+        flapped
+    }
+
+    /// Suppressed routes (ordered by peer & prefix), with their current penalty
+    pub fn suppressed(&self) -> Vec<DampenedRoute> {
+        self.suppressed_at(Utc::now())
+    }
+
+    fn suppressed_at(&self, now: DateTime<Utc>) -> Vec<DampenedRoute> {
+        let mut suppressed: Vec<_> = self
+            .history
+            .iter()
+            .filter(|(_, state)| state.is_suppressed_at(now))
+            .collect();
+        suppressed.sort_by_key(|((peer, prefix), _)| (*peer, prefix.ip(), prefix.prefix()));
+        suppressed
+            .into_iter()
+            .map(|((peer, prefix), state)| {
+                let penalty = state.penalty_at(now);
+                DampenedRoute {
+                    peer: *peer,
+                    prefix: *prefix,
+                    penalty: penalty.round() as u32,
+                    reuse_in: format_elapsed_time(Duration::seconds(reuse_time(
+                        penalty,
+                        &state.config,
+                    ) as i64)),
+                }
+            })
+            .collect()
+    }
+
+    /// Is this route currently suppressed from export?
+    pub fn is_suppressed(&self, peer: IpAddr, nlri: &NLRIEncoding) -> bool {
+        self.is_suppressed_at(peer, nlri, Utc::now())
+    }
+
+    fn is_suppressed_at(&self, peer: IpAddr, nlri: &NLRIEncoding, now: DateTime<Utc>) -> bool {
+        dampening_key(nlri)
+            .and_then(|prefix| self.history.get(&(peer, prefix)))
+            .map(|state| state.is_suppressed_at(now))
+            .unwrap_or(false)
+    }
+
+    fn add_penalty(
+        &mut self,
+        peer: IpAddr,
+        nlri: &NLRIEncoding,
+        penalty: f64,
+        config: &DampeningConfig,
+        now: DateTime<Utc>,
+    ) {
+        let prefix = match dampening_key(nlri) {
+            Some(prefix) => prefix,
+            None => return,
+        };
+        let current = self
+            .history
+            .get(&(peer, prefix))
+            .map(|s| {
+                (
+                    decay(s.penalty, s.last_update, now, config.half_life),
+                    s.suppressed,
+                )
+            })
+            .unwrap_or((0.0, false));
+        let new_penalty = (current.0 + penalty).min(f64::from(config.max_penalty));
+        let suppressed = if new_penalty > f64::from(config.suppress_limit) {
+            if !current.1 {
+                info!(
+                    "Suppressing flapping route {} from {} [penalty {:.0}]",
+                    prefix, peer, new_penalty
+                );
+            }
+            true
+        } else {
+            current.1 && new_penalty >= f64::from(config.reuse_limit)
+        };
+        self.history.insert(
+            (peer, prefix),
+            FlapState {
+                penalty: new_penalty,
+                last_update: now,
+                suppressed,
+                config: config.clone(),
+            },
+        );
+    }
+}
+
+/// Exponentially decay a penalty based on the half-life (seconds)
+fn decay(penalty: f64, since: DateTime<Utc>, now: DateTime<Utc>, half_life: u32) -> f64 {
+    let elapsed = (now - since).num_milliseconds().max(0) as f64 / 1000.0;
+    let half_life = f64::from(half_life.max(1));
+    penalty * 0.5f64.powf(elapsed / half_life)
+}
+
+/// Seconds until a penalty decays below the reuse limit
+fn reuse_time(penalty: f64, config: &DampeningConfig) -> u64 {
+    let reuse_limit = f64::from(config.reuse_limit.max(1));
+    if penalty < reuse_limit {
+        return 0;
+    }
+    (f64::from(config.half_life.max(1)) * (penalty / reuse_limit).log2()).ceil() as u64
+}
+
+/// Only IP prefixes are dampened (all paths for a prefix from a peer together)
+fn dampening_key(nlri: &NLRIEncoding) -> Option<IpNetwork> {
+    match nlri {
+        NLRIEncoding::IP(prefix) | NLRIEncoding::IP_WITH_PATH_ID((prefix, _)) => {
+            let (addr, length) = prefix.into();
+            IpNetwork::new(addr, length).ok()
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DampeningConfig {
+        DampeningConfig {
+            half_life: 900,
+            suppress_limit: 2000,
+            reuse_limit: 750,
+            max_penalty: 12000,
+        }
+    }
+
+    #[test]
+    fn test_flapping_route_suppressed_and_reused() {
+        let mut dampening = Dampening::new();
+        let config = config();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let nlri = NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into());
+        let start = Utc::now();
+
+        // Flap the route a few times in quick succession
+        dampening.add_penalty(peer, &nlri, WITHDRAW_PENALTY, &config, start);
+        dampening.add_penalty(peer, &nlri, REANNOUNCE_PENALTY, &config, start);
+        assert!(!dampening.is_suppressed_at(peer, &nlri, start));
+        dampening.add_penalty(peer, &nlri, WITHDRAW_PENALTY, &config, start);
+        assert!(dampening.is_suppressed_at(peer, &nlri, start));
+
+        // Other peers with the same prefix aren't affected
+        let other: IpAddr = "127.0.0.3".parse().unwrap();
+        assert!(!dampening.is_suppressed_at(other, &nlri, start));
+
+        // Still suppressed after one half-life (2500 -> 1250)
+        let later = start + Duration::seconds(900);
+        assert!(dampening.is_suppressed_at(peer, &nlri, later));
+        // Reused once decayed below the reuse limit (2500 -> 625)
+        let much_later = start + Duration::seconds(1800);
+        assert!(!dampening.is_suppressed_at(peer, &nlri, much_later));
+    }
+
+    #[test]
+    fn test_history_purged() {
+        let mut dampening = Dampening::new();
+        let config = config();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let nlri = |prefix: &str| NLRIEncoding::IP((prefix.parse().unwrap(), 24).into());
+        let start = Utc::now();
+        for _ in 0..3 {
+            dampening.add_penalty(peer, &nlri("10.0.0.0"), WITHDRAW_PENALTY, &config, start);
+        }
+        dampening.add_penalty(peer, &nlri("10.0.1.0"), WITHDRAW_PENALTY, &config, start);
+
+        // 1000 -> 354 is below half the reuse limit (375), 3000 -> 1061 is still suppressed
+        dampening.purge_at(start + Duration::seconds(1350));
+        assert_eq!(dampening.history.len(), 1);
+        assert!(dampening.is_suppressed_at(
+            peer,
+            &nlri("10.0.0.0"),
+            start + Duration::seconds(1350)
+        ));
+        // Reused (3000 -> 472) but not yet forgotten
+        dampening.purge_at(start + Duration::seconds(2400));
+        assert_eq!(dampening.history.len(), 1);
+        // 3000 -> 188
+        dampening.purge_at(start + Duration::seconds(3600));
+        assert!(dampening.history.is_empty());
+
+        // Forgotten routes aren't penalized when re-announced
+        dampening.announced(peer, &nlri("10.0.0.0"), false, &config);
+        assert!(dampening.history.is_empty());
+    }
+
+    #[test]
+    fn test_peer_history_purged() {
+        let mut dampening = Dampening::new();
+        let nlri = NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into());
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let lenient_peer: IpAddr = "127.0.0.3".parse().unwrap();
+        let mut lenient = config();
+        lenient.reuse_limit = 300;
+        let start = Utc::now();
+        dampening.add_penalty(peer, &nlri, WITHDRAW_PENALTY, &config(), start);
+        dampening.add_penalty(lenient_peer, &nlri, WITHDRAW_PENALTY, &lenient, start);
+
+        // Each peer's history is kept until below half of its own reuse limit (1000 -> 354)
+        dampening.purge_at(start + Duration::seconds(1350));
+        let peers: Vec<_> = dampening.history.keys().map(|(peer, _)| *peer).collect();
+        assert_eq!(peers, vec![lenient_peer]);
+        // 1000 -> 125
+        dampening.purge_at(start + Duration::seconds(2700));
+        assert!(dampening.history.is_empty());
+
+        // Scans are spaced out by `PURGE_INTERVAL_SECS`
+        let decayed = start - Duration::seconds(3600);
+        dampening.add_penalty(peer, &nlri, WITHDRAW_PENALTY, &config(), decayed);
+        dampening.last_purge = Some(Utc::now());
+        dampening.purge();
+        assert_eq!(dampening.history.len(), 1);
+        dampening.last_purge = Some(Utc::now() - Duration::seconds(PURGE_INTERVAL_SECS));
+        dampening.purge();
+        assert!(dampening.history.is_empty());
+    }
+
+    #[test]
+    fn test_max_penalty() {
+        let mut dampening = Dampening::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let nlri = NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into());
+        let now = Utc::now();
+        for _ in 0..50 {
+            dampening.add_penalty(peer, &nlri, WITHDRAW_PENALTY, &config(), now);
+        }
+        let prefix = dampening_key(&nlri).unwrap();
+        let state = dampening.history.get(&(peer, prefix)).unwrap();
+        assert_eq!(state.penalty, 12000.0);
+    }
+
+    #[test]
+    fn test_decay() {
+        let start = Utc::now();
+        let decayed = |seconds: i64| decay(2000.0, start, start + Duration::seconds(seconds), 900);
+        assert_eq!(decayed(0), 2000.0);
+        assert!((decayed(450) - 2000.0 / 2f64.sqrt()).abs() < 0.01);
+        assert!((decayed(900) - 1000.0).abs() < 0.01);
+        assert!((decayed(2700) - 250.0).abs() < 0.01);
+        // Time going backwards doesn't increase the penalty
+        assert_eq!(decayed(-60), 2000.0);
+
+        // Reused after log2(penalty / reuse_limit) half-lives
+        assert_eq!(reuse_time(3000.0, &config()), 1800);
+        assert_eq!(reuse_time(1500.0, &config()), 900);
+        assert_eq!(reuse_time(500.0, &config()), 0);
+    }
+
+    #[test]
+    fn test_suppressed_routes() {
+        let mut dampening = Dampening::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let nlri = |prefix: &str| NLRIEncoding::IP((prefix.parse().unwrap(), 24).into());
+        let start = Utc::now();
+        // Each peer's own parameters are used
+        let mut strict = config();
+        strict.suppress_limit = 900;
+        dampening.add_penalty(peer, &nlri("10.0.1.0"), WITHDRAW_PENALTY, &strict, start);
+        dampening.add_penalty(peer, &nlri("10.0.2.0"), WITHDRAW_PENALTY, &config(), start);
+        for _ in 0..3 {
+            dampening.add_penalty(peer, &nlri("10.0.0.0"), WITHDRAW_PENALTY, &config(), start);
+        }
+        assert!(!dampening.is_suppressed_at(peer, &nlri("10.0.2.0"), start));
+
+        let routes = |seconds: i64| -> Vec<(String, u32, String)> {
+            dampening
+                .suppressed_at(start + Duration::seconds(seconds))
+                .into_iter()
+                .map(|r| (r.prefix.to_string(), r.penalty, r.reuse_in))
+                .collect()
+        };
+        // Reused (at 750) after log2(penalty / 750) half-lives
+        assert_eq!(
+            routes(0),
+            vec![
+                ("10.0.0.0/24".to_string(), 3000, "00:30:00".to_string()),
+                ("10.0.1.0/24".to_string(), 1000, "00:06:14".to_string()),
+            ]
+        );
+        assert_eq!(
+            routes(900),
+            vec![("10.0.0.0/24".to_string(), 1500, "00:15:00".to_string())]
+        );
+        assert!(dampening
+            .suppressed_at(start + Duration::seconds(3600))
+            .is_empty());
+    }
+}
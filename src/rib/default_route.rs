@@ -0,0 +1,85 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+use bgp_rs::{ASPath, NLRIEncoding, Origin, AFI, SAFI};
+use chrono::{DateTime, Duration, Utc};
+
+use super::{CommunityList, EntrySource, ExportEntry, ExportedUpdate, Family, PathAttributes};
+use crate::config::PeerConfig;
+
+/// Default routes (0.0.0.0/0, ::/0) originated to a peer (`default_originate`),
+/// one for each of the peer's unicast families
+///
+/// Session routes are tracked by timestamp, so the routes keep the timestamp from when
+/// they were first originated (offset for each family) until they're withdrawn
+pub fn default_routes(config: &PeerConfig, originated: DateTime<Utc>) -> Vec<Arc<ExportEntry>> {
+    config
+        .families
+        .iter()
+        .filter(|family| family.safi == SAFI::Unicast)
+        .enumerate()
+        .filter_map(|(i, family)| {
+            let network: IpAddr = match family.afi {
+                AFI::IPV4 => Ipv4Addr::UNSPECIFIED.into(),
+                AFI::IPV6 => Ipv6Addr::UNSPECIFIED.into(),
+                _ => return None,
+            };
+            let attributes = PathAttributes {
+                // Advertised with the local session address
+                next_hop: None,
+                link_local_next_hop: None,
+                origin: Origin::IGP,
+                as_path: ASPath { segments: vec![] },
+                local_pref: None,
+                multi_exit_disc: None,
+                originator_id: None,
+                cluster_list: vec![],
+                atomic_aggregate: false,
+                aggregator: None,
+                communities: CommunityList(vec![]),
+            };
+            Some(Arc::new(ExportEntry {
+                timestamp: originated + Duration::nanoseconds(i as i64),
+                update: ExportedUpdate {
+                    family: Family::new(family.afi, SAFI::Unicast),
+                    attributes: Arc::new(attributes),
+                    nlri: NLRIEncoding::IP((network, 0).into()),
+                },
+                source: EntrySource::Config,
+                router_id: None,
+                only_to_customer: None,
+            }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_routes() {
+        let config = crate::config::from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "127.0.0.1"
+            remote_as = 65001
+            families = ["ipv4 unicast", "ipv4 flow", "ipv6 unicast"]
+            "#,
+        )
+        .unwrap();
+        let now = Utc::now();
+        let routes = default_routes(&config.peers[0], now);
+        let prefixes: Vec<_> = routes
+            .iter()
+            .map(|e| format!("{:?}", e.update.nlri))
+            .collect();
+        assert_eq!(prefixes, vec!["IP(0.0.0.0/0)", "IP(::/0)"]);
+        assert_eq!(routes[0].timestamp, now);
+        assert_ne!(routes[0].timestamp, routes[1].timestamp);
+        assert!(routes.iter().all(|e| e.source == EntrySource::Config));
+        assert!(routes[1].update.attributes.next_hop.is_none());
+    }
+}
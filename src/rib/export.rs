@@ -1,5 +1,6 @@
 use std::convert::From;
 use std::fmt;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use bgp_rs::NLRIEncoding;
@@ -16,6 +17,10 @@ pub struct ExportEntry {
     pub(crate) timestamp: DateTime<Utc>,
     pub(crate) update: ExportedUpdate,
     pub(crate) source: EntrySource,
+    // BGP Identifier of the source peer (if learned from a peer)
+    pub(crate) router_id: Option<IpAddr>,
+    // Only to Customer (OTC) attribute the route was learned with (BGP Role, RFC 9234)
+    pub(crate) only_to_customer: Option<u32>,
 }
 
 impl ExportEntry {
@@ -24,6 +29,8 @@ impl ExportEntry {
             timestamp: Utc::now(),
             update,
             source,
+            router_id: None,
+            only_to_customer: None,
         }
     }
 }
@@ -34,6 +41,8 @@ impl From<(&RibEntry, Arc<PathAttributes>)> for ExportEntry {
         ExportEntry {
             timestamp: entry.timestamp,
             source: entry.source,
+            router_id: entry.router_id,
+            only_to_customer: entry.only_to_customer,
             update: ExportedUpdate {
                 family: entry.family,
                 attributes,
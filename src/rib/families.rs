@@ -56,7 +56,7 @@ impl<'de> Deserialize<'de> for Family {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let parts: Vec<&str> = s.trim().split_whitespace().collect();
+        let parts: Vec<&str> = s.split_whitespace().collect();
         if parts.len() != 2 {
             return Err(serde::de::Error::custom(format!(
                 "Incorrect family format: '{}'",
@@ -77,6 +77,8 @@ impl<'de> Deserialize<'de> for Family {
         let safi = match parts[1] {
             "unicast" => SAFI::Unicast,
             "flow" => SAFI::Flowspec,
+            "multicast" => SAFI::Multicast,
+            "labeled-unicast" => SAFI::Mpls,
             sfamily => {
                 return Err(serde::de::Error::custom(format!(
                     "Unsupported SAFI: '{}'",
@@ -158,5 +160,23 @@ mod tests {
         let deserializer: StrDeserializer<Error> = "ipv4 flow".into_deserializer();
         let familyi = Family::deserialize(deserializer).unwrap();
         assert_eq!(familyi, Family::new(AFI::IPV4, SAFI::Flowspec));
+
+        let deserializer: StrDeserializer<Error> = "ipv4 multicast".into_deserializer();
+        let familyi = Family::deserialize(deserializer).unwrap();
+        assert_eq!(familyi, Family::new(AFI::IPV4, SAFI::Multicast));
+        assert_eq!(familyi.to_string(), "IPv4 Multicast");
+
+        let deserializer: StrDeserializer<Error> = "ipv6 labeled-unicast".into_deserializer();
+        let familyi = Family::deserialize(deserializer).unwrap();
+        assert_eq!(familyi, Family::new(AFI::IPV6, SAFI::Mpls));
+        assert!(matches!(
+            familyi.to_open_param(),
+            OpenCapability::MultiProtocol((AFI::IPV6, SAFI::Mpls))
+        ));
+
+        for invalid in &["ipv4 labeled", "ipv4", "ipv4 multicast extra"] {
+            let deserializer: StrDeserializer<Error> = invalid.into_deserializer();
+            assert!(Family::deserialize(deserializer).is_err());
+        }
     }
 }
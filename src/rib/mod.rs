@@ -1,26 +1,44 @@
+mod aggregate;
 mod attributes;
+mod best_path;
+mod bogons;
 pub mod community;
+mod community_filter;
+mod dampening;
+mod default_route;
 mod export;
 pub mod families;
 mod parse;
+mod prefix_list;
 pub mod session;
 
-use attributes::PathAttributeCache;
-pub use attributes::{PathAttributeGroup, PathAttributes};
+pub use aggregate::aggregate_routes;
+use attributes::{community_list, PathAttributeCache};
+pub use attributes::{encode_attribute, CacheStats, PathAttributeGroup, PathAttributes};
+pub use best_path::{path_rank, PathRank};
 pub use community::{Community, CommunityList};
+pub use community_filter::{CommunityAction, CommunityMatch};
+use dampening::Dampening;
+pub use default_route::default_routes;
 pub use export::{ExportEntry, ExportedUpdate};
 pub use families::{Families, Family};
+pub use prefix_list::PrefixListEntry;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
 
-use bgp_rs::{Identifier, NLRIEncoding, PathAttribute, Update};
+use bgp_rs::{
+    FlowspecFilter, Identifier, NLRIEncoding, PathAttribute, Prefix, Segment, Update, SAFI,
+};
 use chrono::{DateTime, Utc};
+use ipnetwork::IpNetwork;
 use log::debug;
 
-use crate::session::SessionError;
+use crate::api::rpc::{DampenedRoute, FilterStats};
+use crate::config::PeerConfig;
+use crate::session::{GracefulRestart, SessionError};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum EntrySource {
@@ -48,6 +66,30 @@ struct RibEntry {
     source: EntrySource,
     timestamp: DateTime<Utc>,
     nlri: NLRIEncoding,
+    /// Only export while this peer is Established
+    tracked_peer: Option<IpAddr>,
+    /// Not yet re-announced during an Enhanced Route Refresh (RFC 7313)
+    stale: bool,
+    /// BGP Identifier of the peer this route was learned from (best-path tiebreak)
+    router_id: Option<IpAddr>,
+    /// Only to Customer (OTC) attribute (BGP Role, RFC 9234), not decoded by bgp-rs
+    only_to_customer: Option<u32>,
+}
+
+/// Key for indexing learned NLRI, equal for the same prefix (or Flowspec rule)
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum NlriKey {
+    Prefix((IpAddr, u8)),
+    Other(String),
+}
+
+impl From<&NLRIEncoding> for NlriKey {
+    fn from(nlri: &NLRIEncoding) -> Self {
+        match nlri {
+            NLRIEncoding::IP(prefix) => NlriKey::Prefix(prefix.into()),
+            other => NlriKey::Other(format!("{:?}", other)),
+        }
+    }
 }
 
 /// Routing-information Base
@@ -59,18 +101,104 @@ struct RibEntry {
 pub struct RIB {
     /// Learned Rib entries, keyed by the PathAttributeGroup hash
     entries: HashMap<u64, Vec<RibEntry>>,
+    /// PathAttributeGroup hash for each NLRI learned from a peer (API & Config routes aren't indexed)
+    peer_index: HashMap<IpAddr, HashMap<NlriKey, u64>>,
     /// Cache for grouping and storing common PathAttributes amongst NLRI
     cache: PathAttributeCache,
+    /// Route Flap Dampening state for routes learned from peers with `dampening` enabled
+    dampening: Dampening,
+    /// Peers with an Established session (for tracked static routes)
+    established: HashSet<IpAddr>,
+    /// BGP Identifiers received from Established peers
+    router_ids: HashMap<IpAddr, IpAddr>,
+    /// Count of prefixes dropped from each peer by import filters
+    filter_stats: HashMap<IpAddr, FilterStats>,
+    /// Peers restarting (Graceful Restart), with stale routes for these families until the deadline
+    restarting: HashMap<IpAddr, (DateTime<Utc>, Vec<Family>)>,
+    /// Validate received Flowspec NLRI against unicast routes (RFC 8955 [6])
+    flowspec_validation: bool,
+    /// Route Reflection Cluster ID, for CLUSTER_LIST loop detection (RFC 4456 [8])
+    cluster_id: Option<Ipv4Addr>,
+    /// Prefixes with learned routes changed since the last `take_fib_changes` (if tracked)
+    fib_changes: Option<HashSet<IpNetwork>>,
 }
 
 impl RIB {
     pub fn new() -> Self {
         Self {
             entries: HashMap::with_capacity(64),
+            peer_index: HashMap::new(),
             cache: PathAttributeCache::with_capacity(64),
+            dampening: Dampening::new(),
+            established: HashSet::new(),
+            router_ids: HashMap::new(),
+            filter_stats: HashMap::new(),
+            restarting: HashMap::new(),
+            flowspec_validation: false,
+            cluster_id: None,
+            fib_changes: None,
         }
     }
 
+    /// Learned routes currently suppressed by Route Flap Dampening
+    pub fn dampened_routes(&self) -> Vec<DampenedRoute> {
+        self.dampening.suppressed()
+    }
+
+    /// Drop received Flowspec NLRI that aren't backed by a unicast route from the same peer
+    pub fn enable_flowspec_validation(&mut self) {
+        self.flowspec_validation = true;
+    }
+
+    /// Track prefixes with changed learned routes, for syncing the FIB (see `take_fib_changes`)
+    pub fn track_fib_changes(&mut self) {
+        self.fib_changes = Some(HashSet::new());
+    }
+
+    /// Prefixes with learned routes changed since the last call (routes are in `get_routes_for_prefixes`)
+    pub fn take_fib_changes(&mut self) -> HashSet<IpNetwork> {
+        self.fib_changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Drop routes learned from iBGP peers with this Cluster ID in their CLUSTER_LIST
+    pub fn set_cluster_id(&mut self, cluster_id: Ipv4Addr) {
+        self.cluster_id = Some(cluster_id);
+    }
+
+    /// Log a warning when there are more than `max_groups` distinct PathAttributeGroups
+    pub fn limit_attribute_cache(&mut self, max_groups: usize) {
+        self.cache.set_max_groups(max_groups);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// A peer session reached Established, tracked routes for this peer can be exported
+    pub fn peer_up(&mut self, peer: IpAddr) {
+        self.established.insert(peer);
+    }
+
+    /// A peer session left Established, tracked routes for this peer are withdrawn
+    pub fn peer_down(&mut self, peer: IpAddr) {
+        self.established.remove(&peer);
+        self.router_ids.remove(&peer);
+        self.filter_stats.remove(&peer);
+    }
+
+    /// Store the BGP Identifier received from a peer, for routes learned from it
+    pub fn set_router_id(&mut self, peer: IpAddr, router_id: IpAddr) {
+        self.router_ids.insert(peer, router_id);
+    }
+
+    /// Prefixes dropped from this peer (for the current session) by each import filter
+    pub fn filter_stats(&self, peer: IpAddr) -> FilterStats {
+        self.filter_stats.get(&peer).cloned().unwrap_or_default()
+    }
+
     pub fn len(&self) -> usize {
         self.entries.values().map(|v| v.len()).sum()
     }
@@ -79,6 +207,19 @@ impl RIB {
         self.entries.is_empty()
     }
 
+    /// Count of entries for each family (ordered by AFI/SAFI)
+    pub fn family_counts(&self) -> Vec<(Family, usize)> {
+        let mut counts: HashMap<Family, usize> = HashMap::new();
+        for entry in self.entries.values().flatten() {
+            *counts.entry(entry.family).or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by_key(|(family, _)| (family.afi as u16, family.safi as u8));
+        counts
+    }
+
+    /// All routes, except those suppressed by Route Flap Dampening
+    /// (not used for best-path selection, see `dampened_routes`)
     pub fn get_routes(&self) -> Vec<Arc<ExportEntry>> {
         self.entries
             .iter()
@@ -89,12 +230,55 @@ impl RIB {
                 };
                 entries
                     .iter()
+                    .filter(|e| !self.is_dampened(e))
                     .map(|e| Arc::new((e, attributes.clone()).into()))
                     .collect::<Vec<_>>()
             })
             .collect()
     }
 
+    /// Learned routes for these prefixes, except those suppressed by Route Flap Dampening
+    pub fn get_routes_for_prefixes(&self, prefixes: &HashSet<IpNetwork>) -> Vec<Arc<ExportEntry>> {
+        if prefixes.is_empty() {
+            return vec![];
+        }
+        self.entries
+            .iter()
+            .flat_map(|(group_key, entries)| {
+                entries.iter().map(|e| (group_key, e)).collect::<Vec<_>>()
+            })
+            .filter(|(_, e)| matches!(e.source, EntrySource::Peer(_)))
+            .filter(|(_, e)| {
+                nlri_prefix(&e.nlri)
+                    .and_then(prefix_network)
+                    .map_or(false, |prefix| prefixes.contains(&prefix))
+            })
+            .filter(|(_, e)| !self.is_dampened(e))
+            .map(|(group_key, e)| {
+                let attributes = {
+                    let group = self.cache.get(*group_key).expect("Cached PAs exist");
+                    Arc::new(PathAttributes::from_group(group))
+                };
+                Arc::new((e, attributes).into())
+            })
+            .collect()
+    }
+
+    fn is_dampened(&self, entry: &RibEntry) -> bool {
+        match entry.source {
+            EntrySource::Peer(source) => self.dampening.is_suppressed(source, &entry.nlri),
+            _ => false,
+        }
+    }
+
+    /// Is there a route for this exact prefix (from any source)
+    pub fn contains_prefix(&self, prefix: IpNetwork) -> bool {
+        self.entries
+            .values()
+            .flatten()
+            .any(|e| nlri_prefix(&e.nlri).and_then(prefix_network) == Some(prefix))
+    }
+
     pub fn get_routes_from_peer(&self, peer: IpAddr) -> Vec<Arc<ExportEntry>> {
         self.entries
             .iter()
@@ -122,6 +306,14 @@ impl RIB {
                 entries.iter().map(|e| (group_key, e)).collect::<Vec<_>>()
             })
             .filter(|(_, e)| e.source != EntrySource::Peer(peer))
+            // Suppressed (dampened) routes are not exported
+            .filter(|(_, e)| !self.is_dampened(e))
+            // Tracked routes are only exported while the tracked peer is up
+            .filter(|(_, e)| {
+                e.tracked_peer
+                    .map(|tracked| self.established.contains(&tracked))
+                    .unwrap_or(true)
+            })
             .map(|(group_key, e)| {
                 let attributes = {
                     let group = self.cache.get(*group_key).expect("Cached PAs exist");
@@ -132,33 +324,195 @@ impl RIB {
             .collect()
     }
 
-    pub fn update_from_peer(&mut self, peer: IpAddr, update: Update) -> Result<(), SessionError> {
+    pub fn update_from_peer(
+        &mut self,
+        peer: IpAddr,
+        config: &PeerConfig,
+        update: Update,
+    ) -> Result<(), SessionError> {
+        self.update_from_peer_with_otc(peer, config, update, None)
+    }
+
+    /// Learn routes from a peer's UPDATE, received with this Only to Customer (OTC) attribute
+    pub fn update_from_peer_with_otc(
+        &mut self,
+        peer: IpAddr,
+        config: &PeerConfig,
+        update: Update,
+        only_to_customer: Option<u32>,
+    ) -> Result<(), SessionError> {
         let mp_withdraws: Vec<&NLRIEncoding> = update
             .get(Identifier::MP_UNREACH_NLRI)
             .map(|attr| match attr {
                 PathAttribute::MP_UNREACH_NLRI(nlri) => nlri.withdrawn_routes.iter().collect(),
                 _ => unreachable!(),
             })
-            .unwrap_or_else(Vec::new);
+            .unwrap_or_default();
         let withdraws: Vec<&NLRIEncoding> = mp_withdraws
             .into_iter()
             .chain(update.withdrawn_routes.iter())
             .collect();
         if !withdraws.is_empty() {
+            if let Some(dampening) = &config.dampening {
+                for nlri in &withdraws {
+                    self.dampening.withdrawn(peer, nlri, dampening);
+                }
+            }
             self.withdraw_peer_nlri(peer, withdraws);
         }
-        let (attributes, family, nlri) = parse::parse_update(update)?;
+        let (mut attributes, family, mut nlri) = parse::parse_update(update)?;
+        if config.is_ebgp() && !nlri.is_empty() {
+            if let Some(violation) = as_path_violation(&attributes, config) {
+                debug!(
+                    "Dropped {} prefixes from {}: {}",
+                    nlri.len(),
+                    peer,
+                    violation
+                );
+                let stats = self.filter_stats.entry(peer).or_default();
+                match violation {
+                    AsPathViolation::Loop => stats.as_path_loop += nlri.len() as u64,
+                    AsPathViolation::FirstAs(_) => stats.first_as += nlri.len() as u64,
+                }
+                // Treat as withdrawn, removing any previously learned routes for these prefixes
+                self.withdraw_peer_nlri(peer, nlri.iter().collect());
+                return Ok(());
+            }
+        }
+        if !config.is_ebgp() && !nlri.is_empty() {
+            if let Some(reason) = reflection_loop(&attributes, config, self.cluster_id) {
+                debug!("Dropped {} prefixes from {}: {}", nlri.len(), peer, reason);
+                self.filter_stats.entry(peer).or_default().reflection_loop += nlri.len() as u64;
+                // Treat as withdrawn, removing any previously learned routes for these prefixes
+                self.withdraw_peer_nlri(peer, nlri.iter().collect());
+                return Ok(());
+            }
+        }
+        if !config.community_filter_in.is_empty() && !nlri.is_empty() {
+            let communities = community_list(attributes.iter());
+            let action =
+                community_filter::evaluate_rules(&config.community_filter_in, &communities);
+            if action == CommunityAction::Reject {
+                debug!(
+                    "Dropped {} prefixes from {} rejected by community_filter_in",
+                    nlri.len(),
+                    peer
+                );
+                self.filter_stats.entry(peer).or_default().community += nlri.len() as u64;
+                // Treat as withdrawn, removing any previously learned routes for these prefixes
+                self.withdraw_peer_nlri(peer, nlri.iter().collect());
+                return Ok(());
+            }
+        }
+        if !config.import_rts.is_empty() && !nlri.is_empty() {
+            let communities = community_list(attributes.iter());
+            if !communities.iter().any(|c| config.import_rts.contains(c)) {
+                debug!(
+                    "Dropped {} prefixes from {} without a route target in import_rts",
+                    nlri.len(),
+                    peer
+                );
+                self.filter_stats.entry(peer).or_default().route_target += nlri.len() as u64;
+                // Treat as withdrawn, removing any previously learned routes for these prefixes
+                self.withdraw_peer_nlri(peer, nlri.iter().collect());
+                return Ok(());
+            }
+        }
+        if !config.prefix_list_in.is_empty() && family.safi == SAFI::Unicast {
+            let received = nlri.len();
+            nlri.retain(|nlri| match nlri_prefix(nlri) {
+                Some(prefix) => config.prefix_list_in.iter().any(|e| e.matches(prefix)),
+                None => true,
+            });
+            if nlri.len() < received {
+                let dropped = received - nlri.len();
+                debug!(
+                    "Filtered {} prefixes from {} not permitted by prefix_list_in",
+                    dropped, peer
+                );
+                self.filter_stats.entry(peer).or_default().prefix_list += dropped as u64;
+            }
+            if nlri.is_empty() {
+                return Ok(());
+            }
+        }
+        if config.reject_bogons && family.safi == SAFI::Unicast {
+            let received = nlri.len();
+            nlri.retain(|nlri| !nlri_prefix(nlri).map_or(false, bogons::is_bogon));
+            if nlri.len() < received {
+                let dropped = received - nlri.len();
+                debug!("Dropped {} bogon prefixes from {}", dropped, peer);
+                self.filter_stats.entry(peer).or_default().bogon += dropped as u64;
+            }
+            if nlri.is_empty() {
+                return Ok(());
+            }
+        }
+        if self.flowspec_validation && family.safi == SAFI::Flowspec {
+            let received = nlri.len();
+            nlri.retain(|flow| self.flow_is_feasible(peer, flow));
+            if nlri.len() < received {
+                let dropped = received - nlri.len();
+                debug!(
+                    "Dropped {} Flowspec rules from {} that failed validation",
+                    dropped, peer
+                );
+                self.filter_stats
+                    .entry(peer)
+                    .or_default()
+                    .flowspec_validation += dropped as u64;
+            }
+            if nlri.is_empty() {
+                return Ok(());
+            }
+        }
+        if let Some(dampening) = &config.dampening {
+            let index = self.peer_index.get(&peer);
+            for announced in &nlri {
+                let existing =
+                    index.map_or(false, |index| index.contains_key(&NlriKey::from(announced)));
+                self.dampening
+                    .announced(peer, announced, existing, dampening);
+            }
+        }
+        if nlri.is_empty() {
+            return Ok(());
+        }
+        if let Some(community) = config.tag_community {
+            add_community(&mut attributes, community);
+        }
+        if let Some(local_pref) = config.local_pref_in {
+            replace_attribute(&mut attributes, PathAttribute::LOCAL_PREF(local_pref));
+        }
+        if let Some(med) = config.med_in_override {
+            replace_attribute(&mut attributes, PathAttribute::MULTI_EXIT_DISC(med));
+        } else if config.med_multiplier.is_some() || config.med_offset.is_some() {
+            adjust_med(&mut attributes, config.med_multiplier, config.med_offset);
+        }
+        // Announced NLRI replace existing routes from this peer (RFC 4271 [9])
+        self.remove_indexed(peer, nlri.iter());
+        for announced in &nlri {
+            note_fib_change(&mut self.fib_changes, announced);
+        }
         let group_key = self.cache.insert(attributes);
+        let index = self.peer_index.entry(peer).or_default();
+        index.extend(nlri.iter().map(|nlri| (NlriKey::from(nlri), group_key)));
         let entry = self
             .entries
             .entry(group_key)
             .or_insert_with(|| Vec::with_capacity(nlri.len()));
+        let router_id = self.router_ids.get(&peer).copied();
         entry.extend(nlri.into_iter().map(|nlri| RibEntry {
             source: EntrySource::Peer(peer),
             family,
             timestamp: Utc::now(),
             nlri,
+            tracked_peer: None,
+            stale: false,
+            router_id,
+            only_to_customer,
         }));
+        self.cleanup();
         Ok(())
     }
 
@@ -167,8 +521,9 @@ impl RIB {
         family: Family,
         attributes: Vec<PathAttribute>,
         nlri: NLRIEncoding,
-    ) -> Arc<ExportEntry> {
+    ) -> (u64, Arc<ExportEntry>) {
         let group_key = self.cache.insert(attributes);
+        self.cache.check_limit();
         let entry = self
             .entries
             .entry(group_key)
@@ -178,13 +533,17 @@ impl RIB {
             family,
             timestamp: Utc::now(),
             nlri,
+            tracked_peer: None,
+            stale: false,
+            router_id: None,
+            only_to_customer: None,
         });
         let e = entry.last().expect("Pushed entry exists");
         let attributes = {
             let group = self.cache.get(group_key).expect("Cached PAs exist");
             Arc::new(PathAttributes::from_group(group))
         };
-        Arc::new((e, attributes).into())
+        (group_key, Arc::new((e, attributes).into()))
     }
 
     pub fn insert_from_config(
@@ -192,8 +551,10 @@ impl RIB {
         family: Family,
         attributes: Vec<PathAttribute>,
         nlri: NLRIEncoding,
+        tracked_peer: Option<IpAddr>,
     ) {
         let group_key = self.cache.insert(attributes);
+        self.cache.check_limit();
         let entry = self
             .entries
             .entry(group_key)
@@ -203,43 +564,303 @@ impl RIB {
             family,
             timestamp: Utc::now(),
             nlri,
+            tracked_peer,
+            stale: false,
+            router_id: None,
+            only_to_customer: None,
         });
     }
 
+    /// Remove a static route that is no longer configured (E.g. after a config reload)
+    /// Returns the removed entry; sessions withdraw it from peers on their next sync
+    pub fn remove_from_config(
+        &mut self,
+        family: Family,
+        attributes: Vec<PathAttribute>,
+        nlri: &NLRIEncoding,
+        tracked_peer: Option<IpAddr>,
+    ) -> Option<Arc<ExportEntry>> {
+        let group_key = PathAttributeGroup::from_attributes(attributes).hash();
+        let key = NlriKey::from(nlri);
+        let entries = self.entries.get_mut(&group_key)?;
+        let index = entries.iter().position(|e| {
+            e.source == EntrySource::Config
+                && e.family == family
+                && e.tracked_peer == tracked_peer
+                && NlriKey::from(&e.nlri) == key
+        })?;
+        let entry = entries.remove(index);
+        let attributes = {
+            let group = self.cache.get(group_key).expect("Cached PAs exist");
+            Arc::new(PathAttributes::from_group(group))
+        };
+        let removed = Arc::new((&entry, attributes).into());
+        self.cleanup();
+        Some(removed)
+    }
+
+    /// Remove routes advertised via the API for a given prefix
+    /// Returns the removed entries; sessions withdraw them from peers on their next sync
+    pub fn remove_from_api(&mut self, prefix: IpNetwork) -> Vec<Arc<ExportEntry>> {
+        let is_match = |e: &RibEntry| {
+            e.source == EntrySource::Api
+                && match &e.nlri {
+                    NLRIEncoding::IP(nlri) => prefix_network(nlri) == Some(prefix),
+                    _ => false,
+                }
+        };
+        let mut removed: Vec<Arc<ExportEntry>> = vec![];
+        for (group_key, entries) in self.entries.iter_mut() {
+            if !entries.iter().any(is_match) {
+                continue;
+            }
+            let attributes = {
+                let group = self.cache.get(*group_key).expect("Cached PAs exist");
+                Arc::new(PathAttributes::from_group(group))
+            };
+            removed.extend(
+                entries
+                    .iter()
+                    .filter(|e| is_match(e))
+                    .map(|e| Arc::new((e, attributes.clone()).into())),
+            );
+            entries.retain(|e| !is_match(e));
+        }
+        self.cleanup();
+        debug!("Removed {} API routes for {}", removed.len(), prefix);
+        removed
+    }
+
     /// Remove all learned NLRI from a given peer
     pub fn remove_from_peer(&mut self, peer: IpAddr) {
+        let fib_changes = &mut self.fib_changes;
         let total: usize = self
             .entries
             .values_mut()
             .map(|entries| {
                 let pre = entries.len();
-                entries.retain(|e| e.source != EntrySource::Peer(peer));
+                entries.retain(|e| {
+                    let keep = e.source != EntrySource::Peer(peer);
+                    if !keep {
+                        note_fib_change(fib_changes, &e.nlri);
+                    }
+                    keep
+                });
                 pre - entries.len()
             })
             .sum();
+        self.peer_index.remove(&peer);
         self.cleanup();
         debug!("Removed {} routes from RIB for {}", total, peer);
     }
 
+    /// A peer session ended without a NOTIFICATION, and the peer supports Graceful Restart (RFC 4724)
+    /// Routes for the restarting families are retained as stale until End-of-RIB is received
+    /// (after the session is re-established) or the restart time expires, other routes are removed
+    pub fn retain_for_restart(&mut self, peer: IpAddr, restart: &GracefulRestart) {
+        let fib_changes = &mut self.fib_changes;
+        for entries in self.entries.values_mut() {
+            entries.retain(|e| {
+                let keep =
+                    e.source != EntrySource::Peer(peer) || restart.families.contains(&e.family);
+                if !keep {
+                    note_fib_change(fib_changes, &e.nlri);
+                }
+                keep
+            });
+        }
+        self.reindex_peer(peer);
+        self.cleanup();
+        for family in &restart.families {
+            self.mark_stale(peer, *family);
+        }
+        let deadline = Utc::now() + chrono::Duration::seconds(i64::from(restart.restart_time));
+        self.restarting
+            .insert(peer, (deadline, restart.families.clone()));
+    }
+
+    /// End-of-RIB received, remove any routes (for this family) still stale after a restart
+    pub fn end_of_rib(&mut self, peer: IpAddr, family: Family) {
+        let finished = match self.restarting.get_mut(&peer) {
+            Some((_, families)) if families.contains(&family) => {
+                families.retain(|f| f != &family);
+                families.is_empty()
+            }
+            _ => return,
+        };
+        if finished {
+            self.restarting.remove(&peer);
+        }
+        self.remove_stale(peer, family);
+    }
+
+    /// Forget Route Flap Dampening history that has decayed
+    /// (routes with flap history may have decayed below the reuse limit, so are re-synced to the FIB)
+    pub fn purge_dampening_history(&mut self) {
+        let flapped = self.dampening.purge();
+        if let Some(fib_changes) = &mut self.fib_changes {
+            fib_changes.extend(flapped);
+        }
+    }
+
+    /// Remove stale routes from peers that didn't re-establish within their restart time
+    pub fn purge_expired_restarts(&mut self) {
+        let now = Utc::now();
+        let expired: Vec<_> = self
+            .restarting
+            .iter()
+            .filter(|(_, (deadline, _))| *deadline <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in expired {
+            if let Some((_, families)) = self.restarting.remove(&peer) {
+                debug!("Graceful Restart time expired for {}", peer);
+                for family in families {
+                    self.remove_stale(peer, family);
+                }
+            }
+        }
+    }
+
+    /// Begin-of-RR (RFC 7313) received, mark learned NLRI from a given peer (for a family) as stale
+    /// Routes re-announced by the peer replace the stale entries
+    pub fn mark_stale(&mut self, peer: IpAddr, family: Family) {
+        let total = self
+            .entries
+            .values_mut()
+            .flatten()
+            .filter(|e| e.source == EntrySource::Peer(peer) && e.family == family)
+            .fold(0usize, |total, e| {
+                e.stale = true;
+                total + 1
+            });
+        debug!("Marked {} {} routes from {} as stale", total, family, peer);
+    }
+
+    /// End-of-RR (RFC 7313) received, remove learned NLRI from a given peer (for a family)
+    /// that weren't re-announced since Begin-of-RR
+    pub fn remove_stale(&mut self, peer: IpAddr, family: Family) {
+        let is_stale =
+            |e: &RibEntry| e.stale && e.source == EntrySource::Peer(peer) && e.family == family;
+        let fib_changes = &mut self.fib_changes;
+        let total: usize = self
+            .entries
+            .values_mut()
+            .map(|entries| {
+                let pre = entries.len();
+                entries.retain(|e| {
+                    let keep = !is_stale(e);
+                    if !keep {
+                        note_fib_change(fib_changes, &e.nlri);
+                    }
+                    keep
+                });
+                pre - entries.len()
+            })
+            .sum();
+        self.reindex_peer(peer);
+        self.cleanup();
+        debug!("Removed {} stale {} routes for {}", total, family, peer);
+    }
+
     /// Remove matching learned NLRI from a given peer
     pub fn withdraw_peer_nlri(&mut self, peer: IpAddr, withdrawn: Vec<&NLRIEncoding>) {
-        // TODO: Optimize this, possibly with an index of IP -> PA Group mapping?
-        let mut total = 0usize;
-        for nlri in withdrawn {
-            total += self
-                .entries
-                .values_mut()
-                .map(|entries| {
-                    let pre = entries.len();
-                    entries.retain(|e| !(e.source == EntrySource::Peer(peer) && &e.nlri == nlri));
-                    pre - entries.len()
-                })
-                .sum::<usize>();
-        }
+        let total = self.remove_indexed(peer, withdrawn.into_iter());
         self.cleanup();
         debug!("Withdrew {} routes for {}", total, peer);
     }
 
+    /// Remove learned NLRI from a given peer, only visiting the PathAttributeGroups containing them
+    fn remove_indexed<'a, I>(&mut self, peer: IpAddr, nlri: I) -> usize
+    where
+        I: Iterator<Item = &'a NLRIEncoding>,
+    {
+        let index = match self.peer_index.get_mut(&peer) {
+            Some(index) => index,
+            None => return 0,
+        };
+        let mut groups: HashMap<u64, HashSet<NlriKey>> = HashMap::new();
+        for nlri in nlri {
+            let key = NlriKey::from(nlri);
+            if let Some(group_key) = index.remove(&key) {
+                note_fib_change(&mut self.fib_changes, nlri);
+                groups.entry(group_key).or_default().insert(key);
+            }
+        }
+        let mut total = 0usize;
+        for (group_key, keys) in groups {
+            if let Some(entries) = self.entries.get_mut(&group_key) {
+                let pre = entries.len();
+                entries.retain(|e| {
+                    !(e.source == EntrySource::Peer(peer) && keys.contains(&NlriKey::from(&e.nlri)))
+                });
+                total += pre - entries.len();
+            }
+        }
+        total
+    }
+
+    /// Rebuild the NLRI index for a peer (after removing its routes by other criteria)
+    fn reindex_peer(&mut self, peer: IpAddr) {
+        let index: HashMap<NlriKey, u64> = self
+            .entries
+            .iter()
+            .flat_map(|(group_key, entries)| entries.iter().map(move |e| (group_key, e)))
+            .filter(|(_, e)| e.source == EntrySource::Peer(peer))
+            .map(|(group_key, e)| (NlriKey::from(&e.nlri), *group_key))
+            .collect();
+        if index.is_empty() {
+            self.peer_index.remove(&peer);
+        } else {
+            self.peer_index.insert(peer, index);
+        }
+    }
+
+    /// Flowspec validation procedure (RFC 8955 [6]), a flow is feasible if:
+    ///   - It has a destination prefix component
+    ///   - The best-match unicast route for the destination was learned from the same peer
+    ///   - No more-specific unicast routes for the destination are learned from other peers
+    fn flow_is_feasible(&self, peer: IpAddr, flow: &NLRIEncoding) -> bool {
+        let destination = match flow {
+            NLRIEncoding::FLOWSPEC(filters) => filters.iter().find_map(|filter| match filter {
+                FlowspecFilter::DestinationPrefix(prefix) => prefix_network(prefix),
+                _ => None,
+            }),
+            _ => return true,
+        };
+        let destination = match destination {
+            Some(destination) => destination,
+            None => return false,
+        };
+        let unicast = self
+            .entries
+            .values()
+            .flatten()
+            .filter(|e| e.family.safi == SAFI::Unicast)
+            .filter_map(|e| {
+                nlri_prefix(&e.nlri)
+                    .and_then(prefix_network)
+                    .map(|network| (network, e))
+            })
+            .filter(|(network, _)| network.is_ipv4() == destination.is_ipv4());
+
+        let mut best_match: Option<(IpNetwork, &RibEntry)> = None;
+        for (network, entry) in unicast {
+            if network.prefix() <= destination.prefix() && network.contains(destination.ip()) {
+                if best_match.map_or(true, |(best, _)| network.prefix() > best.prefix()) {
+                    best_match = Some((network, entry));
+                }
+            } else if network.prefix() > destination.prefix()
+                && destination.contains(network.ip())
+                && entry.source != EntrySource::Peer(peer)
+            {
+                // More-specific route from a different source
+                return false;
+            }
+        }
+        matches!(best_match, Some((_, entry)) if entry.source == EntrySource::Peer(peer))
+    }
+
     /// Maintentance cleanup of PathAttributeGroups
     ///   - May be due to sessions ending, withdrawn routes, etc..
     fn cleanup(&mut self) {
@@ -255,6 +876,156 @@ impl RIB {
         for empty_id in empty_groups {
             self.cache.remove(empty_id);
         }
+        self.cache.check_limit();
+    }
+}
+
+/// Reason an eBGP-learned AS_PATH is rejected
+#[derive(Debug, PartialEq)]
+enum AsPathViolation {
+    /// The local AS is already in the path
+    Loop,
+    /// Leftmost AS isn't the peer's AS. [received]
+    FirstAs(u32),
+}
+
+impl fmt::Display for AsPathViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsPathViolation::Loop => write!(f, "AS_PATH loop (contains local AS)"),
+            AsPathViolation::FirstAs(asn) => write!(f, "AS_PATH starts with AS{}", asn),
+        }
+    }
+}
+
+/// Sanity check the AS_PATH received from an eBGP peer:
+///   - The local AS must not be present (loop prevention)
+///   - The leftmost AS must be the peer's AS (if the path starts with an AS_SEQUENCE)
+fn as_path_violation(attributes: &[PathAttribute], config: &PeerConfig) -> Option<AsPathViolation> {
+    let as_path = attributes.iter().find_map(|attribute| match attribute {
+        PathAttribute::AS_PATH(as_path) => Some(as_path),
+        _ => None,
+    })?;
+    let contains_local = as_path.segments.iter().any(|segment| match segment {
+        Segment::AS_SEQUENCE(asns) | Segment::AS_SET(asns) => asns.contains(&config.local_as),
+    });
+    if contains_local {
+        return Some(AsPathViolation::Loop);
+    }
+    match as_path.segments.first() {
+        Some(Segment::AS_SEQUENCE(asns)) => match asns.first() {
+            Some(first) if *first != config.remote_as => Some(AsPathViolation::FirstAs(*first)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Route Reflection loop prevention for routes received from an iBGP peer (RFC 4456 [8]):
+///   - The ORIGINATOR_ID must not be our Router ID (the route originated here)
+///   - The CLUSTER_LIST must not contain our Cluster ID (the route was already reflected by us)
+fn reflection_loop(
+    attributes: &[PathAttribute],
+    config: &PeerConfig,
+    cluster_id: Option<Ipv4Addr>,
+) -> Option<&'static str> {
+    for attribute in attributes {
+        match attribute {
+            PathAttribute::ORIGINATOR_ID(id)
+                if IpAddr::from(Ipv4Addr::from(*id)) == config.local_router_id =>
+            {
+                return Some("ORIGINATOR_ID is the local Router ID");
+            }
+            PathAttribute::CLUSTER_LIST(ids)
+                if cluster_id.map_or(false, |cluster_id| ids.contains(&cluster_id.into())) =>
+            {
+                return Some("CLUSTER_LIST contains the local Cluster ID");
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Add a community to the COMMUNITY (or extended/large community) attribute, if not already present
+fn add_community(attributes: &mut Vec<PathAttribute>, community: Community) {
+    for attribute in attributes.iter_mut() {
+        match (attribute, community) {
+            (PathAttribute::COMMUNITY(communities), Community::STANDARD(c)) => {
+                if !communities.contains(&c) {
+                    communities.push(c);
+                }
+                return;
+            }
+            (PathAttribute::EXTENDED_COMMUNITIES(communities), Community::EXTENDED(c)) => {
+                if !communities.contains(&c) {
+                    communities.push(c);
+                }
+                return;
+            }
+            (PathAttribute::LARGE_COMMUNITY(communities), Community::LARGE(c)) => {
+                if !communities.contains(&c) {
+                    communities.push(c);
+                }
+                return;
+            }
+            // bgp-rs only models a single IPv6 Address Specific community per attribute
+            (
+                PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(existing),
+                Community::IPV6_EXTENDED(c),
+            ) => {
+                *existing = c;
+                return;
+            }
+            _ => (),
+        }
+    }
+    attributes.push(match community {
+        Community::STANDARD(c) => PathAttribute::COMMUNITY(vec![c]),
+        Community::EXTENDED(c) => PathAttribute::EXTENDED_COMMUNITIES(vec![c]),
+        Community::LARGE(c) => PathAttribute::LARGE_COMMUNITY(vec![c]),
+        Community::IPV6_EXTENDED(c) => PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(c),
+    });
+}
+
+/// Scale and offset a received MULTI_EXIT_DISC (if present), saturating at 0 and u32::MAX
+fn adjust_med(attributes: &mut [PathAttribute], multiplier: Option<u32>, offset: Option<i64>) {
+    for attribute in attributes.iter_mut() {
+        if let PathAttribute::MULTI_EXIT_DISC(med) = attribute {
+            let scaled = med.saturating_mul(multiplier.unwrap_or(1));
+            let adjusted = i64::from(scaled).saturating_add(offset.unwrap_or(0));
+            *med = adjusted.clamp(0, i64::from(u32::MAX)) as u32;
+        }
+    }
+}
+
+/// Replace a received attribute of the same type (or add it, if not present)
+fn replace_attribute(attributes: &mut Vec<PathAttribute>, replacement: PathAttribute) {
+    match attributes.iter_mut().find(|a| a.id() == replacement.id()) {
+        Some(existing) => *existing = replacement,
+        None => attributes.push(replacement),
+    }
+}
+
+fn nlri_prefix(nlri: &NLRIEncoding) -> Option<&Prefix> {
+    match nlri {
+        NLRIEncoding::IP(prefix) => Some(prefix),
+        NLRIEncoding::IP_WITH_PATH_ID((prefix, _)) => Some(prefix),
+        _ => None,
+    }
+}
+
+fn prefix_network(prefix: &Prefix) -> Option<IpNetwork> {
+    let (addr, length) = prefix.into();
+    IpNetwork::new(addr, length).ok()
+}
+
+/// Note a changed route prefix (if FIB changes are tracked)
+fn note_fib_change(fib_changes: &mut Option<HashSet<IpNetwork>>, nlri: &NLRIEncoding) {
+    if let Some(fib_changes) = fib_changes {
+        if let Some(prefix) = nlri_prefix(nlri).and_then(prefix_network) {
+            fib_changes.insert(prefix);
+        }
     }
 }
 
@@ -263,3 +1034,876 @@ impl std::default::Default for RIB {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::Origin;
+
+    fn peer_config(options: &str) -> Arc<PeerConfig> {
+        let config = format!(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "127.0.0.0/24"
+            remote_as = 65001
+            {}
+            "#,
+            options
+        );
+        crate::config::from_str(&config).unwrap().peers[0].clone()
+    }
+
+    #[test]
+    fn test_tracked_static_route() {
+        let mut rib = RIB::new();
+        let tracked: IpAddr = "127.0.0.2".parse().unwrap();
+        let other: IpAddr = "127.0.0.3".parse().unwrap();
+        let family = Family::new(bgp_rs::AFI::IPV4, bgp_rs::SAFI::Unicast);
+        let attributes = vec![
+            PathAttribute::ORIGIN(Origin::INCOMPLETE),
+            PathAttribute::NEXT_HOP(tracked),
+        ];
+        let nlri = NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into());
+        rib.insert_from_config(family, attributes, nlri, Some(tracked));
+        assert_eq!(rib.len(), 1);
+
+        // Tracked peer isn't up yet
+        assert!(rib.get_routes_for_peer(other).is_empty());
+        rib.peer_up(tracked);
+        assert_eq!(rib.get_routes_for_peer(other).len(), 1);
+        // Withdrawn when tracked peer goes down, but kept in the RIB
+        rib.peer_down(tracked);
+        assert!(rib.get_routes_for_peer(other).is_empty());
+        assert_eq!(rib.len(), 1);
+        // And restored on re-establishment
+        rib.peer_up(tracked);
+        assert_eq!(rib.get_routes_for_peer(other).len(), 1);
+    }
+
+    #[test]
+    fn test_flowspec_validation() {
+        let mut rib = RIB::new();
+        rib.enable_flowspec_validation();
+        let config = peer_config("");
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let other: IpAddr = "127.0.0.3".parse().unwrap();
+        let unicast = |prefix: &str, length: u8| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), length).into())],
+        };
+        let flow = |prefix: &str, length: u8| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::MP_REACH_NLRI(bgp_rs::MPReachNLRI {
+                    afi: bgp_rs::AFI::IPV4,
+                    safi: SAFI::Flowspec,
+                    next_hop: vec![],
+                    announced_routes: vec![NLRIEncoding::FLOWSPEC(vec![
+                        FlowspecFilter::DestinationPrefix((prefix.parse().unwrap(), length).into()),
+                    ])],
+                }),
+            ],
+            announced_routes: vec![],
+        };
+        rib.update_from_peer(peer, &config, unicast("10.0.0.0", 16))
+            .unwrap();
+        assert_eq!(rib.len(), 1);
+
+        // Backed by the unicast route from the same peer
+        rib.update_from_peer(peer, &config, flow("10.0.1.0", 24))
+            .unwrap();
+        assert_eq!(rib.len(), 2);
+        // No backing unicast route
+        rib.update_from_peer(peer, &config, flow("192.168.0.0", 24))
+            .unwrap();
+        assert_eq!(rib.len(), 2);
+        // Unicast route is from a different peer
+        rib.update_from_peer(other, &config, flow("10.0.2.0", 24))
+            .unwrap();
+        assert_eq!(rib.len(), 2);
+    }
+
+    #[test]
+    fn test_prefix_list_in() {
+        let mut rib = RIB::new();
+        let config = peer_config(r#"prefix_list_in = ["10.0.0.0/8 le 24", "2001:db8::/32 ge 48"]"#);
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefixes: Vec<(&str, u8)>| {
+            let announced_routes = prefixes
+                .into_iter()
+                .map(|(prefix, length)| NLRIEncoding::IP((prefix.parse().unwrap(), length).into()))
+                .collect();
+            Update {
+                withdrawn_routes: vec![],
+                attributes: vec![
+                    PathAttribute::ORIGIN(Origin::IGP),
+                    PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                    PathAttribute::NEXT_HOP(peer),
+                ],
+                announced_routes,
+            }
+        };
+        let v6_update = |prefix: &str, length: u8| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::MP_REACH_NLRI(bgp_rs::MPReachNLRI {
+                    afi: bgp_rs::AFI::IPV6,
+                    safi: SAFI::Unicast,
+                    next_hop: "2001:db8::1"
+                        .parse::<std::net::Ipv6Addr>()
+                        .unwrap()
+                        .octets()
+                        .to_vec(),
+                    announced_routes: vec![NLRIEncoding::IP(
+                        (prefix.parse().unwrap(), length).into(),
+                    )],
+                }),
+            ],
+            announced_routes: vec![],
+        };
+
+        rib.update_from_peer(
+            peer,
+            &config,
+            update(vec![
+                ("10.1.0.0", 16),
+                ("10.1.1.0", 25),
+                ("192.168.0.0", 24),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(rib.len(), 1);
+        rib.update_from_peer(peer, &config, v6_update("2001:db8:1::", 48))
+            .unwrap();
+        assert_eq!(rib.len(), 2);
+        rib.update_from_peer(peer, &config, v6_update("2001:db8::", 32))
+            .unwrap();
+        assert_eq!(rib.len(), 2);
+
+        // No prefix-list, everything is accepted
+        rib.update_from_peer(peer, &peer_config(""), update(vec![("192.168.0.0", 24)]))
+            .unwrap();
+        assert_eq!(rib.len(), 3);
+    }
+
+    #[test]
+    fn test_attribute_cache_churn() {
+        let mut rib = RIB::new();
+        rib.limit_attribute_cache(4);
+        let config = peer_config("");
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefix: &str, med: u32| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+                PathAttribute::MULTI_EXIT_DISC(med),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        };
+        // Re-announcing the same prefixes with new attributes each time
+        for med in 0..100 {
+            rib.update_from_peer(peer, &config, update("10.0.0.0", med))
+                .unwrap();
+            rib.update_from_peer(peer, &config, update("10.0.1.0", med))
+                .unwrap();
+            let stats = rib.cache_stats();
+            assert!(stats.groups <= 1, "{:?}", stats);
+        }
+        assert_eq!(rib.len(), 2);
+        let stats = rib.cache_stats();
+        assert_eq!(stats.misses, 100);
+        assert_eq!(stats.hits, 100);
+        assert_eq!(stats.hit_rate(), 0.5);
+
+        // Withdraw-only updates don't create (empty) groups
+        let withdraw = Update {
+            withdrawn_routes: vec![NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into())],
+            attributes: vec![],
+            announced_routes: vec![],
+        };
+        rib.update_from_peer(peer, &config, withdraw).unwrap();
+        assert_eq!(rib.len(), 1);
+        assert_eq!(rib.cache_stats().groups, 1);
+    }
+
+    #[test]
+    fn test_implicit_withdraw() {
+        let mut rib = RIB::new();
+        let config = peer_config("");
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let other: IpAddr = "127.0.0.3".parse().unwrap();
+        let prefix = || ("10.0.0.0".parse().unwrap(), 24).into();
+        let update = |next_hop: IpAddr, med: u32, nlri: Vec<NLRIEncoding>| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(next_hop),
+                PathAttribute::MULTI_EXIT_DISC(med),
+            ],
+            announced_routes: nlri,
+        };
+        let meds = |rib: &RIB, peer: IpAddr| -> Vec<Option<u32>> {
+            let mut meds: Vec<_> = rib
+                .get_routes_from_peer(peer)
+                .iter()
+                .map(|e| e.update.attributes.multi_exit_disc)
+                .collect();
+            meds.sort();
+            meds
+        };
+        rib.update_from_peer(
+            peer,
+            &config,
+            update(peer, 10, vec![NLRIEncoding::IP(prefix())]),
+        )
+        .unwrap();
+        rib.update_from_peer(
+            other,
+            &config,
+            update(other, 20, vec![NLRIEncoding::IP(prefix())]),
+        )
+        .unwrap();
+        assert_eq!(rib.len(), 2);
+
+        // Announcing the same NLRI replaces the peer's route, without a withdraw
+        rib.update_from_peer(
+            peer,
+            &config,
+            update(peer, 30, vec![NLRIEncoding::IP(prefix())]),
+        )
+        .unwrap();
+        assert_eq!(rib.len(), 2);
+        assert_eq!(meds(&rib, peer), vec![Some(30)]);
+        // Routes for the same prefix from other peers are kept
+        assert_eq!(meds(&rib, other), vec![Some(20)]);
+        // The replaced route's group is removed
+        assert_eq!(rib.cache_stats().groups, 2);
+
+        // Paths with different Path IDs (ADD-PATH) are different NLRI
+        let paths = vec![
+            NLRIEncoding::IP_WITH_PATH_ID((prefix(), 1)),
+            NLRIEncoding::IP_WITH_PATH_ID((prefix(), 2)),
+        ];
+        rib.update_from_peer(peer, &config, update(peer, 40, paths))
+            .unwrap();
+        rib.update_from_peer(
+            peer,
+            &config,
+            update(peer, 50, vec![NLRIEncoding::IP_WITH_PATH_ID((prefix(), 2))]),
+        )
+        .unwrap();
+        assert_eq!(meds(&rib, peer), vec![Some(30), Some(40), Some(50)]);
+    }
+
+    #[test]
+    fn test_dampened_routes() {
+        let mut rib = RIB::new();
+        let config = peer_config("dampening = true");
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let other: IpAddr = "127.0.0.3".parse().unwrap();
+        let nlri = NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into());
+        let announce = Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+            ],
+            announced_routes: vec![nlri.clone()],
+        };
+        let withdraw = Update {
+            withdrawn_routes: vec![nlri],
+            attributes: vec![],
+            announced_routes: vec![],
+        };
+        rib.update_from_peer(peer, &config, announce.clone())
+            .unwrap();
+        assert_eq!(rib.get_routes().len(), 1);
+        // Penalty 1000, 1500, 2500 (suppressed), 3000
+        for update in &[withdraw.clone(), announce.clone(), withdraw, announce] {
+            rib.update_from_peer(peer, &config, update.clone()).unwrap();
+        }
+        assert_eq!(rib.dampened_routes().len(), 1);
+        // Kept in the RIB, but not used for best-path selection (FIB & API), or exported
+        assert_eq!(rib.len(), 1);
+        assert!(rib.get_routes().is_empty());
+        assert!(rib.get_routes_for_peer(other).is_empty());
+    }
+
+    #[test]
+    fn test_fib_changes() {
+        let mut rib = RIB::new();
+        let config = peer_config("");
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefixes: &[&str]| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+            ],
+            announced_routes: prefixes
+                .iter()
+                .map(|prefix| NLRIEncoding::IP((prefix.parse().unwrap(), 24).into()))
+                .collect(),
+        };
+        let prefix = |prefix: &str| prefix.parse::<IpNetwork>().unwrap();
+        // Not tracked unless enabled
+        rib.update_from_peer(peer, &config, update(&["10.0.0.0"]))
+            .unwrap();
+        assert!(rib.take_fib_changes().is_empty());
+
+        rib.track_fib_changes();
+        rib.update_from_peer(peer, &config, update(&["10.1.0.0", "10.2.0.0"]))
+            .unwrap();
+        let changed = rib.take_fib_changes();
+        assert_eq!(changed.len(), 2);
+        assert!(changed.contains(&prefix("10.1.0.0/24")));
+        let routes = rib.get_routes_for_prefixes(&changed);
+        assert_eq!(routes.len(), 2);
+        assert!(rib.take_fib_changes().is_empty());
+
+        rib.withdraw_peer_nlri(
+            peer,
+            vec![&NLRIEncoding::IP(("10.1.0.0".parse().unwrap(), 24).into())],
+        );
+        let changed = rib.take_fib_changes();
+        assert_eq!(
+            changed.into_iter().collect::<Vec<_>>(),
+            vec![prefix("10.1.0.0/24")]
+        );
+
+        rib.remove_from_peer(peer);
+        let changed = rib.take_fib_changes();
+        assert_eq!(changed.len(), 2);
+        assert!(rib.get_routes_for_prefixes(&changed).is_empty());
+    }
+
+    #[test]
+    fn test_learned_router_id() {
+        let mut rib = RIB::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefix: &str| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        };
+        let config = peer_config("");
+        rib.update_from_peer(peer, &config, update("10.0.0.0"))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer)[0].router_id, None);
+
+        rib.remove_from_peer(peer);
+        let router_id: IpAddr = "2.2.2.2".parse().unwrap();
+        rib.set_router_id(peer, router_id);
+        rib.update_from_peer(peer, &config, update("10.0.0.0"))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer)[0].router_id, Some(router_id));
+    }
+
+    #[test]
+    fn test_reflection_loop() {
+        let mut rib = RIB::new();
+        rib.set_cluster_id("1.1.1.1".parse().unwrap());
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefix: &str, originator_id: &str, cluster_list: Vec<u32>| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+                PathAttribute::LOCAL_PREF(100),
+                PathAttribute::ORIGINATOR_ID(originator_id.parse::<Ipv4Addr>().unwrap().into()),
+                PathAttribute::CLUSTER_LIST(cluster_list),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        };
+        let config = peer_config("local_as = 65001\nlocal_router_id = \"3.3.3.3\"");
+        let (other_cluster, local_cluster): (u32, u32) = (
+            "2.2.2.2".parse::<Ipv4Addr>().unwrap().into(),
+            "1.1.1.1".parse::<Ipv4Addr>().unwrap().into(),
+        );
+        rib.update_from_peer(
+            peer,
+            &config,
+            update("10.0.0.0", "4.4.4.4", vec![other_cluster]),
+        )
+        .unwrap();
+        let routes = rib.get_routes_from_peer(peer);
+        assert_eq!(routes.len(), 1);
+        // Retained to be re-advertised
+        let attributes = &routes[0].update.attributes;
+        assert_eq!(attributes.originator_id, Some("4.4.4.4".parse().unwrap()));
+        assert_eq!(
+            attributes.cluster_list,
+            vec!["2.2.2.2".parse::<Ipv4Addr>().unwrap()]
+        );
+
+        // Our Cluster ID in the CLUSTER_LIST is a loop
+        let looped = vec![other_cluster, local_cluster];
+        rib.update_from_peer(peer, &config, update("10.0.1.0", "4.4.4.4", looped))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+        assert_eq!(rib.filter_stats(peer).reflection_loop, 1);
+
+        // As is our Router ID as the ORIGINATOR_ID
+        rib.update_from_peer(peer, &config, update("10.0.2.0", "3.3.3.3", vec![]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+        assert_eq!(rib.filter_stats(peer).reflection_loop, 2);
+
+        // Not checked for eBGP peers
+        let ebgp = peer_config("local_router_id = \"3.3.3.3\"");
+        rib.update_from_peer(
+            peer,
+            &ebgp,
+            update("10.0.3.0", "3.3.3.3", vec![local_cluster]),
+        )
+        .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 2);
+    }
+
+    #[test]
+    fn test_ebgp_as_path_validation() {
+        let mut rib = RIB::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefix: &str, asns: Vec<u32>| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath {
+                    segments: vec![Segment::AS_SEQUENCE(asns)],
+                }),
+                PathAttribute::NEXT_HOP(peer),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        };
+        let config = peer_config("");
+        rib.update_from_peer(peer, &config, update("10.0.0.0", vec![65001, 65002]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+
+        // Local AS (65000) in the path is a loop
+        rib.update_from_peer(peer, &config, update("10.0.1.0", vec![65001, 65000, 65002]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+        assert_eq!(rib.filter_stats(peer).as_path_loop, 1);
+
+        // Leftmost AS isn't the peer's AS
+        rib.update_from_peer(peer, &config, update("10.0.2.0", vec![65002]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+        assert_eq!(rib.filter_stats(peer).as_path_loop, 1);
+
+        // A looped re-announcement replaces the previously learned route
+        rib.update_from_peer(peer, &config, update("10.0.0.0", vec![65001, 65000]))
+            .unwrap();
+        assert!(rib.get_routes_from_peer(peer).is_empty());
+        assert_eq!(rib.filter_stats(peer).as_path_loop, 2);
+
+        // Not validated for iBGP
+        let ibgp = peer_config("local_as = 65001");
+        let ibgp_peer: IpAddr = "127.0.0.3".parse().unwrap();
+        rib.update_from_peer(ibgp_peer, &ibgp, update("10.0.3.0", vec![65001]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(ibgp_peer).len(), 1);
+
+        rib.peer_down(peer);
+        assert_eq!(rib.filter_stats(peer).as_path_loop, 0);
+    }
+
+    #[test]
+    fn test_filter_stats() {
+        let mut rib = RIB::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefixes: &[&str], asns: Vec<u32>| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath {
+                    segments: vec![Segment::AS_SEQUENCE(asns)],
+                }),
+                PathAttribute::NEXT_HOP(peer),
+            ],
+            announced_routes: prefixes
+                .iter()
+                .map(|p| {
+                    let network: IpNetwork = p.parse().unwrap();
+                    NLRIEncoding::IP((network.ip(), network.prefix()).into())
+                })
+                .collect(),
+        };
+        let config = peer_config("reject_bogons = true");
+        rib.update_from_peer(
+            peer,
+            &config,
+            update(&["8.8.8.0/24", "192.168.1.0/24"], vec![65001]),
+        )
+        .unwrap();
+        rib.update_from_peer(peer, &config, update(&["9.9.9.0/24"], vec![65001, 65000]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+
+        let stats = rib.filter_stats(peer);
+        assert_eq!(stats.bogon, 1);
+        assert_eq!(stats.as_path_loop, 1);
+        assert_eq!(stats.prefix_list, 0);
+        assert_eq!(stats.first_as, 0);
+
+        // Bogons are accepted unless reject_bogons is set
+        let other: IpAddr = "127.0.0.3".parse().unwrap();
+        rib.update_from_peer(
+            other,
+            &peer_config(""),
+            update(&["10.0.0.0/24"], vec![65001]),
+        )
+        .unwrap();
+        assert_eq!(rib.get_routes_from_peer(other).len(), 1);
+        assert_eq!(rib.filter_stats(other).bogon, 0);
+    }
+
+    #[test]
+    fn test_indexed_withdrawals() {
+        let mut rib = RIB::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let config = peer_config("");
+        let prefix = |i: u32| -> NLRIEncoding {
+            let addr = std::net::Ipv4Addr::from(0x0a00_0000 + (i << 8));
+            NLRIEncoding::IP((IpAddr::from(addr), 24).into())
+        };
+        let update = |nlri: Vec<NLRIEncoding>, med: u32, withdrawn: Vec<NLRIEncoding>| Update {
+            withdrawn_routes: withdrawn,
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath {
+                    segments: vec![Segment::AS_SEQUENCE(vec![65001])],
+                }),
+                PathAttribute::NEXT_HOP(peer),
+                PathAttribute::MULTI_EXIT_DISC(med),
+            ],
+            announced_routes: nlri,
+        };
+        // 10k prefixes across 10 PathAttributeGroups
+        for group in 0..10 {
+            let nlri = (group * 1000..(group + 1) * 1000).map(prefix).collect();
+            rib.update_from_peer(peer, &config, update(nlri, group, vec![]))
+                .unwrap();
+        }
+        assert_eq!(rib.len(), 10_000);
+        assert_eq!(rib.entries.len(), 10);
+
+        // Withdraw every 10th prefix
+        let withdrawn: Vec<_> = (0..10_000).step_by(10).map(prefix).collect();
+        let start = std::time::Instant::now();
+        rib.withdraw_peer_nlri(peer, withdrawn.iter().collect());
+        debug!("Withdrew 1k of 10k prefixes in {:?}", start.elapsed());
+        assert_eq!(rib.len(), 9_000);
+        assert_eq!(rib.peer_index[&peer].len(), 9_000);
+        let remaining = rib.get_routes_from_peer(peer);
+        assert!(remaining
+            .iter()
+            .all(|e| !withdrawn.contains(&e.update.nlri)));
+
+        // Re-announcing with new attributes moves the prefix to another group
+        rib.update_from_peer(peer, &config, update(vec![prefix(1)], 100, vec![]))
+            .unwrap();
+        assert_eq!(rib.len(), 9_000);
+        assert_eq!(rib.entries.len(), 11);
+        // Withdrawn in the same UPDATE as an announcement
+        rib.update_from_peer(peer, &config, update(vec![prefix(0)], 0, vec![prefix(1)]))
+            .unwrap();
+        assert_eq!(rib.len(), 9_000);
+        assert_eq!(rib.entries.len(), 10);
+
+        rib.remove_from_peer(peer);
+        assert!(rib.is_empty());
+        assert!(rib.peer_index.is_empty());
+    }
+
+    #[test]
+    fn test_community_filter_in() {
+        let mut rib = RIB::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefix: &str, communities: Vec<u32>| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath {
+                    segments: vec![Segment::AS_SEQUENCE(vec![65001])],
+                }),
+                PathAttribute::NEXT_HOP(peer),
+                PathAttribute::COMMUNITY(communities),
+                PathAttribute::EXTENDED_COMMUNITIES(vec![u64::from_be_bytes([
+                    0x00, 0x02, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x01,
+                ])]),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        };
+        let config = peer_config(
+            r#"community_filter_in = [
+                "match 65000:100 accept",
+                "match 65000:666 reject",
+                "match target:65000:1 reject",
+            ]"#,
+        );
+        let (accept, blackhole) = ((65000 << 16) + 100, (65000 << 16) + 666);
+        // First matching rule wins
+        rib.update_from_peer(peer, &config, update("10.0.0.0", vec![accept, blackhole]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+        // Rejected by the extended community
+        rib.update_from_peer(peer, &config, update("10.0.1.0", vec![]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+        // A rejected re-announcement replaces the previously learned route
+        rib.update_from_peer(peer, &config, update("10.0.0.0", vec![blackhole]))
+            .unwrap();
+        assert!(rib.get_routes_from_peer(peer).is_empty());
+        assert_eq!(rib.filter_stats(peer).community, 2);
+
+        // Routes matching no rules are accepted
+        let config = peer_config(r#"community_filter_in = ["match 65000:666 reject"]"#);
+        rib.update_from_peer(peer, &config, update("10.0.2.0", vec![accept]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+    }
+
+    #[test]
+    fn test_import_rts() {
+        let mut rib = RIB::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let target = |local: u8| u64::from_be_bytes([0x00, 0x02, 0xfd, 0xe8, 0, 0, 0, local]);
+        let flow = |prefix: &str, extended: Vec<u64>| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath {
+                    segments: vec![Segment::AS_SEQUENCE(vec![65001])],
+                }),
+                PathAttribute::EXTENDED_COMMUNITIES(extended),
+                PathAttribute::MP_REACH_NLRI(bgp_rs::MPReachNLRI {
+                    afi: bgp_rs::AFI::IPV4,
+                    safi: SAFI::Flowspec,
+                    next_hop: vec![],
+                    announced_routes: vec![NLRIEncoding::FLOWSPEC(vec![
+                        FlowspecFilter::DestinationPrefix((prefix.parse().unwrap(), 24).into()),
+                    ])],
+                }),
+            ],
+            announced_routes: vec![],
+        };
+        let config = peer_config(
+            r#"import_rts = ["target:65000:1", "target:65000:2"]
+            families = ["ipv4 flow"]"#,
+        );
+        rib.update_from_peer(peer, &config, flow("10.0.0.0", vec![target(2)]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+        // No matching route target
+        rib.update_from_peer(peer, &config, flow("10.0.1.0", vec![target(3)]))
+            .unwrap();
+        rib.update_from_peer(peer, &config, flow("10.0.2.0", vec![]))
+            .unwrap();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+        assert_eq!(rib.filter_stats(peer).route_target, 2);
+    }
+
+    #[test]
+    fn test_graceful_restart_stale_routes() {
+        let mut rib = RIB::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let v4 = Family::new(bgp_rs::AFI::IPV4, SAFI::Unicast);
+        let update = |prefix: &str| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        };
+        let config = peer_config("");
+        let learn = |rib: &mut RIB| {
+            for prefix in &["10.0.0.0", "10.0.1.0"] {
+                rib.update_from_peer(peer, &config, update(prefix)).unwrap();
+            }
+        };
+        learn(&mut rib);
+        let restart = GracefulRestart {
+            restart_time: 120,
+            families: vec![v4],
+        };
+        rib.retain_for_restart(peer, &restart);
+        rib.purge_expired_restarts();
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 2);
+
+        // Re-established, only 10.0.1.0/24 is re-announced before End-of-RIB
+        rib.update_from_peer(peer, &config, update("10.0.1.0"))
+            .unwrap();
+        rib.end_of_rib(peer, v4);
+        let routes = rib.get_routes_from_peer(peer);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(
+            routes[0].update.nlri,
+            NLRIEncoding::IP(("10.0.1.0".parse().unwrap(), 24).into())
+        );
+
+        // Stale routes are purged if the peer doesn't return within the restart time
+        rib.retain_for_restart(
+            peer,
+            &GracefulRestart {
+                restart_time: 0,
+                families: vec![v4],
+            },
+        );
+        assert_eq!(rib.get_routes_from_peer(peer).len(), 1);
+        rib.purge_expired_restarts();
+        assert!(rib.get_routes_from_peer(peer).is_empty());
+
+        // Routes for families without Graceful Restart are removed immediately
+        learn(&mut rib);
+        rib.retain_for_restart(
+            peer,
+            &GracefulRestart {
+                restart_time: 120,
+                families: vec![],
+            },
+        );
+        assert!(rib.get_routes_from_peer(peer).is_empty());
+    }
+
+    #[test]
+    fn test_local_pref_in() {
+        let mut rib = RIB::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefix: &str, local_pref: Option<u32>| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+            ]
+            .into_iter()
+            .chain(local_pref.map(PathAttribute::LOCAL_PREF))
+            .collect(),
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        };
+        let local_pref_for = |rib: &RIB, prefix: &str| {
+            let nlri = NLRIEncoding::IP((prefix.parse().unwrap(), 24).into());
+            rib.get_routes_from_peer(peer)
+                .into_iter()
+                .find(|r| r.update.nlri == nlri)
+                .and_then(|r| r.update.attributes.local_pref)
+        };
+
+        let config = peer_config("");
+        rib.update_from_peer(peer, &config, update("10.0.0.0", None))
+            .unwrap();
+        assert_eq!(local_pref_for(&rib, "10.0.0.0"), None);
+
+        let config = peer_config("local_pref_in = 250");
+        rib.update_from_peer(peer, &config, update("10.0.1.0", None))
+            .unwrap();
+        rib.update_from_peer(peer, &config, update("10.0.2.0", Some(100)))
+            .unwrap();
+        assert_eq!(local_pref_for(&rib, "10.0.1.0"), Some(250));
+        assert_eq!(local_pref_for(&rib, "10.0.2.0"), Some(250));
+    }
+
+    #[test]
+    fn test_med_adjustment() {
+        let mut rib = RIB::new();
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        let update = |prefix: &str, med: u32| Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP(peer),
+                PathAttribute::MULTI_EXIT_DISC(med),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        };
+        let med_for = |rib: &RIB, prefix: &str| {
+            let nlri = NLRIEncoding::IP((prefix.parse().unwrap(), 24).into());
+            rib.get_routes_from_peer(peer)
+                .into_iter()
+                .find(|r| r.update.nlri == nlri)
+                .and_then(|r| r.update.attributes.multi_exit_disc)
+        };
+
+        let config = peer_config("med_offset = 50");
+        rib.update_from_peer(peer, &config, update("10.0.0.0", 100))
+            .unwrap();
+        rib.update_from_peer(peer, &config, update("10.0.1.0", u32::MAX - 10))
+            .unwrap();
+        assert_eq!(med_for(&rib, "10.0.0.0"), Some(150));
+        assert_eq!(med_for(&rib, "10.0.1.0"), Some(u32::MAX));
+
+        let config = peer_config("med_multiplier = 3\nmed_offset = -500");
+        rib.update_from_peer(peer, &config, update("10.0.2.0", 100))
+            .unwrap();
+        rib.update_from_peer(peer, &config, update("10.0.3.0", 1000))
+            .unwrap();
+        rib.update_from_peer(peer, &config, update("10.0.4.0", u32::MAX / 2))
+            .unwrap();
+        assert_eq!(med_for(&rib, "10.0.2.0"), Some(0));
+        assert_eq!(med_for(&rib, "10.0.3.0"), Some(2500));
+        assert_eq!(med_for(&rib, "10.0.4.0"), Some(u32::MAX - 500));
+
+        // Override takes precedence over adjustments, and is added if missing
+        let config = peer_config("med_in_override = 42\nmed_offset = 50");
+        rib.update_from_peer(peer, &config, update("10.0.5.0", 100))
+            .unwrap();
+        let mut without_med = update("10.0.6.0", 0);
+        without_med
+            .attributes
+            .retain(|a| !matches!(a, PathAttribute::MULTI_EXIT_DISC(_)));
+        rib.update_from_peer(peer, &config, without_med).unwrap();
+        assert_eq!(med_for(&rib, "10.0.5.0"), Some(42));
+        assert_eq!(med_for(&rib, "10.0.6.0"), Some(42));
+    }
+
+    #[test]
+    fn test_remove_from_api() {
+        let mut rib = RIB::new();
+        let family = Family::new(bgp_rs::AFI::IPV4, bgp_rs::SAFI::Unicast);
+        let attributes = || {
+            vec![
+                PathAttribute::ORIGIN(Origin::INCOMPLETE),
+                PathAttribute::NEXT_HOP("127.0.0.1".parse().unwrap()),
+            ]
+        };
+        let nlri = |prefix: &str| NLRIEncoding::IP((prefix.parse().unwrap(), 24).into());
+        rib.insert_from_api(family, attributes(), nlri("10.0.0.0"));
+        rib.insert_from_api(family, attributes(), nlri("10.0.1.0"));
+        rib.insert_from_config(family, attributes(), nlri("10.0.2.0"), None);
+        assert_eq!(rib.len(), 3);
+
+        let removed = rib.remove_from_api("10.0.0.0/24".parse().unwrap());
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].source, EntrySource::Api);
+        assert_eq!(rib.len(), 2);
+        // No matching API route (only from config)
+        assert!(rib
+            .remove_from_api("10.0.2.0/24".parse().unwrap())
+            .is_empty());
+        assert_eq!(rib.len(), 2);
+    }
+}
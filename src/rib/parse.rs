@@ -30,3 +30,53 @@ pub fn parse_update(
 
     Ok((attributes, family, nlri))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::{ASPath, Origin, Segment};
+
+    use crate::rib::{PathAttributeGroup, PathAttributes};
+
+    #[test]
+    fn test_parse_aggregate_attributes() {
+        let update = Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(ASPath {
+                    segments: vec![Segment::AS_SEQUENCE(vec![65001])],
+                }),
+                PathAttribute::NEXT_HOP("10.0.0.2".parse().unwrap()),
+                PathAttribute::ATOMIC_AGGREGATOR,
+                PathAttribute::AGGREGATOR((65001, "2.2.2.2".parse().unwrap())),
+            ],
+            announced_routes: vec![NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 22).into())],
+        };
+        let (attributes, family, nlri) = parse_update(update).unwrap();
+        assert_eq!(family, Family::new(AFI::IPV4, SAFI::Unicast));
+        assert_eq!(nlri.len(), 1);
+        let attributes =
+            PathAttributes::from_group(&PathAttributeGroup::from_attributes(attributes));
+        assert!(attributes.atomic_aggregate);
+        assert_eq!(
+            attributes.aggregator,
+            Some((65001, "2.2.2.2".parse().unwrap()))
+        );
+
+        let update = Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP("10.0.0.2".parse().unwrap()),
+            ],
+            announced_routes: vec![NLRIEncoding::IP(("10.1.0.0".parse().unwrap(), 24).into())],
+        };
+        let (attributes, _, _) = parse_update(update).unwrap();
+        let attributes =
+            PathAttributes::from_group(&PathAttributeGroup::from_attributes(attributes));
+        assert!(!attributes.atomic_aggregate);
+        assert!(attributes.aggregator.is_none());
+    }
+}
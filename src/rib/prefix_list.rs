@@ -0,0 +1,156 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bgp_rs::Prefix;
+use ipnetwork::IpNetwork;
+use serde::{self, Deserialize, Deserializer};
+
+/// Prefix-list (permit) rule, matching a network and a range of prefix lengths
+/// E.g. "10.0.0.0/8" (exact), "10.0.0.0/8 le 24", "2001:db8::/32 ge 48 le 64"
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrefixListEntry {
+    network: IpNetwork,
+    ge: Option<u8>,
+    le: Option<u8>,
+}
+
+impl PrefixListEntry {
+    /// Does this prefix fall within the network (same family) and length range
+    pub fn matches(&self, prefix: &Prefix) -> bool {
+        let (addr, length) = prefix.into();
+        if addr.is_ipv4() != self.network.is_ipv4() || !self.network.contains(addr) {
+            return false;
+        }
+        length >= self.min_length() && length <= self.max_length()
+    }
+
+    fn min_length(&self) -> u8 {
+        self.ge.unwrap_or_else(|| self.network.prefix())
+    }
+
+    fn max_length(&self) -> u8 {
+        match (self.ge, self.le) {
+            (_, Some(le)) => le,
+            (Some(_), None) => max_length(&self.network),
+            (None, None) => self.network.prefix(),
+        }
+    }
+}
+
+fn max_length(network: &IpNetwork) -> u8 {
+    if network.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+impl FromStr for PrefixListEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let network: IpNetwork = parts
+            .next()
+            .ok_or_else(|| format!("Empty prefix-list entry: '{}'", s))?
+            .parse()
+            .map_err(|err| format!("Invalid prefix-list network '{}': {}", s, err))?;
+        let (mut ge, mut le) = (None, None);
+        while let Some(modifier) = parts.next() {
+            let length: u8 = parts
+                .next()
+                .and_then(|l| l.parse().ok())
+                .ok_or_else(|| format!("Missing or invalid length for '{}': '{}'", modifier, s))?;
+            match modifier {
+                "ge" if ge.is_none() => ge = Some(length),
+                "le" if le.is_none() => le = Some(length),
+                _ => return Err(format!("Unsupported prefix-list modifier: '{}'", s)),
+            }
+        }
+        // network length <= ge <= le <= max length
+        let min = ge.unwrap_or_else(|| network.prefix());
+        let max = le.unwrap_or_else(|| max_length(&network));
+        if min < network.prefix() || min > max || max > max_length(&network) {
+            return Err(format!("Invalid prefix-list length range: '{}'", s));
+        }
+        Ok(Self { network, ge, le })
+    }
+}
+
+impl fmt::Display for PrefixListEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.network)?;
+        if let Some(ge) = self.ge {
+            write!(f, " ge {}", ge)?;
+        }
+        if let Some(le) = self.le {
+            write!(f, " le {}", le)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for PrefixListEntry {
+    fn deserialize<D>(deserializer: D) -> Result<PrefixListEntry, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(network: &str) -> Prefix {
+        let network: IpNetwork = network.parse().unwrap();
+        (network.ip(), network.prefix()).into()
+    }
+
+    fn entry(s: &str) -> PrefixListEntry {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_exact_match() {
+        let exact = entry("10.0.0.0/8");
+        assert!(exact.matches(&prefix("10.0.0.0/8")));
+        assert!(!exact.matches(&prefix("10.1.0.0/16")));
+        assert!(!exact.matches(&prefix("11.0.0.0/8")));
+    }
+
+    #[test]
+    fn test_le_ge_boundaries() {
+        let le = entry("10.0.0.0/8 le 24");
+        assert!(le.matches(&prefix("10.0.0.0/8")));
+        assert!(le.matches(&prefix("10.1.2.0/24")));
+        assert!(!le.matches(&prefix("10.1.2.0/25")));
+        assert!(!le.matches(&prefix("0.0.0.0/0")));
+
+        let ge = entry("10.0.0.0/8 ge 16");
+        assert!(!ge.matches(&prefix("10.0.0.0/15")));
+        assert!(ge.matches(&prefix("10.1.0.0/16")));
+        assert!(ge.matches(&prefix("10.1.2.3/32")));
+
+        let range = entry("2001:db8::/32 ge 48 le 64");
+        assert!(!range.matches(&prefix("2001:db8::/47")));
+        assert!(range.matches(&prefix("2001:db8:1::/48")));
+        assert!(range.matches(&prefix("2001:db8:1:2::/64")));
+        assert!(!range.matches(&prefix("2001:db8:1:2::/65")));
+        // Different family
+        assert!(!range.matches(&prefix("10.0.0.0/8")));
+        assert_eq!(range.to_string(), "2001:db8::/32 ge 48 le 64");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("10.0.0.0/8 le".parse::<PrefixListEntry>().is_err());
+        assert!("10.0.0.0/8 eq 16".parse::<PrefixListEntry>().is_err());
+        assert!("10.0.0.0/16 le 8".parse::<PrefixListEntry>().is_err());
+        assert!("10.0.0.0/8 ge 24 le 16".parse::<PrefixListEntry>().is_err());
+        assert!("10.0.0.0/8 le 33".parse::<PrefixListEntry>().is_err());
+        assert!("10.0.0.0/8 le 24 le 25".parse::<PrefixListEntry>().is_err());
+    }
+}
@@ -1,16 +1,21 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use chrono::{DateTime, Utc};
 use log::error;
 
-use super::{ExportEntry, Families};
+use super::{ExportEntry, Families, Family, NlriKey};
 
 pub struct SessionRoutes {
     pub families: Families,
     pub routes: HashMap<DateTime<Utc>, Arc<ExportEntry>>,
     pending: HashSet<DateTime<Utc>>,
     advertised: HashSet<DateTime<Utc>>,
+    /// Advertised routes no longer exported to this peer (pending withdrawal)
+    withdrawn: Vec<Arc<ExportEntry>>,
+    /// When each NLRI was last advertised (for the Minimum Route Advertisement Interval)
+    last_advertised: HashMap<NlriKey, Instant>,
 }
 
 impl SessionRoutes {
@@ -20,6 +25,8 @@ impl SessionRoutes {
             routes: HashMap::new(),
             pending: HashSet::new(),
             advertised: HashSet::new(),
+            withdrawn: Vec::new(),
+            last_advertised: HashMap::new(),
         }
     }
 
@@ -40,7 +47,24 @@ impl SessionRoutes {
             .collect()
     }
 
+    /// Sync with the routes currently exported to this peer
+    ///   - New entries are added to pending routes
+    ///   - Advertised entries that are no longer present are queued for withdrawal
     pub fn insert_routes(&mut self, entries: Vec<Arc<ExportEntry>>) {
+        let current: HashSet<DateTime<Utc>> = entries.iter().map(|e| e.timestamp).collect();
+        let removed: Vec<DateTime<Utc>> = self
+            .routes
+            .keys()
+            .filter(|ts| !current.contains(ts))
+            .copied()
+            .collect();
+        for ts in removed {
+            let entry = self.routes.remove(&ts).expect("Route exists");
+            self.pending.remove(&ts);
+            if self.advertised.remove(&ts) {
+                self.withdrawn.push(entry);
+            }
+        }
         for entry in entries.into_iter() {
             let ts = entry.timestamp;
             // If this entry is not present, add to pending routes
@@ -50,11 +74,153 @@ impl SessionRoutes {
         }
     }
 
+    /// Take routes pending withdrawal
+    /// Routes replaced by one of `replacements` (for the same NLRI) are kept until the
+    /// replacement is advertised, which implicitly withdraws them (RFC 4271 [3.1])
+    pub fn take_withdrawn(&mut self, replacements: &[Arc<ExportEntry>]) -> Vec<Arc<ExportEntry>> {
+        let replaced: HashSet<NlriKey> = replacements
+            .iter()
+            .map(|entry| NlriKey::from(&entry.update.nlri))
+            .collect();
+        let (deferred, withdrawn): (Vec<_>, Vec<_>) = self
+            .withdrawn
+            .drain(..)
+            .partition(|entry| replaced.contains(&NlriKey::from(&entry.update.nlri)));
+        self.withdrawn = deferred;
+        let families = &self.families;
+        withdrawn
+            .into_iter()
+            .filter(|entry| families.contains(entry.update.family))
+            .collect()
+    }
+
+    /// Time until an entry can be advertised, at least `mrai` after its NLRI was last advertised
+    /// Returns None if it can be advertised now
+    pub fn mrai_delay(
+        &self,
+        entry: &ExportEntry,
+        mrai: Duration,
+        now: Instant,
+    ) -> Option<Duration> {
+        let last = self
+            .last_advertised
+            .get(&NlriKey::from(&entry.update.nlri))?;
+        let elapsed = now.saturating_duration_since(*last);
+        if elapsed < mrai {
+            Some(mrai - elapsed)
+        } else {
+            None
+        }
+    }
+
+    /// Move advertised routes for a family back to pending (E.g. for a ROUTE-REFRESH)
+    /// Returns the number of routes re-queued
+    pub fn requeue_advertised(&mut self, family: Family) -> usize {
+        let requeued: Vec<DateTime<Utc>> = self
+            .routes
+            .iter()
+            .filter(|(ts, entry)| self.advertised.contains(ts) && entry.update.family == family)
+            .map(|(ts, _)| *ts)
+            .collect();
+        for ts in &requeued {
+            self.advertised.remove(ts);
+            self.pending.insert(*ts);
+            // Refreshed routes are re-sent without waiting for the MRAI
+            let key = NlriKey::from(&self.routes[ts].update.nlri);
+            self.last_advertised.remove(&key);
+        }
+        requeued.len()
+    }
+
     pub fn mark_advertised(&mut self, entry: &Arc<ExportEntry>) {
         let ts = entry.timestamp;
         if !self.pending.remove(&ts) {
             error!("No route to remove: {}", ts);
         }
         self.advertised.insert(ts);
+        let key = NlriKey::from(&entry.update.nlri);
+        self.withdrawn
+            .retain(|withdrawn| NlriKey::from(&withdrawn.update.nlri) != key);
+        self.last_advertised.insert(key, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rib::{CommunityList, EntrySource, ExportedUpdate, PathAttributes};
+    use bgp_rs::{ASPath, NLRIEncoding, Origin, AFI, SAFI};
+    use chrono::Duration;
+
+    fn entry(timestamp: DateTime<Utc>) -> Arc<ExportEntry> {
+        let attributes = PathAttributes {
+            next_hop: None,
+            link_local_next_hop: None,
+            origin: Origin::IGP,
+            as_path: ASPath { segments: vec![] },
+            local_pref: None,
+            multi_exit_disc: None,
+            originator_id: None,
+            cluster_list: vec![],
+            atomic_aggregate: false,
+            aggregator: None,
+            communities: CommunityList(vec![]),
+        };
+        let update = ExportedUpdate {
+            family: Family::new(AFI::IPV4, SAFI::Unicast),
+            attributes: Arc::new(attributes),
+            nlri: NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into()),
+        };
+        let mut entry = ExportEntry::new(update, EntrySource::Config);
+        entry.timestamp = timestamp;
+        Arc::new(entry)
+    }
+
+    #[test]
+    fn test_withdraw_removed_routes() {
+        let family = Family::new(AFI::IPV4, SAFI::Unicast);
+        let mut routes = SessionRoutes::new(Families::new(vec![family]));
+        let now = Utc::now();
+        let (advertised, pending) = (entry(now), entry(now + Duration::seconds(1)));
+        routes.insert_routes(vec![advertised.clone(), pending.clone()]);
+        routes.mark_advertised(&advertised);
+        assert_eq!(routes.pending().len(), 1);
+
+        // Both routes removed, only the advertised one needs withdrawing
+        routes.insert_routes(vec![]);
+        assert!(routes.pending().is_empty());
+        assert!(routes.advertised().is_empty());
+        let withdrawn = routes.take_withdrawn(&[]);
+        assert_eq!(withdrawn.len(), 1);
+        assert_eq!(withdrawn[0].timestamp, advertised.timestamp);
+        assert!(routes.take_withdrawn(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_replaced_routes() {
+        let family = Family::new(AFI::IPV4, SAFI::Unicast);
+        let mut routes = SessionRoutes::new(Families::new(vec![family]));
+        let now = Utc::now();
+        let mrai = std::time::Duration::from_secs(30);
+        let (original, replacement) = (entry(now), entry(now + Duration::seconds(1)));
+        routes.insert_routes(vec![original.clone()]);
+        assert!(routes.mrai_delay(&original, mrai, Instant::now()).is_none());
+        routes.mark_advertised(&original);
+
+        // Replaced by a route for the same prefix, held until the MRAI elapses
+        routes.insert_routes(vec![replacement.clone()]);
+        let pending = routes.pending();
+        assert!(routes.take_withdrawn(&pending).is_empty());
+        let delay = routes
+            .mrai_delay(&replacement, mrai, Instant::now())
+            .unwrap();
+        assert!(delay > std::time::Duration::from_secs(29));
+        assert!(routes
+            .mrai_delay(&replacement, mrai, Instant::now() + mrai)
+            .is_none());
+
+        // Advertising the replacement implicitly withdraws the original
+        routes.mark_advertised(&replacement);
+        assert!(routes.take_withdrawn(&[]).is_empty());
     }
 }
@@ -1,28 +1,110 @@
-use std::io::{Error, Read};
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Read, Write};
 use std::result::Result;
 
-use bgp_rs::{Capabilities, Message, Reader};
-use byteorder::{NetworkEndian, ReadBytesExt};
+use bgp_rs::{Capabilities, Message, NLRIEncoding, PathAttribute, Reader, Update};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 use bytes::{Buf, BufMut, BytesMut};
 use tokio::net::TcpStream;
 use tokio_util::codec::{Decoder, Encoder, Framed};
 use twoway::find_bytes;
 
+use crate::rib::encode_attribute;
+
+/// Maximum BGP message size (RFC 4271 [4])
+pub(crate) const MAX_MESSAGE_SIZE: usize = 4096;
+/// Maximum BGP message size once the Extended Message capability is negotiated (RFC 8654)
+pub(crate) const MAX_EXTENDED_MESSAGE_SIZE: usize = 65535;
+/// BGP message header size (marker, length, type)
+pub(crate) const HEADER_SIZE: usize = 19;
+/// Only to Customer (OTC) path attribute type (RFC 9234)
+const ONLY_TO_CUSTOMER: u8 = 35;
+/// Encoded OTC attribute size (flags, type, length, ASN)
+pub(crate) const ONLY_TO_CUSTOMER_SIZE: usize = 7;
+
 pub type MessageProtocol = Framed<TcpStream, MessageCodec>;
 
-#[derive(Debug, Default)]
-pub struct MessageCodec;
+#[derive(Debug)]
+pub struct MessageCodec {
+    max_message_size: usize,
+    /// Capabilities used to decode received messages (E.g. ADD-PATH path IDs)
+    capabilities: Capabilities,
+    /// Total length (including headers) of messages encoded & decoded
+    bytes_sent: u64,
+    bytes_received: u64,
+    /// OTC attribute added to sent UPDATEs with NLRI (BGP Role, RFC 9234)
+    only_to_customer: Option<u32>,
+    /// OTC attribute of the last decoded UPDATE (not decoded by bgp-rs)
+    received_otc: Option<u32>,
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl MessageCodec {
     pub fn new() -> Self {
-        Self
+        Self {
+            max_message_size: MAX_MESSAGE_SIZE,
+            capabilities: Capabilities::default(),
+            bytes_sent: 0,
+            bytes_received: 0,
+            only_to_customer: None,
+            received_otc: None,
+        }
+    }
+
+    /// Decode received NLRI with ADD-PATH (RFC 7911) path IDs (receive negotiated)
+    /// bgp-rs applies this to all families, except IPv4 Unicast NLRI (path IDs are detected)
+    pub fn set_path_ids(&mut self, path_ids: bool) {
+        self.capabilities.EXTENDED_PATH_NLRI_SUPPORT = path_ids;
+    }
+
+    /// Allow messages up to 65535 bytes (Extended Message capability negotiated)
+    pub fn set_extended_messages(&mut self, extended: bool) {
+        self.max_message_size = if extended {
+            MAX_EXTENDED_MESSAGE_SIZE
+        } else {
+            MAX_MESSAGE_SIZE
+        };
+    }
+
+    /// Largest message (including header) that can be sent or received
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Add an Only to Customer (OTC) attribute with this ASN to sent UPDATEs with NLRI
+    /// (bgp-rs can't encode OTC, so it's set for each UPDATE, see `Session::send_announcement`)
+    pub fn set_only_to_customer(&mut self, asn: Option<u32>) {
+        self.only_to_customer = asn;
+    }
+
+    /// Only to Customer (OTC) attribute of the last decoded message (if an UPDATE)
+    pub fn received_otc(&self) -> Option<u32> {
+        self.received_otc
     }
 
-    fn get_reader<T>(&self, stream: T) -> Reader<T, Capabilities>
+    /// Bytes of messages sent (encoded), including headers
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Bytes of messages received (decoded), including headers
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    fn get_reader<T>(&self, stream: T) -> Reader<T, &Capabilities>
     where
         T: Read,
     {
-        Reader::<T, Capabilities>::new(stream)
+        Reader {
+            stream,
+            capabilities: &self.capabilities,
+        }
     }
 }
 
@@ -33,9 +115,31 @@ impl Decoder for MessageCodec {
     // Look for a BGP message (preamble + length), using bgp-rs to decode each message
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Error> {
         if let Ok(range) = find_msg_range(buf) {
-            let mut reader = self.get_reader(&buf[range.start..range.stop]);
+            let length = range.stop - range.start;
+            if length > self.max_message_size {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Message length {} exceeds maximum {}",
+                        length, self.max_message_size
+                    ),
+                ));
+            }
+        }
+        // Wait for more data if the message hasn't been fully received yet
+        if let Some(range) = find_msg_range(buf)
+            .ok()
+            .filter(|range| range.stop <= buf.len())
+        {
+            let message = &buf[range.start..range.stop];
+            self.received_otc = match message.get(HEADER_SIZE - 1) {
+                Some(2 /* UPDATE */) => find_only_to_customer(&message[HEADER_SIZE..]),
+                _ => None,
+            };
+            let mut reader = self.get_reader(message);
             let (_header, message) = reader.read()?;
             buf.advance(range.stop);
+            self.bytes_received += (range.stop - range.start) as u64;
             Ok(Some(message))
         } else {
             Ok(None)
@@ -47,8 +151,143 @@ impl Encoder<Message> for MessageCodec {
     type Error = Error;
 
     fn encode(&mut self, message: Message, buf: &mut BytesMut) -> Result<(), Error> {
-        message.encode(&mut buf.writer())
+        let start = buf.len();
+        match message {
+            Message::Update(update) => {
+                let mut body: Vec<u8> = Vec::with_capacity(64);
+                encode_update(&update, &mut body)?;
+                if let Some(asn) = self.only_to_customer.filter(|_| has_nlri(&update)) {
+                    let mut otc: Vec<u8> = Vec::with_capacity(ONLY_TO_CUSTOMER_SIZE);
+                    otc.extend_from_slice(&[
+                        0xC0, /* Optional, Transitive */
+                        ONLY_TO_CUSTOMER,
+                        4,
+                    ]);
+                    otc.extend_from_slice(&asn.to_be_bytes());
+                    insert_attributes(&mut body, otc)?;
+                }
+                if body.len() + HEADER_SIZE > self.max_message_size {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        format!("Cannot encode message of length {}", body.len()),
+                    ));
+                }
+                let mut writer = buf.writer();
+                writer.write_all(&[0xff; 16])?;
+                writer.write_u16::<NetworkEndian>((body.len() + HEADER_SIZE) as u16)?;
+                writer.write_u8(2 /* UPDATE */)?;
+                writer.write_all(&body)?;
+            }
+            message => message.encode(&mut buf.writer())?,
+        }
+        self.bytes_sent += (buf.len() - start) as u64;
+        Ok(())
+    }
+}
+
+/// Encode an UPDATE message (without header)
+/// Attributes that bgp-rs can't encode are added to the encoded path attributes,
+/// as are withdrawn (IPv4 Unicast) routes with an ADD-PATH path ID
+pub(crate) fn encode_update(update: &Update, buf: &mut Vec<u8>) -> Result<(), Error> {
+    let (unsupported, supported): (Vec<PathAttribute>, Vec<PathAttribute>) =
+        update.attributes.iter().cloned().partition(|attr| {
+            matches!(
+                attr,
+                PathAttribute::LARGE_COMMUNITY(_)
+                    | PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(_)
+                    | PathAttribute::ATOMIC_AGGREGATOR
+                    | PathAttribute::AGGREGATOR(_)
+            )
+        });
+    let path_id_withdrawals = update
+        .withdrawn_routes
+        .iter()
+        .any(|route| matches!(route, NLRIEncoding::IP_WITH_PATH_ID(_)));
+    if unsupported.is_empty() && !path_id_withdrawals {
+        return update.encode(buf);
+    }
+    let mut body: Vec<u8> = Vec::with_capacity(64);
+    Update {
+        withdrawn_routes: if path_id_withdrawals {
+            vec![]
+        } else {
+            update.withdrawn_routes.clone()
+        },
+        attributes: supported,
+        announced_routes: update.announced_routes.clone(),
+    }
+    .encode(&mut body)?;
+    let mut extra: Vec<u8> = Vec::with_capacity(16);
+    for attr in &unsupported {
+        encode_attribute(attr, &mut extra)?;
+    }
+    insert_attributes(&mut body, extra)?;
+    if path_id_withdrawals {
+        let mut withdrawn: Vec<u8> = Vec::with_capacity(8 * update.withdrawn_routes.len());
+        for route in &update.withdrawn_routes {
+            route.encode(&mut withdrawn)?;
+        }
+        let withdrawn_len = u16::try_from(withdrawn.len())
+            .map_err(|_| Error::new(ErrorKind::Other, "Withdrawn routes too long"))?;
+        body.splice(
+            0..2,
+            withdrawn_len.to_be_bytes().iter().copied().chain(withdrawn),
+        );
+    }
+    buf.extend_from_slice(&body);
+    Ok(())
+}
+
+/// Add encoded path attributes to an encoded UPDATE (without header)
+fn insert_attributes(body: &mut Vec<u8>, extra: Vec<u8>) -> Result<(), Error> {
+    // [withdrawn length (2)][withdrawn][attributes length (2)][attributes][NLRI]
+    let withdrawn_len = (&body[0..2]).read_u16::<NetworkEndian>()? as usize;
+    let attrs_len_at = 2 + withdrawn_len;
+    let attrs_len = (&body[attrs_len_at..attrs_len_at + 2]).read_u16::<NetworkEndian>()? as usize;
+    let attrs_end = attrs_len_at + 2 + attrs_len;
+    let new_len = u16::try_from(attrs_len + extra.len())
+        .map_err(|_| Error::new(ErrorKind::Other, "Path attributes too long"))?;
+    body[attrs_len_at..attrs_len_at + 2].copy_from_slice(&new_len.to_be_bytes());
+    body.splice(attrs_end..attrs_end, extra);
+    Ok(())
+}
+
+/// Does an UPDATE announce any NLRI
+fn has_nlri(update: &Update) -> bool {
+    !update.announced_routes.is_empty()
+        || update
+            .attributes
+            .iter()
+            .any(|attr| matches!(attr, PathAttribute::MP_REACH_NLRI(_)))
+}
+
+/// Find the Only to Customer (OTC) attribute in an encoded UPDATE (without header)
+fn find_only_to_customer(body: &[u8]) -> Option<u32> {
+    let withdrawn_len = usize::from(u16::from_be_bytes([*body.first()?, *body.get(1)?]));
+    let attrs_len_at = 2 + withdrawn_len;
+    let attrs_len = usize::from(u16::from_be_bytes([
+        *body.get(attrs_len_at)?,
+        *body.get(attrs_len_at + 1)?,
+    ]));
+    let mut attrs = body.get(attrs_len_at + 2..attrs_len_at + 2 + attrs_len)?;
+    while attrs.len() >= 3 {
+        let (flags, code) = (attrs[0], attrs[1]);
+        // Extended length flag
+        let (length, value_at) = if flags & 0x10 != 0 {
+            (
+                usize::from(u16::from_be_bytes([attrs[2], *attrs.get(3)?])),
+                4,
+            )
+        } else {
+            (usize::from(attrs[2]), 3)
+        };
+        let value = attrs.get(value_at..value_at + length)?;
+        if code == ONLY_TO_CUSTOMER {
+            return <[u8; 4]>::try_from(value).ok().map(u32::from_be_bytes);
+        }
+        attrs = &attrs[value_at + length..];
     }
+    None
 }
 
 #[derive(Debug)]
@@ -63,7 +302,9 @@ fn find_msg_range(data: &[u8]) -> Result<MsgRange, String> {
         let buf = &mut (*data).split_at(start).1;
         let mut _preamble: [u8; 16] = [0; 16];
         let _ = buf.read_exact(&mut _preamble);
-        let length = buf.read_u16::<NetworkEndian>().unwrap();
+        let length = buf
+            .read_u16::<NetworkEndian>()
+            .map_err(|_| "Incomplete BGP message header".to_string())?;
         Ok(MsgRange {
             start,
             stop: start + (length as usize),
@@ -89,6 +330,259 @@ mod tests {
         assert_eq!(range.stop, 45);
     }
 
+    #[test]
+    fn test_decode_partial_message() {
+        let mut codec = MessageCodec::new();
+        let mut keepalive = vec![255u8; 16];
+        keepalive.extend_from_slice(&[0, 19, 4]);
+        // Header split across reads
+        let mut buf = BytesMut::from(&keepalive[..17]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&keepalive[17..18]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(codec.bytes_received(), 0);
+        buf.extend_from_slice(&keepalive[18..]);
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Message::KeepAlive)
+        ));
+        assert!(buf.is_empty());
+        assert_eq!(codec.bytes_received(), 19);
+    }
+
+    fn large_update(routes: u8) -> Update {
+        Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(bgp_rs::Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP("10.0.0.1".parse().unwrap()),
+            ],
+            announced_routes: (0..routes)
+                .flat_map(|a| {
+                    (0..=255u8).map(move |b| {
+                        bgp_rs::NLRIEncoding::IP(
+                            (std::net::Ipv4Addr::new(10, a, b, 0).into(), 24).into(),
+                        )
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_extended_messages() {
+        // 1024 NLRI (4 bytes each) won't fit in a classic 4096 byte message
+        let update = large_update(4);
+        let mut codec = MessageCodec::new();
+        let mut buf = BytesMut::new();
+        assert!(codec
+            .encode(Message::Update(update.clone()), &mut buf)
+            .is_err());
+
+        codec.set_extended_messages(true);
+        assert_eq!(codec.max_message_size(), MAX_EXTENDED_MESSAGE_SIZE);
+        codec.encode(Message::Update(update), &mut buf).unwrap();
+        assert!(buf.len() > MAX_MESSAGE_SIZE);
+        let framed = buf.clone();
+
+        // Received in pieces
+        let mut partial = BytesMut::from(&framed[..MAX_MESSAGE_SIZE]);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+        partial.extend_from_slice(&framed[MAX_MESSAGE_SIZE..]);
+        match codec.decode(&mut partial).unwrap() {
+            Some(Message::Update(update)) => assert_eq!(update.announced_routes.len(), 1024),
+            other => panic!("Expected Update, got {:?}", other),
+        }
+        assert!(partial.is_empty());
+
+        // Without the capability, the message is rejected
+        let mut codec = MessageCodec::new();
+        let mut buf = framed;
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_encode_large_communities() {
+        let update = Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(bgp_rs::Origin::IGP),
+                PathAttribute::NEXT_HOP("10.0.0.1".parse().unwrap()),
+                PathAttribute::LARGE_COMMUNITY(vec![(65000, 1, 2), (4200000000, 0, 100)]),
+            ],
+            announced_routes: vec![bgp_rs::NLRIEncoding::IP(
+                ("10.0.0.0".parse().unwrap(), 24).into(),
+            )],
+        };
+        let mut codec = MessageCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(Message::Update(update), &mut buf).unwrap();
+        let decoded = match codec.decode(&mut buf).unwrap() {
+            Some(Message::Update(update)) => update,
+            other => panic!("Expected Update, got {:?}", other),
+        };
+        match decoded.get(bgp_rs::Identifier::LARGE_COMMUNITY) {
+            Some(PathAttribute::LARGE_COMMUNITY(communities)) => {
+                assert_eq!(communities, &vec![(65000, 1, 2), (4200000000, 0, 100)])
+            }
+            _ => panic!("Expected LARGE_COMMUNITY"),
+        }
+        assert!(decoded.get(bgp_rs::Identifier::NEXT_HOP).is_some());
+        assert_eq!(decoded.announced_routes.len(), 1);
+    }
+
+    #[test]
+    fn test_encode_ipv6_ext_community() {
+        let community = (0x00, 0x0d, "2001:db8::1".parse().unwrap(), 100);
+        let update = Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(bgp_rs::Origin::IGP),
+                PathAttribute::NEXT_HOP("10.0.0.1".parse().unwrap()),
+                PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(community),
+            ],
+            announced_routes: vec![bgp_rs::NLRIEncoding::IP(
+                ("10.0.0.0".parse().unwrap(), 24).into(),
+            )],
+        };
+        let mut codec = MessageCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(Message::Update(update), &mut buf).unwrap();
+        let decoded = match codec.decode(&mut buf).unwrap() {
+            Some(Message::Update(update)) => update,
+            other => panic!("Expected Update, got {:?}", other),
+        };
+        match decoded.get(bgp_rs::Identifier::IPV6_SPECIFIC_EXTENDED_COMMUNITY) {
+            Some(PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(decoded)) => {
+                assert_eq!(decoded, &community)
+            }
+            _ => panic!("Expected IPV6_SPECIFIC_EXTENDED_COMMUNITY"),
+        }
+        assert_eq!(decoded.announced_routes.len(), 1);
+    }
+
+    #[test]
+    fn test_add_path_nlri() {
+        use bgp_rs::{MPReachNLRI, MPUnreachNLRI, AFI, SAFI};
+        let path = |prefix: &str, length: u8, path_id: u32| {
+            NLRIEncoding::IP_WITH_PATH_ID(((prefix.parse().unwrap(), length).into(), path_id))
+        };
+        // Decoded prefixes only keep the significant octets, compare as displayed
+        let displayed = |routes: &[NLRIEncoding]| format!("{:?}", routes);
+        let update = Update {
+            withdrawn_routes: vec![path("10.0.1.0", 24, 7)],
+            attributes: vec![
+                PathAttribute::ORIGIN(bgp_rs::Origin::IGP),
+                PathAttribute::AS_PATH(bgp_rs::ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP("10.0.0.1".parse().unwrap()),
+                PathAttribute::MP_REACH_NLRI(MPReachNLRI {
+                    afi: AFI::IPV6,
+                    safi: SAFI::Unicast,
+                    next_hop: "2001:db8::1"
+                        .parse::<std::net::Ipv6Addr>()
+                        .unwrap()
+                        .octets()
+                        .to_vec(),
+                    announced_routes: vec![path("2001:db8::", 32, 1), path("2001:db8::", 32, 2)],
+                }),
+                PathAttribute::MP_UNREACH_NLRI(MPUnreachNLRI {
+                    afi: AFI::IPV6,
+                    safi: SAFI::Unicast,
+                    withdrawn_routes: vec![path("2001:db8:1::", 48, 3)],
+                }),
+            ],
+            announced_routes: vec![path("10.0.0.0", 24, 1), path("10.0.0.0", 24, 2)],
+        };
+        let mut codec = MessageCodec::new();
+        codec.set_path_ids(true);
+        let mut buf = BytesMut::new();
+        codec
+            .encode(Message::Update(update.clone()), &mut buf)
+            .unwrap();
+        let decoded = match codec.decode(&mut buf).unwrap() {
+            Some(Message::Update(update)) => update,
+            other => panic!("Expected Update, got {:?}", other),
+        };
+        assert_eq!(
+            displayed(&decoded.withdrawn_routes),
+            displayed(&update.withdrawn_routes)
+        );
+        assert_eq!(
+            displayed(&decoded.announced_routes),
+            displayed(&update.announced_routes)
+        );
+        match decoded.get(bgp_rs::Identifier::MP_REACH_NLRI) {
+            Some(PathAttribute::MP_REACH_NLRI(nlri)) => assert_eq!(
+                displayed(&nlri.announced_routes),
+                displayed(&[path("2001:db8::", 32, 1), path("2001:db8::", 32, 2)])
+            ),
+            _ => panic!("Expected MP_REACH_NLRI"),
+        }
+        match decoded.get(bgp_rs::Identifier::MP_UNREACH_NLRI) {
+            Some(PathAttribute::MP_UNREACH_NLRI(nlri)) => {
+                assert_eq!(
+                    displayed(&nlri.withdrawn_routes),
+                    displayed(&[path("2001:db8:1::", 48, 3)])
+                )
+            }
+            _ => panic!("Expected MP_UNREACH_NLRI"),
+        }
+
+        // Without path IDs, the same withdrawn routes are decoded as plain prefixes
+        let mut codec = MessageCodec::new();
+        let mut buf = BytesMut::new();
+        let withdraw = Update {
+            withdrawn_routes: vec![NLRIEncoding::IP(("10.0.1.0".parse().unwrap(), 24).into())],
+            attributes: vec![],
+            announced_routes: vec![],
+        };
+        codec
+            .encode(Message::Update(withdraw.clone()), &mut buf)
+            .unwrap();
+        match codec.decode(&mut buf).unwrap() {
+            Some(Message::Update(decoded)) => {
+                assert_eq!(
+                    displayed(&decoded.withdrawn_routes),
+                    displayed(&withdraw.withdrawn_routes)
+                )
+            }
+            other => panic!("Expected Update, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_only_to_customer() {
+        let mut update = large_update(1);
+        update.announced_routes.truncate(2);
+        let mut codec = MessageCodec::new();
+        codec.set_only_to_customer(Some(65000));
+        let mut buf = BytesMut::new();
+        codec
+            .encode(Message::Update(update.clone()), &mut buf)
+            .unwrap();
+        let mut plain = BytesMut::new();
+        MessageCodec::new()
+            .encode(Message::Update(update), &mut plain)
+            .unwrap();
+        assert_eq!(buf.len(), plain.len() + ONLY_TO_CUSTOMER_SIZE);
+        match codec.decode(&mut buf).unwrap() {
+            Some(Message::Update(decoded)) => assert_eq!(decoded.announced_routes.len(), 2),
+            other => panic!("Expected Update, got {:?}", other),
+        }
+        assert_eq!(codec.received_otc(), Some(65000));
+
+        // Not added to withdrawals
+        let withdraw = Update {
+            withdrawn_routes: vec![NLRIEncoding::IP(("10.0.1.0".parse().unwrap(), 24).into())],
+            attributes: vec![],
+            announced_routes: vec![],
+        };
+        codec.encode(Message::Update(withdraw), &mut buf).unwrap();
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+        assert_eq!(codec.received_otc(), None);
+    }
+
     #[test]
     fn test_find_msg_range_err() {
         let data: [u8; 32] = [
@@ -0,0 +1,165 @@
+//! Generalized TTL Security Mechanism (RFC 5082) for peer sessions
+//!
+//! Only supported on Linux, for peers with a configured `min_ttl`:
+//!   - Packets are sent with a TTL (IPv6 Hop Limit) of 255
+//!   - The kernel drops received packets with a TTL below `min_ttl` (`IP_MINTTL`/`IPV6_MINHOPCOUNT`)
+//!
+//! Options are applied to the outbound socket before connecting, and to inbound
+//! connections once accepted (the listener is shared by all peers, so spoofed SYNs
+//! can still complete a handshake, but no BGP messages are received from them).
+//!
+//! A `min_ttl` of 255 only allows directly-connected peers (the TTL is decremented by
+//! each router in the path), so multihop peers need `min_ttl = 255 - <hops>`.
+use std::io;
+
+/// TTL (IPv6 Hop Limit) sent to GTSM peers
+pub const MAX_TTL: u8 = 255;
+
+/// Is the Generalized TTL Security Mechanism supported on this platform
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Check a minimum TTL can be enforced on this platform
+pub fn validate(min_ttl: u8) -> io::Result<()> {
+    if !SUPPORTED {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "TTL security (min_ttl) is only supported on Linux (requires IP_MINTTL)",
+        ));
+    }
+    if min_ttl == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "min_ttl must be 1-255",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::io;
+    use std::mem;
+    use std::os::unix::io::RawFd;
+
+    use super::MAX_TTL;
+
+    fn set_option(fd: RawFd, level: libc::c_int, option: libc::c_int, value: u8) -> io::Result<()> {
+        let value = libc::c_int::from(value);
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                option,
+                &value as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn set_ttl_security(fd: RawFd, socket_is_v6: bool, min_ttl: u8) -> io::Result<()> {
+        if socket_is_v6 {
+            set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, MAX_TTL)?;
+            set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_MINHOPCOUNT, min_ttl)?;
+        }
+        // Also applies to IPv4-mapped peers on a dual-stack socket
+        set_option(fd, libc::IPPROTO_IP, libc::IP_TTL, MAX_TTL)?;
+        set_option(fd, libc::IPPROTO_IP, libc::IP_MINTTL, min_ttl)?;
+        Ok(())
+    }
+}
+
+/// Send with a TTL of 255 and drop received packets with a TTL below `min_ttl` on a socket
+#[cfg(target_os = "linux")]
+pub fn set_ttl_security<S: std::os::unix::io::AsRawFd>(
+    socket: &S,
+    socket_is_v6: bool,
+    min_ttl: u8,
+) -> io::Result<()> {
+    platform::set_ttl_security(socket.as_raw_fd(), socket_is_v6, min_ttl)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_ttl_security<S>(_socket: &S, _socket_is_v6: bool, _min_ttl: u8) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TTL security requires IP_MINTTL, which is only supported on Linux",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+    use tokio::net::{TcpListener, TcpSocket};
+    use tokio::time::{timeout, Duration};
+
+    fn get_option<S: AsRawFd>(socket: &S, level: libc::c_int, option: libc::c_int) -> i32 {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(ret, 0, "{}", io::Error::last_os_error());
+        value
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(254).is_ok());
+        assert!(validate(255).is_ok());
+        assert!(validate(0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socket_options() {
+        let v4 = TcpSocket::new_v4().unwrap();
+        set_ttl_security(&v4, false, 254).unwrap();
+        assert_eq!(get_option(&v4, libc::IPPROTO_IP, libc::IP_TTL), 255);
+        assert_eq!(get_option(&v4, libc::IPPROTO_IP, libc::IP_MINTTL), 254);
+
+        let v6 = TcpSocket::new_v6().unwrap();
+        set_ttl_security(&v6, true, 253).unwrap();
+        assert_eq!(
+            get_option(&v6, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS),
+            255
+        );
+        assert_eq!(
+            get_option(&v6, libc::IPPROTO_IPV6, libc::IPV6_MINHOPCOUNT),
+            253
+        );
+        assert_eq!(get_option(&v6, libc::IPPROTO_IP, libc::IP_MINTTL), 253);
+    }
+
+    #[tokio::test]
+    async fn test_ttl_security_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        set_ttl_security(&listener, false, 255).unwrap();
+
+        // Sent with the default TTL (64), the kernel drops the SYN
+        let default_ttl = TcpSocket::new_v4().unwrap();
+        let connect = timeout(Duration::from_millis(300), default_ttl.connect(addr)).await;
+        assert!(!matches!(connect, Ok(Ok(_))));
+
+        // Sent with a TTL of 255, the connection is established
+        let secured = TcpSocket::new_v4().unwrap();
+        set_ttl_security(&secured, false, 255).unwrap();
+        let (connect, accept) = tokio::join!(
+            timeout(Duration::from_millis(1000), secured.connect(addr)),
+            timeout(Duration::from_millis(1000), listener.accept())
+        );
+        assert!(matches!(connect, Ok(Ok(_))));
+        assert!(matches!(accept, Ok(Ok(_))));
+    }
+}
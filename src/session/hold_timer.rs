@@ -1,3 +1,4 @@
+use std::cmp;
 use std::fmt;
 use std::time;
 
@@ -17,10 +18,11 @@ pub struct HoldTimer {
 }
 
 impl HoldTimer {
-    pub fn new(hold_timer: u16) -> HoldTimer {
+    /// Keepalive interval is 1/3 of the hold timer, optionally capped by `min_keepalive`
+    pub fn new(hold_timer: u16, min_keepalive: Option<u16>) -> HoldTimer {
         HoldTimer {
             hold_timer,
-            interval: hold_timer / 3,
+            interval: keepalive_interval(hold_timer, min_keepalive),
             timer: interval(time::Duration::from_millis(100)),
             last_received: Utc::now(),
             last_sent: Utc::now(),
@@ -28,15 +30,17 @@ impl HoldTimer {
     }
 
     // Calculate if Keepalive message should be sent
-    // Returns true when:
-    //    Hold time remaining is less than 2/3 of the total hold_timer
-    //    which is 2x the Keepalive timer
+    // Returns true when the keepalive interval has elapsed since the last sent message
+    // (1/3 of the hold_timer, unless capped by `min_keepalive`)
     pub async fn should_send_keepalive(&mut self) -> Result<bool, SessionError> {
         self.timer.tick().await;
         if self.is_expired() {
             return Err(SessionError::HoldTimeExpired(self.interval));
         }
-        Ok(self.get_hold_time().num_seconds() < (2 * i64::from(self.interval)))
+        if self.interval == 0 {
+            return Ok(false);
+        }
+        Ok(get_elapsed_time(self.last_sent) >= Duration::seconds(self.interval.into()))
     }
 
     /// Bump the last received to now
@@ -66,6 +70,15 @@ impl HoldTimer {
     }
 }
 
+/// Keepalive interval derived from the hold timer and capped by `min_keepalive` if provided
+pub fn keepalive_interval(hold_timer: u16, min_keepalive: Option<u16>) -> u16 {
+    let interval = hold_timer / 3;
+    match min_keepalive {
+        Some(cap) if cap > 0 => cmp::min(interval, cap),
+        _ => interval,
+    }
+}
+
 impl fmt::Display for HoldTimer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", format_elapsed_time(self.get_hold_time()))
@@ -78,7 +91,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_interval() {
-        let mut ht = HoldTimer::new(30);
+        let mut ht = HoldTimer::new(30, None);
         assert_eq!(ht.interval, 10);
         assert!(!ht.is_expired());
         // Test that keepalive should not be sent yet
@@ -97,9 +110,30 @@ mod tests {
         // And if hold_time is past, this session is expired
         ht.last_received = ht.last_received - Duration::seconds(30);
         ht.timer = interval(time::Duration::from_millis(1));
-        match ht.should_send_keepalive().await {
-            Ok(_) => panic!("Should return Err"),
-            _ => (),
-        }
+        assert!(
+            ht.should_send_keepalive().await.is_err(),
+            "Should return Err"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_min_keepalive() {
+        let mut ht = HoldTimer::new(240, Some(20));
+        assert_eq!(ht.hold_timer, 240);
+        assert_eq!(ht.interval, 20);
+        ht.last_sent = ht.last_sent - Duration::seconds(19);
+        ht.timer = interval(time::Duration::from_millis(1));
+        assert!(!ht.should_send_keepalive().await.unwrap());
+        ht.last_sent = ht.last_sent - Duration::seconds(1);
+        ht.timer = interval(time::Duration::from_millis(1));
+        assert!(ht.should_send_keepalive().await.unwrap());
+        // Hold time is still used for expiry
+        ht.last_received = ht.last_received - Duration::seconds(200);
+        ht.timer = interval(time::Duration::from_millis(1));
+        assert!(ht.should_send_keepalive().await.is_ok());
+
+        // Cap is only applied if smaller than the derived interval
+        assert_eq!(keepalive_interval(30, Some(20)), 10);
+        assert_eq!(keepalive_interval(30, Some(0)), 10);
     }
 }
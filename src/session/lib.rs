@@ -1,24 +1,34 @@
 use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 use std::io;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use bgp_rs::{
-    ASPath, Capabilities, MPReachNLRI, Message, NLRIEncoding, Notification, Open, OpenCapability,
-    OpenParameter, PathAttribute, Segment, Update, AFI, SAFI,
+    ASPath, AddPathDirection, Capabilities, MPReachNLRI, MPUnreachNLRI, Message, NLRIEncoding,
+    Notification, Open, OpenCapability, OpenParameter, PathAttribute, RouteRefresh, Segment,
+    Update, AFI, SAFI,
 };
 use chrono::{DateTime, Utc};
 use futures::{SinkExt, StreamExt};
-use log::{debug, trace, warn};
+use log::{debug, info, trace, warn};
 use tokio;
 
-use super::codec::MessageProtocol;
-use super::{HoldTimer, MessageCounts};
-use super::{SessionError, SessionState, SessionUpdate};
+use super::codec::{encode_update, MessageProtocol, HEADER_SIZE, ONLY_TO_CUSTOMER_SIZE};
+use super::role::{check_role, received_role, role_capability, Role};
+use super::{GracefulRestart, NotificationRecord, SessionError, SessionState, SessionUpdate};
+use super::{HoldTimer, MessageCounts, MessageTally, PrefixLimit, PrefixLimitStatus, UpdatePacing};
 use crate::config::{AdvertiseSource, PeerConfig};
-use crate::rib::{session::SessionRoutes, EntrySource, ExportedUpdate, Families};
-use crate::utils::{format_time_as_elapsed, get_message_type};
+use crate::rib::{
+    default_routes, encode_attribute, session::SessionRoutes, Community, EntrySource, ExportEntry,
+    ExportedUpdate, Families, Family,
+};
+use crate::utils::{
+    format_time_as_elapsed, get_message_type, notification_to_string, with_log_fields,
+};
 
 /// A `Session` is a stream for processing BGP messages and
 /// handling peer timeouts
@@ -29,21 +39,70 @@ pub struct Session {
     pub(crate) config: Arc<PeerConfig>,
     pub(crate) protocol: MessageProtocol,
     pub(crate) connect_time: DateTime<Utc>,
+    /// When the DelayOpen timer (started on connect) expires, if `delay_open` is set
+    pub(crate) delay_open_until: Option<tokio::time::Instant>,
     pub(crate) hold_timer: HoldTimer,
     pub(crate) counts: MessageCounts,
     pub(crate) routes: SessionRoutes,
+    /// Negotiated capabilities (once an OPEN is received, local capabilities until then)
     pub(crate) capabilities: Capabilities,
+    /// Capabilities advertised in our OPEN
+    pub(crate) local_capabilities: Capabilities,
+    /// Capabilities advertised in the peer's OPEN
+    pub(crate) received_capabilities: Option<Capabilities>,
+    /// OPEN received from the peer (reported to the BMP collector)
+    pub(crate) received_open: Option<Open>,
+    pub(crate) prefix_limit: PrefixLimit,
+    /// Are outbound routes currently limited by `max_prefix_out` (warning already logged)
+    pub(crate) prefix_out_limited: bool,
+    /// When default routes were originated to this peer (while `default_originate` applies)
+    pub(crate) default_originated: Option<DateTime<Utc>>,
+    /// Has the initial route dump been sent (when `initial_batch` is enabled)
+    pub(crate) initial_dump_sent: bool,
+    /// Families with a refresh response pending, sent within BoRR/EoRR markers (RFC 7313)
+    pub(crate) refreshing: Vec<Family>,
+    /// Families with BoRR sent, waiting for (paced) routes to be sent before EoRR
+    pub(crate) refresh_in_progress: Vec<Family>,
+    /// Outbound UPDATE rate limiting (when `max_update_rate` is configured)
+    pub(crate) pacing: Option<UpdatePacing>,
+    /// Restart time from the peer's Graceful Restart capability (if advertised)
+    pub(crate) peer_restart_time: Option<u16>,
+    /// Extended Message capability advertised by the peer
+    pub(crate) peer_extended_messages: bool,
+    /// BGP Role advertised by the peer (if any)
+    pub(crate) peer_role: Option<Role>,
+    /// Extended Message capability negotiated (messages up to 65535 bytes)
+    pub(crate) extended_messages: bool,
+    /// Last NOTIFICATION sent or received (if any, routes aren't retained for Graceful Restart)
+    pub(crate) last_notification: Option<NotificationRecord>,
 }
 
 impl Session {
     /// Build a newly created session from the peer config & BGP Message Stream
     pub fn new(config: Arc<PeerConfig>, protocol: MessageProtocol) -> Session {
-        let hold_timer = config.hold_timer;
-        let capabilities: Vec<OpenCapability> = vec![OpenCapability::FourByteASN(config.local_as)]
-            .into_iter()
-            .chain(config.families.iter().map(|f| f.to_open_param()))
-            .collect();
+        let hold_timer = HoldTimer::new(config.hold_timer, config.min_keepalive);
+        let capabilities: Vec<OpenCapability> = vec![
+            OpenCapability::FourByteASN(config.local_as),
+            OpenCapability::RouteRefresh,
+        ]
+        .into_iter()
+        .chain(config.families.iter().map(|f| f.to_open_param()))
+        .chain(add_path_capability(&config))
+        .collect();
+        let mut capabilities =
+            Capabilities::from_parameters(vec![OpenParameter::Capabilities(capabilities)]);
+        // Not decoded by `from_parameters`
+        if config.graceful_restart.is_some() {
+            capabilities.GRACEFUL_RESTART_SUPPORT =
+                config.families.iter().map(|f| (f.afi, f.safi)).collect();
+        }
+        capabilities.ENHANCED_ROUTE_REFRESH_SUPPORT = true;
         let session_rib = SessionRoutes::new(Families::new(vec![]));
+        let prefix_limit = PrefixLimit::new(config.max_prefixes, config.max_prefix_warning);
+        let pacing = config.max_update_rate.and_then(UpdatePacing::new);
+        let delay_open_until = config
+            .delay_open
+            .map(|delay| tokio::time::Instant::now() + Duration::from_secs(delay.into()));
         Session {
             addr: protocol
                 .get_ref()
@@ -59,12 +118,26 @@ impl Session {
             config,
             protocol,
             connect_time: Utc::now(),
-            hold_timer: HoldTimer::new(hold_timer),
+            delay_open_until,
+            hold_timer,
             counts: MessageCounts::new(),
             routes: session_rib,
-            capabilities: Capabilities::from_parameters(vec![OpenParameter::Capabilities(
-                capabilities,
-            )]),
+            local_capabilities: capabilities.clone(),
+            received_capabilities: None,
+            received_open: None,
+            capabilities,
+            prefix_limit,
+            prefix_out_limited: false,
+            default_originated: None,
+            initial_dump_sent: false,
+            refreshing: vec![],
+            refresh_in_progress: vec![],
+            pacing,
+            peer_restart_time: None,
+            peer_extended_messages: false,
+            peer_role: None,
+            extended_messages: false,
+            last_notification: None,
         }
     }
 
@@ -82,36 +155,131 @@ impl Session {
     }
 
     pub fn update_state(&mut self, new_state: SessionState) {
-        debug!(
-            "{} went from {} to {}",
-            self.addr,
-            self.state.to_string(),
-            new_state.to_string()
+        with_log_fields(
+            &[
+                ("event", &"state_change"),
+                ("peer", &self.addr),
+                ("from", &self.state),
+                ("to", &new_state),
+            ],
+            || {
+                debug!(
+                    "{} went from {} to {}",
+                    self.addr,
+                    self.state.to_string(),
+                    new_state.to_string()
+                )
+            },
         );
         self.state = new_state;
     }
 
     pub fn update_config(&mut self, new_config: Arc<PeerConfig>) {
         debug!("Peer config for {} (active session) updated", self.addr);
+        self.prefix_limit =
+            PrefixLimit::new(new_config.max_prefixes, new_config.max_prefix_warning);
+        if new_config.max_update_rate != self.config.max_update_rate {
+            self.pacing = new_config.max_update_rate.and_then(UpdatePacing::new);
+        }
         self.config = new_config;
     }
 
+    /// Check the count of prefixes received from this peer against `max_prefixes`
+    /// Logs a warning once `max_prefix_warning` percent is crossed
+    pub fn check_prefix_limit(&mut self, received: usize) -> Result<(), SessionError> {
+        match self.prefix_limit.check(received) {
+            PrefixLimitStatus::Ok => Ok(()),
+            PrefixLimitStatus::Warning(threshold) => {
+                warn!(
+                    "{} has sent {} prefixes (warning threshold {}, max {})",
+                    self.addr,
+                    received,
+                    threshold,
+                    self.config.max_prefixes.unwrap_or_default(),
+                );
+                Ok(())
+            }
+            PrefixLimitStatus::Exceeded(max) => Err(SessionError::MaxPrefixesExceeded(max)),
+        }
+    }
+
+    /// Add default routes to the routes to advertise to this peer (`default_originate`)
+    /// `condition_met` is if the `conditional_on` prefix is in the RIB (or there's no condition),
+    /// otherwise defaults are left out (and withdrawn if already advertised)
+    pub fn originate_default(
+        &mut self,
+        mut routes: Vec<Arc<ExportEntry>>,
+        condition_met: bool,
+    ) -> Vec<Arc<ExportEntry>> {
+        if self.config.default_originate.is_none() || !condition_met {
+            if self.default_originated.take().is_some() {
+                debug!("Withdrawing default routes originated to {}", self.addr);
+            }
+            return routes;
+        }
+        let addr = self.addr;
+        let originated = *self.default_originated.get_or_insert_with(|| {
+            debug!("Originating default routes to {}", addr);
+            Utc::now()
+        });
+        routes.extend(default_routes(&self.config, originated));
+        routes
+    }
+
+    /// Limit the routes to advertise to this peer to `max_prefix_out`
+    /// The oldest routes are kept (so advertised routes aren't replaced), logs a warning once
+    pub fn limit_outbound_routes(
+        &mut self,
+        mut routes: Vec<Arc<ExportEntry>>,
+    ) -> Vec<Arc<ExportEntry>> {
+        let max = match self.config.max_prefix_out {
+            Some(max) if routes.len() > max as usize => max as usize,
+            _ => {
+                self.prefix_out_limited = false;
+                return routes;
+            }
+        };
+        if !self.prefix_out_limited {
+            warn!(
+                "{} has {} prefixes to advertise, only advertising {} (max_prefix_out)",
+                self.addr,
+                routes.len(),
+                max,
+            );
+            self.prefix_out_limited = true;
+        }
+        routes.sort_by_key(|entry| entry.timestamp);
+        routes.truncate(max);
+        routes
+    }
+
     /// Main function for making progress with the session
     /// Waits for either a new incoming message or a HoldTimer event
+    /// (or for the next UPDATE to be sent, if pending routes are paced)
     pub async fn run(&mut self) -> Result<Option<SessionUpdate>, SessionError> {
         if !self.config.enabled {
             // Peer has been disabled, shutdown session
-            return Err(SessionError::Deconfigured);
+            return Err(SessionError::AdministrativeShutdown(
+                self.config.shutdown_message.clone(),
+            ));
         }
-        if self.state == SessionState::Connect && self.is_locally_initiated() {
+        // Time left until our OPEN is sent, while waiting for the peer's OPEN (`delay_open`)
+        let open_delay = self.open_delay();
+        if open_delay == Some(Duration::from_secs(0))
+            || (self.state == SessionState::Connect
+                && self.delay_open_until.is_none()
+                && self.is_locally_initiated())
+        {
             let open = self.create_open();
             if let Err(err) = self.send_message(Message::Open(open)).await {
                 warn!("Error sending OPEN message: {}", err);
             }
             self.update_state(SessionState::OpenSent);
         }
+        let open_delay = open_delay.filter(|delay| !delay.is_zero());
         trace!("Hold time on {}: {}", self.addr, self.hold_timer);
 
+        let mut send_delay = None;
         if self.state == SessionState::Established {
             let mut pending_routes: Vec<_> = self
                 .routes
@@ -126,14 +294,75 @@ impl Session {
                     self.config.advertise_sources.contains(&source)
                 })
                 .collect();
-            if !pending_routes.is_empty() {
-                for entry in pending_routes.drain(..) {
-                    self.send_message(Message::Update(self.create_update(&entry.update)))
+            // Path IDs differ for a replacement path, so it doesn't implicitly withdraw
+            let replacements: Vec<_> = pending_routes
+                .iter()
+                .filter(|entry| !self.sends_path_ids(entry.update.family))
+                .cloned()
+                .collect();
+            for entry in self.routes.take_withdrawn(&replacements) {
+                self.send_message(Message::Update(self.create_withdraw(&entry)))
+                    .await?;
+            }
+            if self.config.initial_batch && !self.initial_dump_sent {
+                send_delay = self.send_initial_dump(pending_routes).await?;
+            } else {
+                for family in std::mem::take(&mut self.refreshing) {
+                    self.send_message(route_refresh_marker(family, BEGIN_OF_RR))
                         .await?;
+                    if !self.refresh_in_progress.contains(&family) {
+                        self.refresh_in_progress.push(family);
+                    }
+                }
+                // Families with routes left pending until the pacing rate allows
+                let mut paced: Vec<Family> = vec![];
+                // Families with routes held until the MRAI has elapsed since their NLRI was
+                // last advertised (and the soonest a held route can be sent)
+                let mut held: Vec<Family> = vec![];
+                let mut held_delay: Option<Duration> = None;
+                let mrai = Duration::from_secs(self.config.mrai.into());
+                let now = Instant::now();
+                let mut sending: Vec<Arc<ExportEntry>> = Vec::with_capacity(pending_routes.len());
+                for entry in pending_routes.drain(..) {
+                    let family = entry.update.family;
+                    if let Some(delay) = self.routes.mrai_delay(&entry, mrai, now) {
+                        if !held.contains(&family) {
+                            held.push(family);
+                        }
+                        held_delay = Some(held_delay.map_or(delay, |held| held.min(delay)));
+                        continue;
+                    }
+                    if let Some(pacing) = self.pacing.as_mut() {
+                        if !pacing.try_send(family) {
+                            if !paced.contains(&family) {
+                                paced.push(family);
+                            }
+                            continue;
+                        }
+                    }
+                    sending.push(entry);
+                }
+                // Routes with common path attributes are packed into shared UPDATEs
+                for (update, otc) in self.create_batched_updates(&sending) {
+                    self.send_announcement(update, otc).await?;
+                }
+                for entry in &sending {
                     // TODO: Store actual advertised routes
                     //       so we can report outgoing updates as advertised
-                    self.routes.mark_advertised(&entry);
+                    self.routes.mark_advertised(entry);
+                }
+                for family in std::mem::take(&mut self.refresh_in_progress) {
+                    if paced.contains(&family) || held.contains(&family) {
+                        self.refresh_in_progress.push(family);
+                    } else {
+                        self.send_message(route_refresh_marker(family, END_OF_RR))
+                            .await?;
+                    }
                 }
+                if let Some(pacing) = self.pacing.as_mut() {
+                    send_delay = paced.into_iter().map(|f| pacing.delay(f)).min();
+                }
+                send_delay = send_delay.into_iter().chain(held_delay).min();
             }
         }
 
@@ -149,16 +378,39 @@ impl Session {
                     }
                     Some(Ok(message)) => {
                         let message_type = get_message_type(&message);
-                        trace!("[{}] Incoming: {}", self.addr, message_type);
-                        self.counts.increment_received();
+                        with_log_fields(
+                            &[("peer", &self.addr), ("message_type", &message_type)],
+                            || trace!("[{}] Incoming: {}", self.addr, message_type),
+                        );
+                        self.counts.increment_received((&message).into());
                         self.hold_timer.received();
+                        let prior_state = self.state;
                         let resp = self.process_message(message)?;
                         match resp {
                             MessageResponse::Reply(message) => {
                                 self.send_message(message).await?;
+                                if prior_state != SessionState::Established
+                                    && self.state == SessionState::Established
+                                {
+                                    return Ok(Some(SessionUpdate::Established((self.addr, self.router_id))));
+                                }
+                            }
+                            MessageResponse::Update((update, otc)) => {
+                                return Ok(Some(SessionUpdate::Learned((
+                                    self.addr,
+                                    self.config.clone(),
+                                    update,
+                                    otc,
+                                ))));
+                            }
+                            MessageResponse::BeginRefresh(family) => {
+                                return Ok(Some(SessionUpdate::BeginRefresh((self.addr, family))));
+                            }
+                            MessageResponse::EndRefresh(family) => {
+                                return Ok(Some(SessionUpdate::EndRefresh((self.addr, family))));
                             }
-                            MessageResponse::Update(update) => {
-                                return Ok(Some(SessionUpdate::Learned((self.addr, update))));
+                            MessageResponse::EndOfRib(family) => {
+                                return Ok(Some(SessionUpdate::EndOfRib((self.addr, family))));
                             }
                             _ => (),
                         }
@@ -166,8 +418,8 @@ impl Session {
                     }
                     // Error decoding message
                     Some(Err(err)) => {
-                        Err(SessionError::Other(format!(
-                            "Session ended with {}: {}",
+                        Err(SessionError::CodecError(format!(
+                            "Error decoding message from {}: {}",
                             self.addr, err
                         )))
                     }
@@ -176,13 +428,39 @@ impl Session {
             // Hold Timer
             keepalive = self.hold_timer.should_send_keepalive() => {
                 match keepalive {
-                    Err(err) => Err(err),
-                    Ok(should_send) => {if should_send {
+                    Err(err) => {
+                        // Let the peer know why the session is closing (RFC 4271 6.5)
+                        if let Err(notify_err) = self.notify(4 /* Hold Timer Expired */, 0).await {
+                            debug!("Error sending NOTIFICATION to {}: {}", self.addr, notify_err);
+                        }
+                        Err(err)
+                    }
+                    // No KEEPALIVEs before our (delayed) OPEN is sent
+                    Ok(should_send) => {if should_send && open_delay.is_none() {
                         self.send_message(Message::KeepAlive).await?;
                     } Ok(None)}
                 }
             },
+            // Paced (or MRAI held) routes are waiting to be sent
+            _ = tokio::time::sleep(send_delay.unwrap_or_default()), if send_delay.is_some() => {
+                Ok(None)
+            },
+            // DelayOpen timer expired, OPEN is sent on the next run
+            _ = tokio::time::sleep(open_delay.unwrap_or_default()), if open_delay.is_some() => {
+                Ok(None)
+            },
+        }
+    }
+
+    /// Time left on the DelayOpen timer (started on connect) before our OPEN is sent,
+    /// if `delay_open` is set and no OPEN has been sent or received yet
+    /// Waits for the peer's OPEN on both locally & remotely initiated connections
+    fn open_delay(&self) -> Option<Duration> {
+        if self.state != SessionState::Connect {
+            return None;
         }
+        self.delay_open_until
+            .map(|until| until.saturating_duration_since(tokio::time::Instant::now()))
     }
 
     pub fn process_message(&mut self, message: Message) -> Result<MessageResponse, SessionError> {
@@ -190,8 +468,11 @@ impl Session {
             Message::Open(open) => {
                 let (capabilities, hold_timer) = self.open_received(open)?;
                 self.routes.families = Families::from(&capabilities.MP_BGP_SUPPORT);
+                self.protocol
+                    .codec_mut()
+                    .set_path_ids(capabilities.EXTENDED_PATH_NLRI_SUPPORT);
                 self.capabilities = capabilities;
-                self.hold_timer = HoldTimer::new(hold_timer);
+                self.hold_timer = HoldTimer::new(hold_timer, self.config.min_keepalive);
                 match &self.state {
                     // Remote initiated, reply with OPEN
                     SessionState::Connect => {
@@ -212,38 +493,248 @@ impl Session {
             Message::KeepAlive => match self.state {
                 SessionState::OpenConfirm => {
                     self.update_state(SessionState::Established);
+                    if self.config.log_capabilities {
+                        info!(
+                            "{} Established with capabilities: {}",
+                            self.addr,
+                            capabilities_summary(&self.capabilities)
+                        );
+                    }
                     MessageResponse::Reply(Message::KeepAlive)
                 }
+                SessionState::Connect | SessionState::OpenSent if self.config.strict_fsm => {
+                    return Err(SessionError::FiniteStateMachine(fsm_err_for_state(
+                        self.state,
+                    )));
+                }
                 _ => MessageResponse::Empty,
             },
-            Message::Update(update) => MessageResponse::Update(update),
+            Message::Update(update) if is_end_of_rib(&update) => {
+                let family = end_of_rib_family(&update);
+                debug!("[{}] Received End-of-RIB for {}", self.addr, family);
+                MessageResponse::EndOfRib(family)
+            }
+            Message::Update(mut update) => {
+                let mut otc = self.protocol.codec().received_otc();
+                if let Some(role) = self.config.role {
+                    if role.is_route_leak(otc, self.config.remote_as) {
+                        debug!(
+                            "[{}] Route leak (OTC {}) received, treating routes as withdrawn",
+                            self.addr,
+                            otc.unwrap_or_default()
+                        );
+                        treat_as_withdraw(&mut update);
+                    }
+                    // Routes from a provider, peer, or route server are marked with
+                    // their AS if they aren't already (RFC 9234 [5])
+                    if otc.is_none() && role.neighbor_is_upstream() {
+                        otc = Some(self.config.remote_as);
+                    }
+                }
+                if self.config.normalize_mapped_nexthop {
+                    normalize_mapped_next_hop(&mut update);
+                }
+                MessageResponse::Update((update, otc))
+            }
             Message::Notification(notification) => {
-                warn!("{} NOTIFICATION: {}", self.addr, notification.to_string());
+                warn!(
+                    "{} NOTIFICATION: {}",
+                    self.addr,
+                    notification_to_string(
+                        notification.major_err_code,
+                        notification.minor_err_code,
+                        &notification.data
+                    )
+                );
+                self.last_notification = Some(NotificationRecord {
+                    sent: false,
+                    major: notification.major_err_code,
+                    minor: notification.minor_err_code,
+                    data: notification.data,
+                });
                 MessageResponse::Empty
             }
-            Message::RouteRefresh(_rr_family) => {
-                // TODO: Mark all advertised routes as pending
+            Message::RouteRefresh(rr) if rr.subtype == BEGIN_OF_RR || rr.subtype == END_OF_RR => {
+                let family = Family::new(rr.afi, rr.safi);
+                let (marker, response) = if rr.subtype == BEGIN_OF_RR {
+                    ("Begin-of-RR", MessageResponse::BeginRefresh(family))
+                } else {
+                    ("End-of-RR", MessageResponse::EndRefresh(family))
+                };
+                if self.capabilities.ENHANCED_ROUTE_REFRESH_SUPPORT {
+                    debug!("[{}] Received {} for {}", self.addr, marker, family);
+                    response
+                } else {
+                    debug!(
+                        "[{}] Ignoring {} for {}: Enhanced Route Refresh not negotiated",
+                        self.addr, marker, family
+                    );
+                    MessageResponse::Empty
+                }
+            }
+            Message::RouteRefresh(rr) => {
+                let family = Family::new(rr.afi, rr.safi);
+                if self
+                    .capabilities
+                    .MP_BGP_SUPPORT
+                    .contains(&(family.afi, family.safi))
+                {
+                    debug!("[{}] Received ROUTE-REFRESH for {}", self.addr, family);
+                    self.queue_refresh_response(family);
+                } else {
+                    warn!(
+                        "[{}] Ignoring ROUTE-REFRESH for {}: Family not negotiated",
+                        self.addr, family
+                    );
+                }
                 MessageResponse::Empty
             }
         };
         Ok(response)
     }
 
+    /// Graceful Restart state if this session ends now (without a NOTIFICATION)
+    /// Only if both sides advertised the capability, and the session was Established
+    pub fn graceful_restart(&self) -> Option<GracefulRestart> {
+        let restart_time = self.peer_restart_time?;
+        if self.last_notification.is_some()
+            || self.state != SessionState::Established
+            || self.capabilities.GRACEFUL_RESTART_SUPPORT.is_empty()
+        {
+            return None;
+        }
+        Some(GracefulRestart {
+            restart_time,
+            families: self
+                .capabilities
+                .GRACEFUL_RESTART_SUPPORT
+                .iter()
+                .map(|(afi, safi)| Family::new(*afi, *safi))
+                .collect(),
+        })
+    }
+
+    /// Re-advertise routes for a family in response to a ROUTE-REFRESH
+    /// If Enhanced Route Refresh is negotiated, the routes are sent between BoRR/EoRR markers
+    /// so the peer can remove any routes that are no longer advertised
+    pub fn queue_refresh_response(&mut self, family: Family) {
+        let requeued = self.routes.requeue_advertised(family);
+        debug!(
+            "[{}] Re-advertising {} {} routes for refresh",
+            self.addr, requeued, family
+        );
+        // BoRR/EoRR markers are ROUTE-REFRESH messages, only sent if the peer
+        // advertised Route Refresh (RFC 2918)
+        if self.capabilities.ENHANCED_ROUTE_REFRESH_SUPPORT
+            && self.capabilities.ROUTE_REFRESH_SUPPORT
+            && !self.refreshing.contains(&family)
+        {
+            self.refreshing.push(family);
+        }
+    }
+
     // Send a message, and flush the send buffer afterwards
     pub async fn send_message(&mut self, message: Message) -> Result<(), io::Error> {
         let message_type = get_message_type(&message);
-        trace!("[{}] Outgoing: {}", self.addr, message_type);
+        with_log_fields(
+            &[("peer", &self.addr), ("message_type", &message_type)],
+            || trace!("[{}] Outgoing: {}", self.addr, message_type),
+        );
+        let tally = MessageTally::from(&message);
         self.protocol.send(message).await?;
-        self.counts.increment_sent();
+        self.counts.increment_sent(tally);
         self.hold_timer.sent();
         Ok(())
     }
 
+    /// Send an UPDATE announcing routes with this Only to Customer (OTC) attribute (if any)
+    pub async fn send_announcement(
+        &mut self,
+        update: Update,
+        otc: Option<u32>,
+    ) -> Result<(), io::Error> {
+        self.protocol.codec_mut().set_only_to_customer(otc);
+        self.send_message(Message::Update(update)).await
+    }
+
+    /// Send the initial routes as batched UPDATEs, followed by End-of-RIB for each family
+    /// With `max_update_rate`, routes over the rate are left pending for the next call and
+    /// End-of-RIB waits until they're all sent (returns the delay until more can be sent)
+    async fn send_initial_dump(
+        &mut self,
+        mut routes: Vec<Arc<ExportEntry>>,
+    ) -> Result<Option<Duration>, io::Error> {
+        // Families with routes left pending until the pacing rate allows
+        let mut paced: Vec<Family> = vec![];
+        if let Some(pacing) = self.pacing.as_mut() {
+            routes.retain(|entry| {
+                let family = entry.update.family;
+                let allowed = pacing.try_send(family);
+                if !allowed && !paced.contains(&family) {
+                    paced.push(family);
+                }
+                allowed
+            });
+        }
+        let updates = self.create_batched_updates(&routes);
+        debug!(
+            "[{}] Sending initial {} routes in {} UPDATEs",
+            self.addr,
+            routes.len(),
+            updates.len()
+        );
+        for (update, otc) in updates {
+            self.send_announcement(update, otc).await?;
+        }
+        for entry in &routes {
+            self.routes.mark_advertised(entry);
+        }
+        if let Some(pacing) = self.pacing.as_mut() {
+            if !paced.is_empty() {
+                return Ok(paced.into_iter().map(|f| pacing.delay(f)).min());
+            }
+        }
+        let families: Vec<Family> = self.routes.families.iter().cloned().collect();
+        for family in families {
+            self.send_message(Message::Update(create_end_of_rib(family)))
+                .await?;
+        }
+        self.initial_dump_sent = true;
+        Ok(None)
+    }
+
     pub async fn notify(&mut self, maj: u8, min: u8) -> Result<(), io::Error> {
+        self.send_notification(maj, min, vec![]).await
+    }
+
+    /// Send a Cease NOTIFICATION with an RFC 9003 Shutdown Communication (reason) in the data
+    /// Only valid for the Administrative Shutdown (2) and Administrative Reset (4) subcodes
+    pub async fn notify_with_message(
+        &mut self,
+        maj: u8,
+        min: u8,
+        message: &str,
+    ) -> Result<(), io::Error> {
+        self.send_notification(maj, min, shutdown_communication(message))
+            .await
+    }
+
+    async fn send_notification(
+        &mut self,
+        maj: u8,
+        min: u8,
+        data: Vec<u8>,
+    ) -> Result<(), io::Error> {
+        self.last_notification = Some(NotificationRecord {
+            sent: true,
+            major: maj,
+            minor: min,
+            data: data.clone(),
+        });
         let notif = Notification {
             major_err_code: maj,
             minor_err_code: min,
-            data: vec![],
+            data,
         };
         self.send_message(Message::Notification(notif)).await
     }
@@ -266,16 +757,38 @@ impl Session {
             self.addr,
             received_open.parameters.len()
         );
+        self.peer_role = received_role(&received_open.parameters);
+        if let Some(role) = self.config.role {
+            check_role(role, &received_open.parameters, self.config.strict_role)
+                .map_err(SessionError::RoleMismatch)?;
+        }
         self.router_id = router_id;
-        let received_capabilities = Capabilities::from_parameters(received_open.parameters);
-        let common_capabilities = common_capabilities(&self.capabilities, &received_capabilities)?;
+        self.received_open = Some(received_open.clone());
+        let graceful_restart = parse_graceful_restart(&received_open.parameters);
+        self.peer_extended_messages = has_capability(&received_open.parameters, EXTENDED_MESSAGE);
+        self.extended_messages = self.config.extended_messages && self.peer_extended_messages;
+        self.protocol
+            .codec_mut()
+            .set_extended_messages(self.extended_messages);
+        let enhanced_route_refresh =
+            has_capability(&received_open.parameters, ENHANCED_ROUTE_REFRESH);
+        let mut received_capabilities = Capabilities::from_parameters(received_open.parameters);
+        received_capabilities.ENHANCED_ROUTE_REFRESH_SUPPORT = enhanced_route_refresh;
+        if let Some((restart_time, families)) = graceful_restart {
+            received_capabilities.GRACEFUL_RESTART_SUPPORT = families.into_iter().collect();
+            self.peer_restart_time = Some(restart_time);
+        }
+        let common_capabilities =
+            common_capabilities(&self.local_capabilities, &received_capabilities)?;
+        self.received_capabilities = Some(received_capabilities);
         Ok((common_capabilities, hold_timer))
     }
 
     pub fn create_open(&self) -> Open {
+        // Resolved to an IPv4 address when the config is loaded
         let router_id = match self.config.local_router_id {
             IpAddr::V4(ipv4) => ipv4,
-            _ => unreachable!(),
+            IpAddr::V6(ipv6) => ipv6.to_ipv4_mapped().unwrap_or(Ipv4Addr::UNSPECIFIED),
         };
         let families: Vec<_> = self
             .config
@@ -287,6 +800,28 @@ impl Session {
             Vec::with_capacity(self.config.families.len() + 1);
         capabilities.extend(families);
         capabilities.push(OpenCapability::FourByteASN(self.config.local_as));
+        capabilities.push(OpenCapability::RouteRefresh);
+        capabilities.extend(add_path_capability(&self.config));
+        if let Some(restart_time) = self.config.graceful_restart {
+            capabilities.push(graceful_restart_capability(
+                restart_time,
+                &self.config.families,
+            ));
+        }
+        capabilities.push(OpenCapability::Unknown {
+            cap_code: ENHANCED_ROUTE_REFRESH,
+            cap_length: 0,
+            value: vec![],
+        });
+        if self.config.extended_messages {
+            capabilities.push(OpenCapability::Unknown {
+                cap_code: EXTENDED_MESSAGE,
+                cap_length: 0,
+                value: vec![],
+            });
+        }
+        capabilities.extend(self.config.role.map(role_capability));
+        order_capabilities(&mut capabilities, &self.config.capability_order);
         let two_byte_asn = if self.config.local_as < 65535 {
             self.config.local_as as u16
         } else {
@@ -298,90 +833,215 @@ impl Session {
             peer_asn: two_byte_asn,
             hold_timer: self.hold_timer.hold_timer,
             identifier: u32::from_be_bytes(router_id.octets()),
-            parameters: vec![OpenParameter::Capabilities(capabilities)],
+            parameters: open_parameters(capabilities, self.config.separate_capabilities),
+        }
+    }
+
+    /// Withdraw a previously advertised route
+    ///   - IPv4 Unicast: NLRI in the UPDATE withdrawn routes
+    ///   - Other families: NLRI in an MP_UNREACH_NLRI attribute for the entry family
+    pub fn create_withdraw(&self, entry: &ExportEntry) -> Update {
+        let update = &entry.update;
+        match (update.family.afi, update.family.safi) {
+            (AFI::IPV4, SAFI::Unicast) => Update {
+                withdrawn_routes: vec![self.export_nlri(entry)],
+                attributes: vec![],
+                announced_routes: vec![],
+            },
+            (afi, safi) => Update {
+                withdrawn_routes: vec![],
+                attributes: vec![PathAttribute::MP_UNREACH_NLRI(MPUnreachNLRI {
+                    afi,
+                    safi,
+                    withdrawn_routes: vec![self.export_nlri(entry)],
+                })],
+                announced_routes: vec![],
+            },
+        }
+    }
+
+    /// Next hop to advertise for an update, this session's local address
+    /// if `next_hop_self` is configured for the update family (or the update has no next hop)
+    fn export_next_hop(&self, update: &ExportedUpdate) -> Option<IpAddr> {
+        if !self.config.next_hop_self.contains(&update.family)
+            && update.attributes.next_hop.is_some()
+        {
+            return match (update.family.afi, update.attributes.next_hop) {
+                // IPv4 routes learned over an IPv6 session (E.g. "::ffff:172.16.20.2")
+                // are advertised with a 4-byte NEXT_HOP
+                (AFI::IPV4, Some(IpAddr::V6(next_hop))) => Some(
+                    next_hop
+                        .to_ipv4_mapped()
+                        .map(IpAddr::V4)
+                        .unwrap_or(IpAddr::V6(next_hop)),
+                ),
+                (_, next_hop) => next_hop,
+            };
+        }
+        let local = match self.protocol.get_ref().local_addr() {
+            Ok(addr) => addr.ip(),
+            Err(_) => return update.attributes.next_hop,
+        };
+        match (update.family.afi, local) {
+            // IPv4 session on a dual-stack socket
+            (AFI::IPV4, IpAddr::V6(local)) => local
+                .to_ipv4_mapped()
+                .map(IpAddr::V4)
+                .or(update.attributes.next_hop),
+            (_, local) => Some(local),
+        }
+    }
+
+    /// Link-local next hop (`link_local_next_hop`) advertised after the global next hop,
+    /// only when the local session address is the advertised next hop (RFC 2545 [3])
+    fn export_link_local_next_hop(&self, update: &ExportedUpdate) -> Option<Ipv6Addr> {
+        let next_hop_self = self.config.next_hop_self.contains(&update.family)
+            || update.attributes.next_hop.is_none();
+        self.config.link_local_next_hop.filter(|_| next_hop_self)
+    }
+
+    /// Is ADD-PATH (RFC 7911) send negotiated for a family (NLRI are advertised with a path ID)
+    fn sends_path_ids(&self, family: Family) -> bool {
+        matches!(
+            self.capabilities
+                .ADD_PATH_SUPPORT
+                .get(&(family.afi, family.safi)),
+            Some(AddPathDirection::SendPaths | AddPathDirection::SendReceivePaths)
+        )
+    }
+
+    /// NLRI to advertise (or withdraw) for an entry, with a path ID if ADD-PATH send is negotiated
+    fn export_nlri(&self, entry: &ExportEntry) -> NLRIEncoding {
+        let prefix = match &entry.update.nlri {
+            NLRIEncoding::IP(prefix) | NLRIEncoding::IP_WITH_PATH_ID((prefix, _)) => prefix.clone(),
+            nlri => return nlri.clone(),
+        };
+        if self.sends_path_ids(entry.update.family) {
+            NLRIEncoding::IP_WITH_PATH_ID((prefix, path_id(entry)))
+        } else {
+            NLRIEncoding::IP(prefix)
         }
     }
 
+    /// UPDATE advertising an entry (with a path ID if ADD-PATH send is negotiated)
+    pub fn create_path_update(&self, entry: &ExportEntry) -> Update {
+        with_nlri(
+            &self.create_update(&entry.update),
+            vec![self.export_nlri(entry)],
+        )
+    }
+
     pub fn create_update(&self, update: &ExportedUpdate) -> Update {
         let mut attributes: Vec<PathAttribute> = Vec::with_capacity(4);
         // Well-known, Mandatory Attributes
         attributes.push(PathAttribute::ORIGIN(update.attributes.origin.clone()));
-        if let ((AFI::IPV4, SAFI::Unicast), Some(next_hop)) =
-            ((&update.family).into(), update.attributes.next_hop)
-        {
+        let next_hop = self.export_next_hop(update);
+        if let ((AFI::IPV4, SAFI::Unicast), Some(next_hop)) = ((&update.family).into(), next_hop) {
             attributes.push(PathAttribute::NEXT_HOP(next_hop));
         }
         attributes.push(PathAttribute::LOCAL_PREF(
             update.attributes.local_pref.unwrap_or(100),
         ));
 
-        let mut as_path = update.attributes.as_path.clone();
-        if self.config.is_ebgp() {
-            if as_path.segments.is_empty() {
-                as_path
-                    .segments
-                    .push(Segment::AS_SEQUENCE(vec![self.config.local_as]));
-            } else {
-                // TODO: Support multiple segments?
-                let segment = match &as_path.segments[0] {
-                    Segment::AS_SEQUENCE(seq) => {
-                        let mut seg = seq.clone();
-                        seg.insert(0, self.config.local_as);
-                        Segment::AS_SEQUENCE(seg)
-                    }
-                    Segment::AS_SET(set) => {
-                        let mut seg = set.clone();
-                        seg.insert(0, self.config.local_as);
-                        Segment::AS_SET(seg)
-                    }
-                };
-                as_path = ASPath {
-                    segments: vec![segment],
-                };
-            }
-        }
+        let as_path = if self.config.is_ebgp() {
+            let count = self
+                .config
+                .as_path_prepend
+                .unwrap_or(1)
+                .min(MAX_AS_PATH_PREPEND);
+            prepend_as_path(&update.attributes.as_path, self.config.local_as, count)
+        } else {
+            update.attributes.as_path.clone()
+        };
         attributes.push(PathAttribute::AS_PATH(as_path));
 
         // Optional Attributes
-        if let Some(med) = update.attributes.multi_exit_disc {
+        if let Some(med) = self.config.med_out.or(update.attributes.multi_exit_disc) {
             attributes.push(PathAttribute::MULTI_EXIT_DISC(med));
         }
+        // ORIGINATOR_ID is non-transitive & only sent to iBGP peers
+        if let (Some(IpAddr::V4(originator_id)), false) =
+            (update.attributes.originator_id, self.config.is_ebgp())
+        {
+            attributes.push(PathAttribute::ORIGINATOR_ID(originator_id.into()));
+        }
+        // CLUSTER_LIST is also non-transitive, re-advertised unchanged to iBGP peers
+        if !update.attributes.cluster_list.is_empty() && !self.config.is_ebgp() {
+            let cluster_list = update.attributes.cluster_list.iter();
+            attributes.push(PathAttribute::CLUSTER_LIST(
+                cluster_list.map(|id| u32::from(*id)).collect(),
+            ));
+        }
+
+        if update.attributes.atomic_aggregate {
+            attributes.push(PathAttribute::ATOMIC_AGGREGATOR);
+        }
+        if let Some(aggregator) = update.attributes.aggregator {
+            attributes.push(PathAttribute::AGGREGATOR(aggregator));
+        }
 
         let standard_communities = update.attributes.communities.standard();
         if !standard_communities.is_empty() {
             attributes.push(PathAttribute::COMMUNITY(standard_communities));
         }
-        let extd_communities = update.attributes.communities.extended();
+        let mut extd_communities = update.attributes.communities.extended();
+        let mut ipv6_extd_communities = update.attributes.communities.ipv6_extended();
+        for route_target in &self.config.export_rts {
+            match *route_target {
+                Community::EXTENDED(rt) if !extd_communities.contains(&rt) => {
+                    extd_communities.push(rt)
+                }
+                Community::IPV6_EXTENDED(rt) if !ipv6_extd_communities.contains(&rt) => {
+                    ipv6_extd_communities.push(rt)
+                }
+                _ => (),
+            }
+        }
         if !extd_communities.is_empty() {
             attributes.push(PathAttribute::EXTENDED_COMMUNITIES(extd_communities));
         }
+        let large_communities = update.attributes.communities.large();
+        if !large_communities.is_empty() {
+            attributes.push(PathAttribute::LARGE_COMMUNITY(large_communities));
+        }
+        if let Some(community) = ipv6_extd_communities.first() {
+            attributes.push(PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(*community));
+        }
         let mut to_send = Update {
             withdrawn_routes: Vec::new(),
             attributes,
             announced_routes: Vec::with_capacity(1),
         };
         match &update.nlri {
-            NLRIEncoding::IP(prefix) => match &prefix.protocol {
-                AFI::IPV4 => to_send
-                    .announced_routes
-                    .push(NLRIEncoding::IP(prefix.clone())),
-                AFI::IPV6 => {
-                    let next_hop = match update.attributes.next_hop {
-                        Some(IpAddr::V6(nh)) => nh.octets().to_vec(),
-                        _ => unreachable!(),
-                    };
-                    let mp_nlri = MPReachNLRI {
-                        afi: AFI::IPV6,
-                        safi: update.family.safi,
-                        next_hop,
-                        announced_routes: vec![NLRIEncoding::IP(prefix.clone())],
-                    };
-                    to_send
-                        .attributes
-                        .push(PathAttribute::MP_REACH_NLRI(mp_nlri));
+            // Path IDs are only meaningful to the peer a path was learned from
+            NLRIEncoding::IP(prefix) | NLRIEncoding::IP_WITH_PATH_ID((prefix, _)) => {
+                match &prefix.protocol {
+                    AFI::IPV4 => to_send
+                        .announced_routes
+                        .push(NLRIEncoding::IP(prefix.clone())),
+                    AFI::IPV6 => {
+                        let mut next_hop = match next_hop {
+                            Some(IpAddr::V6(nh)) => nh.octets().to_vec(),
+                            // Learned with a normalized (IPv4-mapped) next hop
+                            Some(IpAddr::V4(nh)) => nh.to_ipv6_mapped().octets().to_vec(),
+                            _ => unreachable!(),
+                        };
+                        if let Some(link_local) = self.export_link_local_next_hop(update) {
+                            next_hop.extend_from_slice(&link_local.octets());
+                        }
+                        let mp_nlri = MPReachNLRI {
+                            afi: AFI::IPV6,
+                            safi: update.family.safi,
+                            next_hop,
+                            announced_routes: vec![NLRIEncoding::IP(prefix.clone())],
+                        };
+                        to_send
+                            .attributes
+                            .push(PathAttribute::MP_REACH_NLRI(mp_nlri));
+                    }
+                    _ => unimplemented!(),
                 }
-                _ => unimplemented!(),
-            },
+            }
             NLRIEncoding::FLOWSPEC(flowspec) => {
                 let mp_nlri = MPReachNLRI {
                     afi: update.family.afi,
@@ -399,6 +1059,156 @@ impl Session {
     }
 }
 
+impl Session {
+    /// Group routes with common path attributes into as few UPDATEs as possible
+    /// (without exceeding the maximum message size), with the OTC attribute to send them with
+    pub fn create_batched_updates(
+        &self,
+        entries: &[Arc<ExportEntry>],
+    ) -> Vec<(Update, Option<u32>)> {
+        // Updates without NLRI, keyed by encoded attributes (in order of first appearance)
+        let mut groups: Vec<(Update, Option<u32>, Vec<NLRIEncoding>)> = vec![];
+        let mut group_index: HashMap<(Vec<u8>, Option<u32>), usize> = HashMap::new();
+        for entry in entries {
+            let otc = self.export_otc(entry);
+            let mut update = self.create_path_update(entry);
+            let mut nlri = std::mem::take(&mut update.announced_routes);
+            for attribute in update.attributes.iter_mut() {
+                if let PathAttribute::MP_REACH_NLRI(mp_nlri) = attribute {
+                    nlri.append(&mut mp_nlri.announced_routes);
+                }
+            }
+            let mut key: Vec<u8> = Vec::with_capacity(64);
+            for attribute in &update.attributes {
+                encode_attribute(attribute, &mut key).expect("Can encode PathAttribute");
+            }
+            match group_index.get(&(key.clone(), otc)) {
+                Some(index) => groups[*index].2.extend(nlri),
+                None => {
+                    group_index.insert((key, otc), groups.len());
+                    groups.push((update, otc, nlri));
+                }
+            }
+        }
+
+        let mut updates: Vec<(Update, Option<u32>)> = Vec::with_capacity(groups.len());
+        for (base, otc, nlri) in groups {
+            let mut base_size: Vec<u8> = Vec::with_capacity(64);
+            encode_update(&base, &mut base_size).expect("Can encode Update");
+            let otc_size = if otc.is_some() {
+                ONLY_TO_CUSTOMER_SIZE
+            } else {
+                0
+            };
+            // Leave room for the attribute growing to extended length
+            let available = self.protocol.codec().max_message_size()
+                - HEADER_SIZE
+                - base_size.len()
+                - otc_size
+                - 2;
+            let mut batch: Vec<NLRIEncoding> = vec![];
+            let mut batch_size = 0usize;
+            for route in nlri {
+                let mut encoded: Vec<u8> = Vec::with_capacity(8);
+                route.encode(&mut encoded).expect("Can encode NLRI");
+                if !batch.is_empty() && batch_size + encoded.len() > available {
+                    updates.push((with_nlri(&base, std::mem::take(&mut batch)), otc));
+                    batch_size = 0;
+                }
+                batch_size += encoded.len();
+                batch.push(route);
+            }
+            if !batch.is_empty() {
+                updates.push((with_nlri(&base, batch), otc));
+            }
+        }
+        updates
+    }
+
+    /// OTC attribute (BGP Role, RFC 9234) sent with a route: the OTC it was learned with
+    /// (kept across iBGP), or the local AS for routes advertised to a customer, peer,
+    /// or route server client (RFC 9234 [5])
+    fn export_otc(&self, entry: &ExportEntry) -> Option<u32> {
+        entry.only_to_customer.or_else(|| {
+            self.config
+                .role
+                .and_then(|role| role.only_to_customer(self.config.local_as))
+        })
+    }
+}
+
+/// Treat the routes announced in an UPDATE as withdrawn (RFC 7606)
+fn treat_as_withdraw(update: &mut Update) {
+    let announced = std::mem::take(&mut update.announced_routes);
+    update.withdrawn_routes.extend(announced);
+    update.attributes = std::mem::take(&mut update.attributes)
+        .into_iter()
+        .filter_map(|attribute| match attribute {
+            PathAttribute::MP_REACH_NLRI(nlri) => {
+                Some(PathAttribute::MP_UNREACH_NLRI(MPUnreachNLRI {
+                    afi: nlri.afi,
+                    safi: nlri.safi,
+                    withdrawn_routes: nlri.announced_routes,
+                }))
+            }
+            attribute @ PathAttribute::MP_UNREACH_NLRI(_) => Some(attribute),
+            _ => None,
+        })
+        .collect();
+}
+
+/// Add NLRI to an UPDATE (in MP_REACH_NLRI if present)
+fn with_nlri(base: &Update, nlri: Vec<NLRIEncoding>) -> Update {
+    let mut update = base.clone();
+    match update.attributes.iter_mut().find_map(|attr| match attr {
+        PathAttribute::MP_REACH_NLRI(mp_nlri) => Some(mp_nlri),
+        _ => None,
+    }) {
+        Some(mp_nlri) => mp_nlri.announced_routes = nlri,
+        None => update.announced_routes = nlri,
+    }
+    update
+}
+
+/// End-of-RIB marker for a family (RFC 4724 [2])
+///   - IPv4 Unicast: An UPDATE with no withdrawn routes, attributes, or NLRI
+///   - Other families: An UPDATE with an empty MP_UNREACH_NLRI
+fn create_end_of_rib(family: Family) -> Update {
+    let attributes = match (family.afi, family.safi) {
+        (AFI::IPV4, SAFI::Unicast) => vec![],
+        (afi, safi) => vec![PathAttribute::MP_UNREACH_NLRI(MPUnreachNLRI {
+            afi,
+            safi,
+            withdrawn_routes: vec![],
+        })],
+    };
+    Update {
+        withdrawn_routes: vec![],
+        attributes,
+        announced_routes: vec![],
+    }
+}
+
+/// Family of an End-of-RIB marker (IPv4 Unicast is an empty UPDATE)
+fn end_of_rib_family(update: &Update) -> Family {
+    match update.attributes.as_slice() {
+        [PathAttribute::MP_UNREACH_NLRI(nlri)] => Family::new(nlri.afi, nlri.safi),
+        _ => Family::new(AFI::IPV4, SAFI::Unicast),
+    }
+}
+
+/// Is this UPDATE an End-of-RIB marker
+fn is_end_of_rib(update: &Update) -> bool {
+    if !update.withdrawn_routes.is_empty() || !update.announced_routes.is_empty() {
+        return false;
+    }
+    match update.attributes.as_slice() {
+        [] => true,
+        [PathAttribute::MP_UNREACH_NLRI(nlri)] => nlri.withdrawn_routes.is_empty(),
+        _ => false,
+    }
+}
+
 impl fmt::Display for Session {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -415,60 +1225,300 @@ impl fmt::Display for Session {
 pub enum MessageResponse {
     Open((Open, Vec<OpenCapability>, u16)),
     Reply(Message),
-    Update(Update),
+    // Received UPDATE, with its OTC attribute (BGP Role, RFC 9234)
+    Update((Update, Option<u32>)),
+    BeginRefresh(Family),
+    EndRefresh(Family),
+    EndOfRib(Family),
     Empty,
 }
 
-fn fsm_err_for_state(state: SessionState) -> u8 {
-    use SessionState::*;
-    match state {
-        OpenSent => 1,
-        OpenConfirm => 2,
-        Established => 3,
-        _ => 0,
+/// Extended Message capability code (RFC 8654)
+const EXTENDED_MESSAGE: u8 = 6;
+/// Enhanced Route Refresh capability code (RFC 7313)
+const ENHANCED_ROUTE_REFRESH: u8 = 70;
+/// Graceful Restart capability code (RFC 4724)
+const GRACEFUL_RESTART: u8 = 64;
+/// Restart time is a 12-bit field
+const MAX_RESTART_TIME: u16 = 0x0FFF;
+
+/// ADD-PATH (RFC 7911) capability (if `add_path` is configured), to send & receive
+/// multiple paths for each of the peer's unicast & multicast families
+fn add_path_capability(config: &PeerConfig) -> Option<OpenCapability> {
+    let families: Vec<_> = config
+        .families
+        .iter()
+        .filter(|family| matches!(family.safi, SAFI::Unicast | SAFI::Multicast))
+        .map(|family| (family.afi, family.safi, AddPathDirection::SendReceivePaths))
+        .collect();
+    if !config.add_path || families.is_empty() {
+        return None;
     }
+    Some(OpenCapability::AddPath(families))
 }
 
-/// Check 4-byte ASN first, fallback to 2-byte
-fn asn_from_open(open: &Open) -> u32 {
-    open.parameters
+/// Negotiated ADD-PATH direction (for the local side), paths are sent if the local side
+/// can send & the remote side can receive (and received for the reverse)
+fn add_path_direction(
+    local: AddPathDirection,
+    remote: AddPathDirection,
+) -> Option<AddPathDirection> {
+    use AddPathDirection::*;
+    let send = matches!(local, SendPaths | SendReceivePaths)
+        && matches!(remote, ReceivePaths | SendReceivePaths);
+    let receive = matches!(local, ReceivePaths | SendReceivePaths)
+        && matches!(remote, SendPaths | SendReceivePaths);
+    match (send, receive) {
+        (true, true) => Some(SendReceivePaths),
+        (true, false) => Some(SendPaths),
+        (false, true) => Some(ReceivePaths),
+        (false, false) => None,
+    }
+}
+
+/// ADD-PATH path ID advertised for an entry, stable while the entry is exported
+/// Entries are identified by their (unique) timestamp, so paths for a prefix have distinct IDs
+fn path_id(entry: &ExportEntry) -> u32 {
+    let nanos = entry.timestamp.timestamp_nanos() as u64;
+    (nanos ^ (nanos >> 32)) as u32
+}
+
+/// Graceful Restart capability, advertising support for the given families
+/// Forwarding state is never preserved (BGPd doesn't keep routes across its own restarts)
+fn graceful_restart_capability(restart_time: u16, families: &[Family]) -> OpenCapability {
+    let mut value = Vec::with_capacity(2 + families.len() * 4);
+    value.extend_from_slice(&restart_time.min(MAX_RESTART_TIME).to_be_bytes());
+    for family in families {
+        value.extend_from_slice(&(family.afi as u16).to_be_bytes());
+        value.push(family.safi as u8);
+        value.push(0); // Flags
+    }
+    OpenCapability::Unknown {
+        cap_code: GRACEFUL_RESTART,
+        cap_length: value.len() as u8,
+        value,
+    }
+}
+
+/// Was a capability (not decoded by bgp-rs) advertised in an OPEN
+fn has_capability(parameters: &[OpenParameter], code: u8) -> bool {
+    parameters.iter().any(|p| match p {
+        OpenParameter::Capabilities(caps) => caps
+            .iter()
+            .any(|c| matches!(c, OpenCapability::Unknown { cap_code, .. } if *cap_code == code)),
+        _ => false,
+    })
+}
+
+/// Decode a received Graceful Restart capability (restart time, families)
+fn parse_graceful_restart(parameters: &[OpenParameter]) -> Option<(u16, Vec<(AFI, SAFI)>)> {
+    let value = parameters
         .iter()
         .flat_map(|p| match p {
-            OpenParameter::Capabilities(caps) => caps.clone(),
+            OpenParameter::Capabilities(caps) => caps.iter().collect(),
             _ => vec![],
         })
-        .map(|c| match c {
-            OpenCapability::FourByteASN(asn) => Some(asn),
+        .find_map(|c| match c {
+            OpenCapability::Unknown {
+                cap_code, value, ..
+            } if *cap_code == GRACEFUL_RESTART => Some(value),
             _ => None,
+        })?;
+    if value.len() < 2 {
+        return None;
+    }
+    let restart_time = u16::from_be_bytes([value[0], value[1]]) & MAX_RESTART_TIME;
+    let families = value[2..]
+        .chunks_exact(4)
+        .filter_map(|chunk| {
+            let afi = AFI::try_from(u16::from_be_bytes([chunk[0], chunk[1]])).ok()?;
+            let safi = SAFI::try_from(chunk[2]).ok()?;
+            Some((afi, safi))
         })
-        .find(|c| c.is_some())
-        .unwrap_or_else(|| Some(u32::from(open.peer_asn)))
-        .unwrap()
+        .collect();
+    Some((restart_time, families))
 }
 
-/// Work out the common set of capabilities between peer config and the received peer's capabilities
-pub fn common_capabilities(
-    a: &Capabilities,
-    b: &Capabilities,
-) -> Result<Capabilities, SessionError> {
-    // And (manually) build an intersection between the two
-    let mut negotiated = Capabilities::default();
+/// Cap for `as_path_prepend`, to guard against absurdly long AS_PATHs
+const MAX_AS_PATH_PREPEND: u8 = 16;
 
-    negotiated.MP_BGP_SUPPORT = a
-        .MP_BGP_SUPPORT
-        .intersection(&b.MP_BGP_SUPPORT)
-        .copied()
-        .collect();
-    negotiated.ROUTE_REFRESH_SUPPORT = a.ROUTE_REFRESH_SUPPORT & b.ROUTE_REFRESH_SUPPORT;
-    negotiated.OUTBOUND_ROUTE_FILTERING_SUPPORT = a
-        .OUTBOUND_ROUTE_FILTERING_SUPPORT
-        .intersection(&b.OUTBOUND_ROUTE_FILTERING_SUPPORT)
-        .copied()
-        .collect();
+/// Prepend an ASN `count` times to the leading AS_SEQUENCE
+/// (or a new AS_SEQUENCE if the path is empty or starts with an AS_SET)
+fn prepend_as_path(as_path: &ASPath, asn: u32, count: u8) -> ASPath {
+    let mut segments = as_path.segments.clone();
+    if count == 0 {
+        return ASPath { segments };
+    }
+    let prepended = std::iter::repeat(asn).take(count.into());
+    match segments.first_mut() {
+        Some(Segment::AS_SEQUENCE(seq)) => {
+            seq.splice(0..0, prepended);
+        }
+        _ => segments.insert(0, Segment::AS_SEQUENCE(prepended.collect())),
+    }
+    ASPath { segments }
+}
 
-    // Attempt at a HashMap intersection. We can be a bit lax here because this isn't a real BGP implementation
-    // so we can not care too much about the values for now.
-    negotiated.EXTENDED_NEXT_HOP_ENCODING = a
+/// Maximum length of a Shutdown Communication (RFC 9003)
+const MAX_SHUTDOWN_COMMUNICATION_LEN: usize = 255;
+
+/// Encode a Shutdown Communication (RFC 9003): Length (1 byte) followed by the UTF-8 message
+/// Messages longer than 255 bytes are truncated (at a character boundary)
+fn shutdown_communication(message: &str) -> Vec<u8> {
+    let mut end = message.len().min(MAX_SHUTDOWN_COMMUNICATION_LEN);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut data = Vec::with_capacity(end + 1);
+    data.push(end as u8);
+    data.extend_from_slice(&message.as_bytes()[..end]);
+    data
+}
+
+/// Enhanced Route Refresh message subtypes (RFC 7313)
+const BEGIN_OF_RR: u8 = 1;
+const END_OF_RR: u8 = 2;
+
+/// Begin-of-RR or End-of-RR marker for a family
+fn route_refresh_marker(family: Family, subtype: u8) -> Message {
+    Message::RouteRefresh(RouteRefresh {
+        afi: family.afi,
+        safi: family.safi,
+        subtype,
+    })
+}
+
+/// Convert IPv4-mapped IPv6 next hops (E.g. "::ffff:10.0.0.1") to IPv4
+fn normalize_mapped_next_hop(update: &mut Update) {
+    for attribute in update.attributes.iter_mut() {
+        match attribute {
+            PathAttribute::NEXT_HOP(IpAddr::V6(next_hop)) => {
+                if let Some(mapped) = next_hop.to_ipv4_mapped() {
+                    *attribute = PathAttribute::NEXT_HOP(IpAddr::V4(mapped));
+                }
+            }
+            PathAttribute::MP_REACH_NLRI(nlri) if nlri.next_hop.len() >= 16 => {
+                let mut octets = [0u8; 16];
+                octets.clone_from_slice(&nlri.next_hop[..16]);
+                if let Some(mapped) = Ipv6Addr::from(octets).to_ipv4_mapped() {
+                    nlri.next_hop = mapped.octets().to_vec();
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn fsm_err_for_state(state: SessionState) -> u8 {
+    use SessionState::*;
+    match state {
+        OpenSent => 1,
+        OpenConfirm => 2,
+        Established => 3,
+        _ => 0,
+    }
+}
+
+/// Capabilities that can be listed in a peer's `capability_order`, with their codes
+const CAPABILITY_NAMES: [(&str, u8); 8] = [
+    ("multiprotocol", 1),
+    ("route_refresh", 2),
+    ("four_octet_asn", 65),
+    ("add_path", 69),
+    ("graceful_restart", 64),
+    ("extended_message", EXTENDED_MESSAGE),
+    ("enhanced_route_refresh", ENHANCED_ROUTE_REFRESH),
+    ("role", 9),
+];
+
+/// Capability code for a `capability_order` name
+pub(crate) fn capability_code(name: &str) -> Result<u8, String> {
+    CAPABILITY_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, code)| *code)
+        .ok_or_else(|| {
+            let names: Vec<_> = CAPABILITY_NAMES.iter().map(|(n, _)| *n).collect();
+            format!(
+                "Unknown capability '{}', expected one of: {}",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+fn open_capability_code(capability: &OpenCapability) -> u8 {
+    match capability {
+        OpenCapability::MultiProtocol(_) => 1,
+        OpenCapability::RouteRefresh => 2,
+        OpenCapability::OutboundRouteFiltering(_) => 3,
+        OpenCapability::FourByteASN(_) => 65,
+        OpenCapability::AddPath(_) => 69,
+        OpenCapability::Unknown { cap_code, .. } => *cap_code,
+    }
+}
+
+/// Sort capabilities by code in `order`, unlisted capabilities follow in their default order
+fn order_capabilities(capabilities: &mut [OpenCapability], order: &[u8]) {
+    capabilities.sort_by_key(|capability| {
+        let code = open_capability_code(capability);
+        order.iter().position(|c| *c == code).unwrap_or(order.len())
+    });
+}
+
+/// Bundle capabilities into OPEN Optional Parameters
+/// Either a single combined Capabilities parameter, or one parameter per capability
+fn open_parameters(capabilities: Vec<OpenCapability>, separate: bool) -> Vec<OpenParameter> {
+    if separate {
+        capabilities
+            .into_iter()
+            .map(|c| OpenParameter::Capabilities(vec![c]))
+            .collect()
+    } else {
+        vec![OpenParameter::Capabilities(capabilities)]
+    }
+}
+
+/// Check 4-byte ASN first, fallback to 2-byte
+fn asn_from_open(open: &Open) -> u32 {
+    open.parameters
+        .iter()
+        .flat_map(|p| match p {
+            OpenParameter::Capabilities(caps) => caps.clone(),
+            _ => vec![],
+        })
+        .map(|c| match c {
+            OpenCapability::FourByteASN(asn) => Some(asn),
+            _ => None,
+        })
+        .find(|c| c.is_some())
+        .unwrap_or_else(|| Some(u32::from(open.peer_asn)))
+        .unwrap()
+}
+
+/// Work out the common set of capabilities between peer config and the received peer's capabilities
+pub fn common_capabilities(
+    a: &Capabilities,
+    b: &Capabilities,
+) -> Result<Capabilities, SessionError> {
+    // And (manually) build an intersection between the two
+    let mut negotiated = Capabilities::default();
+
+    negotiated.MP_BGP_SUPPORT = a
+        .MP_BGP_SUPPORT
+        .intersection(&b.MP_BGP_SUPPORT)
+        .copied()
+        .collect();
+    negotiated.ROUTE_REFRESH_SUPPORT = a.ROUTE_REFRESH_SUPPORT & b.ROUTE_REFRESH_SUPPORT;
+    negotiated.OUTBOUND_ROUTE_FILTERING_SUPPORT = a
+        .OUTBOUND_ROUTE_FILTERING_SUPPORT
+        .intersection(&b.OUTBOUND_ROUTE_FILTERING_SUPPORT)
+        .copied()
+        .collect();
+
+    // Attempt at a HashMap intersection. We can be a bit lax here because this isn't a real BGP implementation
+    // so we can not care too much about the values for now.
+    negotiated.EXTENDED_NEXT_HOP_ENCODING = a
         .EXTENDED_NEXT_HOP_ENCODING
         .iter()
         // .filter(|((afi, safi), _)| b.EXTENDED_NEXT_HOP_ENCODING.contains_key(&(*afi, *safi)))
@@ -494,10 +1544,18 @@ pub fn common_capabilities(
     negotiated.ADD_PATH_SUPPORT = a
         .ADD_PATH_SUPPORT
         .iter()
-        .filter(|((afi, safi), _)| b.ADD_PATH_SUPPORT.contains_key(&(*afi, *safi)))
-        .map(|((afi, safi), val)| ((*afi, *safi), *val))
+        .filter_map(|(family, local)| {
+            let remote = b.ADD_PATH_SUPPORT.get(family)?;
+            add_path_direction(*local, *remote).map(|direction| (*family, direction))
+        })
         .collect();
-    negotiated.EXTENDED_PATH_NLRI_SUPPORT = !negotiated.ADD_PATH_SUPPORT.is_empty();
+    // Received NLRI are decoded with path IDs
+    negotiated.EXTENDED_PATH_NLRI_SUPPORT = negotiated.ADD_PATH_SUPPORT.values().any(|direction| {
+        matches!(
+            direction,
+            AddPathDirection::ReceivePaths | AddPathDirection::SendReceivePaths
+        )
+    });
 
     negotiated.ENHANCED_ROUTE_REFRESH_SUPPORT =
         a.ENHANCED_ROUTE_REFRESH_SUPPORT & b.ENHANCED_ROUTE_REFRESH_SUPPORT;
@@ -506,3 +1564,1847 @@ pub fn common_capabilities(
 
     Ok(negotiated)
 }
+
+/// Summarize negotiated capabilities for logging, E.g.:
+///   families=[IPv4 Unicast, IPv6 Unicast] route_refresh=true add_path=[] graceful_restart=[] four_octet_asn=true
+fn capabilities_summary(capabilities: &Capabilities) -> String {
+    fn families<'a>(families: impl Iterator<Item = &'a (AFI, SAFI)>) -> String {
+        let mut families: Vec<String> = families
+            .map(|(afi, safi)| Family::new(*afi, *safi).to_string())
+            .collect();
+        families.sort();
+        format!("[{}]", families.join(", "))
+    }
+    format!(
+        "families={} route_refresh={} add_path={} graceful_restart={} four_octet_asn={}",
+        families(capabilities.MP_BGP_SUPPORT.iter()),
+        capabilities.ROUTE_REFRESH_SUPPORT,
+        families(capabilities.ADD_PATH_SUPPORT.keys()),
+        families(capabilities.GRACEFUL_RESTART_SUPPORT.iter()),
+        capabilities.FOUR_OCTET_ASN_SUPPORT,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::rpc::RouteSpec;
+    use crate::rib::{PathAttributeGroup, PathAttributes};
+    use crate::utils::parse_route_spec;
+    use ipnetwork::IpNetwork;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::super::codec::MessageCodec;
+
+    thread_local! {
+        static CAPTURED_LOGS: std::cell::RefCell<Vec<String>> = Default::default();
+    }
+
+    /// Logger that records messages logged on the current thread
+    struct CaptureLogger;
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Info
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                let message = record.args().to_string();
+                CAPTURED_LOGS.with(|logs| logs.borrow_mut().push(message));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Run `f` and return the messages it logged (at Info or above)
+    fn capture_logs<F: FnOnce()>(f: F) -> Vec<String> {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Info);
+        });
+        CAPTURED_LOGS.with(|logs| logs.borrow_mut().clear());
+        f();
+        CAPTURED_LOGS.with(|logs| logs.borrow_mut().drain(..).collect())
+    }
+
+    /// Start a Session for the first peer in `config`, connected to a local TCP stream
+    /// Returns the session & the remote (peer) side of the connection
+    async fn session_for(config: &str) -> (Session, MessageProtocol) {
+        let config = crate::config::from_str(config).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (remote, local) = tokio::join!(
+            TcpStream::connect(listener.local_addr().unwrap()),
+            listener.accept()
+        );
+        let session = Session::new(
+            config.peers[0].clone(),
+            MessageProtocol::new(local.unwrap().0, MessageCodec::new()),
+        );
+        (
+            session,
+            MessageProtocol::new(remote.unwrap(), MessageCodec::new()),
+        )
+    }
+
+    /// Establish `session` with an OPEN from a `REMOTE_CONFIG` peer, without the
+    /// capabilities with these codes
+    async fn establish(session: &mut Session, without: &[u8]) {
+        let (peer, _) = session_for(REMOTE_CONFIG).await;
+        let mut open = peer.create_open();
+        for param in open.parameters.iter_mut() {
+            if let OpenParameter::Capabilities(caps) = param {
+                caps.retain(|cap| !without.contains(&open_capability_code(cap)));
+            }
+        }
+        session.process_message(Message::Open(open)).unwrap();
+        session.process_message(Message::KeepAlive).unwrap();
+        assert_eq!(session.state, SessionState::Established);
+    }
+
+    fn peer_config(remote_as: u32) -> String {
+        format!(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "127.0.0.1"
+            remote_as = {}
+            "#,
+            remote_as
+        )
+    }
+
+    fn exported(spec: &RouteSpec) -> ExportedUpdate {
+        let (family, attributes, nlri) = parse_route_spec(spec).unwrap();
+        let group = PathAttributeGroup::from_attributes(attributes);
+        ExportedUpdate {
+            family,
+            attributes: Arc::new(PathAttributes::from_group(&group)),
+            nlri,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_originator_id() {
+        let mut spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        spec.attributes.originator_id = Some("2.2.2.2".parse().unwrap());
+        let (family, mut attributes, nlri) = parse_route_spec(&spec).unwrap();
+        let cluster_id = std::net::Ipv4Addr::new(3, 3, 3, 3);
+        attributes.push(PathAttribute::CLUSTER_LIST(vec![cluster_id.into()]));
+        let group = PathAttributeGroup::from_attributes(attributes);
+        let update = ExportedUpdate {
+            family,
+            attributes: Arc::new(PathAttributes::from_group(&group)),
+            nlri,
+        };
+        assert_eq!(
+            update.attributes.originator_id,
+            spec.attributes.originator_id
+        );
+        assert_eq!(update.attributes.cluster_list, vec![cluster_id]);
+
+        // Preserved when advertised to iBGP peers
+        let (ibgp, _remote) = session_for(&peer_config(65000)).await;
+        let sent = ibgp.create_update(&update);
+        match sent.get(bgp_rs::Identifier::ORIGINATOR_ID) {
+            Some(PathAttribute::ORIGINATOR_ID(id)) => {
+                assert_eq!(*id, u32::from(std::net::Ipv4Addr::new(2, 2, 2, 2)))
+            }
+            _ => panic!("Expected ORIGINATOR_ID"),
+        }
+        match sent.get(bgp_rs::Identifier::CLUSTER_LIST) {
+            Some(PathAttribute::CLUSTER_LIST(ids)) => assert_eq!(*ids, vec![u32::from(cluster_id)]),
+            _ => panic!("Expected CLUSTER_LIST"),
+        }
+        // But not sent to eBGP peers
+        let (ebgp, _remote) = session_for(&peer_config(65001)).await;
+        let sent = ebgp.create_update(&update);
+        assert!(sent.get(bgp_rs::Identifier::ORIGINATOR_ID).is_none());
+        assert!(sent.get(bgp_rs::Identifier::CLUSTER_LIST).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_large_communities() {
+        let mut spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        spec.attributes.communities = vec!["65000:100".to_string(), "65000:1:2".to_string()];
+        let (session, _remote) = session_for(&peer_config(65000)).await;
+        let sent = session.create_update(&exported(&spec));
+        match sent.get(bgp_rs::Identifier::LARGE_COMMUNITY) {
+            Some(PathAttribute::LARGE_COMMUNITY(communities)) => {
+                assert_eq!(communities, &vec![(65000, 1, 2)])
+            }
+            _ => panic!("Expected LARGE_COMMUNITY"),
+        }
+        match sent.get(bgp_rs::Identifier::COMMUNITY) {
+            Some(PathAttribute::COMMUNITY(communities)) => {
+                assert_eq!(communities, &vec![(65000 << 16) + 100])
+            }
+            _ => panic!("Expected COMMUNITY"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mapped_next_hop() {
+        let spec = RouteSpec::new(
+            "10.0.0.0/24".parse().unwrap(),
+            "::ffff:172.16.20.2".parse().unwrap(),
+        );
+        let (session, _remote) = session_for(&peer_config(65000)).await;
+        let update = session.create_update(&exported(&spec));
+        let next_hop = update.get(bgp_rs::Identifier::NEXT_HOP).unwrap();
+        match next_hop {
+            PathAttribute::NEXT_HOP(IpAddr::V4(next_hop)) => {
+                assert_eq!(next_hop, &std::net::Ipv4Addr::new(172, 16, 20, 2))
+            }
+            _ => panic!("Expected an IPv4 NEXT_HOP"),
+        }
+        let mut buf = vec![];
+        next_hop.encode(&mut buf).unwrap();
+        // Flags, type, length (4) & IPv4 address
+        assert_eq!(buf, vec![0x40, 3, 4, 172, 16, 20, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_next_hop_self_per_family() {
+        let v4 = exported(&RouteSpec::new(
+            "10.0.0.0/24".parse().unwrap(),
+            "10.0.0.1".parse().unwrap(),
+        ));
+        let v6 = exported(&RouteSpec::new(
+            "2001:db8::/32".parse().unwrap(),
+            "2001:db8::1".parse().unwrap(),
+        ));
+        let v6_next_hop = |update: &Update| match update.get(bgp_rs::Identifier::MP_REACH_NLRI) {
+            Some(PathAttribute::MP_REACH_NLRI(nlri)) => nlri.next_hop.clone(),
+            _ => panic!("Expected MP_REACH_NLRI"),
+        };
+        let v4_next_hop = |update: &Update| match update.get(bgp_rs::Identifier::NEXT_HOP) {
+            Some(PathAttribute::NEXT_HOP(next_hop)) => *next_hop,
+            _ => panic!("Expected NEXT_HOP"),
+        };
+
+        let config = format!("{}next_hop_self = [\"ipv6 unicast\"]\n", peer_config(65000));
+        let (session, _remote) = session_for(&config).await;
+        // IPv4 next hop is preserved
+        assert_eq!(
+            v4_next_hop(&session.create_update(&v4)),
+            "10.0.0.1".parse::<IpAddr>().unwrap()
+        );
+        // IPv6 next hop is the (IPv4-mapped) local session address
+        assert_eq!(
+            v6_next_hop(&session.create_update(&v6)),
+            std::net::Ipv4Addr::new(127, 0, 0, 1)
+                .to_ipv6_mapped()
+                .octets()
+                .to_vec()
+        );
+
+        // Local session address for all families
+        let config = format!("{}next_hop_self = true\n", peer_config(65000));
+        let (session, _remote) = session_for(&config).await;
+        assert_eq!(
+            v4_next_hop(&session.create_update(&v4)),
+            "127.0.0.1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            v6_next_hop(&session.create_update(&v6)),
+            std::net::Ipv4Addr::new(127, 0, 0, 1)
+                .to_ipv6_mapped()
+                .octets()
+                .to_vec()
+        );
+
+        // Without next_hop_self, both are preserved
+        let (session, _remote) = session_for(&peer_config(65000)).await;
+        assert_eq!(
+            v6_next_hop(&session.create_update(&v6)),
+            "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec()
+        );
+        let config = format!("{}next_hop_self = false\n", peer_config(65000));
+        let (session, _remote) = session_for(&config).await;
+        assert_eq!(
+            v4_next_hop(&session.create_update(&v4)),
+            "10.0.0.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_local_next_hop() {
+        let v6 = exported(&RouteSpec::new(
+            "2001:db8::/32".parse().unwrap(),
+            "2001:db8::1".parse().unwrap(),
+        ));
+        let mapped = std::net::Ipv4Addr::new(127, 0, 0, 1).to_ipv6_mapped();
+        let link_local: Ipv6Addr = "fe80::1".parse().unwrap();
+        let config = format!(
+            "{}link_local_next_hop = \"fe80::1\"\nnext_hop_self = [\"ipv6 unicast\"]\n",
+            peer_config(65000)
+        );
+        let (mut session, mut remote) = session_for(&config).await;
+        let update = session.create_update(&v6);
+        session.send_message(Message::Update(update)).await.unwrap();
+
+        // 32-byte next hop decoded as the global & link-local next hops
+        let received = match remote.next().await {
+            Some(Ok(Message::Update(update))) => update,
+            other => panic!("Expected UPDATE, got {:?}", other),
+        };
+        match received.get(bgp_rs::Identifier::MP_REACH_NLRI) {
+            Some(PathAttribute::MP_REACH_NLRI(nlri)) => {
+                assert_eq!(
+                    nlri.next_hop,
+                    [mapped.octets(), link_local.octets()].concat()
+                )
+            }
+            _ => panic!("Expected MP_REACH_NLRI"),
+        }
+        let attributes =
+            PathAttributes::from_group(&PathAttributeGroup::from_attributes(received.attributes));
+        assert_eq!(attributes.next_hop, Some(IpAddr::V6(mapped)));
+        assert_eq!(attributes.link_local_next_hop, Some(link_local));
+
+        // Not added to a (learned) next hop that isn't the local address
+        let config = format!("{}link_local_next_hop = \"fe80::1\"\n", peer_config(65000));
+        let (mut session, mut remote) = session_for(&config).await;
+        let update = session.create_update(&v6);
+        session.send_message(Message::Update(update)).await.unwrap();
+        let received = match remote.next().await {
+            Some(Ok(Message::Update(update))) => update,
+            other => panic!("Expected UPDATE, got {:?}", other),
+        };
+        let attributes =
+            PathAttributes::from_group(&PathAttributeGroup::from_attributes(received.attributes));
+        assert_eq!(attributes.next_hop, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(attributes.link_local_next_hop, None);
+    }
+
+    #[test]
+    fn test_open_parameters() {
+        let capabilities = vec![
+            OpenCapability::MultiProtocol((AFI::IPV4, SAFI::Unicast)),
+            OpenCapability::MultiProtocol((AFI::IPV6, SAFI::Unicast)),
+            OpenCapability::FourByteASN(65000),
+        ];
+        let combined = open_parameters(capabilities.clone(), false);
+        assert_eq!(combined.len(), 1);
+        match &combined[0] {
+            OpenParameter::Capabilities(caps) => assert_eq!(caps.len(), 3),
+            _ => panic!("Expected Capabilities parameter"),
+        }
+
+        let separate = open_parameters(capabilities, true);
+        assert_eq!(separate.len(), 3);
+        for param in &separate {
+            match param {
+                OpenParameter::Capabilities(caps) => assert_eq!(caps.len(), 1),
+                _ => panic!("Expected Capabilities parameter"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_order_capabilities() {
+        let mut capabilities = vec![
+            OpenCapability::MultiProtocol((AFI::IPV4, SAFI::Unicast)),
+            OpenCapability::MultiProtocol((AFI::IPV6, SAFI::Unicast)),
+            OpenCapability::FourByteASN(65000),
+            OpenCapability::AddPath(vec![(
+                AFI::IPV4,
+                SAFI::Unicast,
+                AddPathDirection::SendReceivePaths,
+            )]),
+            role_capability(Role::Customer),
+        ];
+        order_capabilities(&mut capabilities, &[]);
+        assert!(matches!(
+            capabilities[0],
+            OpenCapability::MultiProtocol((AFI::IPV4, _))
+        ));
+
+        let order = vec![
+            capability_code("role").unwrap(),
+            capability_code("four_octet_asn").unwrap(),
+        ];
+        order_capabilities(&mut capabilities, &order);
+        let codes: Vec<_> = capabilities.iter().map(open_capability_code).collect();
+        assert_eq!(codes, vec![9, 65, 1, 1, 69]);
+        // Unlisted capabilities keep their relative order
+        assert!(matches!(
+            capabilities[2],
+            OpenCapability::MultiProtocol((AFI::IPV4, _))
+        ));
+        assert!(matches!(
+            capabilities[3],
+            OpenCapability::MultiProtocol((AFI::IPV6, _))
+        ));
+
+        assert!(capability_code("route_reflector").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_capability_order_open() {
+        let config = format!(
+            "{}capability_order = [\"four_octet_asn\", \"extended_message\"]\nseparate_capabilities = true\n",
+            peer_config(65001)
+        );
+        let (session, _remote) = session_for(&config).await;
+        let codes: Vec<_> = session
+            .create_open()
+            .parameters
+            .iter()
+            .map(|param| match param {
+                OpenParameter::Capabilities(caps) => {
+                    assert_eq!(caps.len(), 1);
+                    open_capability_code(&caps[0])
+                }
+                _ => panic!("Expected Capabilities parameter"),
+            })
+            .collect();
+        assert_eq!(codes[..2], [65, EXTENDED_MESSAGE]);
+        assert!(codes[2..]
+            .iter()
+            .all(|code| *code != 65 && *code != EXTENDED_MESSAGE));
+    }
+
+    #[test]
+    fn test_capabilities_summary() {
+        let local = Capabilities::from_parameters(vec![OpenParameter::Capabilities(vec![
+            OpenCapability::MultiProtocol((AFI::IPV6, SAFI::Unicast)),
+            OpenCapability::MultiProtocol((AFI::IPV4, SAFI::Unicast)),
+            OpenCapability::FourByteASN(65000),
+        ])]);
+        let remote = Capabilities::from_parameters(vec![OpenParameter::Capabilities(vec![
+            OpenCapability::MultiProtocol((AFI::IPV4, SAFI::Unicast)),
+            OpenCapability::MultiProtocol((AFI::IPV6, SAFI::Unicast)),
+            OpenCapability::RouteRefresh,
+            OpenCapability::FourByteASN(65001),
+        ])]);
+        let negotiated = common_capabilities(&local, &remote).unwrap();
+        assert_eq!(
+            capabilities_summary(&negotiated),
+            "families=[IPv4 Unicast, IPv6 Unicast] route_refresh=false add_path=[] \
+             graceful_restart=[] four_octet_asn=true"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_log_capabilities_on_established() {
+        let (mut session, _remote) = session_for(&peer_config(65001)).await;
+        session.update_state(SessionState::OpenConfirm);
+        let logs = capture_logs(|| {
+            let response = session.process_message(Message::KeepAlive).unwrap();
+            assert!(matches!(
+                response,
+                MessageResponse::Reply(Message::KeepAlive)
+            ));
+        });
+        assert_eq!(session.state, SessionState::Established);
+        let expected = format!(
+            "127.0.0.1 Established with capabilities: {}",
+            capabilities_summary(&session.capabilities)
+        );
+        assert_eq!(
+            logs.iter().filter(|line| line.as_str() == expected).count(),
+            1,
+            "{:?}",
+            logs
+        );
+
+        // Further KEEPALIVEs don't log the summary again
+        let logs = capture_logs(|| {
+            session.process_message(Message::KeepAlive).unwrap();
+        });
+        assert!(!logs.iter().any(|line| line.contains("with capabilities")));
+
+        // Not logged when `log_capabilities` is disabled
+        let config = format!("{}log_capabilities = false\n", peer_config(65001));
+        let (mut session, _remote) = session_for(&config).await;
+        session.update_state(SessionState::OpenConfirm);
+        let logs = capture_logs(|| {
+            session.process_message(Message::KeepAlive).unwrap();
+        });
+        assert_eq!(session.state, SessionState::Established);
+        assert!(!logs.iter().any(|line| line.contains("with capabilities")));
+    }
+
+    #[tokio::test]
+    async fn test_max_prefixes() {
+        let config = format!(
+            "{}max_prefixes = 100\nmax_prefix_warning = 75\n",
+            peer_config(65000)
+        );
+        let (mut session, mut remote) = session_for(&config).await;
+        assert!(session.check_prefix_limit(75).is_ok());
+        assert!(session.check_prefix_limit(100).is_ok());
+        let err = session.check_prefix_limit(101).unwrap_err();
+        assert!(matches!(err, SessionError::MaxPrefixesExceeded(100)));
+
+        // Peer is notified with Cease / Maximum Number of Prefixes Reached
+        let (major, minor) = err.notification().unwrap();
+        session.notify(major, minor).await.unwrap();
+        match remote.next().await {
+            Some(Ok(Message::Notification(notification))) => {
+                assert_eq!(notification.major_err_code, 6);
+                assert_eq!(notification.minor_err_code, 1);
+            }
+            other => panic!("Expected NOTIFICATION, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hold_time_expired() {
+        let (mut session, mut remote) = session_for(&peer_config(65000)).await;
+        session.hold_timer = HoldTimer::new(3, None);
+        session.hold_timer.last_received = Utc::now() - chrono::Duration::seconds(5);
+
+        let err = session.run().await.unwrap_err();
+        assert!(matches!(err, SessionError::HoldTimeExpired(_)));
+        assert_eq!(
+            session.last_notification,
+            Some(NotificationRecord {
+                sent: true,
+                major: 4,
+                minor: 0,
+                data: vec![],
+            })
+        );
+        // NOTIFICATION was written before the session ended
+        loop {
+            match remote.next().await {
+                Some(Ok(Message::Notification(notification))) => {
+                    assert_eq!(notification.major_err_code, 4);
+                    assert_eq!(notification.minor_err_code, 0);
+                    break;
+                }
+                Some(Ok(_)) => continue,
+                other => panic!("Expected NOTIFICATION, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_prefix_in() {
+        let config = format!("{}max_prefix_in = 10\n", peer_config(65000));
+        let (mut session, _remote) = session_for(&config).await;
+        assert_eq!(session.config.max_prefixes, Some(10));
+        assert!(session.check_prefix_limit(10).is_ok());
+        let err = session.check_prefix_limit(11).unwrap_err();
+        assert_eq!(err.notification(), Some((6, 1)));
+    }
+
+    #[tokio::test]
+    async fn test_max_prefix_out() {
+        let config = format!("{}max_prefix_out = 2\n", peer_config(65000));
+        let (mut session, _remote) = session_for(&config).await;
+        let now = Utc::now();
+        let routes = |count: i64| -> Vec<Arc<ExportEntry>> {
+            (0..count)
+                .rev()
+                .map(|i| {
+                    let prefix = format!("10.0.{}.0/24", i).parse().unwrap();
+                    let spec = RouteSpec::new(prefix, "10.255.0.1".parse().unwrap());
+                    let mut entry = ExportEntry::new(exported(&spec), EntrySource::Api);
+                    entry.timestamp = now + chrono::Duration::seconds(i);
+                    Arc::new(entry)
+                })
+                .collect()
+        };
+        assert_eq!(session.limit_outbound_routes(routes(2)).len(), 2);
+        assert!(!session.prefix_out_limited);
+
+        // Oldest routes are kept
+        let limited = session.limit_outbound_routes(routes(3));
+        let timestamps: Vec<_> = limited.iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![now, now + chrono::Duration::seconds(1)]);
+        assert!(session.prefix_out_limited);
+        assert_eq!(session.limit_outbound_routes(routes(4)).len(), 2);
+
+        // Back under the limit, warns again if exceeded later
+        assert_eq!(session.limit_outbound_routes(routes(1)).len(), 1);
+        assert!(!session.prefix_out_limited);
+    }
+
+    #[tokio::test]
+    async fn test_default_originate() {
+        let config = format!(
+            "{}families = [\"ipv4 unicast\"]\ndefault_originate = {{ conditional_on = \"10.0.0.0/8\" }}\n",
+            peer_config(65000)
+        );
+        let (mut session, _remote) = session_for(&config).await;
+        session.routes = SessionRoutes::new(Families::new(session.config.families.clone()));
+        let mut rib = crate::rib::RIB::new();
+        let tracked: IpNetwork = "10.0.0.0/8".parse().unwrap();
+        let sync = |session: &mut Session, rib: &crate::rib::RIB| {
+            let condition = rib.contains_prefix(tracked);
+            let routes =
+                session.originate_default(rib.get_routes_for_peer(session.addr), condition);
+            session.routes.insert_routes(routes);
+        };
+
+        // Tracked prefix not in the RIB yet
+        sync(&mut session, &rib);
+        assert!(session.routes.pending().is_empty());
+
+        // Tracked prefix appears, default is originated (once)
+        let spec = RouteSpec::new(tracked, "10.255.0.1".parse().unwrap());
+        let (family, attributes, nlri) = parse_route_spec(&spec).unwrap();
+        rib.insert_from_api(family, attributes, nlri);
+        sync(&mut session, &rib);
+        let pending = session.routes.pending();
+        let default = pending
+            .iter()
+            .find(|e| format!("{:?}", e.update.nlri) == "IP(0.0.0.0/0)")
+            .expect("Default originated")
+            .clone();
+        assert_eq!(default.source, EntrySource::Config);
+        for entry in &pending {
+            session.routes.mark_advertised(entry);
+        }
+        sync(&mut session, &rib);
+        assert!(session.routes.pending().is_empty());
+
+        // Tracked prefix disappears, default is withdrawn
+        rib.remove_from_api(tracked);
+        sync(&mut session, &rib);
+        let withdrawn = session.routes.take_withdrawn(&[]);
+        assert_eq!(withdrawn.len(), 2);
+        assert!(withdrawn.iter().any(|e| e.timestamp == default.timestamp));
+        assert!(session.default_originated.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_decode_error() {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut session, remote) = session_for(&peer_config(65000)).await;
+        // Valid header with an unknown message type (9)
+        let mut message = vec![255u8; 16];
+        message.extend_from_slice(&[0, 19, 9]);
+        let mut stream = remote.into_inner();
+        stream.write_all(&message).await.unwrap();
+
+        match session.run().await {
+            Err(SessionError::CodecError(reason)) => {
+                assert!(reason.contains(&session.addr.to_string()), "{}", reason);
+            }
+            other => panic!("Expected CodecError, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extended_communities() {
+        let mut spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        spec.attributes.communities =
+            vec!["65000:100".to_string(), "target:65000:1.1.1.1".to_string()];
+        let update = exported(&spec);
+        assert_eq!(update.attributes.communities.standard().len(), 1);
+        assert_eq!(update.attributes.communities.extended().len(), 1);
+
+        let (session, _remote) = session_for(&peer_config(65000)).await;
+        let sent = session.create_update(&update);
+        match sent.get(bgp_rs::Identifier::EXTENDED_COMMUNITIES) {
+            Some(PathAttribute::EXTENDED_COMMUNITIES(communities)) => {
+                assert_eq!(communities.len(), 1);
+                assert_eq!(
+                    communities[0].to_be_bytes(),
+                    [0x00, 0x02, 0xfd, 0xe8, 0x01, 0x01, 0x01, 0x01]
+                );
+            }
+            _ => panic!("Expected EXTENDED_COMMUNITIES"),
+        }
+        match sent.get(bgp_rs::Identifier::COMMUNITY) {
+            Some(PathAttribute::COMMUNITY(communities)) => {
+                assert_eq!(communities, &vec![(65000 << 16) + 100])
+            }
+            _ => panic!("Expected COMMUNITY"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_advertise_aggregate() {
+        let config = format!("{}aggregate = [\"10.0.0.0/22\"]\n", peer_config(65001));
+        let (mut session, mut remote) = session_for(&config).await;
+        let routes: Vec<_> = ["10.0.0.0/24", "10.0.1.0/24", "10.0.2.0/24"]
+            .iter()
+            .map(|prefix| {
+                let spec = RouteSpec::new(prefix.parse().unwrap(), "10.255.0.1".parse().unwrap());
+                Arc::new(ExportEntry::new(exported(&spec), EntrySource::Api))
+            })
+            .collect();
+        let routes = crate::rib::aggregate_routes(routes, &session.config);
+        assert_eq!(routes.len(), 1);
+        let update = session.create_update(&routes[0].update);
+        session.send_message(Message::Update(update)).await.unwrap();
+        let received = match remote.next().await.unwrap().unwrap() {
+            Message::Update(received) => received,
+            message => panic!("Expected UPDATE, got {:?}", message),
+        };
+        let nlri: Vec<_> = received
+            .announced_routes
+            .iter()
+            .map(|n| format!("{:?}", n))
+            .collect();
+        assert_eq!(nlri, vec!["IP(10.0.0.0/22)".to_string()]);
+        assert!(received
+            .get(bgp_rs::Identifier::ATOMIC_AGGREGATOR)
+            .is_some());
+        match received.get(bgp_rs::Identifier::AGGREGATOR) {
+            Some(PathAttribute::AGGREGATOR((asn, router_id))) => {
+                assert_eq!(*asn, 65000);
+                assert_eq!(router_id.to_string(), "1.1.1.1");
+            }
+            _ => panic!("Expected AGGREGATOR"),
+        }
+        // No next hop to propagate, advertised with the local address
+        match received.get(bgp_rs::Identifier::NEXT_HOP) {
+            Some(PathAttribute::NEXT_HOP(next_hop)) => {
+                assert_eq!(next_hop.to_string(), "127.0.0.1")
+            }
+            _ => panic!("Expected NEXT_HOP"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_path() {
+        let config = format!(
+            "{}add_path = true\nfamilies = [\"ipv4 unicast\", \"ipv6 unicast\", \"ipv4 flow\"]",
+            peer_config(65000)
+        );
+        let (mut session, _remote) = session_for(&config).await;
+        let open = session.create_open();
+        let advertised: Vec<_> = open
+            .parameters
+            .iter()
+            .flat_map(|param| match param {
+                OpenParameter::Capabilities(caps) => caps.clone(),
+                _ => vec![],
+            })
+            .filter_map(|cap| match cap {
+                OpenCapability::AddPath(families) => Some(families),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        use AddPathDirection::*;
+        assert_eq!(
+            advertised,
+            vec![
+                (AFI::IPV4, SAFI::Unicast, SendReceivePaths),
+                (AFI::IPV6, SAFI::Unicast, SendReceivePaths),
+            ]
+        );
+        assert_eq!(
+            add_path_direction(SendReceivePaths, ReceivePaths),
+            Some(SendPaths)
+        );
+        assert_eq!(
+            add_path_direction(SendReceivePaths, SendPaths),
+            Some(ReceivePaths)
+        );
+        assert_eq!(add_path_direction(SendPaths, SendPaths), None);
+
+        session.update_state(SessionState::OpenSent);
+        session.process_message(Message::Open(open)).unwrap();
+        assert!(session.capabilities.EXTENDED_PATH_NLRI_SUPPORT);
+
+        // Each path for a prefix is advertised (and withdrawn) with its own path ID
+        let spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        let first = ExportEntry::new(exported(&spec), EntrySource::Api);
+        let mut second = ExportEntry::new(exported(&spec), EntrySource::Config);
+        second.timestamp = first.timestamp + chrono::Duration::milliseconds(1);
+        let prefix: bgp_rs::Prefix = ("10.0.0.0".parse().unwrap(), 24).into();
+        for entry in [&first, &second] {
+            let expected = vec![NLRIEncoding::IP_WITH_PATH_ID((
+                prefix.clone(),
+                path_id(entry),
+            ))];
+            assert_eq!(session.create_path_update(entry).announced_routes, expected);
+            assert_eq!(session.create_withdraw(entry).withdrawn_routes, expected);
+        }
+        assert_ne!(path_id(&first), path_id(&second));
+
+        // Without ADD-PATH, the prefix is advertised without a path ID
+        let (session, _remote) = session_for(&peer_config(65000)).await;
+        assert_eq!(
+            session.create_path_update(&first).announced_routes,
+            vec![NLRIEncoding::IP(prefix)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_mp_unreach() {
+        let (mut session, mut remote) = session_for(&peer_config(65000)).await;
+        let spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        let v4 = ExportEntry::new(exported(&spec), EntrySource::Api);
+        let withdraw = session.create_withdraw(&v4);
+        assert_eq!(withdraw.withdrawn_routes, vec![v4.update.nlri.clone()]);
+        assert!(withdraw.attributes.is_empty());
+
+        let spec = RouteSpec::new("2001:db8::/32".parse().unwrap(), "::1".parse().unwrap());
+        let v6 = ExportEntry::new(exported(&spec), EntrySource::Api);
+        let withdraw = session.create_withdraw(&v6);
+        assert!(withdraw.withdrawn_routes.is_empty());
+        session
+            .send_message(Message::Update(withdraw))
+            .await
+            .unwrap();
+        match remote.next().await.unwrap().unwrap() {
+            Message::Update(received) => match &received.attributes[..] {
+                [PathAttribute::MP_UNREACH_NLRI(nlri)] => {
+                    assert_eq!((nlri.afi, nlri.safi), (AFI::IPV6, SAFI::Unicast));
+                    let withdrawn: Vec<_> = nlri
+                        .withdrawn_routes
+                        .iter()
+                        .map(|n| format!("{:?}", n))
+                        .collect();
+                    assert_eq!(withdrawn, vec!["IP(2001:db8::/32)".to_string()]);
+                }
+                attributes => panic!("Expected MP_UNREACH_NLRI, got {:?}", attributes),
+            },
+            message => panic!("Expected UPDATE, got {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_normalize_mapped_nexthop() {
+        let mapped: Ipv6Addr = "::ffff:10.0.0.1".parse().unwrap();
+        let update = || Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(bgp_rs::Origin::IGP),
+                PathAttribute::MP_REACH_NLRI(MPReachNLRI {
+                    afi: AFI::IPV6,
+                    safi: SAFI::Unicast,
+                    next_hop: mapped.octets().to_vec(),
+                    announced_routes: vec![NLRIEncoding::IP(
+                        ("2001:db8::".parse().unwrap(), 32).into(),
+                    )],
+                }),
+            ],
+            announced_routes: vec![],
+        };
+        let learned_next_hop = |session: &mut Session| {
+            let update = match session.process_message(Message::Update(update())) {
+                Ok(MessageResponse::Update((update, _))) => update,
+                other => panic!("Expected Update, got {:?}", other),
+            };
+            let mut rib = crate::rib::RIB::new();
+            rib.update_from_peer(session.addr, &session.config, update)
+                .unwrap();
+            rib.get_routes_from_peer(session.addr)[0]
+                .update
+                .attributes
+                .next_hop
+        };
+
+        let config = format!("{}normalize_mapped_nexthop = true\n", peer_config(65000));
+        let (mut session, _remote) = session_for(&config).await;
+        assert_eq!(
+            learned_next_hop(&mut session),
+            Some(IpAddr::from([10, 0, 0, 1]))
+        );
+
+        let (mut session, _remote) = session_for(&peer_config(65000)).await;
+        assert_eq!(learned_next_hop(&mut session), Some(IpAddr::V6(mapped)));
+    }
+
+    #[tokio::test]
+    async fn test_initial_batch() {
+        let config = format!("{}initial_batch = true\n", peer_config(65000));
+        let (mut session, mut remote) = session_for(&config).await;
+        session.state = SessionState::Established;
+        session.routes.families = Families::new(vec![Family::new(AFI::IPV4, SAFI::Unicast)]);
+        let now = Utc::now();
+        let entries: Vec<Arc<ExportEntry>> = (0..500u32)
+            .map(|i| {
+                let prefix = std::net::Ipv4Addr::from(0x0a00_0000 + (i << 8));
+                let mut spec = RouteSpec::new(
+                    IpNetwork::new(IpAddr::V4(prefix), 24).unwrap(),
+                    "127.0.0.1".parse().unwrap(),
+                );
+                // Two attribute groups
+                spec.attributes.local_pref = Some(100 + (i % 2) * 100);
+                let mut entry = ExportEntry::new(exported(&spec), EntrySource::Api);
+                entry.timestamp = now + chrono::Duration::milliseconds(i.into());
+                Arc::new(entry)
+            })
+            .collect();
+        session.routes.insert_routes(entries);
+
+        // Initial dump is sent before waiting on incoming messages
+        let _ = tokio::time::timeout(std::time::Duration::from_millis(100), session.run()).await;
+        assert_eq!(session.routes.pending().len(), 0);
+        assert_eq!(session.routes.advertised().len(), 500);
+
+        let mut updates: Vec<Update> = vec![];
+        loop {
+            match remote.next().await {
+                Some(Ok(Message::Update(update))) => {
+                    let end = is_end_of_rib(&update);
+                    updates.push(update);
+                    if end {
+                        break;
+                    }
+                }
+                other => panic!("Expected UPDATE, got {:?}", other),
+            }
+        }
+        let (eor, batched) = updates.split_last().unwrap();
+        assert!(is_end_of_rib(eor));
+        assert_eq!(batched.len(), 2);
+        assert_eq!(
+            batched
+                .iter()
+                .map(|u| u.announced_routes.len())
+                .sum::<usize>(),
+            500
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batched_advertisements() {
+        let (mut session, mut remote) = session_for(&peer_config(65000)).await;
+        session.state = SessionState::Established;
+        session.initial_dump_sent = true;
+        session.routes.families = Families::new(vec![Family::new(AFI::IPV4, SAFI::Unicast)]);
+        let now = Utc::now();
+        let entries: Vec<Arc<ExportEntry>> = (0..50u32)
+            .map(|i| {
+                let prefix = std::net::Ipv4Addr::from(0x0a00_0000 + (i << 8));
+                let spec = RouteSpec::new(
+                    IpNetwork::new(IpAddr::V4(prefix), 24).unwrap(),
+                    "127.0.0.1".parse().unwrap(),
+                );
+                let mut entry = ExportEntry::new(exported(&spec), EntrySource::Api);
+                entry.timestamp = now + chrono::Duration::milliseconds(i.into());
+                Arc::new(entry)
+            })
+            .collect();
+        session.routes.insert_routes(entries);
+
+        let _ = tokio::time::timeout(std::time::Duration::from_millis(100), session.run()).await;
+        assert!(session.routes.pending().is_empty());
+
+        let mut updates: Vec<Update> = vec![];
+        let wait = std::time::Duration::from_millis(100);
+        while let Ok(Some(Ok(Message::Update(update)))) =
+            tokio::time::timeout(wait, remote.next()).await
+        {
+            updates.push(update);
+        }
+        // Identical attributes, packed into a single UPDATE
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].announced_routes.len(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_mrai() {
+        let config = format!("{}mrai = 1\n", peer_config(65000));
+        let (mut session, mut remote) = session_for(&config).await;
+        session.state = SessionState::Established;
+        session.routes.families = Families::new(vec![Family::new(AFI::IPV4, SAFI::Unicast)]);
+        let now = Utc::now();
+        let route = |i: u32| {
+            let mut spec =
+                RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+            spec.attributes.local_pref = Some(100 + i);
+            let mut entry = ExportEntry::new(exported(&spec), EntrySource::Api);
+            entry.timestamp = now + chrono::Duration::milliseconds(i.into());
+            Arc::new(entry)
+        };
+        let wait = std::time::Duration::from_millis(100);
+        let start = Instant::now();
+        session.routes.insert_routes(vec![route(0)]);
+        let _ = tokio::time::timeout(wait, session.run()).await;
+        assert!(session.routes.pending().is_empty());
+
+        // Two rapid changes, held until the MRAI elapses
+        session.routes.insert_routes(vec![route(1)]);
+        let _ = tokio::time::timeout(wait, session.run()).await;
+        session.routes.insert_routes(vec![route(2)]);
+        while !session.routes.pending().is_empty() {
+            let _ = tokio::time::timeout(std::time::Duration::from_secs(2), session.run()).await;
+        }
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        let mut updates: Vec<Update> = vec![];
+        while let Ok(Some(Ok(Message::Update(update)))) =
+            tokio::time::timeout(wait, remote.next()).await
+        {
+            updates.push(update);
+        }
+        let local_prefs: Vec<_> = updates
+            .iter()
+            .map(|update| match update.get(bgp_rs::Identifier::LOCAL_PREF) {
+                Some(PathAttribute::LOCAL_PREF(local_pref)) => *local_pref,
+                _ => panic!("Expected LOCAL_PREF"),
+            })
+            .collect();
+        // Coalesced into one advertisement, implicitly withdrawing the original
+        assert_eq!(local_prefs, vec![100, 102]);
+        assert!(updates
+            .iter()
+            .all(|update| update.withdrawn_routes.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_enhanced_route_refresh_stale_routes() {
+        let family = Family::new(AFI::IPV4, SAFI::Unicast);
+        let marker = |subtype: u8| route_refresh_marker(family, subtype);
+        let update = |prefix: &str| {
+            let spec = RouteSpec::new(prefix.parse().unwrap(), "127.0.0.1".parse().unwrap());
+            let (_, attributes, nlri) = parse_route_spec(&spec).unwrap();
+            Message::Update(Update {
+                withdrawn_routes: vec![],
+                attributes,
+                announced_routes: vec![nlri],
+            })
+        };
+        let process = |session: &mut Session, rib: &mut crate::rib::RIB, message| match session
+            .process_message(message)
+            .unwrap()
+        {
+            MessageResponse::Update((update, _)) => rib
+                .update_from_peer(session.addr, &session.config, update)
+                .unwrap(),
+            MessageResponse::BeginRefresh(family) => rib.mark_stale(session.addr, family),
+            MessageResponse::EndRefresh(family) => rib.remove_stale(session.addr, family),
+            _ => (),
+        };
+
+        // Markers are ignored unless Enhanced Route Refresh was negotiated
+        let (mut session, _remote) = session_for(&peer_config(65001)).await;
+        establish(&mut session, &[ENHANCED_ROUTE_REFRESH]).await;
+        assert!(!session.capabilities.ENHANCED_ROUTE_REFRESH_SUPPORT);
+        let mut rib = crate::rib::RIB::new();
+        process(&mut session, &mut rib, update("10.0.0.0/24"));
+        process(&mut session, &mut rib, update("10.0.1.0/24"));
+        process(&mut session, &mut rib, marker(BEGIN_OF_RR));
+        process(&mut session, &mut rib, marker(END_OF_RR));
+        assert_eq!(rib.len(), 2);
+
+        // Negotiated in the OPEN exchange
+        let (mut session, _remote) = session_for(&peer_config(65001)).await;
+        assert!(has_capability(
+            &session.create_open().parameters,
+            ENHANCED_ROUTE_REFRESH
+        ));
+        establish(&mut session, &[]).await;
+        assert!(session.capabilities.ENHANCED_ROUTE_REFRESH_SUPPORT);
+        let mut rib = crate::rib::RIB::new();
+        process(&mut session, &mut rib, update("10.0.0.0/24"));
+        process(&mut session, &mut rib, update("10.0.1.0/24"));
+        assert_eq!(rib.len(), 2);
+
+        // Only 10.0.1.0/24 is refreshed, 10.0.0.0/24 is removed after EoRR
+        process(&mut session, &mut rib, marker(BEGIN_OF_RR));
+        process(&mut session, &mut rib, update("10.0.1.0/24"));
+        assert_eq!(rib.len(), 2);
+        process(&mut session, &mut rib, marker(END_OF_RR));
+        let routes = rib.get_routes_from_peer(session.addr);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(
+            routes[0].update.nlri,
+            NLRIEncoding::IP(("10.0.1.0".parse().unwrap(), 24).into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multicast_labeled_families() {
+        let config = format!(
+            "{}families = [\"ipv4 unicast\", \"ipv4 multicast\", \"ipv4 labeled-unicast\"]\n",
+            peer_config(65000)
+        );
+        let (mut session, _remote) = session_for(&config).await;
+        let open = session.create_open();
+        let advertised: Vec<_> = open
+            .parameters
+            .iter()
+            .flat_map(|param| match param {
+                OpenParameter::Capabilities(caps) => caps.clone(),
+                _ => vec![],
+            })
+            .filter_map(|cap| match cap {
+                OpenCapability::MultiProtocol(family) => Some(family),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            advertised,
+            vec![
+                (AFI::IPV4, SAFI::Unicast),
+                (AFI::IPV4, SAFI::Multicast),
+                (AFI::IPV4, SAFI::Mpls),
+            ]
+        );
+
+        // Only the families advertised by both sides are negotiated
+        let remote = Capabilities::from_parameters(vec![OpenParameter::Capabilities(vec![
+            OpenCapability::MultiProtocol((AFI::IPV4, SAFI::Unicast)),
+            OpenCapability::MultiProtocol((AFI::IPV4, SAFI::Multicast)),
+            OpenCapability::FourByteASN(65000),
+        ])]);
+        let negotiated = common_capabilities(&session.local_capabilities, &remote).unwrap();
+        assert_eq!(
+            capabilities_summary(&negotiated),
+            "families=[IPv4 Multicast, IPv4 Unicast] route_refresh=false add_path=[] \
+             graceful_restart=[] four_octet_asn=true"
+        );
+
+        session.update_state(SessionState::OpenSent);
+        session.process_message(Message::Open(open)).unwrap();
+        assert!(session
+            .routes
+            .families
+            .contains(Family::new(AFI::IPV4, SAFI::Mpls)));
+    }
+
+    #[tokio::test]
+    async fn test_extended_message_negotiation() {
+        let config = format!("{}extended_messages = false\n", peer_config(65000));
+        let (mut session, _remote) = session_for(&config).await;
+        let open = session.create_open();
+        assert!(!has_capability(&open.parameters, EXTENDED_MESSAGE));
+
+        // Advertised by default, negotiated when both sides advertise it
+        let (mut extended, _remote) = session_for(&peer_config(65000)).await;
+        let extended_open = extended.create_open();
+        assert!(has_capability(&extended_open.parameters, EXTENDED_MESSAGE));
+        extended.update_state(SessionState::OpenSent);
+        extended.process_message(Message::Open(open)).unwrap();
+        assert!(!extended.peer_extended_messages);
+        assert!(!extended.extended_messages);
+        assert_eq!(extended.protocol.codec().max_message_size(), 4096);
+
+        session.update_state(SessionState::OpenSent);
+        session
+            .process_message(Message::Open(extended_open.clone()))
+            .unwrap();
+        assert!(session.peer_extended_messages);
+        assert!(!session.extended_messages);
+
+        let (mut peer, _remote) = session_for(&peer_config(65000)).await;
+        peer.update_state(SessionState::OpenSent);
+        peer.process_message(Message::Open(extended_open)).unwrap();
+        assert!(peer.extended_messages);
+        assert_eq!(peer.protocol.codec().max_message_size(), 65535);
+
+        // Batched updates fill the larger message size
+        let entries: Vec<Arc<ExportEntry>> = (0..2000u32)
+            .map(|i| {
+                let spec = RouteSpec::new(
+                    IpNetwork::new(IpAddr::V4(std::net::Ipv4Addr::from(0x0a00_0000 + i)), 32)
+                        .unwrap(),
+                    "127.0.0.1".parse().unwrap(),
+                );
+                Arc::new(ExportEntry::new(exported(&spec), EntrySource::Api))
+            })
+            .collect();
+        assert_eq!(peer.create_batched_updates(&entries).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_graceful_restart_negotiation() {
+        let (session, _remote) = session_for(&peer_config(65000)).await;
+        assert!(parse_graceful_restart(&session.create_open().parameters).is_none());
+        assert!(session.capabilities.GRACEFUL_RESTART_SUPPORT.is_empty());
+
+        let config = format!("{}graceful_restart = 120\n", peer_config(65000));
+        let (mut session, _remote) = session_for(&config).await;
+        let open = session.create_open();
+        let (restart_time, families) = parse_graceful_restart(&open.parameters).unwrap();
+        assert_eq!(restart_time, 120);
+        assert_eq!(families.len(), session.config.families.len());
+
+        // Own OPEN (same ASN) as the peer's, GR is negotiated for all families
+        session.update_state(SessionState::OpenSent);
+        session.process_message(Message::Open(open)).unwrap();
+        assert_eq!(session.peer_restart_time, Some(120));
+        assert!(session.graceful_restart().is_none()); // Not yet Established
+        session.update_state(SessionState::Established);
+        let restart = session.graceful_restart().unwrap();
+        assert_eq!(restart.restart_time, 120);
+        assert_eq!(restart.families.len(), families.len());
+
+        // Routes aren't retained once a NOTIFICATION is exchanged
+        session.notify(6, 4).await.unwrap();
+        assert!(session.graceful_restart().is_none());
+
+        // End-of-RIB is reported with its family
+        let family = Family::new(AFI::IPV6, SAFI::Unicast);
+        let eor = Message::Update(create_end_of_rib(family));
+        assert!(matches!(
+            session.process_message(eor).unwrap(),
+            MessageResponse::EndOfRib(f) if f == family
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_response_markers() {
+        let (mut session, mut remote) = session_for(&peer_config(65001)).await;
+        establish(&mut session, &[]).await;
+        let family = Family::new(AFI::IPV4, SAFI::Unicast);
+        let spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        let entry = Arc::new(ExportEntry::new(exported(&spec), EntrySource::Api));
+        session.routes.insert_routes(vec![entry.clone()]);
+        session.routes.mark_advertised(&entry);
+
+        session.queue_refresh_response(family);
+        assert_eq!(session.routes.pending().len(), 1);
+        let _ = tokio::time::timeout(std::time::Duration::from_millis(100), session.run()).await;
+        assert_eq!(session.routes.advertised().len(), 1);
+        let mut received = vec![];
+        for _ in 0..3 {
+            received.push(remote.next().await.unwrap().unwrap());
+        }
+        match received.as_slice() {
+            [Message::RouteRefresh(begin), Message::Update(update), Message::RouteRefresh(end)] => {
+                assert_eq!(begin.subtype, BEGIN_OF_RR);
+                assert_eq!(update.announced_routes.len(), 1);
+                assert_eq!(end.subtype, END_OF_RR);
+            }
+            other => panic!("Expected BoRR, UPDATE, EoRR; got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_refresh_capability() {
+        let (mut session, mut remote) = session_for(&peer_config(65001)).await;
+        let open = session.create_open();
+        let advertised = open.parameters.iter().any(|param| match param {
+            OpenParameter::Capabilities(caps) => caps
+                .iter()
+                .any(|cap| matches!(cap, OpenCapability::RouteRefresh)),
+            _ => false,
+        });
+        assert!(advertised);
+
+        // Peer didn't advertise Route Refresh, the refresh response has no BoRR/EoRR markers
+        establish(
+            &mut session,
+            &[open_capability_code(&OpenCapability::RouteRefresh)],
+        )
+        .await;
+        assert!(!session.capabilities.ROUTE_REFRESH_SUPPORT);
+        assert!(session.capabilities.ENHANCED_ROUTE_REFRESH_SUPPORT);
+        let family = Family::new(AFI::IPV4, SAFI::Unicast);
+        let spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        let entry = Arc::new(ExportEntry::new(exported(&spec), EntrySource::Api));
+        session.routes.insert_routes(vec![entry.clone()]);
+        session.routes.mark_advertised(&entry);
+        session.queue_refresh_response(family);
+        assert!(session.refreshing.is_empty());
+        let _ = tokio::time::timeout(Duration::from_millis(100), session.run()).await;
+        match remote.next().await {
+            Some(Ok(Message::Update(update))) => assert_eq!(update.announced_routes.len(), 1),
+            other => panic!("Expected UPDATE, got {:?}", other),
+        }
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), remote.next())
+                .await
+                .is_err(),
+            "No EoRR marker is sent"
+        );
+
+        // Sent once the peer advertised it
+        let (mut session, _remote) = session_for(&peer_config(65001)).await;
+        establish(&mut session, &[]).await;
+        assert!(session.capabilities.ROUTE_REFRESH_SUPPORT);
+        session.queue_refresh_response(family);
+        assert_eq!(session.refreshing, vec![family]);
+    }
+
+    #[tokio::test]
+    async fn test_route_refresh_requeues_routes() {
+        let (mut session, _remote) = session_for(&peer_config(65001)).await;
+        establish(&mut session, &[ENHANCED_ROUTE_REFRESH]).await;
+        let family = Family::new(AFI::IPV4, SAFI::Unicast);
+        let now = Utc::now();
+        let entries: Vec<Arc<ExportEntry>> = ["10.0.0.0/24", "10.0.1.0/24"]
+            .iter()
+            .enumerate()
+            .map(|(i, prefix)| {
+                let spec = RouteSpec::new(prefix.parse().unwrap(), "127.0.0.1".parse().unwrap());
+                let mut entry = ExportEntry::new(exported(&spec), EntrySource::Api);
+                entry.timestamp = now + chrono::Duration::milliseconds(i as i64);
+                Arc::new(entry)
+            })
+            .collect();
+        session.routes.insert_routes(entries.clone());
+        for entry in &entries {
+            session.routes.mark_advertised(entry);
+        }
+        assert!(session.routes.pending().is_empty());
+
+        // Family wasn't negotiated, nothing is re-queued
+        let refresh = |afi, safi| {
+            Message::RouteRefresh(RouteRefresh {
+                afi,
+                safi,
+                subtype: 0,
+            })
+        };
+        session
+            .process_message(refresh(AFI::IPV4, SAFI::Multicast))
+            .unwrap();
+        assert!(session.routes.pending().is_empty());
+
+        session
+            .process_message(refresh(family.afi, family.safi))
+            .unwrap();
+        assert_eq!(session.routes.pending().len(), 2);
+        assert!(session.routes.advertised().is_empty());
+        // Without Enhanced Route Refresh, no BoRR/EoRR markers are sent
+        assert!(session.refreshing.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paced_initial_batch() {
+        let config = format!(
+            "{}initial_batch = true\nmax_update_rate = 100\n",
+            peer_config(65000)
+        );
+        let (mut session, mut remote) = session_for(&config).await;
+        session.state = SessionState::Established;
+        session.routes.families = Families::new(vec![Family::new(AFI::IPV4, SAFI::Unicast)]);
+        let now = Utc::now();
+        let entries: Vec<Arc<ExportEntry>> = (0..300)
+            .map(|i| {
+                let prefix = format!("10.{}.{}.0/24", i / 256, i % 256);
+                let spec = RouteSpec::new(prefix.parse().unwrap(), "127.0.0.1".parse().unwrap());
+                let mut entry = ExportEntry::new(exported(&spec), EntrySource::Api);
+                entry.timestamp = now + chrono::Duration::milliseconds(i);
+                Arc::new(entry)
+            })
+            .collect();
+        session.routes.insert_routes(entries);
+
+        // Clock is advanced (to the next timer) whenever the session is waiting
+        let start = tokio::time::Instant::now();
+        let advertise = async {
+            while !session.initial_dump_sent {
+                session.run().await.unwrap();
+            }
+            start.elapsed()
+        };
+        let receive = async {
+            let mut routes = 0;
+            loop {
+                if let Message::Update(update) = remote.next().await.unwrap().unwrap() {
+                    if is_end_of_rib(&update) {
+                        break routes;
+                    }
+                    routes += update.announced_routes.len();
+                }
+            }
+        };
+        let (elapsed, received) = tokio::join!(advertise, receive);
+        // End-of-RIB follows all initial routes, paced at a burst of 100, then 100/s
+        assert_eq!(received, 300);
+        assert!(
+            elapsed >= std::time::Duration::from_millis(1800),
+            "{:?}",
+            elapsed
+        );
+        assert!(elapsed < std::time::Duration::from_secs(4), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paced_updates() {
+        let config = format!("{}max_update_rate = 100\n", peer_config(65000));
+        let (mut session, mut remote) = session_for(&config).await;
+        session.state = SessionState::Established;
+        session.routes.families = Families::new(vec![Family::new(AFI::IPV4, SAFI::Unicast)]);
+        let now = Utc::now();
+        let entries: Vec<Arc<ExportEntry>> = (0..1000)
+            .map(|i| {
+                let prefix = format!("10.{}.{}.0/24", i / 256, i % 256);
+                let spec = RouteSpec::new(prefix.parse().unwrap(), "127.0.0.1".parse().unwrap());
+                let mut entry = ExportEntry::new(exported(&spec), EntrySource::Api);
+                entry.timestamp = now + chrono::Duration::milliseconds(i);
+                Arc::new(entry)
+            })
+            .collect();
+        session.routes.insert_routes(entries);
+
+        // Clock is advanced (to the next timer) whenever the session is waiting
+        let start = tokio::time::Instant::now();
+        let advertise = async {
+            while !session.routes.pending().is_empty() {
+                session.run().await.unwrap();
+            }
+            start.elapsed()
+        };
+        let receive = async {
+            let mut routes = 0;
+            while routes < 1000 {
+                if let Message::Update(update) = remote.next().await.unwrap().unwrap() {
+                    routes += update.announced_routes.len();
+                }
+            }
+            routes
+        };
+        let (elapsed, received) = tokio::join!(advertise, receive);
+        assert_eq!(received, 1000);
+        // A burst of 100, then the remaining 900 at 100/s
+        assert!(
+            elapsed >= std::time::Duration::from_secs(8),
+            "{:?}",
+            elapsed
+        );
+        assert!(
+            elapsed < std::time::Duration::from_secs(12),
+            "{:?}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn test_prepend_as_path() {
+        let lengths = |as_path: &ASPath| {
+            as_path
+                .segments
+                .iter()
+                .map(|segment| match segment {
+                    Segment::AS_SEQUENCE(asns) => asns.len(),
+                    Segment::AS_SET(asns) => asns.len(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let empty = ASPath { segments: vec![] };
+        assert!(prepend_as_path(&empty, 65000, 0).segments.is_empty());
+        assert_eq!(lengths(&prepend_as_path(&empty, 65000, 1)), vec![1]);
+        assert_eq!(lengths(&prepend_as_path(&empty, 65000, 5)), vec![5]);
+
+        let path = ASPath {
+            segments: vec![
+                Segment::AS_SEQUENCE(vec![100, 200]),
+                Segment::AS_SET(vec![300, 400]),
+            ],
+        };
+        assert_eq!(lengths(&prepend_as_path(&path, 65000, 0)), vec![2, 2]);
+        assert_eq!(lengths(&prepend_as_path(&path, 65000, 1)), vec![3, 2]);
+        let prepended = prepend_as_path(&path, 65000, 5);
+        assert_eq!(lengths(&prepended), vec![7, 2]);
+        match &prepended.segments[0] {
+            Segment::AS_SEQUENCE(asns) => {
+                assert_eq!(asns, &vec![65000, 65000, 65000, 65000, 65000, 100, 200])
+            }
+            _ => panic!("Expected AS_SEQUENCE"),
+        }
+        // A leading AS_SET gets a new AS_SEQUENCE in front
+        let set = ASPath {
+            segments: vec![Segment::AS_SET(vec![300, 400])],
+        };
+        assert_eq!(lengths(&prepend_as_path(&set, 65000, 2)), vec![2, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_as_path_prepend_config() {
+        let spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        let as_path_len = |update: &Update| match update.get(bgp_rs::Identifier::AS_PATH) {
+            Some(PathAttribute::AS_PATH(as_path)) => as_path.sequence().unwrap().len(),
+            _ => panic!("Expected AS_PATH"),
+        };
+        let (ebgp, _remote) = session_for(&peer_config(65001)).await;
+        assert_eq!(as_path_len(&ebgp.create_update(&exported(&spec))), 1);
+        let config = format!("{}as_path_prepend = 5\n", peer_config(65001));
+        let (prepend, _remote) = session_for(&config).await;
+        assert_eq!(as_path_len(&prepend.create_update(&exported(&spec))), 5);
+        // Capped
+        let config = format!("{}as_path_prepend = 200\n", peer_config(65001));
+        let (capped, _remote) = session_for(&config).await;
+        assert_eq!(
+            as_path_len(&capped.create_update(&exported(&spec))),
+            MAX_AS_PATH_PREPEND as usize
+        );
+        // Never prepended for iBGP
+        let config = format!("{}as_path_prepend = 5\n", peer_config(65000));
+        let (ibgp, _remote) = session_for(&config).await;
+        assert_eq!(as_path_len(&ibgp.create_update(&exported(&spec))), 0);
+    }
+
+    #[tokio::test]
+    async fn test_export_rts() {
+        let mut spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        spec.attributes.communities = vec!["target:65000:1".to_string()];
+        let config = format!(
+            "{}export_rts = [\"target:65000:1\", \"target:65000:2\"]\n",
+            peer_config(65000)
+        );
+        let (session, _remote) = session_for(&config).await;
+        let update = session.create_update(&exported(&spec));
+        let extended = match update.get(bgp_rs::Identifier::EXTENDED_COMMUNITIES) {
+            Some(PathAttribute::EXTENDED_COMMUNITIES(extended)) => extended.clone(),
+            _ => panic!("Expected EXTENDED_COMMUNITIES"),
+        };
+        let target = |local: u8| u64::from_be_bytes([0x00, 0x02, 0xfd, 0xe8, 0, 0, 0, local]);
+        // Route targets already on the route aren't duplicated
+        assert_eq!(extended, vec![target(1), target(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_med_out() {
+        let mut spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        let med = |update: &Update| match update.get(bgp_rs::Identifier::MULTI_EXIT_DISC) {
+            Some(PathAttribute::MULTI_EXIT_DISC(med)) => Some(*med),
+            _ => None,
+        };
+        let (session, _remote) = session_for(&peer_config(65001)).await;
+        assert_eq!(med(&session.create_update(&exported(&spec))), None);
+        let config = format!("{}med_out = 200\n", peer_config(65001));
+        let (med_out, _remote) = session_for(&config).await;
+        assert_eq!(med(&med_out.create_update(&exported(&spec))), Some(200));
+        // Overrides the route's MED
+        spec.attributes.multi_exit_disc = Some(10);
+        assert_eq!(med(&session.create_update(&exported(&spec))), Some(10));
+        assert_eq!(med(&med_out.create_update(&exported(&spec))), Some(200));
+    }
+
+    #[test]
+    fn test_shutdown_communication() {
+        let data = shutdown_communication("Maintenance ☕");
+        assert_eq!(data[0], 15);
+        assert_eq!(data.len(), 16);
+        assert_eq!(std::str::from_utf8(&data[1..]).unwrap(), "Maintenance ☕");
+        assert_eq!(shutdown_communication(""), vec![0]);
+
+        // Truncated to 255 bytes, without splitting a multi-byte character
+        let data = shutdown_communication(&"é".repeat(200));
+        assert_eq!(data[0], 254);
+        assert_eq!(data.len(), 255);
+        assert_eq!(std::str::from_utf8(&data[1..]).unwrap(), "é".repeat(127));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_shutdown_message() {
+        let config = format!(
+            "{}enabled = false\nshutdown_message = \"Scheduled maintenance\"\n",
+            peer_config(65000)
+        );
+        let (mut session, mut remote) = session_for(&config).await;
+        let err = session.run().await.unwrap_err();
+        assert_eq!(err.notification(), Some((6, 2)));
+        assert_eq!(err.shutdown_message(), Some("Scheduled maintenance"));
+        session
+            .notify_with_message(6, 2, err.shutdown_message().unwrap())
+            .await
+            .unwrap();
+        match remote.next().await {
+            Some(Ok(Message::Notification(notification))) => {
+                assert_eq!(notification.minor_err_code, 2);
+                assert_eq!(notification.data[0] as usize, notification.data.len() - 1);
+                assert_eq!(&notification.data[1..], b"Scheduled maintenance");
+            }
+            other => panic!("Expected NOTIFICATION, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_byte_counts() {
+        let (mut session, mut remote) = session_for(&peer_config(65000)).await;
+        let spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        let update = session.create_update(&exported(&spec));
+        let mut body: Vec<u8> = vec![];
+        encode_update(&update, &mut body).unwrap();
+        let framed = (HEADER_SIZE + body.len()) as u64;
+
+        assert_eq!(session.protocol.codec().bytes_sent(), 0);
+        session.send_message(Message::Update(update)).await.unwrap();
+        assert_eq!(session.protocol.codec().bytes_sent(), framed);
+        session.send_message(Message::KeepAlive).await.unwrap();
+        assert_eq!(session.protocol.codec().bytes_sent(), framed + 19);
+
+        for _ in 0..2 {
+            remote.next().await.unwrap().unwrap();
+        }
+        assert_eq!(remote.codec().bytes_received(), framed + 19);
+        assert_eq!(remote.codec().bytes_sent(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_batched_updates_message_size() {
+        let (session, _remote) = session_for(&peer_config(65000)).await;
+        let entries: Vec<Arc<ExportEntry>> = (0..2000u32)
+            .map(|i| {
+                let spec = RouteSpec::new(
+                    IpNetwork::new(IpAddr::V4(std::net::Ipv4Addr::from(0x0a00_0000 + i)), 32)
+                        .unwrap(),
+                    "127.0.0.1".parse().unwrap(),
+                );
+                Arc::new(ExportEntry::new(exported(&spec), EntrySource::Api))
+            })
+            .collect();
+        let updates = session.create_batched_updates(&entries);
+        // 2000 * 5 bytes of NLRI requires splitting into multiple messages
+        assert!(updates.len() > 1);
+        assert_eq!(
+            updates
+                .iter()
+                .map(|(u, _)| u.announced_routes.len())
+                .sum::<usize>(),
+            2000
+        );
+        for (update, _) in updates {
+            let mut bytes: Vec<u8> = vec![];
+            update.encode(&mut bytes).unwrap();
+            assert!(bytes.len() + HEADER_SIZE <= super::super::codec::MAX_MESSAGE_SIZE);
+        }
+        assert!(is_end_of_rib(&create_end_of_rib(Family::new(
+            AFI::IPV6,
+            SAFI::Unicast
+        ))));
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_before_open_confirm() {
+        // Lenient (default), ignored
+        let (mut session, _remote) = session_for(&peer_config(65000)).await;
+        session.update_state(SessionState::OpenSent);
+        assert!(matches!(
+            session.process_message(Message::KeepAlive),
+            Ok(MessageResponse::Empty)
+        ));
+        assert_eq!(session.state, SessionState::OpenSent);
+
+        // Strict, FSM Error (Unexpected message in OpenSent)
+        let config = format!("{}strict_fsm = true\n", peer_config(65000));
+        let (mut session, _remote) = session_for(&config).await;
+        session.update_state(SessionState::OpenSent);
+        let err = session.process_message(Message::KeepAlive).unwrap_err();
+        assert!(matches!(err, SessionError::FiniteStateMachine(1)));
+        assert_eq!(err.notification(), Some((5, 1)));
+    }
+
+    #[tokio::test]
+    async fn test_role_negotiation() {
+        let with_role = |role: &str, strict: bool| {
+            format!(
+                "{}role = \"{}\"\nstrict_role = {}\n",
+                peer_config(65001),
+                role,
+                strict
+            )
+        };
+        // OPEN sent by the peer (AS 65001)
+        let open_from = |role: Option<&str>| {
+            let config = format!(
+                "router_id = \"2.2.2.2\"\ndefault_as = 65001\n[[peers]]\n\
+                 remote_ip = \"127.0.0.1\"\nremote_as = 65000\n{}",
+                role.map(|r| format!("role = \"{}\"\n", r))
+                    .unwrap_or_default()
+            );
+            async move { session_for(&config).await.0.create_open() }
+        };
+        let (mut provider, _remote) = session_for(&with_role("provider", true)).await;
+        assert!(has_capability(&provider.create_open().parameters, 9));
+        assert!(!has_capability(&open_from(None).await.parameters, 9));
+
+        provider.update_state(SessionState::OpenSent);
+        provider
+            .process_message(Message::Open(open_from(Some("customer")).await))
+            .unwrap();
+        assert_eq!(provider.peer_role, Some(Role::Customer));
+
+        // Peer advertises the same role
+        let (mut session, mut remote) = session_for(&with_role("provider", false)).await;
+        session.update_state(SessionState::OpenSent);
+        let err = session
+            .process_message(Message::Open(open_from(Some("provider")).await))
+            .unwrap_err();
+        assert!(matches!(err, SessionError::RoleMismatch(_)));
+        let (major, minor) = err.notification().unwrap();
+        assert_eq!((major, minor), (2, 11));
+        session.notify(major, minor).await.unwrap();
+        match remote.next().await {
+            Some(Ok(Message::Notification(notification))) => {
+                assert_eq!(notification.major_err_code, 2);
+                assert_eq!(notification.minor_err_code, 11);
+            }
+            other => panic!("Expected NOTIFICATION, got {:?}", other),
+        }
+
+        // No role advertised, only a mismatch when strict
+        let (mut lenient, _remote) = session_for(&with_role("peer", false)).await;
+        lenient.update_state(SessionState::OpenSent);
+        assert!(lenient
+            .process_message(Message::Open(open_from(None).await))
+            .is_ok());
+        let (mut strict, _remote) = session_for(&with_role("peer", true)).await;
+        strict.update_state(SessionState::OpenSent);
+        assert!(matches!(
+            strict.process_message(Message::Open(open_from(None).await)),
+            Err(SessionError::RoleMismatch(_))
+        ));
+    }
+
+    /// Run a session until it returns an update (E.g. not just a timer tick)
+    async fn next_update(session: &mut Session) -> SessionUpdate {
+        let next = async {
+            loop {
+                if let Some(update) = session.run().await.unwrap() {
+                    break update;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), next)
+            .await
+            .expect("Session update")
+    }
+
+    #[tokio::test]
+    async fn test_only_to_customer() {
+        let spec = RouteSpec::new("10.0.0.0/24".parse().unwrap(), "127.0.0.1".parse().unwrap());
+        let learned = |otc: Option<u32>| {
+            let mut entry =
+                ExportEntry::new(exported(&spec), EntrySource::Peer([10, 0, 0, 2].into()));
+            entry.only_to_customer = otc;
+            Arc::new(entry)
+        };
+        let sent_otcs = |session: &Session| -> Vec<Option<u32>> {
+            session
+                .create_batched_updates(&[learned(None), learned(Some(65010))])
+                .into_iter()
+                .map(|(_, otc)| otc)
+                .collect()
+        };
+        // Routes advertised to a customer are marked with the local AS,
+        // unless they already have OTC (RFC 9234 [5])
+        let config = format!("{}role = \"provider\"\n", peer_config(65001));
+        let (mut session, mut remote) = session_for(&config).await;
+        assert_eq!(sent_otcs(&session), vec![Some(65000), Some(65010)]);
+        // OTC is kept when advertised over iBGP
+        let (ibgp, _remote) = session_for(&peer_config(65000)).await;
+        assert_eq!(sent_otcs(&ibgp), vec![None, Some(65010)]);
+
+        let (update, otc) = session.create_batched_updates(&[learned(None)]).remove(0);
+        session
+            .send_announcement(update.clone(), otc)
+            .await
+            .unwrap();
+        remote.next().await.unwrap().unwrap();
+        assert_eq!(remote.codec().received_otc(), Some(65000));
+
+        // Routes with OTC received from a customer are a leak, and treated as withdrawn
+        session.update_state(SessionState::Established);
+        remote.codec_mut().set_only_to_customer(Some(65002));
+        remote.send(Message::Update(update.clone())).await.unwrap();
+        match next_update(&mut session).await {
+            SessionUpdate::Learned((_, _, update, _)) => {
+                assert!(update.announced_routes.is_empty());
+                assert_eq!(update.withdrawn_routes.len(), 1);
+            }
+            other => panic!("Expected Learned, got {:?}", other),
+        }
+
+        // Routes from a provider are marked with its AS if they don't have OTC
+        let config = format!("{}role = \"customer\"\n", peer_config(65001));
+        let (mut session, mut remote) = session_for(&config).await;
+        session.update_state(SessionState::Established);
+        let update = Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(bgp_rs::Origin::IGP),
+                PathAttribute::AS_PATH(ASPath {
+                    segments: vec![Segment::AS_SEQUENCE(vec![65001])],
+                }),
+                PathAttribute::NEXT_HOP("127.0.0.1".parse().unwrap()),
+            ],
+            announced_routes: vec![NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into())],
+        };
+        for (sent, learned) in [(None, Some(65001)), (Some(65002), Some(65002))] {
+            remote.codec_mut().set_only_to_customer(sent);
+            remote.send(Message::Update(update.clone())).await.unwrap();
+            match next_update(&mut session).await {
+                SessionUpdate::Learned((peer, config, update, otc)) => {
+                    assert_eq!(otc, learned);
+                    // Stored with the learned route
+                    let mut rib = crate::rib::RIB::new();
+                    rib.update_from_peer_with_otc(peer, &config, update, otc)
+                        .unwrap();
+                    assert_eq!(rib.get_routes_from_peer(peer)[0].only_to_customer, learned);
+                }
+                other => panic!("Expected Learned, got {:?}", other),
+            }
+        }
+    }
+
+    /// Config for the other side of `peer_config(65001)` sessions
+    const REMOTE_CONFIG: &str = "router_id = \"2.2.2.2\"\ndefault_as = 65001\n[[peers]]\n\
+                                 remote_ip = \"127.0.0.1\"\nremote_as = 65000\n";
+
+    #[tokio::test]
+    async fn test_delay_open() {
+        // Neither side initiated the connection (to `dest_port`), so only the delayed
+        // OPEN gets the session going
+        let config = format!("{}delay_open = 5\n", peer_config(65001));
+        let (session, remote) = session_for(&config).await;
+        let remote_config = crate::config::from_str(REMOTE_CONFIG).unwrap();
+        let peer = Session::new(remote_config.peers[0].clone(), remote);
+        // Clock is advanced (to the next timer) whenever the sessions are waiting
+        tokio::time::pause();
+        let start = tokio::time::Instant::now();
+        assert!(session.open_delay().is_some());
+
+        let establish = |mut session: Session| async move {
+            while session.state != SessionState::Established {
+                session.run().await.unwrap();
+            }
+            session
+        };
+        let (session, peer) = tokio::time::timeout(Duration::from_secs(60), async {
+            tokio::join!(establish(session), establish(peer))
+        })
+        .await
+        .expect("Sessions established");
+        assert!(start.elapsed() >= Duration::from_secs(5));
+        assert_eq!(peer.state, SessionState::Established);
+        assert!(session.open_delay().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delay_open_peer_first() {
+        let config = format!("{}delay_open = 60\n", peer_config(65001));
+        let (mut session, mut remote) = session_for(&config).await;
+        let (peer, _) = session_for(REMOTE_CONFIG).await;
+        remote
+            .send(Message::Open(peer.create_open()))
+            .await
+            .unwrap();
+
+        // OPEN is sent right away in reply, without waiting for the DelayOpen timer
+        // (`run()` may also return for a hold timer tick)
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while session.state == SessionState::Connect {
+                assert!(session.run().await.unwrap().is_none());
+            }
+        })
+        .await
+        .expect("OPEN received");
+        assert_eq!(session.state, SessionState::OpenConfirm);
+        assert!(session.open_delay().is_none());
+        match remote.next().await {
+            Some(Ok(Message::Open(open))) => assert_eq!(open.peer_asn, 65000),
+            other => panic!("Expected OPEN, got {:?}", other),
+        }
+
+        remote.send(Message::KeepAlive).await.unwrap();
+        let established = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                if let Some(update) = session.run().await.unwrap() {
+                    break update;
+                }
+            }
+        })
+        .await;
+        assert!(
+            matches!(established, Ok(SessionUpdate::Established(_))),
+            "{:?}",
+            established
+        );
+        assert_eq!(session.state, SessionState::Established);
+    }
+}
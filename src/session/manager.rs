@@ -12,9 +12,13 @@ use tokio::{
 };
 
 use super::codec::{MessageCodec, MessageProtocol};
-use super::{Poller, PollerTx, Session, SessionError, SessionUpdate};
+use super::{
+    GracefulRestart, PeerHistory, Poller, PollerTx, Session, SessionError, SessionState,
+    SessionUpdate,
+};
 use crate::config::{PeerConfig, ServerConfig};
-use crate::rib::RIB;
+use crate::rib::{aggregate_routes, ExportEntry, RIB};
+use crate::utils::longest_match;
 
 /// Struct to contain active [`Session`s](session/struct.Session.html) and managing
 /// of new incoming/outbound sessions (via `Poller`)
@@ -22,7 +26,12 @@ pub struct SessionManager {
     pub(crate) idle_peers: Poller,
     // Active Sessions                  remote_ip: session
     pub(crate) sessions: Arc<RwLock<HashMap<IpAddr, Session>>>,
-    config: Arc<ServerConfig>,
+    // Last error & NOTIFICATION for peers with ended sessions
+    pub(crate) history: RwLock<HashMap<IpAddr, PeerHistory>>,
+    // Peers enabled/disabled at runtime (by config network), these take precedence over
+    // `enabled` in the config file (and are kept across config reloads until BGPd restarts)
+    enabled_overrides: std::sync::RwLock<HashMap<IpNetwork, bool>>,
+    pub(crate) config: Arc<ServerConfig>,
     poller_tx: PollerTx,
     config_watch: watch::Receiver<Arc<ServerConfig>>,
 }
@@ -30,11 +39,11 @@ pub struct SessionManager {
 impl SessionManager {
     pub fn new(
         config: Arc<ServerConfig>,
-        listener: TcpListener,
+        listeners: Vec<TcpListener>,
         config_watch: watch::Receiver<Arc<ServerConfig>>,
     ) -> Self {
         let (poller_tx, poller_rx) = mpsc::unbounded_channel();
-        let mut poller = Poller::new(listener, config.poll_interval.into(), poller_rx);
+        let mut poller = Poller::new(listeners, config.poll_interval.into(), poller_rx);
         for peer_config in config.peers.iter() {
             poller.upsert_config(peer_config.clone());
         }
@@ -42,6 +51,8 @@ impl SessionManager {
         Self {
             idle_peers: poller,
             sessions: Arc::new(RwLock::new(HashMap::with_capacity(config.peers.len()))),
+            history: RwLock::new(HashMap::new()),
+            enabled_overrides: std::sync::RwLock::new(HashMap::new()),
             config,
             poller_tx,
             config_watch,
@@ -49,7 +60,156 @@ impl SessionManager {
     }
 
     pub fn get_peer_configs(&self) -> Vec<Arc<PeerConfig>> {
-        self.config.peers.to_vec()
+        self.config
+            .peers
+            .iter()
+            .map(|config| self.with_overrides(config))
+            .collect()
+    }
+
+    /// Peer config with any runtime `enabled` override applied
+    fn with_overrides(&self, config: &Arc<PeerConfig>) -> Arc<PeerConfig> {
+        let overrides = self.enabled_overrides.read().expect("Overrides lock");
+        match overrides.get(&config.remote_ip) {
+            Some(enabled) if *enabled != config.enabled => Arc::new(PeerConfig {
+                enabled: *enabled,
+                ..(**config).clone()
+            }),
+            _ => config.clone(),
+        }
+    }
+
+    /// Enable or disable a configured peer (or peer network) at runtime
+    /// Disabling ends active sessions (Cease NOTIFICATION) and stops reconnecting,
+    /// enabling resumes polling the peer
+    pub async fn set_peer_enabled(&self, peer: IpAddr, enabled: bool) -> Result<(), SessionError> {
+        let config = longest_match(self.config.peers.iter().map(|p| &p.remote_ip), peer)
+            .and_then(|network| self.config.peers.iter().find(|p| p.remote_ip == *network))
+            .ok_or_else(|| SessionError::Other(format!("No peer configured for {}", peer)))?;
+        let current = self.with_overrides(config);
+        self.enabled_overrides
+            .write()
+            .expect("Overrides lock")
+            .insert(config.remote_ip, enabled);
+        if current.enabled == enabled {
+            return Ok(());
+        }
+        let config = self.with_overrides(config);
+        info!(
+            "Peer {} {}",
+            config.remote_ip,
+            if enabled { "enabled" } else { "disabled" }
+        );
+        let mut sessions = self.sessions.write().await;
+        let mut active = 0;
+        for session in sessions
+            .values_mut()
+            .filter(|s| s.config.remote_ip == config.remote_ip)
+        {
+            // Disabled sessions are ended (without returning to Idle Peers) on the next run
+            session.update_config(config.clone());
+            active += 1;
+        }
+        if !enabled || active == 0 {
+            self.poller_tx
+                .send(config)
+                .map_err(|err| SessionError::Other(err.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Last error & NOTIFICATION recorded for a peer (from ended sessions)
+    pub async fn get_history(&self, peer: IpAddr) -> Option<PeerHistory> {
+        self.history.read().await.get(&peer).cloned()
+    }
+
+    /// Record why a session ended (the error, if any, and the last NOTIFICATION)
+    async fn record_ended(&self, peer: IpAddr, session: &Session, error: Option<&SessionError>) {
+        let mut history = self.history.write().await;
+        let history = history.entry(peer).or_default();
+        if let Some(error) = error {
+            history.last_error = Some(error.to_string());
+        }
+        if let Some(notification) = &session.last_notification {
+            history.last_notification = Some(notification.clone());
+        }
+    }
+
+    /// End an Established session (Cease NOTIFICATION) and return the peer to Idle peers,
+    /// so it can be re-established by the poller
+    /// An optional `reason` is sent to the peer as an RFC 9003 Shutdown Communication
+    pub async fn clear_session(
+        &self,
+        peer: IpAddr,
+        reason: Option<&str>,
+    ) -> Result<(), SessionError> {
+        let mut sessions = self.sessions.write().await;
+        match sessions.get(&peer) {
+            Some(session) if session.state == SessionState::Established => (),
+            _ => {
+                return Err(SessionError::Other(format!(
+                    "No established session for {}",
+                    peer
+                )))
+            }
+        }
+        let mut session = sessions.remove(&peer).expect("Active session");
+        warn!("Clearing session with {}", peer);
+        match reason {
+            Some(reason) => {
+                session
+                    .notify_with_message(
+                        6, /* Cease */
+                        4, /* Administrative Reset */
+                        reason,
+                    )
+                    .await?
+            }
+            None => {
+                session
+                    .notify(6 /* Cease */, 4 /* Administrative Reset */)
+                    .await?
+            }
+        }
+        self.record_ended(peer, &session, None).await;
+        self.poller_tx
+            .send(session.config.clone())
+            .map_err(|err| SessionError::Other(err.to_string()))?;
+        Ok(())
+    }
+
+    /// End all sessions, sending Established peers a Cease NOTIFICATION (Administrative Shutdown)
+    /// An optional `message` is sent to each peer as an RFC 9003 Shutdown Communication
+    /// Returns the peers that were notified
+    pub async fn shutdown_sessions(&self, message: Option<&str>) -> Vec<IpAddr> {
+        let mut notified: Vec<IpAddr> = Vec::new();
+        for (peer, mut session) in self.sessions.write().await.drain() {
+            if session.state != SessionState::Established {
+                continue;
+            }
+            warn!("Shutting down session with {}", peer);
+            let result = match message {
+                Some(message) => {
+                    session
+                        .notify_with_message(
+                            6, /* Cease */
+                            2, /* Administrative Shutdown */
+                            message,
+                        )
+                        .await
+                }
+                None => {
+                    session
+                        .notify(6 /* Cease */, 2 /* Administrative Shutdown */)
+                        .await
+                }
+            };
+            match result {
+                Ok(()) => notified.push(peer),
+                Err(err) => warn!("Error notifying {} of shutdown: {}", peer, err),
+            }
+        }
+        notified
     }
 
     pub async fn get_update(
@@ -68,45 +228,67 @@ impl SessionManager {
         //     select_all(futs).fuse()
         // };
         {
-            // Store sessions that have ended (remote_ip, router_id)
-            let mut ended_sessions: Vec<IpAddr> = Vec::new();
+            // Store sessions that have ended (remote_ip, graceful restart state)
+            let mut ended_sessions: Vec<(IpAddr, Option<GracefulRestart>)> = Vec::new();
             let mut sessions = self.sessions.write().await;
             for (remote_ip, session) in sessions.iter_mut() {
-                let routes = rib.read().await.get_routes_for_peer(session.addr);
+                let (routes, received, default_condition) = {
+                    let rib = rib.read().await;
+                    let default_condition = session
+                        .config
+                        .default_originate
+                        .as_ref()
+                        .and_then(|d| d.conditional_on)
+                        .map(|prefix| rib.contains_prefix(prefix))
+                        .unwrap_or(true);
+                    (
+                        rib.get_routes_for_peer(session.addr),
+                        rib.get_routes_from_peer(session.addr).len(),
+                        default_condition,
+                    )
+                };
+                let routes = aggregate_routes(routes, &session.config);
+                let routes = session.originate_default(routes, default_condition);
+                let routes = filter_route_leaks(routes, &session.config);
+                let routes = session.limit_outbound_routes(routes);
                 session.routes.insert_routes(routes);
 
-                match session.run().await {
+                let result = match session.check_prefix_limit(received) {
+                    Ok(()) => session.run().await,
+                    Err(err) => Err(err),
+                };
+                match result {
                     Ok(update) => {
                         if update.is_some() {
                             return Ok(update);
                         }
                     }
                     Err(err) => {
-                        match err {
-                            SessionError::Deconfigured => {
-                                session.notify(6, 3).await?;
-                                break; // Don't re-add the peer to Idle Peers
-                            }
-                            SessionError::HoldTimeExpired(_) => {
-                                session.notify(4, 0).await?;
-                            }
-                            SessionError::FiniteStateMachine(minor) => {
-                                session.notify(5, minor).await?;
-                            }
-                            SessionError::OpenAsnMismatch(_, _) => {
-                                session.notify(3, 2).await?;
+                        match (err.notification(), err.shutdown_message()) {
+                            // Already notified by the session before it ended
+                            _ if matches!(err, SessionError::HoldTimeExpired(_)) => (),
+                            (Some((major, minor)), Some(message)) => {
+                                session.notify_with_message(major, minor, message).await?;
                             }
+                            (Some((major, minor)), None) => session.notify(major, minor).await?,
                             _ => (),
                         }
+                        self.record_ended(*remote_ip, session, Some(&err)).await;
+                        if let SessionError::Deconfigured
+                        | SessionError::AdministrativeShutdown(_) = err
+                        {
+                            ended_sessions.push((*remote_ip, None));
+                            break; // Don't re-add the peer to Idle Peers
+                        }
                         warn!("{}", err);
                         self.poller_tx.send(session.config.clone()).unwrap();
-                        ended_sessions.push(*remote_ip);
+                        ended_sessions.push((*remote_ip, session.graceful_restart()));
                     }
                 }
             }
             // Remove ended sessions and alert handler for RIB removal
             if !ended_sessions.is_empty() {
-                for remote_ip in &ended_sessions {
+                for (remote_ip, _) in &ended_sessions {
                     sessions.remove(remote_ip);
                 }
                 return Ok(Some(SessionUpdate::Ended(ended_sessions)));
@@ -133,13 +315,15 @@ impl SessionManager {
                 Ok(None)
             },
             Ok(()) = self.config_watch.changed() => {
-                let new_config = self.config_watch.borrow();
-                self.config = self.config_watch.borrow().clone();
+                // Not holding the watch lock across awaits (so `get_update()` is `Send`)
+                let new_config = self.config_watch.borrow().clone();
+                self.config = new_config.clone();
                 let configs_by_network: HashMap<IpNetwork, Arc<PeerConfig>> = new_config
                     .peers
                     .iter()
-                    .map(|p| (p.remote_ip, p.clone()))
+                    .map(|p| (p.remote_ip, self.with_overrides(p)))
                     .collect();
+                let mut ended_sessions = vec![];
                 { // Current Sessions lock scope
                     let mut current_sessions = self.sessions.write().await;
                     let removed_peers = find_removed_peers(&mut current_sessions, &configs_by_network);
@@ -154,17 +338,39 @@ impl SessionManager {
                         warn!("Session ended with {}, peer de-configured", removed_ip);
                         let mut session = current_sessions.remove(&removed_ip).expect("Active session");
                         session.notify(6 /* Cease */, 3/* Deconfigured */).await?;
+                        self.record_ended(removed_ip, &session, Some(&SessionError::Deconfigured)).await;
+                        ended_sessions.push((removed_ip, None));
                     }
                 }
 
-                self.idle_peers.replace_configs(configs_by_network.into_iter().map(|(_, c)| c).collect());
-                Ok(None)
+                self.idle_peers.replace_configs(configs_by_network.into_values().collect());
+                // Alert handler for RIB removal of de-configured peers
+                if ended_sessions.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(SessionUpdate::Ended(ended_sessions)))
+                }
             },
             else => Ok(None),
         }
     }
 }
 
+/// Routes with an Only to Customer (OTC) attribute (E.g. learned from a provider, peer,
+/// or route server) are only advertised to customers & route server clients (RFC 9234 [5])
+fn filter_route_leaks(routes: Vec<Arc<ExportEntry>>, config: &PeerConfig) -> Vec<Arc<ExportEntry>> {
+    if !config
+        .role
+        .map_or(false, |role| role.neighbor_is_upstream())
+    {
+        return routes;
+    }
+    routes
+        .into_iter()
+        .filter(|entry| entry.only_to_customer.is_none())
+        .collect()
+}
+
 fn find_removed_peers(
     sessions: &mut HashMap<IpAddr, Session>,
     configs: &HashMap<IpNetwork, Arc<PeerConfig>>,
@@ -172,7 +378,7 @@ fn find_removed_peers(
     sessions
         .iter_mut()
         .filter_map(|(addr, current_session)| {
-            if let Some(network) = configs.keys().find(|n| n.contains(*addr)) {
+            if let Some(network) = longest_match(configs.keys(), *addr) {
                 let config = configs.get(network).expect("Network has config");
                 current_session.update_config(config.clone());
                 None
@@ -182,3 +388,297 @@ fn find_removed_peers(
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::Message;
+    use futures::StreamExt;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_clear_session() {
+        let config = Arc::new(
+            crate::config::from_str(
+                r#"
+                router_id = "1.1.1.1"
+                default_as = 65000
+                [[peers]]
+                remote_ip = "127.0.0.1"
+                remote_as = 65001
+                "#,
+            )
+            .unwrap(),
+        );
+        let (_config_tx, config_rx) = watch::channel(config.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (remote, local) = tokio::join!(
+            TcpStream::connect(listener.local_addr().unwrap()),
+            listener.accept()
+        );
+        let mut remote = MessageProtocol::new(remote.unwrap(), MessageCodec::new());
+        let manager = SessionManager::new(config.clone(), vec![listener], config_rx);
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut session = Session::new(
+            config.peers[0].clone(),
+            MessageProtocol::new(local.unwrap().0, MessageCodec::new()),
+        );
+
+        // Not established yet
+        session.state = SessionState::OpenConfirm;
+        manager.sessions.write().await.insert(peer, session);
+        assert!(manager.clear_session(peer, None).await.is_err());
+
+        manager.sessions.write().await.get_mut(&peer).unwrap().state = SessionState::Established;
+        manager.clear_session(peer, Some("Testing")).await.unwrap();
+        assert!(manager.sessions.read().await.is_empty());
+        let history = manager.get_history(peer).await.unwrap();
+        assert_eq!(
+            history.last_notification.unwrap().to_string(),
+            "Cease / Administrative Reset: \"Testing\" (sent)"
+        );
+        match remote.next().await {
+            Some(Ok(Message::Notification(notification))) => {
+                assert_eq!(notification.major_err_code, 6);
+                assert_eq!(notification.minor_err_code, 4);
+                assert_eq!(notification.data, b"\x07Testing");
+            }
+            other => panic!("Expected NOTIFICATION, got {:?}", other),
+        }
+        // No longer an active session
+        assert!(manager.clear_session(peer, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_peer_enabled() {
+        let config = Arc::new(
+            crate::config::from_str(
+                r#"
+                router_id = "1.1.1.1"
+                default_as = 65000
+                [[peers]]
+                remote_ip = "127.0.0.1"
+                remote_as = 65001
+                shutdown_message = "Disabled via API"
+                "#,
+            )
+            .unwrap(),
+        );
+        let (config_tx, config_rx) = watch::channel(config.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (remote, local) = tokio::join!(
+            TcpStream::connect(listener.local_addr().unwrap()),
+            listener.accept()
+        );
+        let mut remote = MessageProtocol::new(remote.unwrap(), MessageCodec::new());
+        let mut manager = SessionManager::new(config.clone(), vec![listener], config_rx);
+        let rib = Arc::new(RwLock::new(RIB::new()));
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let network = config.peers[0].remote_ip;
+        let mut session = Session::new(
+            config.peers[0].clone(),
+            MessageProtocol::new(local.unwrap().0, MessageCodec::new()),
+        );
+        session.state = SessionState::Established;
+        manager.sessions.write().await.insert(peer, session);
+        assert!(manager
+            .set_peer_enabled("10.0.0.1".parse().unwrap(), false)
+            .await
+            .is_err());
+
+        // Active session is ended (and not returned to Idle Peers)
+        manager.set_peer_enabled(peer, false).await.unwrap();
+        match manager.get_update(rib.clone()).await.unwrap() {
+            Some(SessionUpdate::Ended(ended)) => assert_eq!(ended, vec![(peer, None)]),
+            other => panic!("Expected ended session, got {:?}", other),
+        }
+        match remote.next().await {
+            Some(Ok(Message::Notification(notification))) => {
+                assert_eq!(notification.major_err_code, 6);
+                assert_eq!(notification.minor_err_code, 2);
+                assert_eq!(notification.data, b"\x10Disabled via API");
+            }
+            other => panic!("Expected NOTIFICATION, got {:?}", other),
+        }
+        assert!(manager.sessions.read().await.is_empty());
+        assert!(!manager.get_peer_configs()[0].enabled);
+
+        // Kept across config reloads
+        let reloaded = Arc::new(
+            crate::config::from_str(
+                r#"
+                router_id = "1.1.1.1"
+                default_as = 65000
+                [[peers]]
+                remote_ip = "127.0.0.1"
+                remote_as = 65001
+                "#,
+            )
+            .unwrap(),
+        );
+        config_tx.send(reloaded.clone()).unwrap();
+        for _ in 0..5 {
+            if Arc::ptr_eq(&manager.config, &reloaded) {
+                break;
+            }
+            manager.get_update(rib.clone()).await.unwrap();
+        }
+        assert!(Arc::ptr_eq(&manager.config, &reloaded));
+        assert!(!manager.get_peer_configs()[0].enabled);
+        assert_eq!(
+            manager.idle_peers.get_peer_state(&network),
+            Some(SessionState::Idle)
+        );
+
+        manager.set_peer_enabled(peer, true).await.unwrap();
+        assert!(manager.get_peer_configs()[0].enabled);
+    }
+
+    #[tokio::test]
+    async fn test_deconfigured_peer() {
+        let config = Arc::new(
+            crate::config::from_str(
+                r#"
+                router_id = "1.1.1.1"
+                default_as = 65000
+                [[peers]]
+                remote_ip = "127.0.0.1"
+                remote_as = 65001
+                "#,
+            )
+            .unwrap(),
+        );
+        let (config_tx, config_rx) = watch::channel(config.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (remote, local) = tokio::join!(
+            TcpStream::connect(listener.local_addr().unwrap()),
+            listener.accept()
+        );
+        let mut remote = MessageProtocol::new(remote.unwrap(), MessageCodec::new());
+        let mut manager = SessionManager::new(config.clone(), vec![listener], config_rx);
+        let rib = Arc::new(RwLock::new(RIB::new()));
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut session = Session::new(
+            config.peers[0].clone(),
+            MessageProtocol::new(local.unwrap().0, MessageCodec::new()),
+        );
+        session.state = SessionState::Established;
+        manager.sessions.write().await.insert(peer, session);
+
+        // Peer is removed from the config, the session is ended for RIB removal
+        let reloaded = Arc::new(
+            crate::config::from_str(
+                r#"
+                router_id = "1.1.1.1"
+                default_as = 65000
+                "#,
+            )
+            .unwrap(),
+        );
+        config_tx.send(reloaded).unwrap();
+        let mut ended = None;
+        for _ in 0..5 {
+            if let Some(SessionUpdate::Ended(peers)) =
+                manager.get_update(rib.clone()).await.unwrap()
+            {
+                ended = Some(peers);
+                break;
+            }
+        }
+        assert_eq!(ended, Some(vec![(peer, None)]));
+        match remote.next().await {
+            Some(Ok(Message::Notification(notification))) => {
+                assert_eq!(notification.major_err_code, 6);
+                assert_eq!(notification.minor_err_code, 3);
+            }
+            other => panic!("Expected NOTIFICATION, got {:?}", other),
+        }
+        assert!(manager.sessions.read().await.is_empty());
+        assert!(manager.get_peer_configs().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_sessions() {
+        let config = Arc::new(
+            crate::config::from_str(
+                r#"
+                router_id = "1.1.1.1"
+                default_as = 65000
+                [[peers]]
+                remote_ip = "127.0.0.1"
+                remote_as = 65001
+                "#,
+            )
+            .unwrap(),
+        );
+        let (_config_tx, config_rx) = watch::channel(config.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (remote, local) = tokio::join!(
+            TcpStream::connect(listener.local_addr().unwrap()),
+            listener.accept()
+        );
+        let mut remote = MessageProtocol::new(remote.unwrap(), MessageCodec::new());
+        let manager = SessionManager::new(config.clone(), vec![listener], config_rx);
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut session = Session::new(
+            config.peers[0].clone(),
+            MessageProtocol::new(local.unwrap().0, MessageCodec::new()),
+        );
+        session.state = SessionState::Established;
+        manager.sessions.write().await.insert(peer, session);
+
+        let notified = manager.shutdown_sessions(Some("Maintenance")).await;
+        assert_eq!(notified, vec![peer]);
+        assert!(manager.sessions.read().await.is_empty());
+        match remote.next().await {
+            Some(Ok(Message::Notification(notification))) => {
+                assert_eq!(notification.major_err_code, 6);
+                assert_eq!(notification.minor_err_code, 2);
+                assert_eq!(notification.data, b"\x0bMaintenance");
+            }
+            other => panic!("Expected NOTIFICATION, got {:?}", other),
+        }
+        assert!(manager.shutdown_sessions(None).await.is_empty());
+    }
+
+    #[test]
+    fn test_filter_route_leaks() {
+        let config = crate::config::from_str(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_ip = "10.0.0.1"
+            remote_as = 65001
+            role = "customer"
+            [[peers]]
+            remote_ip = "10.0.0.2"
+            remote_as = 65002
+            role = "provider"
+            "#,
+        )
+        .unwrap();
+        let routes = || -> Vec<Arc<ExportEntry>> {
+            [None, Some(65002), Some(65004)]
+                .iter()
+                .map(|otc| {
+                    let mut entry = Arc::try_unwrap(
+                        crate::rib::default_routes(&config.peers[0], chrono::Utc::now()).remove(0),
+                    )
+                    .unwrap();
+                    entry.only_to_customer = *otc;
+                    Arc::new(entry)
+                })
+                .collect()
+        };
+        let otcs = |routes: Vec<Arc<ExportEntry>>| -> Vec<Option<u32>> {
+            routes.iter().map(|e| e.only_to_customer).collect()
+        };
+        // Routes with OTC (E.g. learned from a provider or over iBGP) aren't advertised to a provider
+        let advertised = filter_route_leaks(routes(), &config.peers[0]);
+        assert_eq!(otcs(advertised), vec![None]);
+        // All routes are advertised to customers
+        let advertised = filter_route_leaks(routes(), &config.peers[1]);
+        assert_eq!(advertised.len(), 3);
+    }
+}
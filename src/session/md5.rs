@@ -0,0 +1,191 @@
+//! TCP MD5 Signature Option (RFC 2385) for peer sessions
+//!
+//! Only supported on Linux, keys are applied with the `TCP_MD5SIG(_EXT)` socket option:
+//!   - On the BGP listener (for inbound connections), accepted sockets inherit the keys
+//!   - On the outbound socket before connecting
+//!
+//! Segments from a peer with a configured key but no (or a bad) signature are
+//! dropped by the kernel, so these connections are never seen by BGPd
+use std::io;
+
+use ipnetwork::IpNetwork;
+
+/// Maximum key length accepted by the kernel
+pub const MAX_KEY_LEN: usize = 80;
+
+/// Is the TCP MD5 Signature option supported on this platform
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Check a password can be used as an MD5 key on this platform
+pub fn validate(password: &str) -> io::Result<()> {
+    if !SUPPORTED {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "TCP MD5 signatures (md5_password) are only supported on Linux",
+        ));
+    }
+    if password.is_empty() || password.len() > MAX_KEY_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("md5_password must be 1-{} bytes", MAX_KEY_LEN),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::io;
+    use std::mem;
+    use std::net::IpAddr;
+    use std::os::unix::io::RawFd;
+
+    use ipnetwork::IpNetwork;
+
+    use super::MAX_KEY_LEN;
+
+    // Not exposed by libc
+    const TCP_MD5SIG_FLAG_PREFIX: u8 = 0x1;
+
+    /// `struct tcp_md5sig` (linux/tcp.h)
+    #[repr(C)]
+    struct TcpMd5Sig {
+        addr: libc::sockaddr_storage,
+        flags: u8,
+        prefix_len: u8,
+        key_len: u16,
+        _pad: u32,
+        key: [u8; MAX_KEY_LEN],
+    }
+
+    pub fn set_md5_key(
+        fd: RawFd,
+        socket_is_v6: bool,
+        peer: IpNetwork,
+        key: Option<&str>,
+    ) -> io::Result<()> {
+        // IPv4 peers connecting to a dual-stack socket are IPv4-mapped
+        let (addr, prefix_len) = match (peer.ip(), socket_is_v6) {
+            (IpAddr::V4(addr), true) => (IpAddr::V6(addr.to_ipv6_mapped()), peer.prefix() + 96),
+            (addr, _) => (addr, peer.prefix()),
+        };
+        let mut sig: TcpMd5Sig = unsafe { mem::zeroed() };
+        write_sockaddr(&mut sig.addr, addr);
+        let is_host = u32::from(prefix_len) == if addr.is_ipv4() { 32 } else { 128 };
+        if !is_host {
+            sig.flags = TCP_MD5SIG_FLAG_PREFIX;
+            sig.prefix_len = prefix_len;
+        }
+        // A zero-length key removes the key for this peer
+        if let Some(key) = key {
+            sig.key_len = key.len() as u16;
+            sig.key[..key.len()].copy_from_slice(key.as_bytes());
+        }
+        let option = if is_host {
+            libc::TCP_MD5SIG
+        } else {
+            libc::TCP_MD5SIG_EXT
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                option,
+                &sig as *const TcpMd5Sig as *const libc::c_void,
+                mem::size_of::<TcpMd5Sig>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn write_sockaddr(storage: &mut libc::sockaddr_storage, addr: IpAddr) {
+        match addr {
+            IpAddr::V4(addr) => {
+                let sin = storage as *mut _ as *mut libc::sockaddr_in;
+                unsafe {
+                    (*sin).sin_family = libc::AF_INET as libc::sa_family_t;
+                    (*sin).sin_addr.s_addr = u32::from_ne_bytes(addr.octets());
+                }
+            }
+            IpAddr::V6(addr) => {
+                let sin6 = storage as *mut _ as *mut libc::sockaddr_in6;
+                unsafe {
+                    (*sin6).sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                    (*sin6).sin6_addr.s6_addr = addr.octets();
+                }
+            }
+        }
+    }
+}
+
+/// Set (or remove if `key` is None) the MD5 key for a peer (or network of peers) on a socket
+#[cfg(target_os = "linux")]
+pub fn set_md5_key<S: std::os::unix::io::AsRawFd>(
+    socket: &S,
+    socket_is_v6: bool,
+    peer: IpNetwork,
+    key: Option<&str>,
+) -> io::Result<()> {
+    platform::set_md5_key(socket.as_raw_fd(), socket_is_v6, peer, key)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_md5_key<S>(
+    _socket: &S,
+    _socket_is_v6: bool,
+    _peer: IpNetwork,
+    _key: Option<&str>,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP MD5 signatures are only supported on Linux",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpSocket};
+    use tokio::time::{timeout, Duration};
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("secret").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate(&"a".repeat(MAX_KEY_LEN + 1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_md5_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer: IpNetwork = "127.0.0.1/32".parse().unwrap();
+        set_md5_key(&listener, false, peer, Some("secret")).unwrap();
+        // Subnet keys are also accepted
+        set_md5_key(
+            &listener,
+            false,
+            "10.0.0.0/24".parse().unwrap(),
+            Some("other"),
+        )
+        .unwrap();
+
+        // Without a signature, the kernel drops the SYN
+        let unsigned = TcpSocket::new_v4().unwrap();
+        let connect = timeout(Duration::from_millis(300), unsigned.connect(addr)).await;
+        assert!(!matches!(connect, Ok(Ok(_))));
+
+        // With a matching key, the connection is established
+        let signed = TcpSocket::new_v4().unwrap();
+        set_md5_key(&signed, false, IpNetwork::from(addr.ip()), Some("secret")).unwrap();
+        let (connect, accept) = tokio::join!(
+            timeout(Duration::from_millis(1000), signed.connect(addr)),
+            timeout(Duration::from_millis(1000), listener.accept())
+        );
+        assert!(matches!(connect, Ok(Ok(_))));
+        assert!(matches!(accept, Ok(Ok(_))));
+    }
+}
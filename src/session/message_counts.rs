@@ -1,7 +1,71 @@
+use std::collections::HashMap;
+
+use bgp_rs::{Message, PathAttribute};
+
+use crate::utils::get_message_type;
+
+/// Message types, in the order they're reported
+pub const MESSAGE_TYPES: [&str; 5] = [
+    "OPEN",
+    "UPDATE",
+    "KEEPALIVE",
+    "NOTIFICATION",
+    "ROUTEREFRESH",
+];
+
+/// Sent & received count for a single statistic
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Directional {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// What a message adds to the counts (taken before a message is sent)
+#[derive(Clone, Copy, Debug)]
+pub struct MessageTally {
+    message_type: &'static str,
+    announced: u64,
+    withdrawn: u64,
+}
+
+impl From<&Message> for MessageTally {
+    fn from(message: &Message) -> Self {
+        let (announced, withdrawn) = match message {
+            Message::Update(update) => {
+                let mut announced = update.announced_routes.len();
+                let mut withdrawn = update.withdrawn_routes.len();
+                for attribute in &update.attributes {
+                    match attribute {
+                        PathAttribute::MP_REACH_NLRI(nlri) => {
+                            announced += nlri.announced_routes.len()
+                        }
+                        PathAttribute::MP_UNREACH_NLRI(nlri) => {
+                            withdrawn += nlri.withdrawn_routes.len()
+                        }
+                        _ => (),
+                    }
+                }
+                (announced as u64, withdrawn as u64)
+            }
+            _ => (0, 0),
+        };
+        Self {
+            message_type: get_message_type(message),
+            announced,
+            withdrawn,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct MessageCounts {
     received: u64,
     sent: u64,
+    /// Counts for each message type (E.g. "UPDATE")
+    by_type: HashMap<&'static str, Directional>,
+    /// NLRI announced & withdrawn in UPDATEs
+    announced: Directional,
+    withdrawn: Directional,
 }
 
 impl MessageCounts {
@@ -12,14 +76,85 @@ impl MessageCounts {
     pub fn received(&self) -> u64 {
         self.received
     }
-    pub fn increment_received(&mut self) {
+    pub fn increment_received(&mut self, tally: MessageTally) {
         self.received += 1;
+        self.by_type.entry(tally.message_type).or_default().received += 1;
+        self.announced.received += tally.announced;
+        self.withdrawn.received += tally.withdrawn;
     }
 
     pub fn sent(&self) -> u64 {
         self.sent
     }
-    pub fn increment_sent(&mut self) {
+    pub fn increment_sent(&mut self, tally: MessageTally) {
         self.sent += 1;
+        self.by_type.entry(tally.message_type).or_default().sent += 1;
+        self.announced.sent += tally.announced;
+        self.withdrawn.sent += tally.withdrawn;
+    }
+
+    /// Counts for a message type (E.g. "KEEPALIVE")
+    pub fn by_type(&self, message_type: &str) -> Directional {
+        self.by_type.get(message_type).copied().unwrap_or_default()
+    }
+
+    /// NLRI announced in UPDATEs
+    pub fn announced(&self) -> Directional {
+        self.announced
+    }
+
+    /// NLRI withdrawn in UPDATEs
+    pub fn withdrawn(&self) -> Directional {
+        self.withdrawn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::{MPUnreachNLRI, NLRIEncoding, Update, AFI, SAFI};
+
+    #[test]
+    fn test_message_counts() {
+        let mut counts = MessageCounts::new();
+        counts.increment_sent((&Message::KeepAlive).into());
+        counts.increment_sent((&Message::KeepAlive).into());
+        counts.increment_received((&Message::KeepAlive).into());
+        let update = Message::Update(Update {
+            withdrawn_routes: vec![NLRIEncoding::IP(("10.0.0.0".parse().unwrap(), 24).into())],
+            attributes: vec![PathAttribute::MP_UNREACH_NLRI(MPUnreachNLRI {
+                afi: AFI::IPV6,
+                safi: SAFI::Unicast,
+                withdrawn_routes: vec![NLRIEncoding::IP(
+                    ("2001:db8::".parse().unwrap(), 32).into(),
+                )],
+            })],
+            announced_routes: vec![
+                NLRIEncoding::IP(("10.0.1.0".parse().unwrap(), 24).into()),
+                NLRIEncoding::IP(("10.0.2.0".parse().unwrap(), 24).into()),
+            ],
+        });
+        counts.increment_received((&update).into());
+
+        assert_eq!(counts.sent(), 2);
+        assert_eq!(counts.received(), 2);
+        assert_eq!(
+            counts.by_type("KEEPALIVE"),
+            Directional {
+                sent: 2,
+                received: 1
+            }
+        );
+        assert_eq!(
+            counts.by_type("UPDATE"),
+            Directional {
+                sent: 0,
+                received: 1
+            }
+        );
+        assert_eq!(counts.by_type("OPEN"), Directional::default());
+        assert_eq!(counts.announced().received, 2);
+        assert_eq!(counts.withdrawn().received, 2);
+        assert_eq!(counts.announced().sent, 0);
     }
 }
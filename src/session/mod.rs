@@ -1,8 +1,13 @@
 mod codec;
+mod gtsm;
 mod hold_timer;
 mod manager;
+mod md5;
 mod message_counts;
+mod pacing;
 mod poller;
+mod prefix_limit;
+mod role;
 mod lib;
 
 use std::convert::From;
@@ -10,21 +15,83 @@ use std::error;
 use std::fmt;
 use std::io;
 use std::net::IpAddr;
+use std::sync::Arc;
 
+pub(crate) use codec::MessageCodec;
+pub(crate) use gtsm::validate as validate_min_ttl;
+pub(crate) use hold_timer::keepalive_interval;
 use hold_timer::HoldTimer;
 pub use manager::SessionManager;
-use message_counts::MessageCounts;
+pub(crate) use md5::validate as validate_md5_password;
+pub(crate) use message_counts::MESSAGE_TYPES;
+use message_counts::{MessageCounts, MessageTally};
+use pacing::UpdatePacing;
+pub use poller::bind_listener;
 use poller::{Poller, PollerTx};
+use prefix_limit::{PrefixLimit, PrefixLimitStatus};
+pub use role::Role;
+pub(crate) use lib::capability_code;
 pub use lib::Session;
 
 use bgp_rs::Update;
 
-#[derive(Debug)]
+use crate::config::PeerConfig;
+use crate::rib::Family;
+use crate::utils::notification_to_string;
+
+#[derive(Clone, Debug)]
 pub enum SessionUpdate {
-    // Update received from a peer (PeerIP, Update)
-    Learned((IpAddr, Update)),
+    // Update received from a peer (PeerIP, PeerConfig, Update, OTC attribute)
+    Learned((IpAddr, Arc<PeerConfig>, Update, Option<u32>)),
+    // Session with this peer has reached Established (PeerIP, RouterID)
+    Established((IpAddr, IpAddr)),
     // Sessions are ended, clear RIB for these peers
-    Ended(Vec<IpAddr>),
+    // (or retain routes as stale, if the peer is restarting with Graceful Restart)
+    Ended(Vec<(IpAddr, Option<GracefulRestart>)>),
+    // Begin-of-RR received, mark routes from this peer (for a family) as stale
+    BeginRefresh((IpAddr, Family)),
+    // End-of-RR received, remove routes from this peer (for a family) that are still stale
+    EndRefresh((IpAddr, Family)),
+    // End-of-RIB received, remove routes from this peer (for a family) retained after a restart
+    EndOfRib((IpAddr, Family)),
+}
+
+/// Graceful Restart (RFC 4724) state for a peer session that ended without a NOTIFICATION
+#[derive(Clone, Debug, PartialEq)]
+pub struct GracefulRestart {
+    /// Restart time advertised by the peer (seconds)
+    pub restart_time: u16,
+    /// Families for which routes are retained
+    pub families: Vec<Family>,
+}
+
+/// A NOTIFICATION sent to (or received from) a peer
+#[derive(Clone, Debug, PartialEq)]
+pub struct NotificationRecord {
+    /// Sent by us (otherwise received from the peer)
+    pub sent: bool,
+    pub major: u8,
+    pub minor: u8,
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for NotificationRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = if self.sent { "sent" } else { "received" };
+        write!(
+            f,
+            "{} ({})",
+            notification_to_string(self.major, self.minor, &self.data),
+            direction
+        )
+    }
+}
+
+/// Why the most recent session with a peer ended (kept after the session is removed)
+#[derive(Clone, Debug, Default)]
+pub struct PeerHistory {
+    pub last_error: Option<String>,
+    pub last_notification: Option<NotificationRecord>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -55,30 +122,69 @@ impl fmt::Display for SessionState {
 pub enum SessionError {
     /// Peer De-configured
     Deconfigured,
+    /// Peer disabled. [shutdown message]
+    AdministrativeShutdown(Option<String>),
     /// Received an unexpected ASN. [received, expected]
     OpenAsnMismatch(u32, u32),
     /// Finite State Machine error, unexpected transition [minor_err_codes]
     FiniteStateMachine(u8),
     /// Hold time expired. [interval]
     HoldTimeExpired(u16),
+    /// Received more prefixes than allowed. [max_prefixes]
+    MaxPrefixesExceeded(u32),
+    /// Peer's BGP Role doesn't correspond to the local role. [reason]
+    RoleMismatch(String),
     /// Something happened in transport. [reason]
     TransportError(String),
+    /// Received message couldn't be decoded. [reason]
+    CodecError(String),
     /// Some other issue happened. [reason]
     Other(String),
 }
 
+impl SessionError {
+    /// NOTIFICATION (major, minor) error codes to send the peer for this error (if any)
+    pub fn notification(&self) -> Option<(u8, u8)> {
+        use SessionError::*;
+        match self {
+            Deconfigured => Some((6 /* Cease */, 3 /* Deconfigured */)),
+            AdministrativeShutdown(_) => {
+                Some((6 /* Cease */, 2 /* Administrative Shutdown */))
+            }
+            OpenAsnMismatch(_, _) => Some((3 /* OPEN Error */, 2 /* Bad Peer AS */)),
+            FiniteStateMachine(minor) => Some((5 /* FSM Error */, *minor)),
+            HoldTimeExpired(_) => Some((4 /* Hold Timer Expired */, 0)),
+            MaxPrefixesExceeded(_) => Some((6 /* Cease */, 1 /* Max Prefixes Reached */)),
+            RoleMismatch(_) => Some((2 /* OPEN Error */, 11 /* Role Mismatch */)),
+            _ => None,
+        }
+    }
+
+    /// Shutdown Communication (RFC 9003) to include in the NOTIFICATION (if any)
+    pub fn shutdown_message(&self) -> Option<&str> {
+        match self {
+            SessionError::AdministrativeShutdown(message) => message.as_deref(),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for SessionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str("Session Error: ")?;
         use SessionError::*;
         match self {
             Deconfigured => write!(f, "Peer De-configured")?,
+            AdministrativeShutdown(_) => write!(f, "Peer disabled")?,
             OpenAsnMismatch(r, e) => {
                 write!(f, "Open ASN Mismatch (received={}, expected={})", r, e)?;
             }
             HoldTimeExpired(h) => write!(f, "Hold time expired after {} seconds", h)?,
+            MaxPrefixesExceeded(m) => write!(f, "Maximum prefixes exceeded ({})", m)?,
+            RoleMismatch(r) => write!(f, "BGP Role mismatch [{}]", r)?,
             FiniteStateMachine(minor) => write!(f, "Finite State Machine err [{}]", minor)?,
             TransportError(r) => write!(f, "Transport error [{}]", r)?,
+            CodecError(r) => write!(f, "Codec error [{}]", r)?,
             Other(r) => write!(f, "{}", r)?,
         }
         Ok(())
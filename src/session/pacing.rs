@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::rib::Family;
+
+/// Outbound UPDATE pacing for a peer, with a token bucket for each family
+/// Each bucket holds up to `rate` tokens (a one second burst) and refills at `rate` per second
+#[derive(Debug)]
+pub struct UpdatePacing {
+    rate: u32,
+    buckets: HashMap<Family, TokenBucket>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl UpdatePacing {
+    /// Pacing is disabled for a rate of 0
+    pub fn new(rate: u32) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+        Some(Self {
+            rate,
+            buckets: HashMap::new(),
+        })
+    }
+
+    /// Take a token to send an UPDATE for this family
+    /// Returns false if the family is already at the maximum rate
+    pub fn try_send(&mut self, family: Family) -> bool {
+        self.try_send_at(family, Instant::now())
+    }
+
+    /// Time until the next token is available for this family
+    pub fn delay(&mut self, family: Family) -> Duration {
+        self.delay_at(family, Instant::now())
+    }
+
+    fn try_send_at(&mut self, family: Family, now: Instant) -> bool {
+        let bucket = self.refill(family, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn delay_at(&mut self, family: Family, now: Instant) -> Duration {
+        let rate = f64::from(self.rate);
+        let bucket = self.refill(family, now);
+        Duration::from_secs_f64((1.0 - bucket.tokens).max(0.0) / rate)
+    }
+
+    fn refill(&mut self, family: Family, now: Instant) -> &mut TokenBucket {
+        let rate = f64::from(self.rate);
+        let bucket = self.buckets.entry(family).or_insert(TokenBucket {
+            tokens: rate,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.last_refill = now;
+        bucket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::{AFI, SAFI};
+
+    #[test]
+    fn test_token_bucket() {
+        assert!(UpdatePacing::new(0).is_none());
+        let mut pacing = UpdatePacing::new(10).unwrap();
+        let v4 = Family::new(AFI::IPV4, SAFI::Unicast);
+        let v6 = Family::new(AFI::IPV6, SAFI::Unicast);
+        let start = Instant::now();
+        // Initial burst of `rate` UPDATEs
+        for _ in 0..10 {
+            assert!(pacing.try_send_at(v4, start));
+        }
+        assert!(!pacing.try_send_at(v4, start));
+        assert_eq!(pacing.delay_at(v4, start), Duration::from_millis(100));
+        // Families are paced independently
+        assert!(pacing.try_send_at(v6, start));
+
+        let later = start + Duration::from_millis(250);
+        assert!(pacing.try_send_at(v4, later));
+        assert!(pacing.try_send_at(v4, later));
+        assert!(!pacing.try_send_at(v4, later));
+        // Never refills beyond the burst size
+        let much_later = later + Duration::from_secs(10);
+        assert_eq!(pacing.delay_at(v4, much_later), Duration::from_secs(0));
+        for _ in 0..10 {
+            assert!(pacing.try_send_at(v4, much_later));
+        }
+        assert!(!pacing.try_send_at(v4, much_later));
+    }
+}
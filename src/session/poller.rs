@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::io;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
+use futures::future::select_all;
 use futures::StreamExt;
 use ipnetwork::IpNetwork;
 use log::{debug, trace, warn};
@@ -16,43 +17,89 @@ use tokio::{
 };
 use tokio_util::time::DelayQueue;
 
+use super::{gtsm, md5, SessionState};
 use crate::config::PeerConfig;
-use crate::utils::get_host_address;
+use crate::utils::{get_host_address, longest_match};
 
 const TCP_INIT_TIMEOUT_MS: u16 = 1000;
+/// Cap for the ConnectRetry backoff (as a multiple of the peer's `connect_retry`)
+const MAX_CONNECT_RETRY_BACKOFF: u32 = 8;
 
 pub type PollerTx = mpsc::UnboundedSender<Arc<PeerConfig>>;
 pub type PollerRx = mpsc::UnboundedReceiver<Arc<PeerConfig>>;
 
 #[derive(Debug)]
-pub struct IdlePeer(Arc<PeerConfig>);
+pub struct IdlePeer {
+    config: Arc<PeerConfig>,
+    state: SessionState,
+    /// Consecutive failed outbound connection attempts
+    failures: u32,
+}
 
 impl IdlePeer {
     pub fn new(config: Arc<PeerConfig>) -> Self {
-        Self(config)
+        let state = if !config.enabled {
+            SessionState::Idle
+        } else if config.passive || get_host_address(&config.remote_ip).is_none() {
+            // Only waiting for incoming connections
+            SessionState::Active
+        } else {
+            SessionState::Connect
+        };
+        Self {
+            config,
+            state,
+            failures: 0,
+        }
     }
 
     pub fn get_config(&self) -> Arc<PeerConfig> {
-        Arc::clone(&self.0)
+        Arc::clone(&self.config)
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    /// Outbound connection failed, move to Active (incoming connections are still accepted)
+    /// Returns the ConnectRetry delay until the next attempt
+    fn connect_failed(&mut self) -> Duration {
+        self.failures += 1;
+        self.state = SessionState::Active;
+        connect_retry_delay(self.config.connect_retry, self.failures)
     }
 
     async fn connect(
         &self,
         source_addr: SocketAddr,
     ) -> Result<(TcpStream, Arc<PeerConfig>), io::Error> {
-        if let Some(remote_ip) = get_host_address(&self.0.remote_ip) {
-            let peer_addr = SocketAddr::new(remote_ip, self.0.dest_port);
+        if let Some(remote_ip) = get_host_address(&self.config.remote_ip) {
+            let peer_addr = SocketAddr::new(remote_ip, self.config.dest_port);
             let builder = match peer_addr {
                 SocketAddr::V4(_) => TcpBuilder::new_v4()?,
                 SocketAddr::V6(_) => TcpBuilder::new_v6()?,
             };
             builder.reuse_address(true)?;
             builder.bind(source_addr)?;
-            let s = TcpSocket::from_std_stream(builder.to_tcp_stream()?);
+            if let Some(password) = &self.config.md5_password {
+                md5::set_md5_key(
+                    &builder,
+                    peer_addr.is_ipv6(),
+                    IpNetwork::from(remote_ip),
+                    Some(password),
+                )?;
+            }
+            if let Some(min_ttl) = self.config.min_ttl {
+                gtsm::set_ttl_security(&builder, peer_addr.is_ipv6(), min_ttl)?;
+            }
+            let stream = builder.to_tcp_stream()?;
+            // Otherwise connect & reads block the runtime thread
+            stream.set_nonblocking(true)?;
+            let s = TcpSocket::from_std_stream(stream);
             let connect = s.connect(peer_addr);
             return match timeout(Duration::from_millis(TCP_INIT_TIMEOUT_MS.into()), connect).await?
             {
-                Ok(stream) => Ok((stream, self.0.clone())),
+                Ok(stream) => Ok((stream, self.config.clone())),
                 Err(err) => Err(err),
             };
         }
@@ -62,33 +109,117 @@ impl IdlePeer {
 
 impl fmt::Display for IdlePeer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "<IdlePeer {}>", self.0.remote_ip)
+        write!(f, "<IdlePeer {} {}>", self.config.remote_ip, self.state)
     }
 }
 
+/// ConnectRetry delay, doubling with each consecutive failure up to `MAX_CONNECT_RETRY_BACKOFF`
+fn connect_retry_delay(connect_retry: u16, failures: u32) -> Duration {
+    let backoff = 2u32
+        .saturating_pow(failures.saturating_sub(1))
+        .min(MAX_CONNECT_RETRY_BACKOFF);
+    Duration::from_secs(u64::from(connect_retry) * u64::from(backoff))
+}
+
+/// Bind a BGP listening socket with a pending connection queue of `backlog`
+/// IPv6 sockets only accept IPv6 connections if `only_v6` is set (E.g. when also listening on IPv4)
+pub fn bind_listener(addr: SocketAddr, backlog: u32, only_v6: bool) -> io::Result<TcpListener> {
+    let builder = match addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => {
+            let builder = TcpBuilder::new_v6()?;
+            builder.only_v6(only_v6)?;
+            builder
+        }
+    };
+    builder.reuse_address(true)?;
+    builder.bind(addr)?;
+    let listener = builder.listen(backlog.min(i32::MAX as u32) as i32)?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
 /// Stores Idle peers and checks every interval if there are peers that the Handler
 /// can attempt to connect to
 pub struct Poller {
     idle_peers: HashMap<IpNetwork, IdlePeer>,
-    tcp_listener: TcpListener,
+    tcp_listeners: Vec<TcpListener>,
     rx: PollerRx,
     interval: Duration,
     delay_queue: DelayQueue<IpAddr>,
+    // Peers with an MD5 key applied to the listeners
+    md5_peers: HashSet<IpNetwork>,
 }
 
 impl Poller {
-    pub fn new(listener: TcpListener, interval: u32 /* seconds */, rx: PollerRx) -> Self {
+    pub fn new(
+        listeners: Vec<TcpListener>,
+        interval: u32, /* seconds */
+        rx: PollerRx,
+    ) -> Self {
+        assert!(
+            !listeners.is_empty(),
+            "At least one BGP listener is required"
+        );
         Self {
             idle_peers: HashMap::new(),
-            tcp_listener: listener,
+            tcp_listeners: listeners,
             interval: Duration::from_secs(interval.into()),
             delay_queue: DelayQueue::with_capacity(4),
             rx,
+            md5_peers: HashSet::new(),
+        }
+    }
+
+    /// Set (or remove) the MD5 key for a peer on the listeners (inherited by accepted sockets)
+    fn set_listener_md5_key(&mut self, network: IpNetwork, password: Option<&str>) {
+        for listener in &self.tcp_listeners {
+            let is_v6 = match listener.local_addr() {
+                Ok(addr) => addr.is_ipv6(),
+                Err(_) => false,
+            };
+            // An IPv4-only listener won't see connections from IPv6 peers
+            if !is_v6 && network.is_ipv6() {
+                continue;
+            }
+            if let Err(err) = md5::set_md5_key(listener, is_v6, network, password) {
+                warn!("Error setting MD5 key for {}: {}", network, err);
+                return;
+            }
+        }
+        if password.is_some() {
+            self.md5_peers.insert(network);
+        } else {
+            self.md5_peers.remove(&network);
         }
     }
 
+    /// Local address for outbound connections to a peer without a `local_address`
+    /// (from a listener of the same address family, if there is one)
+    fn outbound_addr(&self, peer: IpAddr) -> SocketAddr {
+        let addrs: Vec<SocketAddr> = self
+            .tcp_listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect();
+        let addr = addrs
+            .iter()
+            .find(|addr| addr.is_ipv4() == peer.is_ipv4())
+            .or_else(|| addrs.first())
+            .expect("Has local address");
+        SocketAddr::new(addr.ip(), 0u16)
+    }
+
+    /// FSM state of a configured peer without an active session
+    pub fn get_peer_state(&self, network: &IpNetwork) -> Option<SessionState> {
+        self.idle_peers.get(network).map(|peer| peer.state())
+    }
+
     pub fn upsert_config(&mut self, config: Arc<PeerConfig>) {
         let network = config.remote_ip;
+        if let Some(password) = &config.md5_password {
+            self.set_listener_md5_key(network, Some(password));
+        }
 
         if self
             .idle_peers
@@ -97,13 +228,28 @@ impl Poller {
         {
             debug!("Peer config for {} updated", network);
         } else if let Some(remote_ip) = get_host_address(&network) {
-            // Add to outgoing connection queue if there was no existing config
-            // and if it's a single host
-            self.delay_queue.insert(remote_ip, self.interval);
+            // Add to outgoing connection queue if there was no existing config,
+            // it's a single host, and isn't passive
+            if !self.idle_peers[&network].config.passive {
+                self.delay_queue.insert(remote_ip, self.interval);
+            }
         }
     }
 
     pub fn replace_configs(&mut self, configs: Vec<Arc<PeerConfig>>) {
+        let removed_md5: Vec<IpNetwork> = self
+            .md5_peers
+            .iter()
+            .filter(|network| {
+                !configs
+                    .iter()
+                    .any(|c| c.remote_ip == **network && c.md5_password.is_some())
+            })
+            .copied()
+            .collect();
+        for network in removed_md5 {
+            self.set_listener_md5_key(network, None);
+        }
         self.delay_queue.clear();
         self.idle_peers.clear();
         for config in configs.into_iter() {
@@ -114,17 +260,25 @@ impl Poller {
     pub async fn get_connection(
         &mut self,
     ) -> Result<Option<(TcpStream, Arc<PeerConfig>)>, io::Error> {
-        let local_outbound_addr = self.tcp_listener.local_addr().expect("Has local address");
-        let listener = timeout(
-            Duration::from_millis(TCP_INIT_TIMEOUT_MS.into()),
-            self.tcp_listener.accept(),
+        let accept = select_all(
+            self.tcp_listeners
+                .iter()
+                .map(|listener| Box::pin(listener.accept())),
         );
+        let listener = timeout(Duration::from_millis(TCP_INIT_TIMEOUT_MS.into()), accept);
 
         tokio::select! {
             incoming = listener => {
-                if let Ok(Ok((stream, socket))) = incoming {
+                if let Ok((Ok((stream, socket)), _, _)) = incoming {
                     if let Some(config) = get_config_for_peer(&self.idle_peers, socket.ip()) {
                         if config.enabled {
+                            if let Some(min_ttl) = config.min_ttl {
+                                let is_v6 = stream.local_addr().map(|a| a.is_ipv6()).unwrap_or(false);
+                                if let Err(err) = gtsm::set_ttl_security(&stream, is_v6, min_ttl) {
+                                    warn!("Error setting TTL security for {}: {}", socket.ip(), err);
+                                    return Ok(None);
+                                }
+                            }
                             let config = if get_host_address(&config.remote_ip).is_some() {
                                 // Only remove from idle peers if this a for a single peer
                                 self.idle_peers.remove(&config.remote_ip)
@@ -157,12 +311,22 @@ impl Poller {
                     // was established simultaneously
                     if let Some(config) = get_config_for_peer(&self.idle_peers, addr) {
                         if config.enabled && !config.passive {
-                            let peer = self.idle_peers.remove(&config.remote_ip).expect("Idle peer exists");
-                            match peer.connect(SocketAddr::new(local_outbound_addr.ip(), 0u16)).await {
+                            let mut peer = self.idle_peers.remove(&config.remote_ip).expect("Idle peer exists");
+                            peer.state = SessionState::Connect;
+                            let source_addr = match config.local_address {
+                                Some(local_address) => SocketAddr::new(local_address, 0u16),
+                                None => self.outbound_addr(addr),
+                            };
+                            match peer.connect(source_addr).await {
                                 Ok(connection) => return Ok(Some(connection)),
                                 Err(err) => {
-                                    warn!("Error polling {}: {}", addr, err);
-                                    self.delay_queue.insert(addr, self.interval);
+                                    let retry = peer.connect_failed();
+                                    warn!(
+                                        "Error polling {}: {} (retrying in {}s)",
+                                        addr, err, retry.as_secs()
+                                    );
+                                    self.idle_peers.insert(config.remote_ip, peer);
+                                    self.delay_queue.insert(addr, retry);
                                 }
                             }
                         }
@@ -173,10 +337,13 @@ impl Poller {
             peer = self.rx.recv() => {
                 if let Some(config) = peer {
                     let network = config.remote_ip;
+                    let passive = config.passive;
                     self.idle_peers
                         .insert(config.remote_ip, IdlePeer::new(config));
                     if let Some(addr) = get_host_address(&network) {
-                        self.delay_queue.insert(addr, self.interval);
+                        if !passive {
+                            self.delay_queue.insert(addr, self.interval);
+                        }
                     }
                 }
                 Ok(None)
@@ -195,9 +362,185 @@ fn get_config_for_peer(
     idle_peers: &HashMap<IpNetwork, IdlePeer>,
     peer: IpAddr,
 ) -> Option<Arc<PeerConfig>> {
-    if let Some(network) = idle_peers.keys().find(|n| n.contains(peer)) {
-        idle_peers.get(network).map(|c| c.get_config())
-    } else {
-        None
+    // Most-specific config wins if peer networks overlap
+    longest_match(idle_peers.keys(), peer)
+        .and_then(|network| idle_peers.get(network))
+        .map(|c| c.get_config())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_config(options: &str) -> Arc<PeerConfig> {
+        let config = format!(
+            r#"
+            router_id = "1.1.1.1"
+            default_as = 65000
+            [[peers]]
+            remote_as = 65001
+            {}
+            "#,
+            options
+        );
+        crate::config::from_str(&config).unwrap().peers[0].clone()
+    }
+
+    #[tokio::test]
+    async fn test_multiple_listeners() {
+        let v4 = bind_listener("127.0.0.1:0".parse().unwrap(), 16, true).unwrap();
+        let v4_addr = v4.local_addr().unwrap();
+        // Same port on IPv6, only possible without IPv4-mapped addresses
+        let v6 = bind_listener(
+            SocketAddr::new("::".parse().unwrap(), v4_addr.port()),
+            16,
+            true,
+        )
+        .unwrap();
+        let (_tx, rx) = mpsc::unbounded_channel();
+        let mut poller = Poller::new(vec![v4, v6], 0, rx);
+        poller.upsert_config(peer_config("remote_ip = \"::1\"\npassive = true"));
+        poller.upsert_config(peer_config("remote_ip = \"127.0.0.1\"\npassive = true"));
+        assert_eq!(
+            poller.outbound_addr("10.0.0.1".parse().unwrap()),
+            "127.0.0.1:0".parse().unwrap()
+        );
+        assert_eq!(
+            poller.outbound_addr("2001:db8::1".parse().unwrap()),
+            "[::]:0".parse().unwrap()
+        );
+
+        for addr in [
+            v4_addr,
+            SocketAddr::new("::1".parse().unwrap(), v4_addr.port()),
+        ] {
+            let (stream, connection) = tokio::join!(TcpStream::connect(addr), async {
+                loop {
+                    if let Some(connection) = poller.get_connection().await.unwrap() {
+                        return connection;
+                    }
+                }
+            });
+            let stream = stream.unwrap();
+            assert_eq!(
+                connection.0.peer_addr().unwrap(),
+                stream.local_addr().unwrap()
+            );
+            assert_eq!(connection.1.remote_ip.ip(), addr.ip());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passive_peer_not_polled() {
+        // Would accept an outbound connection, if one was attempted
+        let remote = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = peer_config(&format!(
+            "remote_ip = \"127.0.0.1\"\ndest_port = {}\npassive = true",
+            remote.local_addr().unwrap().port()
+        ));
+        let network = config.remote_ip;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut poller = Poller::new(vec![listener], 0, rx);
+        poller.upsert_config(config.clone());
+        assert!(poller.delay_queue.is_empty());
+        assert!(poller.get_connection().await.unwrap().is_none());
+
+        // Also when returned to the poller after a session ends
+        tx.send(config).unwrap();
+        assert!(poller.get_connection().await.unwrap().is_none());
+        assert!(poller.delay_queue.is_empty());
+        assert_eq!(poller.get_peer_state(&network), Some(SessionState::Active));
+        let accept = timeout(Duration::from_millis(100), remote.accept()).await;
+        assert!(accept.is_err(), "Unexpected outbound connection");
+    }
+
+    #[test]
+    fn test_overlapping_peer_configs() {
+        let mut idle_peers = HashMap::new();
+        for network in &["10.0.0.0/8", "10.1.0.0/16", "10.1.1.0/24", "2001:db8::/32"] {
+            let config = peer_config(&format!("remote_ip = \"{}\"", network));
+            idle_peers.insert(config.remote_ip, IdlePeer::new(config));
+        }
+        let matched = |addr: &str| {
+            get_config_for_peer(&idle_peers, addr.parse().unwrap()).map(|c| c.remote_ip.to_string())
+        };
+        assert_eq!(matched("10.1.1.5").as_deref(), Some("10.1.1.0/24"));
+        assert_eq!(matched("10.1.2.5").as_deref(), Some("10.1.0.0/16"));
+        assert_eq!(matched("10.2.0.1").as_deref(), Some("10.0.0.0/8"));
+        assert_eq!(matched("2001:db8::1").as_deref(), Some("2001:db8::/32"));
+        assert_eq!(matched("192.168.0.1"), None);
+    }
+
+    #[test]
+    fn test_connect_retry_backoff() {
+        let delays: Vec<u64> = (1..=6)
+            .map(|failures| connect_retry_delay(120, failures).as_secs())
+            .collect();
+        assert_eq!(delays, vec![120, 240, 480, 960, 960, 960]);
+    }
+
+    #[test]
+    fn test_initial_state() {
+        let state = |options| IdlePeer::new(peer_config(options)).state();
+        assert_eq!(state(r#"remote_ip = "127.0.0.2""#), SessionState::Connect);
+        assert_eq!(
+            state("remote_ip = \"127.0.0.2\"\npassive = true"),
+            SessionState::Active
+        );
+        assert_eq!(state(r#"remote_ip = "127.0.0.0/24""#), SessionState::Active);
+        assert_eq!(
+            state("remote_ip = \"127.0.0.2\"\nenabled = false"),
+            SessionState::Idle
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_failure_moves_to_active() {
+        // Nothing is listening on this port
+        let closed_port = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let config = peer_config(&format!(
+            "remote_ip = \"127.0.0.1\"\ndest_port = {}\nconnect_retry = 5",
+            closed_port
+        ));
+        let network = config.remote_ip;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (_tx, rx) = mpsc::unbounded_channel();
+        let mut poller = Poller::new(vec![listener], 0, rx);
+        poller.upsert_config(config);
+        assert_eq!(poller.get_peer_state(&network), Some(SessionState::Connect));
+
+        assert!(poller.get_connection().await.unwrap().is_none());
+        assert_eq!(poller.get_peer_state(&network), Some(SessionState::Active));
+        assert_eq!(poller.idle_peers[&network].failures, 1);
+        // Retry is scheduled after the ConnectRetry delay
+        assert_eq!(poller.delay_queue.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_local_address() {
+        let remote = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = peer_config(&format!(
+            "remote_ip = \"127.0.0.1\"\ndest_port = {}\nlocal_address = \"127.0.0.2\"",
+            remote.local_addr().unwrap().port()
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (_tx, rx) = mpsc::unbounded_channel();
+        let mut poller = Poller::new(vec![listener], 0, rx);
+        poller.upsert_config(config);
+
+        let (accepted, connection) = tokio::join!(remote.accept(), async {
+            loop {
+                if let Some(connection) = poller.get_connection().await.unwrap() {
+                    return connection;
+                }
+            }
+        });
+        let (_, source) = accepted.unwrap();
+        assert_eq!(source.ip(), "127.0.0.2".parse::<IpAddr>().unwrap());
+        assert_eq!(connection.0.local_addr().unwrap(), source);
     }
 }
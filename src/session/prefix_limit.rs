@@ -0,0 +1,104 @@
+/// Result of checking received prefixes against a `PrefixLimit`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PrefixLimitStatus {
+    /// Below the warning threshold (or already warned)
+    Ok,
+    /// Warning threshold crossed for the first time. [threshold]
+    Warning(u32),
+    /// Maximum prefixes exceeded, session should be torn down. [max_prefixes]
+    Exceeded(u32),
+}
+
+/// Track received prefixes against a peer's `max_prefixes`
+/// (and optional warning percentage)
+#[derive(Debug)]
+pub struct PrefixLimit {
+    max_prefixes: Option<u32>,
+    warning_pct: Option<u8>,
+    // Has the warning threshold been crossed (and logged) yet
+    warned: bool,
+}
+
+impl PrefixLimit {
+    pub fn new(max_prefixes: Option<u32>, warning_pct: Option<u8>) -> Self {
+        Self {
+            max_prefixes,
+            warning_pct,
+            warned: false,
+        }
+    }
+
+    /// Number of prefixes at which a warning is issued
+    fn threshold(&self) -> Option<u32> {
+        let max = self.max_prefixes?;
+        let pct = u64::from(self.warning_pct?.min(100));
+        Some((u64::from(max) * pct / 100) as u32)
+    }
+
+    /// Are received prefixes at (or over) the warning threshold, as of the last check
+    pub fn warning(&self) -> bool {
+        self.warned
+    }
+
+    /// Check the current count of received prefixes
+    /// A warning is only reported once, until the count drops below the threshold again
+    pub fn check(&mut self, received: usize) -> PrefixLimitStatus {
+        let max = match self.max_prefixes {
+            Some(max) => max,
+            None => return PrefixLimitStatus::Ok,
+        };
+        if received > max as usize {
+            return PrefixLimitStatus::Exceeded(max);
+        }
+        match self.threshold() {
+            Some(threshold) if received >= threshold as usize => {
+                if self.warned {
+                    PrefixLimitStatus::Ok
+                } else {
+                    self.warned = true;
+                    PrefixLimitStatus::Warning(threshold)
+                }
+            }
+            _ => {
+                self.warned = false;
+                PrefixLimitStatus::Ok
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_limit_warning() {
+        let mut limit = PrefixLimit::new(Some(100), Some(75));
+        assert_eq!(limit.check(10), PrefixLimitStatus::Ok);
+        assert_eq!(limit.check(74), PrefixLimitStatus::Ok);
+        let statuses: Vec<_> = (75..=100).map(|count| limit.check(count)).collect();
+        assert_eq!(
+            statuses
+                .iter()
+                .filter(|s| matches!(s, PrefixLimitStatus::Warning(_)))
+                .count(),
+            1
+        );
+        assert_eq!(statuses[0], PrefixLimitStatus::Warning(75));
+        assert!(limit.warning());
+        // Cleared once the count drops below the threshold
+        assert_eq!(limit.check(50), PrefixLimitStatus::Ok);
+        assert!(!limit.warning());
+        assert_eq!(limit.check(101), PrefixLimitStatus::Exceeded(100));
+    }
+
+    #[test]
+    fn test_prefix_limit_disabled() {
+        let mut limit = PrefixLimit::new(None, Some(75));
+        assert_eq!(limit.check(100_000), PrefixLimitStatus::Ok);
+        // No warning percentage, only enforce the maximum
+        let mut limit = PrefixLimit::new(Some(100), None);
+        assert_eq!(limit.check(100), PrefixLimitStatus::Ok);
+        assert_eq!(limit.check(101), PrefixLimitStatus::Exceeded(100));
+    }
+}
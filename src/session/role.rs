@@ -0,0 +1,261 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+use bgp_rs::{OpenCapability, OpenParameter};
+
+/// BGP Role capability code (RFC 9234)
+const BGP_ROLE: u8 = 9;
+
+/// BGP Role (RFC 9234) of the local side of an eBGP session, E.g. `Provider` to a customer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Provider,
+    RouteServer,
+    RouteServerClient,
+    Customer,
+    Peer,
+}
+
+impl Role {
+    /// Role value advertised in the BGP Role capability
+    fn code(self) -> u8 {
+        match self {
+            Role::Provider => 0,
+            Role::RouteServer => 1,
+            Role::RouteServerClient => 2,
+            Role::Customer => 3,
+            Role::Peer => 4,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Role::Provider),
+            1 => Some(Role::RouteServer),
+            2 => Some(Role::RouteServerClient),
+            3 => Some(Role::Customer),
+            4 => Some(Role::Peer),
+            _ => None,
+        }
+    }
+
+    /// Role a correctly configured neighbor has (E.g. `Customer` for a `Provider`)
+    fn neighbor_role(self) -> Self {
+        match self {
+            Role::Provider => Role::Customer,
+            Role::RouteServer => Role::RouteServerClient,
+            Role::RouteServerClient => Role::RouteServer,
+            Role::Customer => Role::Provider,
+            Role::Peer => Role::Peer,
+        }
+    }
+
+    /// Is the neighbor a provider, peer, or route server
+    /// Routes learned from these neighbors are Only to Customer (OTC), and routes
+    /// learned from other such neighbors must not be advertised to them
+    pub fn neighbor_is_upstream(self) -> bool {
+        matches!(self, Role::Customer | Role::Peer | Role::RouteServerClient)
+    }
+
+    /// OTC attribute value added to routes advertised to the neighbor
+    /// (if it's a customer, peer, or route server client)
+    pub(super) fn only_to_customer(self, local_as: u32) -> Option<u32> {
+        match self {
+            Role::Provider | Role::Peer | Role::RouteServer => Some(local_as),
+            _ => None,
+        }
+    }
+
+    /// Is a route received with an OTC attribute a route leak (RFC 9234 [5])
+    /// Routes from customers & route server clients can't have OTC, and only routes
+    /// marked by the peer itself are accepted from a peer
+    pub(super) fn is_route_leak(self, otc: Option<u32>, remote_as: u32) -> bool {
+        match (self, otc) {
+            (_, None) => false,
+            (Role::Provider, Some(_)) | (Role::RouteServer, Some(_)) => true,
+            (Role::Peer, Some(asn)) => asn != remote_as,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let role = match self {
+            Role::Provider => "provider",
+            Role::RouteServer => "rs",
+            Role::RouteServerClient => "rs-client",
+            Role::Customer => "customer",
+            Role::Peer => "peer",
+        };
+        write!(f, "{}", role)
+    }
+}
+
+impl TryFrom<&str> for Role {
+    type Error = io::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "provider" => Ok(Role::Provider),
+            "rs" => Ok(Role::RouteServer),
+            "rs-client" => Ok(Role::RouteServerClient),
+            "customer" => Ok(Role::Customer),
+            "peer" => Ok(Role::Peer),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid role '{}' (provider, customer, peer, rs, or rs-client)",
+                    value
+                ),
+            )),
+        }
+    }
+}
+
+/// BGP Role capability, advertising the local role
+pub(super) fn role_capability(role: Role) -> OpenCapability {
+    OpenCapability::Unknown {
+        cap_code: BGP_ROLE,
+        cap_length: 1,
+        value: vec![role.code()],
+    }
+}
+
+/// Role advertised by the peer (if any), checked against the local role
+/// A mismatched pair, conflicting roles, or (with `strict`) no role is a Role Mismatch
+pub(super) fn check_role(
+    local: Role,
+    parameters: &[OpenParameter],
+    strict: bool,
+) -> Result<Option<Role>, String> {
+    let mut advertised: Vec<Option<Role>> = parameters
+        .iter()
+        .flat_map(|p| match p {
+            OpenParameter::Capabilities(caps) => caps.iter().collect(),
+            _ => vec![],
+        })
+        .filter_map(|c| match c {
+            OpenCapability::Unknown {
+                cap_code, value, ..
+            } if *cap_code == BGP_ROLE => Some(match value.as_slice() {
+                [code] => Role::from_code(*code),
+                _ => None,
+            }),
+            _ => None,
+        })
+        .collect();
+    advertised.dedup();
+    match advertised.as_slice() {
+        [] if strict => Err("No role advertised by the peer (strict_role)".to_string()),
+        [] => Ok(None),
+        [Some(role)] if *role == local.neighbor_role() => Ok(Some(*role)),
+        [Some(role)] => Err(format!("Local role {}, peer role {}", local, role)),
+        [None] => Err("Unknown role advertised by the peer".to_string()),
+        _ => Err("Multiple roles advertised by the peer".to_string()),
+    }
+}
+
+/// Local role for a received BGP Role capability (if advertised)
+pub(super) fn received_role(parameters: &[OpenParameter]) -> Option<Role> {
+    parameters
+        .iter()
+        .flat_map(|p| match p {
+            OpenParameter::Capabilities(caps) => caps.iter().collect(),
+            _ => vec![],
+        })
+        .find_map(|c| match c {
+            OpenCapability::Unknown {
+                cap_code, value, ..
+            } if *cap_code == BGP_ROLE && value.len() == 1 => Role::from_code(value[0]),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::Open;
+
+    #[test]
+    fn test_role_capability() {
+        let open = Open {
+            version: 4,
+            peer_asn: 65000,
+            hold_timer: 90,
+            identifier: 0x0101_0101,
+            parameters: vec![OpenParameter::Capabilities(vec![role_capability(
+                Role::Customer,
+            )])],
+        };
+        let mut buf: Vec<u8> = vec![];
+        open.encode(&mut buf).unwrap();
+        // [Capabilities (2), length][BGP Role (9), length, Customer (3)]
+        assert!(buf.ends_with(&[2, 3, 9, 1, 3]), "{:?}", buf);
+
+        let roles = ["provider", "rs", "rs-client", "customer", "peer"];
+        for (code, name) in roles.iter().enumerate() {
+            let role = Role::try_from(*name).unwrap();
+            assert_eq!(role.code(), code as u8);
+            assert_eq!(Role::from_code(code as u8), Some(role));
+            assert_eq!(role.to_string(), *name);
+        }
+        assert!(Role::try_from("transit").is_err());
+    }
+
+    #[test]
+    fn test_check_role() {
+        let open = |roles: &[Role]| -> Vec<OpenParameter> {
+            roles
+                .iter()
+                .map(|role| OpenParameter::Capabilities(vec![role_capability(*role)]))
+                .collect()
+        };
+        for (local, remote) in &[
+            (Role::Provider, Role::Customer),
+            (Role::Customer, Role::Provider),
+            (Role::RouteServer, Role::RouteServerClient),
+            (Role::RouteServerClient, Role::RouteServer),
+            (Role::Peer, Role::Peer),
+        ] {
+            assert_eq!(
+                check_role(*local, &open(&[*remote]), true),
+                Ok(Some(*remote))
+            );
+            assert_eq!(received_role(&open(&[*remote])), Some(*remote));
+        }
+        assert!(check_role(Role::Provider, &open(&[Role::Provider]), false).is_err());
+        assert!(check_role(Role::Peer, &open(&[Role::Customer]), false).is_err());
+        // Duplicate roles must agree
+        assert!(check_role(Role::Peer, &open(&[Role::Peer, Role::Peer]), false).is_ok());
+        assert!(check_role(Role::Peer, &open(&[Role::Peer, Role::Customer]), false).is_err());
+        // No role is only a mismatch in strict mode
+        assert_eq!(check_role(Role::Peer, &[], false), Ok(None));
+        assert!(check_role(Role::Peer, &[], true).is_err());
+        let unknown = vec![OpenParameter::Capabilities(vec![OpenCapability::Unknown {
+            cap_code: BGP_ROLE,
+            cap_length: 1,
+            value: vec![7],
+        }])];
+        assert!(check_role(Role::Peer, &unknown, false).is_err());
+    }
+
+    #[test]
+    fn test_only_to_customer() {
+        // Added to routes advertised to customers, peers & RS-clients
+        assert_eq!(Role::Provider.only_to_customer(65000), Some(65000));
+        assert_eq!(Role::Peer.only_to_customer(65000), Some(65000));
+        assert_eq!(Role::RouteServer.only_to_customer(65000), Some(65000));
+        assert_eq!(Role::Customer.only_to_customer(65000), None);
+        assert_eq!(Role::RouteServerClient.only_to_customer(65000), None);
+
+        // Leaked by a customer, RS-client, or a peer (marked by another AS)
+        assert!(Role::Provider.is_route_leak(Some(65001), 65001));
+        assert!(Role::RouteServer.is_route_leak(Some(65001), 65001));
+        assert!(Role::Peer.is_route_leak(Some(65002), 65001));
+        assert!(!Role::Peer.is_route_leak(Some(65001), 65001));
+        assert!(!Role::Customer.is_route_leak(Some(65002), 65001));
+        assert!(!Role::Provider.is_route_leak(None, 65001));
+    }
+}
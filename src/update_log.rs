@@ -0,0 +1,246 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+
+use bgp_rs::Update;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, warn};
+
+/// Received UPDATEs queued for the writer, once full UPDATEs are dropped
+const QUEUE_SIZE: usize = 1024;
+/// Size the log file can reach before it's rotated (E.g. to `updates.log.1`)
+const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+/// Rotated log files to keep
+const MAX_ROTATED_FILES: usize = 3;
+
+/// An UPDATE received from a peer, waiting to be written
+struct LoggedUpdate {
+    timestamp: DateTime<Utc>,
+    peer: IpAddr,
+    update: Update,
+}
+
+/// Debug log of every UPDATE received from peers (decoded & pretty-printed)
+///
+/// UPDATEs are written to a rotating file in a background thread, so a slow disk
+/// doesn't hold up session processing (UPDATEs are dropped if the writer falls behind)
+pub struct UpdateLog {
+    tx: SyncSender<LoggedUpdate>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl UpdateLog {
+    /// Open the log file (appending to it if it exists) and start the writer
+    pub fn start(path: &str) -> io::Result<Self> {
+        let writer = RotatingFile::open(PathBuf::from(path), MAX_FILE_SIZE)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer_dropped = dropped.clone();
+        thread::Builder::new()
+            .name("update-log".to_string())
+            .spawn(move || write_updates(writer, rx, &writer_dropped))?;
+        Ok(Self { tx, dropped })
+    }
+
+    /// Queue an UPDATE received from a peer to be written
+    pub fn log(&self, peer: IpAddr, update: &Update) {
+        let logged = LoggedUpdate {
+            timestamp: Utc::now(),
+            peer,
+            update: update.clone(),
+        };
+        match self.tx.try_send(logged) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!("Update log writer is falling behind, dropping UPDATEs");
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                debug!("Update log writer has ended, dropping UPDATE")
+            }
+        }
+    }
+
+    /// Number of UPDATEs dropped because the writer fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Write queued UPDATEs until the `UpdateLog` is dropped
+/// (noting any UPDATEs dropped since the last one was written)
+fn write_updates(mut writer: RotatingFile, rx: Receiver<LoggedUpdate>, dropped: &AtomicU64) {
+    let mut reported = 0;
+    for logged in rx {
+        let mut entry = String::new();
+        let total = dropped.load(Ordering::Relaxed);
+        if total > reported {
+            entry.push_str(&format!(
+                "{} [{} UPDATEs dropped]\n",
+                format_timestamp(logged.timestamp),
+                total - reported
+            ));
+            reported = total;
+        }
+        entry.push_str(&format_update(&logged));
+        if let Err(err) = writer.write(entry.as_bytes()) {
+            warn!(
+                "Error writing update log {}: {}",
+                writer.path.display(),
+                err
+            );
+        }
+    }
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn format_update(logged: &LoggedUpdate) -> String {
+    format!(
+        "{} UPDATE from {}\n{:#?}\n",
+        format_timestamp(logged.timestamp),
+        logged.peer,
+        logged.update
+    )
+}
+
+/// Log file that's renamed (E.g. `updates.log` -> `updates.log.1`) once it grows too large
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+        })
+    }
+
+    fn write(&mut self, entry: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + entry.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(entry)?;
+        self.size += entry.len() as u64;
+        Ok(())
+    }
+
+    /// Shift rotated files up one (dropping the oldest) and start a new file
+    fn rotate(&mut self) -> io::Result<()> {
+        for i in (1..MAX_ROTATED_FILES).rev() {
+            let from = rotated_path(&self.path, i);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, i + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgp_rs::{ASPath, NLRIEncoding, Origin, PathAttribute};
+
+    fn update(prefix: &str) -> Update {
+        Update {
+            withdrawn_routes: vec![],
+            attributes: vec![
+                PathAttribute::ORIGIN(Origin::IGP),
+                PathAttribute::AS_PATH(ASPath { segments: vec![] }),
+                PathAttribute::NEXT_HOP("10.0.0.1".parse().unwrap()),
+            ],
+            announced_routes: vec![NLRIEncoding::IP((prefix.parse().unwrap(), 24).into())],
+        }
+    }
+
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bgpd-{}-{}.log", name, std::process::id()));
+        for i in 0..=MAX_ROTATED_FILES {
+            let _ = fs::remove_file(if i == 0 {
+                path.clone()
+            } else {
+                rotated_path(&path, i)
+            });
+        }
+        path
+    }
+
+    #[test]
+    fn test_update_log() {
+        let path = log_path("updates");
+        let writer = RotatingFile::open(path.clone(), MAX_FILE_SIZE).unwrap();
+        let (tx, rx) = mpsc::sync_channel(1);
+        let log = UpdateLog {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        let peer: IpAddr = "127.0.0.2".parse().unwrap();
+        log.log(peer, &update("10.10.10.0"));
+        // Queue is full (the writer hasn't started)
+        log.log(peer, &update("10.10.20.0"));
+        assert_eq!(log.dropped(), 1);
+
+        let dropped = log.dropped.clone();
+        drop(log);
+        write_updates(writer, rx, &dropped);
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("UPDATE from 127.0.0.2"), "{}", contents);
+        assert!(contents.contains("10.10.10.0/24"), "{}", contents);
+        assert!(!contents.contains("10.10.20.0/24"), "{}", contents);
+        assert!(contents.contains("[1 UPDATEs dropped]"), "{}", contents);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let path = log_path("rotated");
+        let mut file = RotatingFile::open(path.clone(), 10).unwrap();
+        for entry in &["first\n", "second\n", "third\n", "fourth\n", "fifth\n"] {
+            file.write(entry.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fifth\n");
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "fourth\n"
+        );
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 3)).unwrap(),
+            "second\n"
+        );
+        // Oldest file is dropped
+        assert!(!rotated_path(&path, 4).exists());
+        for i in 0..=MAX_ROTATED_FILES {
+            let _ = fs::remove_file(rotated_path(&path, i));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,8 +1,11 @@
+use std::fmt::Write;
 use std::net::IpAddr;
 
-use bgp_rs::Message;
+use bgp_rs::{FlowspecFilter, Message, NumericOperator};
 use chrono::{DateTime, Duration, TimeZone, Utc};
 
+use super::parse::is_range;
+
 pub fn get_message_type(message: &Message) -> &'static str {
     match message {
         Message::KeepAlive => "KEEPALIVE",
@@ -13,6 +16,113 @@ pub fn get_message_type(message: &Message) -> &'static str {
     }
 }
 
+/// Name of a NOTIFICATION error code & subcode, E.g. "Cease / Administrative Shutdown"
+pub fn notification_name(major: u8, minor: u8) -> String {
+    let (name, subcodes): (&str, &[&str]) = match major {
+        1 => (
+            "Message Header Error",
+            &[
+                "Connection Not Synchronized",
+                "Bad Message Length",
+                "Bad Message Type",
+            ],
+        ),
+        2 => (
+            "OPEN Message Error",
+            &[
+                "Unsupported Version Number",
+                "Bad Peer AS",
+                "Bad BGP Identifier",
+                "Unsupported Optional Parameter",
+                "Deprecated",
+                "Unacceptable Hold Time",
+                "Unsupported Capability",
+                "Deprecated",
+                "Deprecated",
+                "Deprecated",
+                "Role Mismatch",
+            ],
+        ),
+        3 => (
+            "UPDATE Message Error",
+            &[
+                "Malformed Attribute List",
+                "Unrecognized Well-known Attribute",
+                "Missing Well-known Attribute",
+                "Attribute Flags Error",
+                "Attribute Length Error",
+                "Invalid ORIGIN Attribute",
+                "Deprecated",
+                "Invalid NEXT_HOP Attribute",
+                "Optional Attribute Error",
+                "Invalid Network Field",
+                "Malformed AS_PATH",
+            ],
+        ),
+        4 => ("Hold Timer Expired", &[]),
+        5 => (
+            "Finite State Machine Error",
+            &[
+                "Unexpected Message in OpenSent",
+                "Unexpected Message in OpenConfirm",
+                "Unexpected Message in Established",
+            ],
+        ),
+        6 => (
+            "Cease",
+            &[
+                "Maximum Number of Prefixes Reached",
+                "Administrative Shutdown",
+                "Peer De-configured",
+                "Administrative Reset",
+                "Connection Rejected",
+                "Other Configuration Change",
+                "Connection Collision Resolution",
+                "Out of Resources",
+                "Hard Reset",
+            ],
+        ),
+        7 => ("ROUTE-REFRESH Message Error", &["Invalid Message Length"]),
+        _ => return format!("Unknown Error {}/{}", major, minor),
+    };
+    match minor {
+        0 => name.to_string(),
+        minor => match subcodes.get(usize::from(minor) - 1) {
+            Some(subcode) => format!("{} / {}", name, subcode),
+            None => format!("{} / Subcode {}", name, minor),
+        },
+    }
+}
+
+/// Describe a NOTIFICATION, including any Shutdown Communication (RFC 8203/9003) in the data
+/// E.g. "Cease / Administrative Shutdown: \"Upgrading\"", "Cease / Connection Rejected"
+pub fn notification_to_string(major: u8, minor: u8, data: &[u8]) -> String {
+    let name = notification_name(major, minor);
+    if data.is_empty() {
+        return name;
+    }
+    // Administrative Shutdown or Reset
+    if (major, minor) == (6, 2) || (major, minor) == (6, 4) {
+        if let Some(message) = shutdown_communication(data) {
+            return format!("{}: {:?}", name, message);
+        }
+    }
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data {
+        write!(hex, "{:02x}", byte).expect("Write to String");
+    }
+    format!("{} [data: {}]", name, hex)
+}
+
+/// Length-prefixed UTF-8 message (empty if the length is 0)
+fn shutdown_communication(data: &[u8]) -> Option<&str> {
+    let (length, message) = data.split_first()?;
+    if usize::from(*length) != message.len() {
+        return None;
+    }
+    std::str::from_utf8(message).ok()
+}
+
 /// Convert an ASN (4 byte) as dotted if it exceeds the 2-byte limit
 /// E.g. 42598400100 -> "65000.100"
 pub fn u32_to_dotted(asn: u32, sep: char) -> String {
@@ -29,6 +139,56 @@ pub fn u32_to_dotted(asn: u32, sep: char) -> String {
     }
 }
 
+/// Flowspec match rule in the format used to advertise flows (the inverse of parsing a match)
+/// E.g. "source 192.168.0.0/16" or "destination-port >=8000 <=8080"
+pub fn flowspec_match_to_string(filter: &FlowspecFilter) -> String {
+    use FlowspecFilter::*;
+    match filter {
+        DestinationPrefix(prefix) => format!("destination {}", prefix),
+        SourcePrefix(prefix) => format!("source {}", prefix),
+        IpProtocol(values) => num_operators_to_string("protocol", values),
+        Port(values) => num_operators_to_string("port", values),
+        DestinationPort(values) => num_operators_to_string("destination-port", values),
+        SourcePort(values) => num_operators_to_string("source-port", values),
+        IcmpType(values) => num_operators_to_string("icmp-type", values),
+        IcmpCode(values) => num_operators_to_string("icmp-code", values),
+        PacketLength(values) => num_operators_to_string("packet-length", values),
+        DSCP(values) => num_operators_to_string("dscp", values),
+        // Binary operators aren't supported in match rules
+        filter => filter.to_string(),
+    }
+}
+
+/// Operator/value pairs (E.g. ">1024 <2048"), with '&' or '|' only where it isn't implied
+fn num_operators_to_string<T>(name: &str, values: &[(NumericOperator, T)]) -> String
+where
+    T: std::fmt::Display,
+{
+    let mut output = name.to_string();
+    for (i, (oper, value)) in values.iter().enumerate() {
+        output.push(' ');
+        if i > 0 {
+            let and = oper.contains(NumericOperator::AND);
+            match (and, is_range(values[i - 1].0, *oper)) {
+                (true, false) => output.push('&'),
+                (false, true) => output.push('|'),
+                _ => (),
+            }
+        }
+        if oper.contains(NumericOperator::LT) {
+            output.push('<');
+        }
+        if oper.contains(NumericOperator::GT) {
+            output.push('>');
+        }
+        if oper.contains(NumericOperator::EQ) {
+            output.push('=');
+        }
+        let _ = write!(output, "{}", value);
+    }
+    output
+}
+
 /// Convert first 16 bytes (1 IPv6 address) to IpAddr
 /// TODO: Handle multiple next hops
 ///       Can they be variable length?
@@ -53,7 +213,7 @@ where
 
 /// Given a duration, format like "00:00:00"
 pub fn format_elapsed_time(elapsed: Duration) -> String {
-    let elapsed = elapsed.num_seconds().abs() as u64;
+    let elapsed = elapsed.num_seconds().unsigned_abs();
     let (hours, remainder) = fit_with_remainder(elapsed, 3600);
     let (minutes, seconds) = fit_with_remainder(remainder, 60);
     format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
@@ -71,6 +231,111 @@ where
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_notification_name() {
+        assert_eq!(notification_name(6, 2), "Cease / Administrative Shutdown");
+        assert_eq!(notification_name(4, 0), "Hold Timer Expired");
+        assert_eq!(notification_name(2, 2), "OPEN Message Error / Bad Peer AS");
+        assert_eq!(notification_name(6, 42), "Cease / Subcode 42");
+        assert_eq!(notification_name(9, 1), "Unknown Error 9/1");
+    }
+
+    #[test]
+    fn test_notification_to_string() {
+        assert_eq!(
+            notification_to_string(2, 7, &[]),
+            "OPEN Message Error / Unsupported Capability"
+        );
+        assert_eq!(notification_to_string(4, 0, &[]), "Hold Timer Expired");
+        assert_eq!(
+            notification_to_string(6, 5, &[]),
+            "Cease / Connection Rejected"
+        );
+        assert_eq!(
+            notification_to_string(5, 3, &[]),
+            "Finite State Machine Error / Unexpected Message in Established"
+        );
+        assert_eq!(
+            notification_to_string(6, 2, b"\x09Upgrading"),
+            "Cease / Administrative Shutdown: \"Upgrading\""
+        );
+        assert_eq!(
+            notification_to_string(6, 4, b"\x00"),
+            "Cease / Administrative Reset: \"\""
+        );
+        // Malformed Shutdown Communication (length mismatch)
+        assert_eq!(
+            notification_to_string(6, 2, b"\x05Hi"),
+            "Cease / Administrative Shutdown [data: 054869]"
+        );
+        assert_eq!(
+            notification_to_string(2, 2, &[0xfd, 0xe8]),
+            "OPEN Message Error / Bad Peer AS [data: fde8]"
+        );
+    }
+
+    #[test]
+    fn test_flowspec_match_to_string() {
+        use crate::api::rpc::FlowSpec;
+        use crate::utils::parse_flow_spec;
+        use bgp_rs::{Capabilities, Header, MPReachNLRI, NLRIEncoding, PathAttribute, Update};
+        let filters = |afi: u16, matches: &[&str]| {
+            let matches = matches.iter().map(|m| m.to_string()).collect();
+            match parse_flow_spec(&FlowSpec::new(afi, "discard".to_string(), matches)) {
+                Ok((_, _, NLRIEncoding::FLOWSPEC(filters))) => filters,
+                other => panic!("Expected Flowspec NLRI, got {:?}", other),
+            }
+        };
+
+        // Encode & decode a flow, as learned from a peer
+        let update = Update {
+            withdrawn_routes: vec![],
+            attributes: vec![PathAttribute::MP_REACH_NLRI(MPReachNLRI {
+                afi: bgp_rs::AFI::IPV6,
+                safi: bgp_rs::SAFI::Flowspec,
+                next_hop: vec![],
+                announced_routes: vec![NLRIEncoding::FLOWSPEC(filters(
+                    2,
+                    &["source 3001:100::/56", "destination-port >=8000 <=8080"],
+                ))],
+            })],
+            announced_routes: vec![],
+        };
+        let mut buf = vec![];
+        update.encode(&mut buf).unwrap();
+        let header = Header {
+            marker: [0xff; 16],
+            length: buf.len() as u16 + 19,
+            record_type: 2,
+        };
+        let decoded = Update::parse(&header, &mut &buf[..], &Capabilities::default()).unwrap();
+        let learned = match decoded.get(bgp_rs::Identifier::MP_REACH_NLRI) {
+            Some(PathAttribute::MP_REACH_NLRI(nlri)) => match &nlri.announced_routes[0] {
+                NLRIEncoding::FLOWSPEC(filters) => filters.clone(),
+                other => panic!("Expected Flowspec NLRI, got {:?}", other),
+            },
+            _ => panic!("Expected MP_REACH_NLRI"),
+        };
+        let rules: Vec<_> = learned.iter().map(flowspec_match_to_string).collect();
+        assert_eq!(
+            rules,
+            vec!["source 3001:100::/56", "destination-port >=8000 <=8080"]
+        );
+
+        // Explicit AND/OR is kept where it isn't implied by a range
+        for rule in &[
+            "destination 10.0.0.0/8",
+            "port =80 =443",
+            "port >1024 <2048",
+            "packet-length <100 >1500",
+            "destination-port >=8000 |<=100 &>=50",
+            "protocol =6",
+        ] {
+            let filter = &filters(1, &[rule])[0];
+            assert_eq!(&flowspec_match_to_string(filter), rule);
+        }
+    }
+
     #[test]
     fn test_u32_to_dotted() {
         assert_eq!(u32_to_dotted(100, '.'), "100".to_string());
@@ -0,0 +1,97 @@
+//! JSON log lines (`--log-format json`) for log aggregation
+//!
+//! Each record is a single JSON object, with any structured fields attached by the
+//! call site (E.g. peer address & session state) alongside the message.
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{SecondsFormat, Utc};
+use env_logger::fmt::Formatter;
+use log::Record;
+use serde_json::{Map, Value};
+
+/// Fields are only collected once JSON logging is enabled
+static FIELDS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Structured fields for records logged in the current `with_log_fields`
+    static FIELDS: RefCell<Vec<(&'static str, String)>> = RefCell::new(vec![]);
+}
+
+/// Collect structured fields (from `with_log_fields`) for JSON log lines
+pub fn enable_log_fields() {
+    FIELDS_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Attach structured fields to the records logged by `log`
+pub fn with_log_fields<F: FnOnce()>(fields: &[(&'static str, &dyn Display)], log: F) {
+    if !FIELDS_ENABLED.load(Ordering::Relaxed) {
+        return log();
+    }
+    FIELDS.with(|current| {
+        *current.borrow_mut() = fields
+            .iter()
+            .map(|(key, value)| (*key, value.to_string()))
+            .collect();
+    });
+    log();
+    FIELDS.with(|current| current.borrow_mut().clear());
+}
+
+/// `env_logger` format for JSON log lines
+pub fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let line = FIELDS.with(|fields| json_record(record, &fields.borrow()));
+    writeln!(buf, "{}", line)
+}
+
+fn json_record(record: &Record, fields: &[(&'static str, String)]) -> Value {
+    let mut line = Map::new();
+    line.insert(
+        "timestamp".into(),
+        Utc::now()
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+            .into(),
+    );
+    line.insert("level".into(), record.level().as_str().into());
+    line.insert("target".into(), record.target().into());
+    line.insert("message".into(), record.args().to_string().into());
+    for (key, value) in fields {
+        line.insert((*key).into(), value.as_str().into());
+    }
+    Value::Object(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn test_json_record() {
+        let fields = vec![
+            ("peer", "127.0.0.2".to_string()),
+            ("to", "Established".to_string()),
+        ];
+        let line = json_record(
+            &Record::builder()
+                .args(format_args!(
+                    "127.0.0.2 went from OpenConfirm to Established"
+                ))
+                .level(Level::Debug)
+                .target("bgpd_rs::session::lib")
+                .build(),
+            &fields,
+        );
+        assert_eq!(line["level"], "DEBUG");
+        assert_eq!(line["target"], "bgpd_rs::session::lib");
+        assert_eq!(
+            line["message"],
+            "127.0.0.2 went from OpenConfirm to Established"
+        );
+        assert_eq!(line["peer"], "127.0.0.2");
+        assert_eq!(line["to"], "Established");
+        assert!(line["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+}
@@ -1,4 +1,6 @@
 mod display;
 pub use display::*;
+mod logging;
+pub use logging::*;
 mod parse;
 pub use parse::*;
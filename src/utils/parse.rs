@@ -57,6 +57,17 @@ pub fn get_host_address(network: &IpNetwork) -> Option<IpAddr> {
     }
 }
 
+/// Find the most-specific (longest prefix) network containing this address
+pub fn longest_match<'a, I>(networks: I, addr: IpAddr) -> Option<&'a IpNetwork>
+where
+    I: IntoIterator<Item = &'a IpNetwork>,
+{
+    networks
+        .into_iter()
+        .filter(|network| network.is_ipv4() == addr.is_ipv4() && network.contains(addr))
+        .max_by_key(|network| network.prefix())
+}
+
 /// Convert an ASN string to a u32
 /// E.g. "65000.100" -> 42598400100
 pub fn asn_from_dotted(value: &str) -> std::result::Result<u32, ParseError> {
@@ -84,6 +95,51 @@ pub fn asn_from_dotted(value: &str) -> std::result::Result<u32, ParseError> {
     Ok((chunks[1] * 65536) + chunks[0])
 }
 
+/// Parse AS_PATH segments, where ASNs in braces form an AS_SET
+/// E.g. ["100", "200", "{300", "400}"] is AS_SEQUENCE [100, 200], AS_SET [300, 400]
+fn parse_as_path(path: &[String]) -> Result<ASPath, ParseError> {
+    let path = path.join(" ").replace('{', " { ").replace('}', " } ");
+    let mut segments: Vec<Segment> = vec![];
+    let mut sequence: Vec<u32> = vec![];
+    let mut set: Option<Vec<u32>> = None;
+    for word in path.split_whitespace() {
+        match (word, set.as_mut()) {
+            ("{", None) => {
+                if !sequence.is_empty() {
+                    segments.push(Segment::AS_SEQUENCE(std::mem::take(&mut sequence)));
+                }
+                set = Some(vec![]);
+            }
+            ("}", Some(asns)) if !asns.is_empty() => {
+                segments.push(Segment::AS_SET(std::mem::take(asns)));
+                set = None;
+            }
+            ("{", Some(_)) | ("}", _) => {
+                return Err(ParseError::new(format!(
+                    "Invalid AS_SET in '{}'",
+                    path.trim()
+                )))
+            }
+            (asn, asns) => {
+                let asn = asn_from_dotted(asn)
+                    .map_err(|err| ParseError::new(format!("Error parsing ASN: {}", err.reason)))?;
+                asns.unwrap_or(&mut sequence).push(asn);
+            }
+        }
+    }
+    if set.is_some() {
+        return Err(ParseError::new(format!(
+            "Unclosed AS_SET in '{}'",
+            path.trim()
+        )));
+    }
+    // An empty path is an empty AS_SEQUENCE
+    if !sequence.is_empty() || segments.is_empty() {
+        segments.push(Segment::AS_SEQUENCE(sequence));
+    }
+    Ok(ASPath { segments })
+}
+
 /// Convert a CIDR prefix (E.g. "192.168.0.0/24") to a bgp_rs::Prefix
 /// ```
 /// use bgp_rs::Prefix;
@@ -148,7 +204,9 @@ pub fn parse_flow_spec(
     let family = Family::new(AFI::try_from(spec.afi)?, SAFI::Flowspec);
     let mut attributes = parse_attributes(&spec.attributes)?;
     // Parse Action
-    attributes.push(parse_flowspec_action(&spec.action)?.into());
+    if let Some(action) = parse_flowspec_action(&spec.action)?.attribute() {
+        attributes.push(action);
+    }
     // Parse Filters
     let filters: Vec<_> = spec
         .matches
@@ -176,22 +234,19 @@ fn parse_attributes(attrs: &SpecAttributes) -> Result<Vec<PathAttribute>, ParseE
     if let Some(med) = attrs.multi_exit_disc {
         attributes.push(PathAttribute::MULTI_EXIT_DISC(med));
     }
-
-    let as_path = {
-        let mut asns: Vec<u32> = Vec::with_capacity(attrs.as_path.len());
-        for asn in &attrs.as_path {
-            asns.push(asn_from_dotted(asn).map_err(|err| {
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("Error parsing ASN: {}", err.reason),
-                )
-            })?);
-        }
-        ASPath {
-            segments: vec![Segment::AS_SEQUENCE(asns)],
+    if let Some(originator_id) = attrs.originator_id {
+        match originator_id {
+            IpAddr::V4(addr) => attributes.push(PathAttribute::ORIGINATOR_ID(addr.into())),
+            IpAddr::V6(_) => {
+                return Err(ParseError::new(format!(
+                    "Originator ID must be an IPv4 address '{}'",
+                    originator_id
+                )))
+            }
         }
-    };
-    attributes.push(PathAttribute::AS_PATH(as_path));
+    }
+
+    attributes.push(PathAttribute::AS_PATH(parse_as_path(&attrs.as_path)?));
     let communities = {
         let mut comms: Vec<Community> = Vec::with_capacity(attrs.communities.len());
         for comm in &attrs.communities {
@@ -207,11 +262,22 @@ fn parse_attributes(attrs: &SpecAttributes) -> Result<Vec<PathAttribute>, ParseE
     if !extd_communities.is_empty() {
         attributes.push(PathAttribute::EXTENDED_COMMUNITIES(extd_communities));
     }
+    let large_communities = communities.large();
+    if !large_communities.is_empty() {
+        attributes.push(PathAttribute::LARGE_COMMUNITY(large_communities));
+    }
+    if let Some(community) = communities.ipv6_extended().first() {
+        attributes.push(PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(*community));
+    }
     Ok(attributes)
 }
 
 #[derive(Debug)]
 pub enum FlowAction {
+    /// Accept (no action community, matching traffic is permitted)
+    Accept,
+    /// Discard (Traffic Rate of 0)
+    Discard,
     /// Redirect (with 2:4 byte route target)
     Redirect(Community),
     /// Traffic Rate (with 2-byte ASN and 4 byte float)
@@ -222,10 +288,13 @@ pub enum FlowAction {
     MarkDSCP(u8),
 }
 
-impl From<FlowAction> for PathAttribute {
-    fn from(action: FlowAction) -> PathAttribute {
+impl FlowAction {
+    /// Extended community attribute for this action (if any)
+    pub fn attribute(self) -> Option<PathAttribute> {
         use FlowAction::*;
-        let community = match action {
+        let community = match self {
+            Accept => return None,
+            Discard => u64::from_be_bytes([0x80, 0x06, 0, 0, 0, 0, 0, 0]),
             TrafficRate(bps) => {
                 let mut comm_bytes = [0x80, 0x06, 0, 0, 0, 0, 0, 0];
                 comm_bytes[4..8].clone_from_slice(&bps.to_be_bytes());
@@ -254,7 +323,13 @@ impl From<FlowAction> for PathAttribute {
                     comm_bytes[6..8].clone_from_slice(&[bytes[2], bytes[3]]);
                     u64::from_be_bytes(comm_bytes)
                 }
-                _ => unreachable!(),
+                // E.g. "redirect:65000:100"
+                Community::EXTENDED(val) => val,
+                Community::LARGE(_) => unreachable!("Large redirect communities aren't parsed"),
+                // E.g. "redirect:2001:db8::1:100"
+                Community::IPV6_EXTENDED(val) => {
+                    return Some(PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY(val))
+                }
             },
             MarkDSCP(dscp) => {
                 let mut comm_bytes = [0x80, 0x09, 0, 0, 0, 0, 0, 0];
@@ -262,7 +337,7 @@ impl From<FlowAction> for PathAttribute {
                 u64::from_be_bytes(comm_bytes)
             }
         };
-        PathAttribute::EXTENDED_COMMUNITIES(vec![community])
+        Some(PathAttribute::EXTENDED_COMMUNITIES(vec![community]))
     }
 }
 
@@ -271,41 +346,76 @@ fn parse_flowspec_action(action: &str) -> Result<FlowAction, ParseError> {
     if words.is_empty() {
         return Err(ParseError::new(String::from("No FlowSpec Action found")));
     }
+    let name = words[0].to_lowercase();
+    match name.as_str() {
+        "accept" => return Ok(FlowAction::Accept),
+        "discard" => return Ok(FlowAction::Discard),
+        "redirect" | "traffic-action" | "traffic-rate" | "mark" => (),
+        _ => {
+            return Err(ParseError::new(format!(
+                "Unsupported Flowspec Action '{}' (expected accept, discard, redirect, traffic-rate, traffic-action, or mark)",
+                words[0]
+            )))
+        }
+    }
     if words.len() < 2 {
         return Err(ParseError::new(format!(
             "Cannot parse action: '{}'",
             action
         )));
     }
-    match words[0].to_lowercase().as_str() {
-        "redirect" => Ok(FlowAction::Redirect(
-            Community::try_from(words[1]).map_err(|_| {
-                ParseError::new(format!("Unable to parse redirect community '{}'", words[1]))
-            })?,
-        )),
-        "traffic-action" => Ok(FlowAction::TrafficAction((
-            words.contains(&"sample"),
-            false,
-        ))),
-        "traffic-rate" => Ok(FlowAction::TrafficRate(words[1].parse::<f32>().map_err(
-            |_| ParseError::new(format!("Unable to parse traffic-rate bps '{}'", words[1])),
-        )?)),
+    match name.as_str() {
+        // A community, E.g. "6:302", or an IPv6 route target, E.g. "2001:db8::1:100"
+        "redirect" => match Community::try_from(words[1])
+            .or_else(|_| Community::try_from(format!("redirect:{}", words[1]).as_str()))
+        {
+            Ok(Community::LARGE(_)) | Err(_) => Err(ParseError::new(format!(
+                "Unable to parse redirect community '{}'",
+                words[1]
+            ))),
+            Ok(comm) => Ok(FlowAction::Redirect(comm)),
+        },
+        "traffic-action" => {
+            let (mut sample, mut terminal) = (false, false);
+            for word in &words[1..] {
+                match word.to_lowercase().as_str() {
+                    "sample" => sample = true,
+                    "terminal" => terminal = true,
+                    _ => {
+                        return Err(ParseError::new(format!(
+                            "Unsupported traffic-action '{}' (expected 'sample' or 'terminal')",
+                            word
+                        )))
+                    }
+                }
+            }
+            Ok(FlowAction::TrafficAction((sample, terminal)))
+        }
+        "traffic-rate" => {
+            let bps = words[1].parse::<f32>().map_err(|_| {
+                ParseError::new(format!("Unable to parse traffic-rate bps '{}'", words[1]))
+            })?;
+            if !bps.is_finite() || bps < 0.0 {
+                return Err(ParseError::new(format!(
+                    "Not a valid traffic-rate '{}' (expected bps >= 0)",
+                    words[1]
+                )));
+            }
+            Ok(FlowAction::TrafficRate(bps))
+        }
         "mark" => {
             let dscp = words[1].parse::<u8>().map_err(|_| {
                 ParseError::new(format!("Unable to parse DSCP value '{}'", words[1]))
             })?;
-            if dscp > 64 {
+            if dscp > 63 {
                 return Err(ParseError::new(format!(
-                    "Not a valid DSCP value '{}'",
+                    "Not a valid DSCP value '{}' (expected 0-63)",
                     dscp
                 )));
             }
             Ok(FlowAction::MarkDSCP(dscp))
         }
-        _ => Err(ParseError::new(format!(
-            "Unsupported Flowspec Action: {}",
-            words[0]
-        ))),
+        _ => unreachable!("Action names are checked above"),
     }
 }
 
@@ -328,62 +438,15 @@ fn parse_flowspec_match(rule: &str) -> Result<FlowspecFilter, ParseError> {
                 .map_err(|_| ParseError::new(format!("Unable to parse prefix '{}'", words[1])))?;
             Ok(FlowspecFilter::SourcePrefix(src))
         }
-        "protocol" => {
-            let values: Vec<_> = words[1]
-                .split_whitespace()
-                .enumerate()
-                .map(|(i, w)| parse_num_operator(w, i))
-                .collect::<Result<Vec<_>, _>>()?;
-            Ok(FlowspecFilter::IpProtocol(values))
-        }
-        "port" => {
-            let values: Vec<_> = words[1]
-                .split_whitespace()
-                .enumerate()
-                .map(|(i, w)| parse_num_operator(w, i))
-                .collect::<Result<Vec<_>, _>>()?;
-            Ok(FlowspecFilter::Port(values))
-        }
-        "destination-port" => {
-            let values: Vec<_> = words[1]
-                .split_whitespace()
-                .enumerate()
-                .map(|(i, w)| parse_num_operator(w, i))
-                .collect::<Result<Vec<_>, _>>()?;
-            Ok(FlowspecFilter::DestinationPort(values))
-        }
-        "source-port" => {
-            let values: Vec<_> = words[1]
-                .split_whitespace()
-                .enumerate()
-                .map(|(i, w)| parse_num_operator(w, i))
-                .collect::<Result<Vec<_>, _>>()?;
-            Ok(FlowspecFilter::SourcePort(values))
-        }
-        "icmp-type" => {
-            let values: Vec<_> = words[1]
-                .split_whitespace()
-                .enumerate()
-                .map(|(i, w)| parse_num_operator(w, i))
-                .collect::<Result<Vec<_>, _>>()?;
-            Ok(FlowspecFilter::IcmpType(values))
-        }
-        "icmp-code" => {
-            let values: Vec<_> = words[1]
-                .split_whitespace()
-                .enumerate()
-                .map(|(i, w)| parse_num_operator(w, i))
-                .collect::<Result<Vec<_>, _>>()?;
-            Ok(FlowspecFilter::IcmpCode(values))
-        }
-        "packet-length" => {
-            let values: Vec<_> = words[1]
-                .split_whitespace()
-                .enumerate()
-                .map(|(i, w)| parse_num_operator(w, i))
-                .collect::<Result<Vec<_>, _>>()?;
-            Ok(FlowspecFilter::PacketLength(values))
-        }
+        "protocol" => Ok(FlowspecFilter::IpProtocol(parse_num_operators(words[1])?)),
+        "port" => Ok(FlowspecFilter::Port(parse_num_operators(words[1])?)),
+        "destination-port" => Ok(FlowspecFilter::DestinationPort(parse_num_operators(
+            words[1],
+        )?)),
+        "source-port" => Ok(FlowspecFilter::SourcePort(parse_num_operators(words[1])?)),
+        "icmp-type" => Ok(FlowspecFilter::IcmpType(parse_num_operators(words[1])?)),
+        "icmp-code" => Ok(FlowspecFilter::IcmpCode(parse_num_operators(words[1])?)),
+        "packet-length" => Ok(FlowspecFilter::PacketLength(parse_num_operators(words[1])?)),
         _ => Err(ParseError::new(format!(
             "Unsupported Flowspec Match: {}",
             words[0]
@@ -391,7 +454,44 @@ fn parse_flowspec_match(rule: &str) -> Result<FlowspecFilter, ParseError> {
     }
 }
 
-fn parse_num_operator<T>(word: &str, index: usize) -> Result<(NumericOperator, T), ParseError>
+/// Parse a list of numeric operator/value pairs (E.g. "80 443" or ">1024 <2048")
+///   - Items are OR'd by default ("80 443" matches 80 OR 443)
+///   - A lower bound followed by an upper bound is AND'd as a range (">1024 <2048")
+///   - Prefix an item with '&' or '|' to explicitly AND/OR with the previous item
+fn parse_num_operators<T>(values: &str) -> Result<Vec<(NumericOperator, T)>, ParseError>
+where
+    T: std::str::FromStr,
+{
+    let mut parsed: Vec<(NumericOperator, T)> = vec![];
+    for word in values.split_whitespace() {
+        let (explicit, word) = match word.chars().next() {
+            Some('&') => (Some(true), &word[1..]),
+            Some('|') => (Some(false), &word[1..]),
+            _ => (None, word),
+        };
+        let (mut oper, value) = parse_num_operator(word)?;
+        let and = match (explicit, parsed.last()) {
+            (_, None) => false,
+            (Some(and), _) => and,
+            (None, Some((prev, _))) => is_range(*prev, oper),
+        };
+        if and {
+            oper |= NumericOperator::AND;
+        }
+        parsed.push((oper, value));
+    }
+    Ok(parsed)
+}
+
+/// Is this pair of operators a lower bound followed by an upper bound?
+pub(super) fn is_range(prev: NumericOperator, next: NumericOperator) -> bool {
+    prev.contains(NumericOperator::GT)
+        && !prev.contains(NumericOperator::LT)
+        && next.contains(NumericOperator::LT)
+        && !next.contains(NumericOperator::GT)
+}
+
+fn parse_num_operator<T>(word: &str) -> Result<(NumericOperator, T), ParseError>
 where
     T: std::str::FromStr,
 {
@@ -399,7 +499,6 @@ where
     let mut oper = NumericOperator::new(0);
     for (i, chr) in word.chars().enumerate() {
         match chr {
-            // '&' => oper |= NumericOperator::AND,
             '>' => oper |= NumericOperator::GT,
             '<' => oper |= NumericOperator::LT,
             '=' => oper |= NumericOperator::EQ,
@@ -416,10 +515,6 @@ where
     if oper.is_empty() {
         oper |= NumericOperator::EQ;
     }
-    // All subsequent items in the same filter are AND'd
-    if index > 0 {
-        oper |= NumericOperator::AND;
-    }
     Ok((oper, value))
 }
 
@@ -450,6 +545,39 @@ mod tests {
         assert!(asn_from_dotted("test").is_err());
     }
 
+    #[test]
+    fn test_parse_as_path() {
+        let path = |words: &[&str]| {
+            let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+            parse_as_path(&words).map(|path| format!("{:?}", path.segments))
+        };
+        assert_eq!(
+            path(&["100", "200", "{300", "400}"]).unwrap(),
+            "[AS_SEQUENCE([100, 200]), AS_SET([300, 400])]"
+        );
+        assert_eq!(
+            path(&["{", "300", "}", "100", "65000.100"]).unwrap(),
+            "[AS_SET([300]), AS_SEQUENCE([100, 4259840100])]"
+        );
+        // Sets in a single word (E.g. from the API)
+        assert_eq!(
+            path(&["100", "{300 400}", "{500}"]).unwrap(),
+            "[AS_SEQUENCE([100]), AS_SET([300, 400]), AS_SET([500])]"
+        );
+        assert_eq!(path(&["100", "200"]).unwrap(), "[AS_SEQUENCE([100, 200])]");
+        assert_eq!(path(&[]).unwrap(), "[AS_SEQUENCE([])]");
+
+        for invalid in &[
+            &["{100", "{200}}"][..],
+            &["100", "{300"],
+            &["100}"],
+            &["{}"],
+            &["{100", "x}"],
+        ] {
+            assert!(path(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
     #[test]
     fn test_prefix_from_string() {
         let prefix = prefix_from_str("1.1.1.0/24").unwrap();
@@ -471,6 +599,24 @@ mod tests {
             }
             _ => panic!(),
         }
+        match parse_flowspec_action("redirect 2001:db8::1:100") {
+            Ok(FlowAction::Redirect(comm)) => {
+                assert_eq!(comm.to_string(), "redirect:2001:db8::1:100");
+                match FlowAction::Redirect(comm).attribute() {
+                    Some(PathAttribute::IPV6_SPECIFIC_EXTENDED_COMMUNITY((
+                        c_type,
+                        sub_type,
+                        addr,
+                        100,
+                    ))) => {
+                        assert_eq!((c_type, sub_type), (0x00, 0x0d));
+                        assert_eq!(addr, "2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap());
+                    }
+                    attribute => panic!("Expected IPv6 extended community, got {:?}", attribute),
+                }
+            }
+            _ => panic!(),
+        }
         match parse_flowspec_action("redirect vrf:test") {
             Err(_) => (),
             _ => panic!(),
@@ -501,6 +647,51 @@ mod tests {
             Err(_) => (),
             _ => panic!(),
         }
+        match parse_flowspec_action("mark 64") {
+            Err(err) => assert!(err.reason.contains("expected 0-63")),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_flowspec_accept_discard() {
+        match parse_flowspec_action("discard") {
+            Ok(FlowAction::Discard) => (),
+            _ => panic!(),
+        }
+        // Discard is encoded as a Traffic Rate of 0
+        let discard = parse_flowspec_action("Discard").unwrap().attribute();
+        let rate_zero = parse_flowspec_action("traffic-rate 0").unwrap().attribute();
+        match (discard, rate_zero) {
+            (
+                Some(PathAttribute::EXTENDED_COMMUNITIES(discard)),
+                Some(PathAttribute::EXTENDED_COMMUNITIES(rate_zero)),
+            ) => {
+                assert_eq!(discard, rate_zero);
+                assert_eq!(discard[0].to_be_bytes(), [0x80, 0x06, 0, 0, 0, 0, 0, 0]);
+            }
+            _ => panic!(),
+        }
+        match parse_flowspec_action("accept") {
+            Ok(action) => assert!(action.attribute().is_none()),
+            _ => panic!(),
+        }
+        match parse_flowspec_action("traffic-action sample terminal") {
+            Ok(FlowAction::TrafficAction((sample, terminal))) => assert!(sample && terminal),
+            _ => panic!(),
+        }
+        match parse_flowspec_action("traffic-action drop") {
+            Err(err) => assert!(err.reason.contains("'drop'")),
+            _ => panic!(),
+        }
+        match parse_flowspec_action("traffic-rate -100") {
+            Err(_) => (),
+            _ => panic!(),
+        }
+        match parse_flowspec_action("drop") {
+            Err(err) => assert!(err.reason.contains("expected accept, discard")),
+            _ => panic!(),
+        }
     }
 
     #[test]
@@ -571,4 +762,50 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn test_flowspec_numeric_and_or() {
+        // Separate values are OR'd
+        match parse_flowspec_match("port 80 443") {
+            Ok(FlowspecFilter::Port(values)) => {
+                assert_eq!(values.len(), 2);
+                assert_eq!(values[0], (NumericOperator::EQ, 80));
+                assert_eq!(values[1], (NumericOperator::EQ, 443));
+            }
+            _ => panic!(),
+        }
+        // Lower & upper bounds form an AND range
+        match parse_flowspec_match("port >1024 <2048") {
+            Ok(FlowspecFilter::Port(values)) => {
+                assert_eq!(values[0], (NumericOperator::GT, 1024));
+                assert_eq!(
+                    values[1],
+                    (NumericOperator::LT | NumericOperator::AND, 2048)
+                );
+            }
+            _ => panic!(),
+        }
+        // Outside of a range is OR'd
+        match parse_flowspec_match("packet-length <100 >1500") {
+            Ok(FlowspecFilter::PacketLength(values)) => {
+                assert_eq!(values[0], (NumericOperator::LT, 100));
+                assert_eq!(values[1], (NumericOperator::GT, 1500));
+            }
+            _ => panic!(),
+        }
+        // Explicit AND/OR
+        match parse_flowspec_match("destination-port >=8000 |<=100 &>=50") {
+            Ok(FlowspecFilter::DestinationPort(values)) => {
+                assert_eq!(values.len(), 3);
+                assert_eq!(values[0].0, NumericOperator::GT | NumericOperator::EQ);
+                assert_eq!(values[1].0, NumericOperator::LT | NumericOperator::EQ);
+                assert_eq!(
+                    values[2].0,
+                    NumericOperator::GT | NumericOperator::EQ | NumericOperator::AND
+                );
+            }
+            _ => panic!(),
+        }
+        assert!(parse_flowspec_match("port 80 &abc").is_err());
+    }
 }
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use bgp_rs::NLRIEncoding;
+use tokio::time::timeout;
+
+use bgpd_rs::api::rpc::{ApiServer, RouteSpec};
+use bgpd_rs::config;
+use bgpd_rs::handler::{Server, ServerBuilder};
+use bgpd_rs::session::SessionUpdate;
+
+async fn server(contents: &str) -> Server {
+    let config = config::from_str(contents).unwrap();
+    ServerBuilder::new(config).build().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_embedded_servers() {
+    // Advertises routes to the receiver, which connects to the port it's listening on
+    let advertiser = server(
+        r#"
+        router_id = "1.1.1.1"
+        default_as = 65000
+        bgp_sockets = ["127.0.0.1:0"]
+        [[peers]]
+        remote_ip = "127.0.0.1"
+        remote_as = 65001
+        passive = true
+        "#,
+    )
+    .await;
+    let port = advertiser.local_addrs()[0].port();
+    assert_ne!(port, 0);
+    let receiver = server(&format!(
+        r#"
+        router_id = "2.2.2.2"
+        default_as = 65001
+        bgp_sockets = ["127.0.0.1:0"]
+        poll_interval = 1
+        [[peers]]
+        remote_ip = "127.0.0.1"
+        remote_as = 65000
+        dest_port = {}
+        "#,
+        port
+    ))
+    .await;
+
+    let route = advertiser
+        .advertise_route(RouteSpec::new(
+            "100.1.0.0/16".parse().unwrap(),
+            "127.0.0.1".parse().unwrap(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(route.prefix, "100.1.0.0/16");
+    let rib = ApiServer::show_rib(&advertiser, None).await.unwrap();
+    assert!(rib.iter().any(|path| path.route.prefix == "100.1.0.0/16"));
+
+    let mut events = receiver.subscribe();
+    let handles: Vec<_> = [advertiser.clone(), receiver.clone()]
+        .iter()
+        .cloned()
+        .map(|mut server| tokio::spawn(async move { server.run().await.is_ok() }))
+        .collect();
+
+    let learned = timeout(Duration::from_secs(30), async {
+        loop {
+            match events.recv().await.unwrap() {
+                SessionUpdate::Learned((peer, _, update, _)) => {
+                    let prefixes: Vec<String> = update
+                        .announced_routes
+                        .iter()
+                        .filter_map(|nlri| match nlri {
+                            NLRIEncoding::IP(prefix) => Some(format!("{:?}", prefix)),
+                            _ => None,
+                        })
+                        .collect();
+                    if !prefixes.is_empty() {
+                        break (peer, prefixes);
+                    }
+                }
+                _ => continue,
+            }
+        }
+    })
+    .await
+    .expect("Route learned from the advertiser");
+    assert_eq!(learned.0, "127.0.0.1".parse::<std::net::IpAddr>().unwrap());
+    assert_eq!(learned.1, vec!["100.1.0.0/16"]);
+
+    assert_eq!(
+        advertiser
+            .withdraw_route("100.1.0.0/16".parse().unwrap())
+            .await
+            .len(),
+        1
+    );
+    advertiser.stop();
+    receiver.stop();
+    for handle in handles {
+        assert!(timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap());
+    }
+}